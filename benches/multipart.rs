@@ -0,0 +1,72 @@
+/*!
+Time parsing a large multipart upload, which is mostly spent searching
+for boundaries.
+
+Run with `cargo bench`.
+*/
+
+use std::time::Instant;
+
+use dumb_cgi::{parse_multipart, Body, MultipartBuffer};
+
+const BOUNDARY: &str = "----WebKitFormBoundary7MA4YWxkTrZu0gW";
+const UPLOAD_BYTES: usize = 32 * 1024 * 1024;
+const ITERATIONS: u32 = 5;
+
+fn upload() -> Vec<u8> {
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"big.bin\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n",
+        BOUNDARY
+    )
+    .into_bytes();
+    // Binary data with plenty of newlines and hyphens in it.
+    let mut x: u32 = 1;
+    body.extend((0..UPLOAD_BYTES).map(|_| {
+        x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        match (x >> 16) % 64 {
+            0 => b'\r',
+            1 => b'\n',
+            2 => b'-',
+            n => n as u8,
+        }
+    }));
+    body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+    body
+}
+
+fn main() {
+    let body = upload();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        match parse_multipart(&body, BOUNDARY) {
+            Body::Multipart(form) => assert_eq!(form[0].body.len(), UPLOAD_BYTES),
+            _ => panic!("failed to parse"),
+        }
+    }
+    let elapsed = start.elapsed();
+    let mb = (body.len() as f64) * f64::from(ITERATIONS) / (1024.0 * 1024.0);
+    println!(
+        "parse_multipart: {:.1} MB in {:?} ({:.0} MB/s)",
+        mb,
+        elapsed,
+        mb / elapsed.as_secs_f64()
+    );
+
+    // Without copying the part, this is nearly all boundary searching.
+    let mut elapsed = std::time::Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let copy = body.clone();
+        let start = Instant::now();
+        let form = MultipartBuffer::parse(copy, BOUNDARY).unwrap();
+        elapsed += start.elapsed();
+        assert_eq!(form.parts()[0].body.len(), UPLOAD_BYTES);
+    }
+    println!(
+        "MultipartBuffer: {:.1} MB in {:?} ({:.0} MB/s)",
+        mb,
+        elapsed,
+        mb / elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,151 @@
+/*!
+Static files (stylesheets, scripts, icons) built into the program, so a
+CGI program can be deployed as a single file, without having to know
+where its static files ended up.
+
+The `include_assets!` macro embeds files from a directory of the crate
+(relative to its `Cargo.toml`):
+
+```rust,ignore
+use dumb_cgi::{include_assets, assets::Assets};
+
+static ASSETS: Assets = include_assets!("static", "style.css", "app.js", "img/logo.png");
+```
+
+and `Assets::serve()` answers requests for them, with the right
+`Content-type`, an `ETag` (answering `If-None-Match` with
+`304 Not Modified`), and a `Cache-Control` header:
+
+```rust
+use dumb_cgi::{assets::Assets, testing::MockRequest};
+
+static ASSETS: Assets = Assets::new(&[
+    ("style.css", b"body { color: #333; }"),
+    ("app.js", b"console.log('hi');"),
+])
+.with_prefix("/static");
+
+let req = MockRequest::get("/static/style.css").build().unwrap();
+let response = ASSETS.serve(&req).unwrap();
+assert_eq!(response.get_status(), 200);
+assert_eq!(response.get_content_type(), "text/css; charset=utf-8");
+
+let etag = response.get_header("etag").unwrap();
+let req = MockRequest::get("/static/style.css")
+    .header("If-None-Match", etag)
+    .build()
+    .unwrap();
+assert_eq!(ASSETS.serve(&req).unwrap().get_status(), 304);
+
+// Not an asset; the program handles it some other way.
+let req = MockRequest::get("/index.html").build().unwrap();
+assert!(ASSETS.serve(&req).is_none());
+```
+*/
+
+use crate::cache::CacheControl;
+use crate::mime;
+use crate::{Request, Response};
+
+/**
+Embed files from the directory `dir` (relative to the crate's
+`Cargo.toml`) in the program, returning an `Assets` that serves them
+under their names relative to `dir`.
+
+```rust,ignore
+static ASSETS: Assets = include_assets!("static", "style.css", "img/logo.png");
+```
+*/
+#[macro_export]
+macro_rules! include_assets {
+    ($dir:literal, $($file:literal),+ $(,)?) => {
+        $crate::assets::Assets::new(&[$((
+            $file,
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $dir, "/", $file)) as &[u8],
+        )),+])
+    };
+}
+
+/**
+A set of files built into the program, by name.
+*/
+#[derive(Debug, Clone)]
+pub struct Assets {
+    files: &'static [(&'static str, &'static [u8])],
+    prefix: &'static str,
+    max_age: u64,
+}
+
+impl Assets {
+    /**
+    Serve the `(name, contents)` pairs in `files` (usually made with
+    `include_assets!`), at paths starting with `/` followed by the name.
+    */
+    pub const fn new(files: &'static [(&'static str, &'static [u8])]) -> Assets {
+        Assets {
+            files,
+            prefix: "",
+            max_age: 60 * 60,
+        }
+    }
+
+    /// Builder-pattern method for serving the files under `prefix` (like
+    /// `/static`) instead of at the top level.
+    pub const fn with_prefix(self, prefix: &'static str) -> Assets {
+        let mut new = self;
+        new.prefix = prefix;
+        new
+    }
+
+    /**
+    Builder-pattern method for setting how many seconds clients may cache
+    the files without checking whether they've changed (an hour by
+    default).
+    */
+    pub const fn with_max_age(self, seconds: u64) -> Assets {
+        let mut new = self;
+        new.max_age = seconds;
+        new
+    }
+
+    /// Return the contents of the file named `name`.
+    pub fn get(&self, name: &str) -> Option<&'static [u8]> {
+        self.files
+            .iter()
+            .find(|(file, _)| *file == name)
+            .map(|(_, bytes)| *bytes)
+    }
+
+    /// Iterate over the names of the files.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.files.iter().map(|(name, _)| *name)
+    }
+
+    /**
+    If the request's `PATH_INFO` is for one of the files, return the
+    response to send: the file, or `304 Not Modified` if the client's
+    copy (according to `If-None-Match`) is current, or
+    `405 Method Not Allowed` if it isn't a `GET` or `HEAD` request.
+
+    Return `None` if the path isn't for one of the files.
+    */
+    pub fn serve(&self, req: &Request) -> Option<Response> {
+        let path = req.var("PATH_INFO").unwrap_or("");
+        let name = path.strip_prefix(self.prefix)?.strip_prefix('/')?;
+        let bytes = self.get(name)?;
+
+        match req.var("REQUEST_METHOD") {
+            Some("GET") | Some("HEAD") => {}
+            _ => return Some(Response::new(405).with_header("Allow", "GET, HEAD")),
+        }
+
+        Some(
+            Response::new(200)
+                .with_content_type(mime::from_path(name).unwrap_or(mime::DEFAULT))
+                .with_body(bytes)
+                .with_etag_from_body()
+                .with_cache_control(CacheControl::public().max_age(self.max_age))
+                .not_modified_if(req),
+        )
+    }
+}
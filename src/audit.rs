@@ -0,0 +1,451 @@
+/*!
+An append-only, tamper-evident audit log.
+
+Each record contains the SHA-256 hash of the record before it, and its own
+hash covers that link; editing, removing, or reordering any record (other
+than truncating the end of the log) breaks the chain, which `verify()`
+will detect.
+
+On its own, that only catches accidents and careless tampering: anyone who
+can edit the log can recompute the hashes of every record after the one
+they changed, and the chain checks out again. To catch that, either give
+the log a secret key with `.with_key()` (the hashes are then HMAC-SHA-256
+under it, which can't be recomputed without the key, so keep it out of
+the log's reach), or keep a copy of the latest hash (as returned by
+`.append()`) somewhere the log's editors can't reach, and compare it with
+the log's last record.
+
+Records are lines of text with six tab-separated fields (sequence number,
+timestamp, actor, action, previous hash, and hash), with any tabs,
+newlines, or backslashes in the actor or action escaped.
+
+```rust
+use dumb_cgi::audit::AuditLog;
+
+let path = std::env::temp_dir().join("dumb_cgi_audit_doctest.log");
+# _ = std::fs::remove_file(&path);
+let log = AuditLog::new(&path);
+log.append("dan", "deleted user dave").unwrap();
+log.append("dan", "changed site title").unwrap();
+
+assert_eq!(log.verify().unwrap(), 2);
+# std::fs::remove_file(&path).unwrap();
+```
+*/
+
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::hash::{hmac_sha256, sha256, to_hex};
+use crate::lockfile::LockGuard;
+use crate::rotate::{numbered, Rotation};
+use crate::Error;
+
+/// The "previous hash" of the first record in a log.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// How much of the end of the file to read when looking for the last record.
+const TAIL_BYTES: u64 = 8192;
+
+fn log_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!("Error accessing audit log {}: {}", path.display(), &e),
+    }
+}
+
+fn corrupt(path: &Path, line: usize, details: &str) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!(
+            "audit log {} verification failed at line {}: {}",
+            path.display(),
+            line,
+            details
+        ),
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('t') => unescaped.push('\t'),
+                Some('n') => unescaped.push('\n'),
+                Some('r') => unescaped.push('\r'),
+                Some(c) => unescaped.push(c),
+                None => {}
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+/**
+A single entry in an audit log.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Position of this record in the log (starting at 1).
+    pub sequence: u64,
+    /// When the record was written (seconds since the Unix epoch).
+    pub timestamp: u64,
+    /// Who performed the action.
+    pub actor: String,
+    /// What was done.
+    pub action: String,
+    /// The hash of the previous record.
+    pub prev_hash: String,
+    /// The hash of this record.
+    pub hash: String,
+}
+
+impl Record {
+    /*
+    The text covered by a record's hash (everything but the hash).
+    */
+    fn body(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.sequence,
+            self.timestamp,
+            escape(&self.actor),
+            escape(&self.action),
+            &self.prev_hash
+        )
+    }
+
+    /*
+    The record's hash: plain SHA-256, or HMAC-SHA-256 under `key`.
+    */
+    fn compute_hash(&self, key: Option<&[u8]>) -> String {
+        match key {
+            Some(key) => to_hex(&hmac_sha256(key, self.body().as_bytes())),
+            None => to_hex(&sha256(self.body().as_bytes())),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        format!("{}\t{}\n", self.body(), &self.hash)
+    }
+
+    fn parse(line: &str) -> Option<Record> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        Some(Record {
+            sequence: fields[0].parse().ok()?,
+            timestamp: fields[1].parse().ok()?,
+            actor: unescape(fields[2]),
+            action: unescape(fields[3]),
+            prev_hash: fields[4].to_owned(),
+            hash: fields[5].to_owned(),
+        })
+    }
+}
+
+/**
+A hash-chained audit log stored in a single file.
+*/
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    key: Option<Vec<u8>>,
+}
+
+impl AuditLog {
+    /// Create a handle to the audit log at `path` (which need not exist yet).
+    pub fn new<P: Into<PathBuf>>(path: P) -> AuditLog {
+        AuditLog {
+            path: path.into(),
+            rotation: None,
+            key: None,
+        }
+    }
+
+    /**
+    Builder-pattern method for keying the hash chain with `key`, a secret
+    kept away from the log (see the module documentation). A log has to
+    be verified with the key it was written with.
+
+    ```rust
+    # use dumb_cgi::audit::AuditLog;
+    let path = std::env::temp_dir().join("dumb_cgi_audit_key_doctest.log");
+    # _ = std::fs::remove_file(&path);
+    let log = AuditLog::new(&path).with_key("server-side secret key");
+    log.append("dan", "deleted user dave").unwrap();
+    assert_eq!(log.verify().unwrap(), 1);
+    assert!(AuditLog::new(&path).verify().is_err());
+    # std::fs::remove_file(&path).unwrap();
+    ```
+    */
+    pub fn with_key<K: Into<Vec<u8>>>(self, key: K) -> AuditLog {
+        let mut new = self;
+        new.key = Some(key.into());
+        new
+    }
+
+    /**
+    Builder-pattern method for rotating the log file (see the `rotate`
+    module); it's checked before each record is appended.
+
+    The chain carries on across files: the first record in a new file
+    follows on from the last one in the file before it, and `verify()`
+    checks the old files along with the current one, so a missing or
+    replaced file is caught like a missing or edited record. Only the
+    oldest file kept can't be checked against the ones before it (which
+    are gone).
+
+    The rotation has to keep at least one old file (`append()` returns
+    an error otherwise), as discarding a full log would throw away audit
+    records.
+
+    ```rust
+    # use dumb_cgi::{audit::AuditLog, rotate::Rotation};
+    let path = std::env::temp_dir().join("dumb_cgi_audit_rotation_doctest.log");
+    # for p in ["", ".1", ".2"] { _ = std::fs::remove_file(format!("{}{}", path.display(), p)); }
+    let log = AuditLog::new(&path)
+        .with_rotation(Rotation::new().with_max_bytes(1).with_keep(2));
+    for action in ["first", "second", "third"] {
+        log.append("dan", action).unwrap();
+    }
+    // One record in each file, but they make one chain.
+    assert_eq!(log.records().unwrap()[0].sequence, 3);
+    assert_eq!(log.verify().unwrap(), 3);
+    # for p in ["", ".1", ".2"] { _ = std::fs::remove_file(format!("{}{}", path.display(), p)); }
+    ```
+    */
+    pub fn with_rotation(self, rotation: Rotation) -> AuditLog {
+        let mut new = self;
+        new.rotation = Some(rotation);
+        new
+    }
+
+    /// Return the path of the log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(".lock");
+        PathBuf::from(name)
+    }
+
+    /*
+    The `rotation`'s old copies of the log that exist, oldest first, and
+    whether the oldest is the oldest one kept (so older ones may have
+    been discarded). Every copy newer than the oldest has to be there.
+    */
+    fn old_files(&self) -> Result<(Vec<PathBuf>, bool), Error> {
+        let keep = match &self.rotation {
+            Some(rotation) => rotation.keep(),
+            None => return Ok((Vec::new(), false)),
+        };
+        let mut files: Vec<PathBuf> = Vec::new();
+        let mut full = false;
+        for n in (1..=keep).rev() {
+            let path = numbered(&self.path, n);
+            if path.exists() {
+                full |= n == keep;
+                files.push(path);
+            } else if !files.is_empty() {
+                return Err(Error {
+                    code: 500,
+                    message: "Internal server error.".to_owned(),
+                    details: format!(
+                        "audit log verification failed: rotated file {} is missing",
+                        path.display()
+                    ),
+                });
+            }
+        }
+        Ok((files, full))
+    }
+
+    /*
+    The record the next one appended follows on from: the last in the
+    log, or if the log has just been rotated away, the last in the newest
+    old copy.
+    */
+    fn chain_head(&self) -> Result<Option<Record>, Error> {
+        match last_record(&self.path)? {
+            Some(r) => Ok(Some(r)),
+            None if self.rotation.is_some() => last_record(&numbered(&self.path, 1)),
+            None => Ok(None),
+        }
+    }
+
+    /**
+    Append a record of `actor` performing `action`, returning the record
+    as written.
+    */
+    pub fn append(&self, actor: &str, action: &str) -> Result<Record, Error> {
+        if self.rotation.is_some_and(|r| r.keep() == 0) {
+            return Err(Error {
+                code: 500,
+                message: "Internal server error.".to_owned(),
+                details: format!(
+                    "audit log {} rotation must keep at least one old file",
+                    self.path.display()
+                ),
+            });
+        }
+        let lock_path = self.lock_path();
+        let _lock = LockGuard::acquire(lock_path.clone()).map_err(|e| log_error(&lock_path, e))?;
+        if let Some(rotation) = &self.rotation {
+            rotation.rotate_locked(&self.path)?;
+        }
+
+        let (sequence, prev_hash) = match self.chain_head()? {
+            Some(r) => (r.sequence + 1, r.hash),
+            None => (1, GENESIS.to_owned()),
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut record = Record {
+            sequence,
+            timestamp,
+            actor: actor.to_owned(),
+            action: action.to_owned(),
+            prev_hash,
+            hash: String::new(),
+        };
+        record.hash = record.compute_hash(self.key.as_deref());
+
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| log_error(&self.path, e))?;
+        f.write_all(record.to_line().as_bytes())
+            .map_err(|e| log_error(&self.path, e))?;
+        f.sync_data().map_err(|e| log_error(&self.path, e))?;
+
+        Ok(record)
+    }
+
+    /**
+    Read and return every record in the log file (not any rotated-away
+    old copies), without verifying them.
+    */
+    pub fn records(&self) -> Result<Vec<Record>, Error> {
+        records(&self.path)
+    }
+
+    /**
+    Check the whole chain, returning the number of records if it's intact,
+    or an error describing the first broken link.
+
+    With rotation (see `.with_rotation()`), this checks the old copies
+    too, oldest first, and counts their records along with the log's.
+    */
+    pub fn verify(&self) -> Result<usize, Error> {
+        let (mut files, full) = self.old_files()?;
+        files.push(self.path.clone());
+
+        // Where the chain should pick up: from the start, unless older
+        // files might have been discarded, in which case from wherever
+        // the oldest one starts.
+        let mut expected: Option<(u64, String)> = if full {
+            None
+        } else {
+            Some((1, GENESIS.to_owned()))
+        };
+        let mut count = 0;
+        for path in files.iter() {
+            for (n, r) in records(path)?.into_iter().enumerate() {
+                let (sequence, prev_hash) = expected
+                    .take()
+                    .unwrap_or_else(|| (r.sequence, r.prev_hash.clone()));
+                if r.sequence != sequence {
+                    return Err(corrupt(path, n + 1, "sequence number out of order"));
+                }
+                if r.prev_hash != prev_hash {
+                    return Err(corrupt(path, n + 1, "previous hash does not match"));
+                }
+                if r.compute_hash(self.key.as_deref()) != r.hash {
+                    return Err(corrupt(path, n + 1, "record hash does not match contents"));
+                }
+                expected = Some((r.sequence + 1, r.hash));
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+/*
+Return the last record in the log file at `path` (if any), reading only
+the end of the file.
+*/
+fn last_record(path: &Path) -> Result<Option<Record>, Error> {
+    let mut f = match std::fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(log_error(path, e)),
+    };
+    let len = f.metadata().map_err(|e| log_error(path, e))?.len();
+    let start = len.saturating_sub(TAIL_BYTES);
+    f.seek(SeekFrom::Start(start))
+        .map_err(|e| log_error(path, e))?;
+    let mut tail = Vec::new();
+    f.read_to_end(&mut tail).map_err(|e| log_error(path, e))?;
+    let tail = String::from_utf8_lossy(&tail);
+    let lines: Vec<&str> = tail.lines().collect();
+    match lines.iter().enumerate().rfind(|(_, l)| !l.is_empty()) {
+        None => Ok(None),
+        Some((n, line)) => match Record::parse(line) {
+            Some(r) => Ok(Some(r)),
+            // The last record might be longer than the chunk we read.
+            None if start > 0 => Ok(records(path)?.pop()),
+            // Otherwise the chunk is the whole file, so `n` counts
+            // from its start.
+            None => Err(corrupt(path, n + 1, "unable to parse last record")),
+        },
+    }
+}
+
+/*
+Read every record in the log file at `path`.
+*/
+fn records(path: &Path) -> Result<Vec<Record>, Error> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(log_error(path, e)),
+    };
+    let mut records = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        match Record::parse(line) {
+            Some(r) => records.push(r),
+            None => return Err(corrupt(path, n + 1, "unable to parse record")),
+        }
+    }
+    Ok(records)
+}
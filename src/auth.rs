@@ -0,0 +1,552 @@
+/*!
+Password hashing, a simple user file, and cookie-based login/logout.
+
+This module is only available with the `auth` feature enabled. It is meant
+to cover the needs of small CGI sites that would otherwise reach for HTTP
+Basic authentication: a flat file of `user:hash` lines and a signed,
+expiring cookie that marks a user agent as logged in.
+
+```rust
+use dumb_cgi::auth::{hash_password_with, Authenticator, Users};
+
+let mut users = Users::new();
+users.insert("dan", hash_password_with("hunter2", b"saltsaltsaltsalt", 1000));
+
+let auth = Authenticator::new(users, "server-side secret key");
+// This is the value of a `Set-Cookie` header to send on successful login.
+let cookie = auth.login("dan", "hunter2").unwrap();
+assert!(cookie.starts_with("auth="));
+assert!(auth.login("dan", "wrong").is_none());
+```
+*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cookie::{Cookie, SameSite};
+use crate::hash::{constant_time_eq, fill_random, hmac_sha256, pbkdf2_sha256, to_hex};
+use crate::lockout::Lockout;
+use crate::{base64, Error, Request};
+
+/// Number of PBKDF2 iterations used by `hash_password()`.
+pub const DEFAULT_ITERATIONS: u32 = 100_000;
+
+const PBKDF2_TAG: &str = "$pbkdf2-sha256$";
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+const DEFAULT_COOKIE_NAME: &str = "auth";
+const DEFAULT_MAX_AGE: u64 = 60 * 60 * 24;
+const SESSION_ID_LEN: usize = 16;
+
+/*
+This hash is verified against when a nonexistent user attempts to log in,
+so that the response time doesn't reveal which user names exist.
+*/
+const DUMMY_HASH: &str = "$pbkdf2-sha256$100000$AAAAAAAAAAAAAAAAAAAAAA$\
+                          AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+
+/**
+Read `n` bytes from the operating system's random number source
+(`/dev/urandom`).
+*/
+pub fn random_bytes(n: usize) -> Result<Vec<u8>, Error> {
+    let mut bytes = vec![0u8; n];
+    fill_random(&mut bytes)?;
+    Ok(bytes)
+}
+
+/**
+Hash `password` with a random salt and `DEFAULT_ITERATIONS` rounds of
+PBKDF2-HMAC-SHA-256.
+
+The returned string has the form `$pbkdf2-sha256$iterations$salt$hash`
+(with the salt and hash base64-encoded) and contains everything
+`verify_password()` needs to check a password against it.
+*/
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let salt = random_bytes(SALT_LEN)?;
+    Ok(hash_password_with(password, &salt, DEFAULT_ITERATIONS))
+}
+
+/**
+Hash `password` with the supplied `salt` and number of `iterations`.
+
+You probably want `hash_password()` instead; this is mainly useful for
+generating hashes with a lower work factor for testing.
+*/
+pub fn hash_password_with(password: &str, salt: &[u8], iterations: u32) -> String {
+    let mut out = [0u8; HASH_LEN];
+    pbkdf2_sha256(password.as_bytes(), salt, iterations, &mut out);
+    format!(
+        "{}{}${}${}",
+        PBKDF2_TAG,
+        iterations,
+        base64::encode(salt).trim_end_matches('='),
+        base64::encode(&out).trim_end_matches('=')
+    )
+}
+
+/**
+Check whether `password` matches `hash`.
+
+In addition to hashes generated by `hash_password()`, this understands the
+bcrypt (`$2y$`), MD5-crypt (`$apr1$`), and `{SHA}` formats written by
+Apache's `htpasswd` utility, so existing user files can be reused.
+
+Returns `false` if `hash` isn't in a recognized format.
+
+```rust
+# use dumb_cgi::auth::{hash_password_with, verify_password};
+let hash = hash_password_with("hunter2", b"saltsaltsaltsalt", 1000);
+assert!(verify_password("hunter2", &hash));
+assert!(!verify_password("hunter3", &hash));
+```
+*/
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let rest = match hash.strip_prefix(PBKDF2_TAG) {
+        Some(rest) => rest,
+        None => return crate::htpasswd::verify(password, hash).unwrap_or(false),
+    };
+    let mut chunks = rest.split('$');
+    let (iterations, salt, expected) = match (chunks.next(), chunks.next(), chunks.next()) {
+        (Some(i), Some(s), Some(h)) => (i, s, h),
+        _ => return false,
+    };
+    let iterations = match iterations.parse::<u32>() {
+        Ok(n) if n > 0 => n,
+        _ => return false,
+    };
+    // A digest of any other length (an empty one would match anything)
+    // or a missing salt can't have come from `hash_password()`.
+    let (salt, expected) = match (base64::decode(salt), base64::decode(expected)) {
+        (Some(s), Some(h)) if !s.is_empty() && h.len() == HASH_LEN => (s, h),
+        _ => return false,
+    };
+
+    let mut out = [0u8; HASH_LEN];
+    pbkdf2_sha256(password.as_bytes(), &salt, iterations, &mut out);
+    constant_time_eq(&out, &expected)
+}
+
+/**
+A collection of user names and their password hashes, usually read from a
+file of `name:hash` lines (like an Apache `htpasswd` file; see
+`verify_password()` for the supported hash formats).
+
+Blank lines and lines beginning with `#` are ignored, as are any lines
+without a `:`.
+
+```rust
+# use dumb_cgi::auth::{hash_password_with, Users};
+let hash = hash_password_with("hunter2", b"saltsaltsaltsalt", 1000);
+let text = format!("# admins\ndan:{}\n", &hash);
+let users = Users::parse(&text);
+
+assert!(users.contains("dan"));
+assert!(users.verify("dan", "hunter2"));
+assert!(!users.verify("dave", "hunter2"));
+```
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Users {
+    users: HashMap<String, String>,
+}
+
+impl Users {
+    /// Create an empty collection of users.
+    pub fn new() -> Users {
+        Users::default()
+    }
+
+    /// Parse a collection of users from the text of a user file.
+    pub fn parse(text: &str) -> Users {
+        let mut users = Users::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some((name, hash)) = line.split_once(':') {
+                users.insert(name, hash);
+            }
+        }
+        users
+    }
+
+    /// Read and parse the user file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Users, Error> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(Users::parse(&text)),
+            Err(e) => Err(Error {
+                code: 500,
+                message: "Internal server error.".to_owned(),
+                details: format!("Unable to read user file {}: {}", path.display(), &e),
+            }),
+        }
+    }
+
+    /// Add a user (or replace an existing user's password hash).
+    pub fn insert<N, H>(&mut self, name: N, hash: H)
+    where
+        N: Into<String>,
+        H: Into<String>,
+    {
+        self.users.insert(name.into(), hash.into());
+    }
+
+    /// Remove a user, returning their password hash if they existed.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.users.remove(name)
+    }
+
+    /// Return whether a user with the given `name` exists.
+    pub fn contains(&self, name: &str) -> bool {
+        self.users.contains_key(name)
+    }
+
+    /// Return the password hash stored for the user `name` (if they exist).
+    pub fn hash(&self, name: &str) -> Option<&str> {
+        self.users.get(name).map(|h| h.as_str())
+    }
+
+    /**
+    Return whether `name` is a user whose password is `password`.
+
+    This takes about as long for nonexistent users as it does for
+    existing ones.
+    */
+    pub fn verify(&self, name: &str, password: &str) -> bool {
+        match self.users.get(name) {
+            Some(hash) => verify_password(password, hash),
+            None => {
+                _ = verify_password(password, DUMMY_HASH);
+                false
+            }
+        }
+    }
+
+    /**
+    Return the collection in user file format (sorted by user name, so
+    that rewriting an unchanged file doesn't reorder it).
+    */
+    pub fn to_file_string(&self) -> String {
+        let mut names: Vec<&String> = self.users.keys().collect();
+        names.sort();
+        let mut s = String::new();
+        for name in names.into_iter() {
+            s.push_str(name);
+            s.push(':');
+            s.push_str(&self.users[name]);
+            s.push('\n');
+        }
+        s
+    }
+}
+
+/*
+Return the current time as a number of seconds since the Unix epoch.
+*/
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn session_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!("Error accessing session file {}: {}", path.display(), &e),
+    }
+}
+
+/**
+Logs users in and out by issuing (and checking) signed, expiring cookies.
+
+The cookie value contains the user name, an expiration time, and a
+fingerprint of the user's password hash, authenticated with HMAC-SHA-256
+under a server-side secret `key`. Anyone who knows the key can forge
+login cookies, so keep it out of the document root.
+
+Changing a user's password (or removing the user) invalidates every
+login cookie issued to them. On its own, though, nothing is stored on the
+server, so logging out only asks the user agent to forget its cookie; a
+copy of it would still work until it expired. To be able to revoke
+logins, keep sessions on the server with `.with_sessions()`: each login
+then also has to match a file there, which `.logout_from()` removes.
+
+```rust
+# use dumb_cgi::auth::{hash_password_with, Authenticator, Users};
+# use dumb_cgi::testing::MockRequest;
+let dir = std::env::temp_dir().join("dumb_cgi_auth_sessions_doctest");
+let mut users = Users::new();
+users.insert("dan", hash_password_with("hunter2", b"saltsaltsaltsalt", 1000));
+let auth = Authenticator::new(users, "server-side secret key").with_sessions(&dir);
+
+let set_cookie = auth.login("dan", "hunter2").unwrap();
+let cookie = set_cookie.split(';').next().unwrap();
+let req = MockRequest::get("/").header("Cookie", cookie).build().unwrap();
+assert_eq!(auth.user(&req).as_deref(), Some("dan"));
+
+auth.logout_from(&req).unwrap();
+assert_eq!(auth.user(&req), None);
+# std::fs::remove_dir_all(&dir).unwrap();
+```
+*/
+#[derive(Debug, Clone)]
+pub struct Authenticator {
+    users: Users,
+    key: Vec<u8>,
+    cookie_name: String,
+    max_age: u64,
+    secure: bool,
+    lockout: Option<Lockout>,
+    sessions: Option<PathBuf>,
+}
+
+impl Authenticator {
+    /**
+    Create a new `Authenticator` that checks credentials against `users`
+    and signs cookies with `key`.
+
+    By default, the cookie is named `auth` and is valid for 24 hours.
+    */
+    pub fn new<K: Into<Vec<u8>>>(users: Users, key: K) -> Authenticator {
+        Authenticator {
+            users,
+            key: key.into(),
+            cookie_name: DEFAULT_COOKIE_NAME.to_owned(),
+            max_age: DEFAULT_MAX_AGE,
+            secure: false,
+            lockout: None,
+            sessions: None,
+        }
+    }
+
+    /// Builder-pattern method for changing the name of the login cookie.
+    pub fn with_cookie_name<T: Into<String>>(self, name: T) -> Authenticator {
+        let mut new = self;
+        new.cookie_name = name.into();
+        new
+    }
+
+    /// Builder-pattern method for changing how long (in seconds) a login
+    /// remains valid.
+    pub fn with_max_age(self, seconds: u64) -> Authenticator {
+        let mut new = self;
+        new.max_age = seconds;
+        new
+    }
+
+    /// Builder-pattern method for marking the login cookie `Secure` (so it
+    /// is only sent over HTTPS).
+    pub fn with_secure(self, secure: bool) -> Authenticator {
+        let mut new = self;
+        new.secure = secure;
+        new
+    }
+
+    /// Builder-pattern method for throttling failed login attempts made
+    /// through `.login_from()`.
+    pub fn with_lockout(self, lockout: Lockout) -> Authenticator {
+        let mut new = self;
+        new.lockout = Some(lockout);
+        new
+    }
+
+    /**
+    Builder-pattern method for keeping a file for each login in the
+    directory `dir` (created if need be), so logins can be revoked with
+    `.logout_from()`.
+
+    A file is removed when its user logs out; the files of logins that
+    just expire stay behind, so collect them with a `gc::Gc` with a
+    `max_age` longer than the login's.
+    */
+    pub fn with_sessions<P: Into<PathBuf>>(self, dir: P) -> Authenticator {
+        let mut new = self;
+        new.sessions = Some(dir.into());
+        new
+    }
+
+    /// Return a reference to the users this `Authenticator` checks against.
+    pub fn users(&self) -> &Users {
+        &self.users
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        base64::encode_url(&hmac_sha256(&self.key, payload.as_bytes()))
+    }
+
+    /*
+    A fingerprint of `name`'s current password hash (so changing it
+    invalidates logins), or `None` if there's no such user.
+    */
+    fn fingerprint(&self, name: &str) -> Option<String> {
+        let hash = self.users.hash(name)?;
+        let mac = hmac_sha256(&self.key, format!("password-hash\0{}", hash).as_bytes());
+        Some(base64::encode_url(&mac[..12]))
+    }
+
+    /*
+    The session file for the session `id`, if sessions are kept (and `id`
+    could be one).
+    */
+    fn session_path(&self, id: &str) -> Option<PathBuf> {
+        let dir = self.sessions.as_ref()?;
+        let valid = id.len() == 2 * SESSION_ID_LEN && id.bytes().all(|b| b.is_ascii_hexdigit());
+        valid.then(|| dir.join(id))
+    }
+
+    /*
+    Return the `Set-Cookie` header value logging `name` in, first storing
+    a session for it if sessions are kept.
+    */
+    fn issue(&self, name: &str) -> Result<String, Error> {
+        let fingerprint = self.fingerprint(name).unwrap_or_default();
+        let expires = now_secs().saturating_add(self.max_age);
+        let mut payload = format!(
+            "{}.{}.{}",
+            base64::encode_url(name.as_bytes()),
+            expires,
+            &fingerprint
+        );
+        if let Some(dir) = &self.sessions {
+            let id = to_hex(&random_bytes(SESSION_ID_LEN)?);
+            std::fs::create_dir_all(dir).map_err(|e| session_error(dir, e))?;
+            let path = dir.join(&id);
+            std::fs::write(&path, name).map_err(|e| session_error(&path, e))?;
+            payload.push('.');
+            payload.push_str(&id);
+        }
+        let token = format!("{}.{}", &payload, self.sign(&payload));
+        let cookie = Cookie::new(&self.cookie_name, token)
+            .with_path("/")
+            .with_max_age(Duration::from_secs(self.max_age))
+            .with_secure(self.secure)
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax);
+        Ok(cookie.to_string())
+    }
+
+    /**
+    Check `name` and `password`; if they are valid, return the value of a
+    `Set-Cookie` header that will log the user agent in.
+
+    Also returns `None` if the session (see `.with_sessions()`) can't be
+    stored; `.login_from()` returns that as an error.
+    */
+    pub fn login(&self, name: &str, password: &str) -> Option<String> {
+        if !self.users.verify(name, password) {
+            return None;
+        }
+        self.issue(name).ok()
+    }
+
+    /**
+    Like `.login()`, but for a login attempt made by `req`; if a `Lockout`
+    has been set with `.with_lockout()`, attempts are refused while the
+    user is locked out from the request's `REMOTE_ADDR`, and failures are
+    recorded.
+
+    On failure, the returned `Error` is suitable for turning directly into
+    a response (a 403, or a 429 when locked out).
+    */
+    pub fn login_from(&self, req: &Request, name: &str, password: &str) -> Result<String, Error> {
+        let addr = req.var("REMOTE_ADDR").unwrap_or("");
+        // Counted as a failure (so parallel guesses can't all get past the
+        // lockout) unless it turns out to succeed.
+        let attempt = match &self.lockout {
+            Some(lockout) => Some(lockout.attempt(name, addr)?),
+            None => None,
+        };
+        if !self.users.verify(name, password) {
+            return Err(Error {
+                code: 403,
+                message: "Invalid user name or password.".to_owned(),
+                details: format!("failed login attempt for {} from {}", name, addr),
+            });
+        }
+        if let Some(attempt) = attempt {
+            attempt.succeeded()?;
+        }
+        self.issue(name)
+    }
+
+    /**
+    Return the value of a `Set-Cookie` header that will log the user
+    agent out (by expiring the login cookie).
+
+    This doesn't revoke the login cookie, only asks the user agent to
+    forget it; see `.logout_from()`.
+    */
+    pub fn logout(&self) -> String {
+        Cookie::removal(&self.cookie_name)
+            .with_path("/")
+            .with_secure(self.secure)
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax)
+            .to_string()
+    }
+
+    /**
+    Like `.logout()`, but if sessions are kept (see `.with_sessions()`),
+    also revoke the login `req` carries, so that copies of its cookie stop
+    working too.
+    */
+    pub fn logout_from(&self, req: &Request) -> Result<String, Error> {
+        if let Some(path) = self.session(req).and_then(|(_, id)| self.session_path(&id)) {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(session_error(&path, e));
+                }
+                _ => {}
+            }
+        }
+        Ok(self.logout())
+    }
+
+    /*
+    The user name and session ID (empty if sessions aren't kept) from the
+    request's login cookie, if it's correctly signed and unexpired.
+    */
+    fn session(&self, req: &Request) -> Option<(String, String)> {
+        let token = req.cookie(&self.cookie_name)?;
+        let (payload, sig) = token.rsplit_once('.')?;
+        if !constant_time_eq(self.sign(payload).as_bytes(), sig.as_bytes()) {
+            return None;
+        }
+        let mut fields = payload.split('.');
+        let (name, expires, fingerprint) = (fields.next()?, fields.next()?, fields.next()?);
+        let id = fields.next().unwrap_or("");
+        if fields.next().is_some() || expires.parse::<u64>().ok()? < now_secs() {
+            return None;
+        }
+        let name = String::from_utf8(base64::decode_url(name)?).ok()?;
+        let current = self.fingerprint(&name)?;
+        if !constant_time_eq(current.as_bytes(), fingerprint.as_bytes()) {
+            return None;
+        }
+        Some((name, id.to_owned()))
+    }
+
+    /**
+    If the request carries a valid, unexpired login cookie for a user that
+    still exists (with the same password as when they logged in, and,
+    if sessions are kept, a session that hasn't been logged out), return
+    that user's name.
+    */
+    pub fn user(&self, req: &Request) -> Option<String> {
+        let (name, id) = self.session(req)?;
+        if self.sessions.is_some() {
+            let stored = std::fs::read_to_string(self.session_path(&id)?).ok()?;
+            if stored != name {
+                return None;
+            }
+        }
+        Some(name)
+    }
+}
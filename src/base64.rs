@@ -0,0 +1,92 @@
+/*!
+Base64 encoding and decoding (as per
+[RFC 4648](https://www.rfc-editor.org/rfc/rfc4648)), in both the standard
+and the URL- and filename-safe alphabets.
+*/
+
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const PAD: u8 = b'=';
+
+fn encode_with(bytes: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        let out_len = chunk.len() + 1;
+        for idx in 0..4 {
+            if idx < out_len {
+                let sextet = (n >> (18 - 6 * idx)) & 0x3f;
+                s.push(alphabet[sextet as usize] as char);
+            } else if pad {
+                s.push(PAD as char);
+            }
+        }
+    }
+    s
+}
+
+fn decode_with(text: &str, alphabet: &[u8; 64]) -> Option<Vec<u8>> {
+    let bytes = text.trim_end_matches(PAD as char).as_bytes();
+    if bytes.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut v = Vec::with_capacity(bytes.len() * 3 / 4);
+    for chunk in bytes.chunks(4) {
+        let mut n: u32 = 0;
+        for (idx, b) in chunk.iter().enumerate() {
+            let sextet = alphabet.iter().position(|a| a == b)? as u32;
+            n |= sextet << (18 - 6 * idx);
+        }
+        let n_bytes = chunk.len() - 1;
+        for idx in 0..n_bytes {
+            v.push((n >> (16 - 8 * idx)) as u8);
+        }
+    }
+    Some(v)
+}
+
+/**
+Encode `bytes` using the standard alphabet, with padding.
+
+```rust
+# use dumb_cgi::base64;
+assert_eq!(base64::encode(b"dumb"), "ZHVtYg==");
+```
+*/
+pub fn encode(bytes: &[u8]) -> String {
+    encode_with(bytes, STANDARD, true)
+}
+
+/**
+Decode standard-alphabet base64 (with or without padding). Returns `None`
+if `text` isn't valid base64.
+
+```rust
+# use dumb_cgi::base64;
+assert_eq!(base64::decode("ZHVtYg==").unwrap(), b"dumb");
+assert_eq!(base64::decode("ZHVtYg").unwrap(), b"dumb");
+assert!(base64::decode("ZH*tYg").is_none());
+```
+*/
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    decode_with(text, STANDARD)
+}
+
+/**
+Encode `bytes` using the URL- and filename-safe alphabet, without padding
+(suitable for cookie values and query strings).
+*/
+pub fn encode_url(bytes: &[u8]) -> String {
+    encode_with(bytes, URL_SAFE, false)
+}
+
+/// Decode URL- and filename-safe base64 (with or without padding).
+pub fn decode_url(text: &str) -> Option<Vec<u8>> {
+    decode_with(text, URL_SAFE)
+}
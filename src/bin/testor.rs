@@ -6,7 +6,7 @@ use std::io::Write;
 const FULL_BODY_LIMIT: usize = 64;
 const BODY_PREV: usize = 8;
 
-use dumb_cgi::{Body, EmptyResponse, FullResponse, Query, Request};
+use dumb_cgi::{Body, Query, Request, Response};
 #[cfg(feature = "log")]
 use simplelog::{Config, LevelFilter, WriteLogger};
 
@@ -22,7 +22,7 @@ where
     }
 }
 
-fn wrapped_main() -> Result<FullResponse, ErrorShim> {
+fn wrapped_main() -> Result<Response, ErrorShim> {
     let cgi = match Request::new() {
         Ok(cgi) => cgi,
         Err(e) => {
@@ -31,7 +31,7 @@ fn wrapped_main() -> Result<FullResponse, ErrorShim> {
         }
     };
 
-    let mut r = EmptyResponse::new(200).with_content_type("text/plain");
+    let mut r = Response::new(200).with_content_type("text/plain");
 
     writeln!(&mut r, "Environment Variables:")?;
     for (k, v) in cgi.vars() {
@@ -102,7 +102,7 @@ fn main() {
     match wrapped_main() {
         Err(e) => {
             let err_body: Vec<u8> = e.0.into();
-            let r = EmptyResponse::new(500)
+            let r = Response::new(500)
                 .with_content_type("text/plain")
                 .with_body(err_body);
             r.respond().unwrap();
@@ -0,0 +1,204 @@
+/*!
+Building `Cache-Control` headers.
+
+```rust
+use dumb_cgi::cache::CacheControl;
+use dumb_cgi::Response;
+
+let cc = CacheControl::public().max_age(3600).stale_while_revalidate(60);
+assert_eq!(cc.to_string(), "public, max-age=3600, stale-while-revalidate=60");
+
+let r = Response::new(200).with_cache_control(CacheControl::new().no_store());
+assert_eq!(r.get_header("Cache-Control"), Some("no-store"));
+```
+
+A misspelled directive is ignored by caches without complaint, so the
+response is cached in some other way than meant; building the header
+with methods lets the compiler catch the misspelling instead. Directives
+are always written in the same order (who may cache the response, then
+how long for, then what to do once it's stale), whatever order they
+were set in.
+*/
+
+use std::fmt::{Display, Formatter};
+
+/*
+Who may keep a copy of the response.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Visibility {
+    Public,
+    Private,
+}
+
+/**
+The value of a `Cache-Control` response header, written by its `Display`
+implementation.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    visibility: Option<Visibility>,
+    no_cache: bool,
+    no_store: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+    stale_while_revalidate: Option<u64>,
+    stale_if_error: Option<u64>,
+    must_revalidate: bool,
+    proxy_revalidate: bool,
+    no_transform: bool,
+    immutable: bool,
+}
+
+impl CacheControl {
+    /// Return a `CacheControl` with no directives set.
+    pub fn new() -> CacheControl {
+        CacheControl::default()
+    }
+
+    /// Return a `CacheControl` saying shared caches (proxies and CDNs)
+    /// may keep the response, as well as the browser (`public`).
+    pub fn public() -> CacheControl {
+        CacheControl {
+            visibility: Some(Visibility::Public),
+            ..Default::default()
+        }
+    }
+
+    /// Return a `CacheControl` saying only the user's own browser may
+    /// keep the response (`private`), for pages that differ from user to
+    /// user.
+    pub fn private() -> CacheControl {
+        CacheControl {
+            visibility: Some(Visibility::Private),
+            ..Default::default()
+        }
+    }
+
+    /// Builder-pattern method for saying caches have to check with the
+    /// server before every use of the response (`no-cache`).
+    pub fn no_cache(self) -> CacheControl {
+        let mut new = self;
+        new.no_cache = true;
+        new
+    }
+
+    /// Builder-pattern method for saying the response mustn't be kept at
+    /// all (`no-store`).
+    pub fn no_store(self) -> CacheControl {
+        let mut new = self;
+        new.no_store = true;
+        new
+    }
+
+    /// Builder-pattern method for setting how many seconds the response
+    /// stays fresh (`max-age`).
+    pub fn max_age(self, seconds: u64) -> CacheControl {
+        let mut new = self;
+        new.max_age = Some(seconds);
+        new
+    }
+
+    /// Builder-pattern method for setting how many seconds the response
+    /// stays fresh in shared caches, overriding `max-age` there
+    /// (`s-maxage`).
+    pub fn s_maxage(self, seconds: u64) -> CacheControl {
+        let mut new = self;
+        new.s_maxage = Some(seconds);
+        new
+    }
+
+    /// Builder-pattern method for letting caches use the response for up
+    /// to `seconds` after it goes stale while they fetch a fresh one in
+    /// the background (`stale-while-revalidate`).
+    pub fn stale_while_revalidate(self, seconds: u64) -> CacheControl {
+        let mut new = self;
+        new.stale_while_revalidate = Some(seconds);
+        new
+    }
+
+    /// Builder-pattern method for letting caches use the response for up
+    /// to `seconds` after it goes stale if the server is failing
+    /// (`stale-if-error`).
+    pub fn stale_if_error(self, seconds: u64) -> CacheControl {
+        let mut new = self;
+        new.stale_if_error = Some(seconds);
+        new
+    }
+
+    /// Builder-pattern method for saying caches mustn't use the response
+    /// once it's stale without checking with the server
+    /// (`must-revalidate`).
+    pub fn must_revalidate(self) -> CacheControl {
+        let mut new = self;
+        new.must_revalidate = true;
+        new
+    }
+
+    /// Builder-pattern method for `must-revalidate`, for shared caches
+    /// only (`proxy-revalidate`).
+    pub fn proxy_revalidate(self) -> CacheControl {
+        let mut new = self;
+        new.proxy_revalidate = true;
+        new
+    }
+
+    /// Builder-pattern method for saying caches mustn't change the body
+    /// (recompressing images, say) (`no-transform`).
+    pub fn no_transform(self) -> CacheControl {
+        let mut new = self;
+        new.no_transform = true;
+        new
+    }
+
+    /// Builder-pattern method for saying the response will never change
+    /// while it's fresh, so browsers needn't check it even when the user
+    /// reloads the page (`immutable`), for files with versioned names.
+    pub fn immutable(self) -> CacheControl {
+        let mut new = self;
+        new.immutable = true;
+        new
+    }
+}
+
+impl Display for CacheControl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut directives: Vec<String> = Vec::new();
+        match self.visibility {
+            Some(Visibility::Public) => directives.push("public".to_owned()),
+            Some(Visibility::Private) => directives.push("private".to_owned()),
+            None => {}
+        }
+        let flags = [(self.no_cache, "no-cache"), (self.no_store, "no-store")];
+        directives.extend(
+            flags
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, d)| d.to_string()),
+        );
+        let times = [
+            (self.max_age, "max-age"),
+            (self.s_maxage, "s-maxage"),
+            (self.stale_while_revalidate, "stale-while-revalidate"),
+            (self.stale_if_error, "stale-if-error"),
+        ];
+        directives.extend(
+            times
+                .iter()
+                .filter_map(|(secs, d)| secs.map(|secs| format!("{}={}", d, secs))),
+        );
+        let flags = [
+            (self.must_revalidate, "must-revalidate"),
+            (self.proxy_revalidate, "proxy-revalidate"),
+            (self.no_transform, "no-transform"),
+            (self.immutable, "immutable"),
+        ];
+        directives.extend(
+            flags
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, d)| d.to_string()),
+        );
+        f.write_str(&directives.join(", "))
+    }
+}
@@ -0,0 +1,147 @@
+/*!
+Pre-parsed copies of files, kept on disk between requests.
+
+Used by `Config::load_cached()` and `Template::load_cached()`. A cache
+file starts with eight bytes naming what's in it, then the `Stamp` of the
+file it was made from; the rest is up to the caller, written with a
+`CacheWriter` and read back with a `CacheReader`. Numbers are big-endian,
+and strings are a `u32` length followed by that many bytes of UTF-8.
+*/
+
+use std::fs::Metadata;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/*
+What a cache file records about the file it was made from: modification
+time (seconds and nanoseconds) and length.
+*/
+pub(crate) type Stamp = (u64, u32, u64);
+
+/*
+The `Stamp` of the file with metadata `meta`.
+*/
+pub(crate) fn source_stamp(meta: &Metadata) -> Stamp {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    (mtime.as_secs(), mtime.subsec_nanos(), meta.len())
+}
+
+/*
+The path of the cache kept next to `path`: `path` with `.cache` appended.
+*/
+pub(crate) fn beside(path: &Path) -> PathBuf {
+    let mut cache = path.as_os_str().to_owned();
+    cache.push(".cache");
+    PathBuf::from(cache)
+}
+
+/*
+Encodes the contents of a cache file.
+*/
+pub(crate) struct CacheWriter {
+    bytes: Vec<u8>,
+}
+
+impl CacheWriter {
+    /*
+    Start a cache file of the kind `magic`, made from a file with the
+    given `stamp`.
+    */
+    pub(crate) fn new(magic: &[u8; 8], stamp: Stamp) -> CacheWriter {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(magic);
+        bytes.extend_from_slice(&stamp.0.to_be_bytes());
+        bytes.extend_from_slice(&stamp.1.to_be_bytes());
+        bytes.extend_from_slice(&stamp.2.to_be_bytes());
+        CacheWriter { bytes }
+    }
+
+    pub(crate) fn u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+
+    pub(crate) fn u32(&mut self, n: u32) {
+        self.bytes.extend_from_slice(&n.to_be_bytes());
+    }
+
+    pub(crate) fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    /*
+    Write the cache to a temporary file and rename it into place at
+    `cache`, so another process never reads a half-written cache.
+    */
+    pub(crate) fn write(self, cache: &Path) -> std::io::Result<()> {
+        let mut tmp = cache.as_os_str().to_owned();
+        tmp.push(format!(".{}.tmp", std::process::id()));
+        let tmp = PathBuf::from(tmp);
+        let result = std::fs::File::create(&tmp)
+            .and_then(|mut f| f.write_all(&self.bytes))
+            .and_then(|_| std::fs::rename(&tmp, cache));
+        if result.is_err() {
+            _ = std::fs::remove_file(&tmp);
+        }
+        result
+    }
+}
+
+/*
+Decodes the contents of a cache file. Every method returns `None` if the
+file ends too soon.
+*/
+pub(crate) struct CacheReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> CacheReader<'a> {
+    /*
+    Start reading `bytes`, returning `None` unless it's a cache file of
+    the kind `magic` made from a file with the given `stamp`.
+    */
+    pub(crate) fn new(bytes: &'a [u8], magic: &[u8; 8], stamp: Stamp) -> Option<CacheReader<'a>> {
+        let mut reader = CacheReader { bytes };
+        if reader.take(magic.len())? != magic {
+            return None;
+        }
+        let cached = (reader.u64()?, reader.u32()?, reader.u64()?);
+        (cached == stamp).then_some(reader)
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let chunk = self.bytes.get(..n)?;
+        self.bytes = &self.bytes[n..];
+        Some(chunk)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub(crate) fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    /*
+    Whether everything has been read; a cache with anything left over is
+    malformed.
+    */
+    pub(crate) fn is_done(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
@@ -0,0 +1,174 @@
+/*!
+Canonical paths: one URL per page.
+
+A CGI program sees `/Items/`, `/items/`, and `/items` as different paths
+(in `PATH_INFO`), but they're probably the same page. A `PathPolicy` picks
+a canonical form (with or without a trailing slash, lower-cased or not),
+and either redirects requests for other forms to it with a
+`301 Moved Permanently` (so caches and search engines only ever see one
+URL), or just treats them as the canonical path.
+
+```rust
+use dumb_cgi::canonical::{PathPolicy, TrailingSlash};
+use dumb_cgi::testing::MockRequest;
+
+let policy = PathPolicy::new()
+    .with_trailing_slash(TrailingSlash::Strip)
+    .with_lowercase(true);
+
+let req = MockRequest::get("/Items/")
+    .var("SCRIPT_NAME", "/cgi-bin/shop")
+    .query("page=2")
+    .build()
+    .unwrap();
+let redirect = policy.check(&req).unwrap_err();
+assert_eq!(redirect.get_status(), 301);
+assert_eq!(
+    redirect.get_header("location"),
+    Some("/cgi-bin/shop/items?page=2")
+);
+
+let req = MockRequest::get("/items").build().unwrap();
+assert_eq!(policy.check(&req).unwrap(), "/items");
+```
+*/
+
+use std::borrow::Cow;
+
+use crate::{url_encode, Request, Response};
+
+/// What to do with a slash at the end of a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Leave paths alone (the default).
+    Ignore,
+    /// `/items/` becomes `/items`.
+    Strip,
+    /// `/items` becomes `/items/`, except where the last segment has a `.`
+    /// in it (so `/style.css` is left alone).
+    Add,
+}
+
+/**
+How to turn a request path into its canonical form, and what to do with
+requests for paths that aren't canonical.
+
+The root path `/` (or an empty `PATH_INFO`) is always canonical.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathPolicy {
+    trailing_slash: TrailingSlash,
+    lowercase: bool,
+    redirect: bool,
+}
+
+impl Default for PathPolicy {
+    fn default() -> PathPolicy {
+        PathPolicy {
+            trailing_slash: TrailingSlash::Ignore,
+            lowercase: false,
+            redirect: true,
+        }
+    }
+}
+
+impl PathPolicy {
+    /// Create a policy that leaves paths alone, and redirects when it doesn't.
+    pub fn new() -> PathPolicy {
+        PathPolicy::default()
+    }
+
+    /// Builder-pattern method for setting what to do with trailing slashes.
+    pub fn with_trailing_slash(self, policy: TrailingSlash) -> PathPolicy {
+        let mut new = self;
+        new.trailing_slash = policy;
+        new
+    }
+
+    /// Builder-pattern method for setting whether canonical paths are
+    /// lower-case.
+    pub fn with_lowercase(self, lowercase: bool) -> PathPolicy {
+        let mut new = self;
+        new.lowercase = lowercase;
+        new
+    }
+
+    /**
+    Builder-pattern method for setting whether requests for non-canonical
+    paths are redirected (the default), or just treated as if they were
+    for the canonical path.
+    */
+    pub fn with_redirect(self, redirect: bool) -> PathPolicy {
+        let mut new = self;
+        new.redirect = redirect;
+        new
+    }
+
+    /**
+    Return the canonical form of `path`.
+
+    ```rust
+    # use dumb_cgi::canonical::{PathPolicy, TrailingSlash};
+    let policy = PathPolicy::new().with_trailing_slash(TrailingSlash::Add);
+    assert_eq!(policy.normalize("/docs"), "/docs/");
+    assert_eq!(policy.normalize("/docs/"), "/docs/");
+    assert_eq!(policy.normalize("/docs/intro.html"), "/docs/intro.html");
+    ```
+    */
+    pub fn normalize<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        if path.is_empty() || path == "/" {
+            return Cow::Borrowed(path);
+        }
+        let mut path = Cow::Borrowed(path);
+        if self.lowercase && path.chars().any(char::is_uppercase) {
+            path = Cow::Owned(path.to_lowercase());
+        }
+        match self.trailing_slash {
+            TrailingSlash::Ignore => {}
+            TrailingSlash::Strip => {
+                let stripped = path.trim_end_matches('/');
+                if stripped.len() < path.len() {
+                    let stripped = if stripped.is_empty() { "/" } else { stripped };
+                    path = Cow::Owned(stripped.to_owned());
+                }
+            }
+            TrailingSlash::Add => {
+                let last = path.rsplit('/').next().unwrap_or("");
+                if !last.is_empty() && !last.contains('.') {
+                    path.to_mut().push('/');
+                }
+            }
+        }
+        path
+    }
+
+    /**
+    Check the `PATH_INFO` of `req` against the policy.
+
+    Returns the path to use (the canonical path, if it isn't redirecting),
+    or, if it's redirecting and the path isn't canonical, a
+    `301 Moved Permanently` response to send back instead, pointing at the
+    canonical URL (with the `SCRIPT_NAME` in front and the query string
+    kept).
+    */
+    pub fn check<'a>(&self, req: &'a Request) -> Result<Cow<'a, str>, Response> {
+        let path = req.var("PATH_INFO").unwrap_or("");
+        let canonical = self.normalize(path);
+        if canonical == path || !self.redirect {
+            return Ok(canonical);
+        }
+
+        let mut location = req.var("SCRIPT_NAME").unwrap_or("").to_owned();
+        for (n, segment) in canonical.split('/').enumerate() {
+            if n > 0 {
+                location.push('/');
+            }
+            location.push_str(&url_encode(segment));
+        }
+        if let Some(query) = req.var("QUERY_STRING").filter(|q| !q.is_empty()) {
+            location.push('?');
+            location.push_str(query);
+        }
+        Err(Response::new(301).with_header("Location", location))
+    }
+}
@@ -0,0 +1,156 @@
+/*!
+`serde` support for request captures (the `serde` feature).
+
+A serialized `Request` holds the same things as `Request::to_capture()`:
+
+  * `vars`: the environment variables, as a map of names to values
+  * `headers`: the headers, as a map of (lower-case) names to values
+  * `query`: the decoded query string, as a map, or null if there wasn't
+    one (or it couldn't be decoded); this is only for reading, and is
+    ignored when deserializing, as the query is parsed again from the
+    `QUERY_STRING` variable
+  * `body`: the body, base64-encoded, or null if there wasn't one
+  * `error`: the error reading the body (with `code`, `message`, and
+    `details`), or null if there wasn't one
+
+Deserializing one parses it exactly as `Request::new()` would have, so a
+request dumped from production can be replayed locally against the same
+handler.
+*/
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_core::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde_core::ser::{Serialize, SerializeMap, SerializeStruct, Serializer};
+
+use crate::{base64, Error, Query, Request};
+
+const REQUEST_FIELDS: &[&str] = &["vars", "headers", "query", "body", "error"];
+const ERROR_FIELDS: &[&str] = &["code", "message", "details"];
+
+/*
+Name/value pairs, serialized as a map.
+*/
+struct Pairs<'a, V>(&'a [(&'a str, V)]);
+
+impl<V: AsRef<str>> Serialize for Pairs<'_, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (k, v) in self.0.iter() {
+            map.serialize_entry(k, v.as_ref())?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Error", ERROR_FIELDS.len())?;
+        s.serialize_field("code", &self.code)?;
+        s.serialize_field("message", &self.message)?;
+        s.serialize_field("details", &self.details)?;
+        s.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Error {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Error, D::Error> {
+        struct ErrorVisitor;
+
+        impl<'de> Visitor<'de> for ErrorVisitor {
+            type Value = Error;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a dumb_cgi::Error")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Error, A::Error> {
+                let mut code: Option<u16> = None;
+                let mut message: Option<String> = None;
+                let mut details: Option<String> = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "code" => code = Some(map.next_value()?),
+                        "message" => message = Some(map.next_value()?),
+                        "details" => details = Some(map.next_value()?),
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(Error {
+                    code: code.ok_or_else(|| de::Error::missing_field("code"))?,
+                    message: message.unwrap_or_default(),
+                    details: details.unwrap_or_default(),
+                })
+            }
+        }
+
+        deserializer.deserialize_struct("Error", ERROR_FIELDS, ErrorVisitor)
+    }
+}
+
+impl Serialize for Request {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let captured = self.captured();
+        let query: Option<Vec<(&str, &str)>> = match self.query() {
+            Query::Some(map) => {
+                let mut pairs: Vec<(&str, &str)> =
+                    map.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+                pairs.sort();
+                Some(pairs)
+            }
+            _ => None,
+        };
+
+        let mut s = serializer.serialize_struct("Request", REQUEST_FIELDS.len())?;
+        s.serialize_field("vars", &Pairs(&captured.vars))?;
+        s.serialize_field("headers", &Pairs(&captured.headers))?;
+        s.serialize_field("query", &query.as_deref().map(Pairs))?;
+        s.serialize_field("body", &captured.body.as_deref().map(base64::encode))?;
+        s.serialize_field("error", &captured.error)?;
+        s.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Request {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Request, D::Error> {
+        struct RequestVisitor;
+
+        impl<'de> Visitor<'de> for RequestVisitor {
+            type Value = Request;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a serialized dumb_cgi::Request")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Request, A::Error> {
+                let mut vars: Option<BTreeMap<String, String>> = None;
+                let mut headers: BTreeMap<String, String> = BTreeMap::new();
+                let mut body: Vec<u8> = Vec::new();
+                let mut error: Option<Error> = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "vars" => vars = Some(map.next_value()?),
+                        "headers" => headers = map.next_value()?,
+                        "body" => {
+                            if let Some(b) = map.next_value::<Option<String>>()? {
+                                body = base64::decode(&b).ok_or_else(|| {
+                                    de::Error::invalid_value(de::Unexpected::Str(&b), &"base64")
+                                })?;
+                            }
+                        }
+                        "error" => error = map.next_value()?,
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+                let vars = vars.ok_or_else(|| de::Error::missing_field("vars"))?;
+                Request::from_captured(vars, headers, body, error).map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_struct("Request", REQUEST_FIELDS, RequestVisitor)
+    }
+}
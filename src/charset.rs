@@ -0,0 +1,243 @@
+/*!
+Decoding form data sent in something other than UTF-8.
+
+Browsers encode a form's fields in the encoding of the page the form was
+on (or the one named in its `accept-charset` attribute), so a form on an
+old Windows-1252 page arrives in Windows-1252, and decoding it as UTF-8
+turns every accented letter into a replacement character.
+
+A form can say which encoding it was sent in with a hidden field named
+`_charset_`, which browsers fill in themselves:
+
+```html
+<form method="post" action="/guestbook">
+  <input type="hidden" name="_charset_">
+  ...
+</form>
+```
+
+and `Request::form_charset()` looks for that (and for a `charset` in the
+`Content-type`) before falling back to whatever the program knows the
+page was sent in; `Request::decoded_form()` decodes the form with it.
+
+```rust
+# use dumb_cgi::{charset::Charset, testing::MockRequest};
+let req = MockRequest::post("/guestbook")
+    .header("Content-Type", "application/x-www-form-urlencoded")
+    .body("_charset_=windows-1252&name=Ren%E9e&says=%93hi%94")
+    .build()
+    .unwrap();
+
+assert_eq!(req.form_charset(Charset::Utf8), Charset::Windows1252);
+let form = req.decoded_form(Charset::Utf8).unwrap();
+assert_eq!(form["name"], "Renée");
+assert_eq!(form["says"], "\u{201c}hi\u{201d}");
+```
+
+Only single-byte Western encodings are supported (besides UTF-8);
+multi-byte legacy encodings like Shift-JIS need tables far larger than
+belong in this crate.
+
+Text from Windows programs often starts with a byte-order mark (BOM),
+which a JSON or CSV parser reading the text will choke on or take for
+part of the first value. `decode_text()` (and `Request::body_text()`,
+which uses it) removes one, and goes by it to decode UTF-16. Going the
+other way, Excel only reads a CSV file as UTF-8 if it starts with a BOM
+(see `with_bom()`, and `Response::with_utf8_bom()`).
+*/
+
+/// The UTF-8 byte-order mark, U+FEFF encoded as UTF-8.
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/*
+The characters Windows-1252 has at 0x80..=0x9F, where ISO-8859-1 has C1
+control characters. The five bytes Windows-1252 leaves undefined decode
+to the control characters, as browsers do.
+*/
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/**
+A character encoding form data can be decoded from.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Charset {
+    /// UTF-8, the default.
+    #[default]
+    Utf8,
+    /// US-ASCII; bytes above 0x7F are an error.
+    Ascii,
+    /// ISO-8859-1 (Latin-1).
+    Latin1,
+    /// ISO-8859-15 (Latin-9): Latin-1 with the euro sign and a few letters
+    /// for French and Finnish.
+    Latin9,
+    /// Windows-1252, the usual encoding of old Western European pages
+    /// (and what browsers actually use for pages labelled ISO-8859-1).
+    Windows1252,
+}
+
+impl Charset {
+    /**
+    Return the charset with the (case-insensitive) name or alias `label`,
+    as found in a `charset` parameter or a `_charset_` field.
+
+    ```rust
+    # use dumb_cgi::charset::Charset;
+    assert_eq!(Charset::from_label(" CP1252 "), Some(Charset::Windows1252));
+    assert_eq!(Charset::from_label("latin1"), Some(Charset::Latin1));
+    assert_eq!(Charset::from_label("shift_jis"), None);
+    ```
+    */
+    pub fn from_label(label: &str) -> Option<Charset> {
+        let label = label.trim().trim_matches('"').to_ascii_lowercase();
+        let charset = match label.as_str() {
+            "utf-8" | "utf8" | "unicode-1-1-utf-8" => Charset::Utf8,
+            "us-ascii" | "ascii" => Charset::Ascii,
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => {
+                Charset::Latin1
+            }
+            "iso-8859-15" | "iso8859-15" | "iso_8859-15" | "latin9" | "latin-9" => Charset::Latin9,
+            "windows-1252" | "cp1252" | "x-cp1252" => Charset::Windows1252,
+            _ => return None,
+        };
+        Some(charset)
+    }
+
+    /// Return the charset's preferred name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Charset::Utf8 => "UTF-8",
+            Charset::Ascii => "US-ASCII",
+            Charset::Latin1 => "ISO-8859-1",
+            Charset::Latin9 => "ISO-8859-15",
+            Charset::Windows1252 => "windows-1252",
+        }
+    }
+
+    /**
+    Decode `bytes` from this charset. On failure (invalid UTF-8, or a
+    non-ASCII byte in US-ASCII), the returned `String` describes what
+    went wrong; the other charsets can decode anything.
+
+    ```rust
+    # use dumb_cgi::charset::Charset;
+    assert_eq!(Charset::Latin9.decode(b"\xa4 5").unwrap(), "\u{20ac} 5");
+    assert_eq!(Charset::Windows1252.decode(b"\x80 5").unwrap(), "\u{20ac} 5");
+    assert!(Charset::Utf8.decode(b"\x80 5").is_err());
+    ```
+    */
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, String> {
+        match self {
+            Charset::Utf8 => {
+                String::from_utf8(bytes.to_vec()).map_err(|e| format!("not valid UTF-8: {}", &e))
+            }
+            Charset::Ascii => match bytes.iter().position(|b| !b.is_ascii()) {
+                Some(idx) => Err(format!("not valid US-ASCII at byte {}", idx)),
+                None => Ok(bytes.iter().map(|&b| b as char).collect()),
+            },
+            Charset::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+            Charset::Latin9 => Ok(bytes.iter().map(|&b| latin9_char(b)).collect()),
+            Charset::Windows1252 => Ok(bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                    _ => b as char,
+                })
+                .collect()),
+        }
+    }
+}
+
+impl std::fmt::Display for Charset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The eight places ISO-8859-15 differs from ISO-8859-1.
+fn latin9_char(b: u8) -> char {
+    match b {
+        0xA4 => '\u{20AC}',
+        0xA6 => '\u{0160}',
+        0xA8 => '\u{0161}',
+        0xB4 => '\u{017D}',
+        0xB8 => '\u{017E}',
+        0xBC => '\u{0152}',
+        0xBD => '\u{0153}',
+        0xBE => '\u{0178}',
+        _ => b as char,
+    }
+}
+
+/**
+Return `bytes` without the UTF-8 byte-order mark they start with, if
+they do.
+
+```rust
+# use dumb_cgi::charset::strip_bom;
+assert_eq!(strip_bom(b"\xEF\xBB\xBF{\"a\":1}"), b"{\"a\":1}");
+assert_eq!(strip_bom(b"{\"a\":1}"), b"{\"a\":1}");
+```
+*/
+pub fn strip_bom(bytes: &[u8]) -> &[u8] {
+    bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes)
+}
+
+/**
+Return `text` as UTF-8 starting with a byte-order mark, as Excel needs
+to read a CSV file as UTF-8 rather than the Windows code page.
+
+```rust
+# use dumb_cgi::charset::with_bom;
+assert_eq!(with_bom("n\u{e9}e"), b"\xEF\xBB\xBFn\xC3\xA9e");
+```
+*/
+pub fn with_bom(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(UTF8_BOM.len() + text.len());
+    bytes.extend_from_slice(UTF8_BOM);
+    bytes.extend_from_slice(text.as_bytes());
+    bytes
+}
+
+/**
+Decode `bytes` as text, the way browsers do: if they start with a
+byte-order mark, it says the encoding (UTF-8, or UTF-16 in either byte
+order), and is removed; otherwise they're decoded from `default`. On
+failure, the returned `String` describes what went wrong.
+
+```rust
+# use dumb_cgi::charset::{decode_text, Charset};
+assert_eq!(decode_text(b"\xEF\xBB\xBF[1]", Charset::Utf8).unwrap(), "[1]");
+assert_eq!(decode_text(b"\xFF\xFE[\x001\x00]\x00", Charset::Utf8).unwrap(), "[1]");
+assert_eq!(decode_text(b"caf\xe9", Charset::Latin1).unwrap(), "café");
+```
+*/
+pub fn decode_text(bytes: &[u8], default: Charset) -> Result<String, String> {
+    if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+        return Charset::Utf8.decode(rest);
+    }
+    let (rest, big_endian) = match bytes {
+        [0xFE, 0xFF, rest @ ..] => (rest, true),
+        [0xFF, 0xFE, rest @ ..] => (rest, false),
+        _ => return default.decode(bytes),
+    };
+    if rest.len() % 2 != 0 {
+        return Err("not valid UTF-16: odd number of bytes".to_owned());
+    }
+    let units = rest.chunks_exact(2).map(|pair| {
+        let pair = [pair[0], pair[1]];
+        if big_endian {
+            u16::from_be_bytes(pair)
+        } else {
+            u16::from_le_bytes(pair)
+        }
+    });
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| format!("not valid UTF-16: {}", &e))
+}
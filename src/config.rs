@@ -0,0 +1,272 @@
+/*!
+Simple INI-style configuration files.
+
+```text
+# Comments start with '#' or ';'.
+site_name = My Site
+
+[database]
+path = "/var/lib/mysite/data.db"
+timeout = 5
+```
+
+Keys in a section are named `section.key` (so the above defines
+`site_name`, `database.path`, and `database.timeout`). Values have
+surrounding whitespace removed, as well as one pair of surrounding
+double quotes if present.
+
+A CGI program re-reads its configuration on every request, so for large
+files `Config::load_cached()` keeps a pre-parsed copy next to the file,
+which is used until the file is modified.
+
+```rust
+use dumb_cgi::config::Config;
+
+let config = Config::parse("
+site_name = My Site
+
+[database]
+timeout = 5
+").unwrap();
+
+assert_eq!(config.get("site_name"), Some("My Site"));
+assert_eq!(config.get_parsed::<u32>("database.timeout").unwrap(), Some(5));
+assert_eq!(config.get_or("database.path", "data.db"), "data.db");
+```
+*/
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cachefile::{self, source_stamp, CacheReader, CacheWriter, Stamp};
+use crate::Error;
+
+/// First bytes of a pre-parsed configuration cache file.
+const CACHE_MAGIC: &[u8; 8] = b"DCGICFG1";
+
+fn config_error(details: String) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details,
+    }
+}
+
+/**
+A parsed configuration file.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    values: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Create an empty configuration.
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /**
+    Parse the text of a configuration file.
+
+    Returns an error describing the first line that isn't blank, a
+    comment, a `[section]` header, or a `key = value` pair.
+    */
+    pub fn parse(text: &str) -> Result<Config, Error> {
+        let mut config = Config::new();
+        let mut section = String::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let bad_line = || {
+                config_error(format!(
+                    "Unable to parse line {} of configuration: {:?}",
+                    n + 1,
+                    line
+                ))
+            };
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name.strip_suffix(']').ok_or_else(bad_line)?.trim();
+                if name.is_empty() {
+                    return Err(bad_line());
+                }
+                section = format!("{}.", name);
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(bad_line)?;
+            let key = key.trim();
+            if key.is_empty() {
+                return Err(bad_line());
+            }
+            let value = value.trim();
+            let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(unquoted) => unquoted,
+                None => value,
+            };
+            config
+                .values
+                .insert(format!("{}{}", &section, key), value.to_owned());
+        }
+
+        Ok(config)
+    }
+
+    /// Read and parse the configuration file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(e) => Err(config_error(format!(
+                "Unable to read configuration file {}: {}",
+                path.display(),
+                &e
+            ))),
+        }
+    }
+
+    /**
+    Load the configuration file at `path`, using a pre-parsed copy (kept
+    in `path` with `.cache` appended) if one exists and the file hasn't
+    changed since it was made.
+
+    If the cache is missing or out of date, the file is parsed and a new
+    cache written. Failing to write the cache (say, because the program
+    can't write to that directory) isn't an error; it just means the file
+    will be parsed every time. See `load_cached_in()` to keep the cache
+    somewhere else.
+    */
+    pub fn load_cached<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let path = path.as_ref();
+        Config::load_with_cache(path, &cachefile::beside(path))
+    }
+
+    /**
+    Like `load_cached()`, but keeps the pre-parsed copy in the directory
+    `cache_dir` instead of next to the file.
+
+    Don't use a directory other users can write to (like `/tmp`), as
+    anyone who can write the cache controls the configuration.
+    */
+    pub fn load_cached_in<P, D>(path: P, cache_dir: D) -> Result<Config, Error>
+    where
+        P: AsRef<Path>,
+        D: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => return Config::load(path),
+        };
+        let mut cache_name = name.to_owned();
+        cache_name.push(".cache");
+        Config::load_with_cache(path, &cache_dir.as_ref().join(cache_name))
+    }
+
+    fn load_with_cache(path: &Path, cache: &Path) -> Result<Config, Error> {
+        let meta = std::fs::metadata(path).map_err(|e| {
+            config_error(format!(
+                "Unable to read configuration file {}: {}",
+                path.display(),
+                &e
+            ))
+        })?;
+        let stamp = source_stamp(&meta);
+
+        if let Ok(bytes) = std::fs::read(cache) {
+            if let Some(config) = Config::from_cache_bytes(&bytes, stamp) {
+                return Ok(config);
+            }
+        }
+
+        let config = Config::load(path)?;
+        // The cache is only an optimization, so failing to write it is fine.
+        _ = config.to_cache(stamp).write(cache);
+        Ok(config)
+    }
+
+    pub(crate) fn to_cache(&self, stamp: Stamp) -> CacheWriter {
+        let mut cache = CacheWriter::new(CACHE_MAGIC, stamp);
+        cache.u32(self.values.len() as u32);
+        for (k, v) in self.values.iter() {
+            cache.str(k);
+            cache.str(v);
+        }
+        cache
+    }
+
+    /*
+    Decode a cache file, returning `None` if it's malformed or was made
+    from a different version of the source file.
+    */
+    fn from_cache_bytes(bytes: &[u8], stamp: Stamp) -> Option<Config> {
+        let mut cache = CacheReader::new(bytes, CACHE_MAGIC, stamp)?;
+        let count = cache.u32()?;
+        let mut values = BTreeMap::new();
+        for _ in 0..count {
+            let k = cache.str()?;
+            let v = cache.str()?;
+            values.insert(k, v);
+        }
+        cache.is_done().then_some(Config { values })
+    }
+
+    /// Return the value of `key` (as `key` or `section.key`).
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+
+    /// Return the value of `key`, or `default` if it isn't set.
+    pub fn get_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.get(key).unwrap_or(default)
+    }
+
+    /**
+    Return the value of `key` parsed as a `T`, `None` if it isn't set, or
+    an error if it can't be parsed.
+    */
+    pub fn get_parsed<T>(&self, key: &str) -> Result<Option<T>, Error>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        match self.get(key) {
+            None => Ok(None),
+            Some(v) => v.parse().map(Some).map_err(|e| {
+                config_error(format!(
+                    "Invalid value {:?} for configuration key {}: {}",
+                    v, key, e
+                ))
+            }),
+        }
+    }
+
+    /// Set `key` to `value`.
+    pub fn set<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.values.insert(key.into(), value.into());
+    }
+
+    /// Iterate over every `(key, value)` pair, in order of key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Return the number of keys.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Return whether there are no keys.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
@@ -0,0 +1,237 @@
+/*!
+Building `Set-Cookie` headers.
+
+```rust
+use std::time::Duration;
+use dumb_cgi::{cookie::{Cookie, SameSite}, Response};
+
+let cookie = Cookie::new("theme", "dark")
+    .with_path("/")
+    .with_max_age(Duration::from_secs(30 * 24 * 60 * 60))
+    .with_secure(true)
+    .with_http_only(true)
+    .with_same_site(SameSite::Lax);
+assert_eq!(
+    cookie.to_string(),
+    "theme=dark; Path=/; Max-Age=2592000; Secure; HttpOnly; SameSite=Lax"
+);
+
+let response = Response::new(204).with_cookie(cookie);
+assert_eq!(response.get_cookies().len(), 1);
+```
+*/
+
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::time::http_date;
+
+/// How long (in seconds) an unread flash message lasts.
+pub(crate) const FLASH_MAX_AGE: u64 = 5 * 60;
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Sent with cross-site requests too. Browsers require such cookies to
+    /// be `Secure`, so this implies `with_secure(true)`.
+    None,
+}
+
+/**
+A cookie to set on the user agent, written as the value of a `Set-Cookie`
+header by its `Display` implementation.
+
+Any characters in the value that aren't allowed in a cookie (spaces,
+quotes, commas, semicolons, backslashes, control and non-ASCII
+characters) are percent-encoded; `url_decode()` will undo this.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<u64>,
+    expires: Option<i64>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+/*
+Percent-encode anything not allowed in a cookie value (RFC 6265's
+`cookie-octet`), plus `%` itself so it can be decoded again.
+*/
+fn encode_value(value: &str) -> String {
+    const HEX: &[u8] = b"0123456789ABCDEF";
+    let mut encoded = String::with_capacity(value.len());
+    for &b in value.as_bytes() {
+        let allowed = (0x21..=0x7e).contains(&b) && !b"\",;\\%".contains(&b);
+        if allowed {
+            encoded.push(b as char);
+        } else {
+            encoded.push('%');
+            encoded.push(HEX[(b >> 4) as usize] as char);
+            encoded.push(HEX[(b & 0xf) as usize] as char);
+        }
+    }
+    encoded
+}
+
+impl Cookie {
+    /// Create a session cookie (one with no expiry) named `name`.
+    pub fn new<N, V>(name: N, value: V) -> Cookie
+    where
+        N: Into<String>,
+        V: AsRef<str>,
+    {
+        Cookie {
+            name: name.into(),
+            value: encode_value(value.as_ref()),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /**
+    Create a cookie that removes the cookie `name` from the user agent
+    (by expiring it). Its path and domain have to match the original's.
+
+    ```rust
+    # use dumb_cgi::cookie::Cookie;
+    assert_eq!(
+        Cookie::removal("theme").with_path("/").to_string(),
+        "theme=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+    );
+    ```
+    */
+    pub fn removal<N: Into<String>>(name: N) -> Cookie {
+        let mut cookie = Cookie::new(name, "");
+        cookie.max_age = Some(0);
+        cookie.expires = Some(0);
+        cookie
+    }
+
+    /// Return the cookie's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the cookie's (encoded) value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Builder-pattern method for setting the path the cookie is sent for.
+    pub fn with_path<P: Into<String>>(self, path: P) -> Cookie {
+        let mut new = self;
+        new.path = Some(path.into());
+        new
+    }
+
+    /// Builder-pattern method for setting the domain the cookie is sent to
+    /// (which includes its subdomains).
+    pub fn with_domain<D: Into<String>>(self, domain: D) -> Cookie {
+        let mut new = self;
+        new.domain = Some(domain.into());
+        new
+    }
+
+    /// Builder-pattern method for setting how long (from now) the cookie
+    /// lasts. Whole seconds only.
+    pub fn with_max_age(self, max_age: Duration) -> Cookie {
+        let mut new = self;
+        new.max_age = Some(max_age.as_secs());
+        new
+    }
+
+    /**
+    Builder-pattern method for setting when the cookie expires. (Where
+    both are set, user agents go by `with_max_age()`, but some old ones only
+    understand this.)
+
+    ```rust
+    # use std::time::{Duration, UNIX_EPOCH};
+    # use dumb_cgi::cookie::Cookie;
+    let when = UNIX_EPOCH + Duration::from_secs(784_111_777);
+    assert_eq!(
+        Cookie::new("a", "b").with_expires(when).to_string(),
+        "a=b; Expires=Sun, 06 Nov 1994 08:49:37 GMT"
+    );
+    ```
+    */
+    pub fn with_expires(self, when: SystemTime) -> Cookie {
+        let mut new = self;
+        let unix = match when.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        new.expires = Some(unix);
+        new
+    }
+
+    /// Builder-pattern method for setting whether the cookie is only sent
+    /// over HTTPS.
+    pub fn with_secure(self, secure: bool) -> Cookie {
+        let mut new = self;
+        new.secure = secure;
+        new
+    }
+
+    /// Builder-pattern method for setting whether the cookie is hidden from
+    /// scripts in the page.
+    pub fn with_http_only(self, http_only: bool) -> Cookie {
+        let mut new = self;
+        new.http_only = http_only;
+        new
+    }
+
+    /// Builder-pattern method for setting whether the cookie is sent with
+    /// cross-site requests.
+    pub fn with_same_site(self, same_site: SameSite) -> Cookie {
+        let mut new = self;
+        new.same_site = Some(same_site);
+        if same_site == SameSite::None {
+            new.secure = true;
+        }
+        new
+    }
+}
+
+/// Writes the value of a `Set-Cookie` header.
+impl Display for Cookie {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", &self.name, &self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
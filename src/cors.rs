@@ -0,0 +1,361 @@
+/*!
+Cross-origin resource sharing (CORS): the `Access-Control-*` headers that
+let scripts on other sites' pages use a response.
+
+```rust
+use dumb_cgi::cors::Cors;
+use dumb_cgi::testing::MockRequest;
+use dumb_cgi::Response;
+
+let cors = Cors::new()
+    .with_origin("https://app.example.com")
+    .with_methods(["GET", "POST", "DELETE"])
+    .with_allowed_headers(["Content-Type", "X-Requested-With"])
+    .with_expose_headers(["X-Total-Count"])
+    .with_credentials(true)
+    .with_max_age(600);
+
+// A preflight (`OPTIONS`) request is answered on its own...
+let req = MockRequest::new("OPTIONS", "/api/items/3")
+    .header("Origin", "https://app.example.com")
+    .header("Access-Control-Request-Method", "DELETE")
+    .build()
+    .unwrap();
+let r = cors.preflight(&req).unwrap();
+assert_eq!(r.get_status(), 204);
+assert_eq!(r.get_header("Access-Control-Allow-Methods"), Some("GET, POST, DELETE"));
+assert_eq!(r.get_header("Access-Control-Max-Age"), Some("600"));
+
+// ...and the actual request's response gets the headers added.
+let req = MockRequest::get("/api/items")
+    .header("Origin", "https://app.example.com")
+    .build()
+    .unwrap();
+assert!(cors.preflight(&req).is_none());
+let r = cors.apply(&req, Response::new(200).with_header("X-Total-Count", "12"));
+assert_eq!(r.get_header("Access-Control-Allow-Origin"), Some("https://app.example.com"));
+assert_eq!(r.get_header("Access-Control-Allow-Credentials"), Some("true"));
+assert_eq!(r.get_header("Access-Control-Expose-Headers"), Some("X-Total-Count"));
+```
+
+Requests from origins that aren't allowed get no CORS headers at all, so
+the browser keeps the response from the script that asked for it.
+
+An allowed origin is answered with `*` when any origin is allowed, and
+with the request's own `Origin` otherwise. Credentials are only ever
+allowed for origins that were listed or passed a validator: with
+`.with_any_origin()`, `.with_credentials(true)` has no effect, since
+repeating back whatever `Origin` came in (even `null`) along with
+`Access-Control-Allow-Credentials: true` would let every site on the
+web read its visitors' logged-in responses.
+
+Since the headers depend on the request's `Origin` (whether it's there,
+and whether it's allowed, even when the answer is `*`), every response
+the policy sees gets `Vary: Origin`, and preflight responses also
+`Vary: Access-Control-Request-Method, Access-Control-Request-Headers`.
+Without that, a cache (a CDN, say) in front of the server could hand one
+origin's answer to another, or a response fetched without CORS to a
+script that needs it.
+*/
+
+use std::sync::Arc;
+
+use crate::{Request, Response};
+
+/**
+The type of a callback deciding whether an origin (like
+`https://app.example.com`) is allowed.
+*/
+pub type OriginValidator = dyn Fn(&str) -> bool + Send + Sync;
+
+/*
+Which origins are allowed.
+*/
+#[derive(Clone)]
+enum Origins {
+    List(Vec<String>),
+    Any,
+    Validator(Arc<OriginValidator>),
+}
+
+impl std::fmt::Debug for Origins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origins::List(list) => f.debug_tuple("List").field(list).finish(),
+            Origins::Any => f.write_str("Any"),
+            Origins::Validator(_) => f.write_str("Validator"),
+        }
+    }
+}
+
+/**
+A CORS policy: which origins may use responses, and how (see the module
+documentation).
+*/
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<String>,
+    allowed_headers: Vec<String>,
+    any_header: bool,
+    expose_headers: Vec<String>,
+    max_age: Option<u64>,
+    credentials: bool,
+    timing_allow: bool,
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors {
+            origins: Origins::List(Vec::new()),
+            methods: vec!["GET".to_owned(), "HEAD".to_owned(), "POST".to_owned()],
+            allowed_headers: Vec::new(),
+            any_header: false,
+            expose_headers: Vec::new(),
+            max_age: None,
+            credentials: false,
+            timing_allow: false,
+        }
+    }
+}
+
+fn to_strings<I, T>(items: I) -> Vec<String>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<String>,
+{
+    items.into_iter().map(Into::into).collect()
+}
+
+impl Cors {
+    /**
+    Return a policy that allows no origins (until some are added), the
+    methods `GET`, `HEAD`, and `POST`, and no extra request headers, without
+    credentials.
+    */
+    pub fn new() -> Cors {
+        Cors::default()
+    }
+
+    /// Builder-pattern method for allowing the origin `origin` (like
+    /// `https://app.example.com`, with no trailing slash).
+    pub fn with_origin<T: Into<String>>(self, origin: T) -> Cors {
+        let mut new = self;
+        let origin = origin.into();
+        match &mut new.origins {
+            Origins::List(list) => list.push(origin),
+            _ => new.origins = Origins::List(vec![origin]),
+        }
+        new
+    }
+
+    /// Builder-pattern method for allowing every origin, without
+    /// credentials (see `.with_credentials()`).
+    pub fn with_any_origin(self) -> Cors {
+        let mut new = self;
+        new.origins = Origins::Any;
+        new
+    }
+
+    /**
+    Builder-pattern method for allowing the origins `validator` returns
+    `true` for (replacing any set with `.with_origin()`), for rules a list
+    can't express.
+
+    ```rust
+    # use dumb_cgi::cors::Cors;
+    let cors = Cors::new().with_origin_validator(|origin| {
+        origin == "https://example.com" || origin.ends_with(".example.com")
+            && origin.starts_with("https://")
+    });
+    assert!(cors.allows_origin("https://shop.example.com"));
+    assert!(!cors.allows_origin("https://example.com.evil.net"));
+    ```
+    */
+    pub fn with_origin_validator<F>(self, validator: F) -> Cors
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        let mut new = self;
+        new.origins = Origins::Validator(Arc::new(validator));
+        new
+    }
+
+    /// Builder-pattern method for setting the methods scripts may use
+    /// (`Access-Control-Allow-Methods`).
+    pub fn with_methods<I, T>(self, methods: I) -> Cors
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut new = self;
+        new.methods = to_strings(methods);
+        new
+    }
+
+    /// Builder-pattern method for setting the request headers scripts may
+    /// send (`Access-Control-Allow-Headers`).
+    pub fn with_allowed_headers<I, T>(self, headers: I) -> Cors
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut new = self;
+        new.allowed_headers = to_strings(headers);
+        new
+    }
+
+    /// Builder-pattern method for allowing whatever request headers a
+    /// preflight asks for (by repeating them back).
+    pub fn with_any_header(self) -> Cors {
+        let mut new = self;
+        new.any_header = true;
+        new
+    }
+
+    /// Builder-pattern method for setting the response headers scripts
+    /// may read, beyond the few they always can
+    /// (`Access-Control-Expose-Headers`).
+    pub fn with_expose_headers<I, T>(self, headers: I) -> Cors
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut new = self;
+        new.expose_headers = to_strings(headers);
+        new
+    }
+
+    /// Builder-pattern method for setting how many seconds browsers may
+    /// remember a preflight's answer (`Access-Control-Max-Age`).
+    pub fn with_max_age(self, seconds: u64) -> Cors {
+        let mut new = self;
+        new.max_age = Some(seconds);
+        new
+    }
+
+    /// Builder-pattern method for setting whether requests may carry
+    /// cookies and HTTP authentication (`Access-Control-Allow-Credentials`).
+    /// This is ignored with `.with_any_origin()`; credentials need a list
+    /// of origins or a validator.
+    pub fn with_credentials(self, credentials: bool) -> Cors {
+        let mut new = self;
+        new.credentials = credentials;
+        new
+    }
+
+    /// Builder-pattern method for setting whether allowed origins may
+    /// also see the response's detailed timing (`Timing-Allow-Origin`).
+    pub fn with_timing_allow(self, timing_allow: bool) -> Cors {
+        let mut new = self;
+        new.timing_allow = timing_allow;
+        new
+    }
+
+    /// Return whether `origin` is allowed.
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        match &self.origins {
+            Origins::List(list) => list.iter().any(|o| o == origin),
+            Origins::Any => true,
+            Origins::Validator(validator) => validator(origin),
+        }
+    }
+
+    /*
+    Return the `Access-Control-Allow-Origin` value for `req`, if it's a
+    cross-origin request from an allowed origin.
+    */
+    fn allow_origin(&self, req: &Request) -> Option<String> {
+        let origin = req.header("origin")?;
+        // It's going to be repeated back in a header.
+        if origin.bytes().any(|b| b.is_ascii_control()) || !self.allows_origin(origin) {
+            return None;
+        }
+        match &self.origins {
+            Origins::Any => Some("*".to_owned()),
+            _ => Some(origin.to_owned()),
+        }
+    }
+
+    /*
+    Whether to send `Access-Control-Allow-Credentials`: not when every
+    origin is allowed.
+    */
+    fn allows_credentials(&self) -> bool {
+        self.credentials && !matches!(self.origins, Origins::Any)
+    }
+
+    /*
+    Add the headers every response to an allowed origin gets.
+    */
+    fn add_common(&self, r: &mut Response, allow_origin: &str) {
+        r.set_header("Access-Control-Allow-Origin", allow_origin);
+        if self.allows_credentials() {
+            r.set_header("Access-Control-Allow-Credentials", "true");
+        }
+        if self.timing_allow {
+            r.set_header("Timing-Allow-Origin", allow_origin);
+        }
+    }
+
+    /**
+    If `req` is a preflight request (an `OPTIONS` request with an `Origin`
+    and an `Access-Control-Request-Method`), return the response to it: a
+    `204 No Content`, with the policy's headers if the origin is allowed,
+    and without them (so the browser won't make the real request) if not.
+
+    Return `None` for any other request.
+    */
+    pub fn preflight(&self, req: &Request) -> Option<Response> {
+        if req.method() != "OPTIONS" || req.header("origin").is_none() {
+            return None;
+        }
+        req.header("access-control-request-method")?;
+
+        let mut r = Response::new(204);
+        r.add_vary("Origin");
+        r.add_vary("Access-Control-Request-Method");
+        r.add_vary("Access-Control-Request-Headers");
+        let allow_origin = match self.allow_origin(req) {
+            Some(allow_origin) => allow_origin,
+            None => return Some(r),
+        };
+        self.add_common(&mut r, &allow_origin);
+        r.set_header("Access-Control-Allow-Methods", self.methods.join(", "));
+        let requested = req
+            .header("access-control-request-headers")
+            .filter(|h| !h.bytes().any(|b| b.is_ascii_control()));
+        let headers = match (self.any_header, requested) {
+            (true, Some(requested)) => requested.to_owned(),
+            _ => self.allowed_headers.join(", "),
+        };
+        if !headers.is_empty() {
+            r.set_header("Access-Control-Allow-Headers", headers);
+        }
+        if let Some(max_age) = self.max_age {
+            r.set_header("Access-Control-Max-Age", max_age.to_string());
+        }
+        Some(r)
+    }
+
+    /**
+    Builder-style method for adding the policy's headers to `response`,
+    the response to `req` (not a preflight), if `req` comes from an
+    allowed origin. Whether it does or not, `Origin` is added to the
+    response's `Vary` header.
+    */
+    pub fn apply(&self, req: &Request, response: Response) -> Response {
+        let mut r = response;
+        r.add_vary("Origin");
+        if let Some(allow_origin) = self.allow_origin(req) {
+            self.add_common(&mut r, &allow_origin);
+            if !self.expose_headers.is_empty() {
+                r.set_header(
+                    "Access-Control-Expose-Headers",
+                    self.expose_headers.join(", "),
+                );
+            }
+        }
+        r
+    }
+}
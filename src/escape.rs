@@ -0,0 +1,176 @@
+/*!
+Escaping text for the places it's written to.
+
+Each function wraps a string in an adapter that escapes it as it's
+formatted, so escaped text can be written straight into a response with
+`write!()`, without building an escaped copy first.
+
+```rust
+use dumb_cgi::escape;
+
+let name = "<Bobby & \"Tables\">";
+assert_eq!(
+    format!("<p>Hello, {}!</p>", escape::html(name)),
+    "<p>Hello, &lt;Bobby &amp; &quot;Tables&quot;&gt;!</p>"
+);
+```
+
+Text has to be escaped differently depending on where it goes:
+
+| where                           | use               |
+|---------------------------------|-------------------|
+| element text, quoted attribute  | `html()`          |
+| unquoted attribute value        | `attr()`          |
+| JavaScript string literal       | `js_string()`     |
+| URL path segment or query value | `url_component()` |
+
+```rust
+use dumb_cgi::escape::{attr, js_string, url_component};
+
+let q = "fish & chips";
+assert_eq!(
+    format!("<a href=\"/search?q={}\" title={}>", url_component(q), attr(q)),
+    "<a href=\"/search?q=fish%20%26%20chips\" title=fish&#x20;&#x26;&#x20;chips>"
+);
+assert_eq!(
+    format!("<script>let q = '{}';</script>", js_string("</script>'")),
+    "<script>let q = '\\x3C\\x2Fscript\\x3E\\x27';</script>"
+);
+```
+*/
+
+use std::fmt::{Display, Formatter, Write};
+
+/**
+Write `s` to `f`, replacing each character for which `replace` returns
+something with that.
+*/
+fn write_escaped<F>(f: &mut Formatter<'_>, s: &str, replace: F) -> std::fmt::Result
+where
+    F: Fn(char) -> Option<&'static str>,
+{
+    let mut start = 0;
+    for (n, c) in s.char_indices() {
+        if let Some(r) = replace(c) {
+            f.write_str(&s[start..n])?;
+            f.write_str(r)?;
+            start = n + c.len_utf8();
+        }
+    }
+    f.write_str(&s[start..])
+}
+
+/// `s`, escaped for HTML text (see `html()`).
+#[derive(Debug, Clone, Copy)]
+pub struct Html<'a>(pub &'a str);
+
+impl Display for Html<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write_escaped(f, self.0, |c| match c {
+            '&' => Some("&amp;"),
+            '<' => Some("&lt;"),
+            '>' => Some("&gt;"),
+            '"' => Some("&quot;"),
+            '\'' => Some("&#39;"),
+            _ => None,
+        })
+    }
+}
+
+/**
+Escape `s` for the text of an HTML element (and quoted attribute
+values): `&`, `<`, `>`, `"`, and `'` are replaced with character
+references.
+*/
+pub fn html(s: &str) -> Html<'_> {
+    Html(s)
+}
+
+/// `s`, escaped for an HTML attribute value (see `attr()`).
+#[derive(Debug, Clone, Copy)]
+pub struct Attr<'a>(pub &'a str);
+
+impl Display for Attr<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for c in self.0.chars() {
+            if c.is_ascii_alphanumeric() || !c.is_ascii() {
+                f.write_char(c)?;
+            } else {
+                write!(f, "&#x{:02X};", c as u32)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+Escape `s` for an HTML attribute value, even an unquoted one: every
+ASCII character except letters and digits is replaced with a numeric
+character reference.
+*/
+pub fn attr(s: &str) -> Attr<'_> {
+    Attr(s)
+}
+
+/// `s`, escaped for a JavaScript string literal (see `js_string()`).
+#[derive(Debug, Clone, Copy)]
+pub struct JsString<'a>(pub &'a str);
+
+impl Display for JsString<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                c if c.is_ascii_alphanumeric() || c == ' ' => f.write_char(c)?,
+                c if c.is_ascii() => write!(f, "\\x{:02X}", c as u32)?,
+                // These end a line in JavaScript (before ES2019).
+                '\u{2028}' | '\u{2029}' => write!(f, "\\u{:04X}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+Escape `s` for the inside of a JavaScript string literal (quoted with
+`'`, `"`, or `` ` ``), including one in a `<script>` element or an event
+handler attribute: every ASCII character except letters, digits, and
+spaces is written as a `\xHH` escape.
+*/
+pub fn js_string(s: &str) -> JsString<'_> {
+    JsString(s)
+}
+
+/// `s`, percent-encoded for part of a URL (see `url_component()`).
+#[derive(Debug, Clone, Copy)]
+pub struct UrlComponent<'a>(pub &'a str);
+
+impl Display for UrlComponent<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for &b in self.0.as_bytes() {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                f.write_char(b as char)?;
+            } else {
+                write!(f, "%{:02X}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+Percent-encode `s` for a URL path segment or query string name or value
+(as `url_encode()` does). The result still needs escaping with `html()`
+if it's going in an HTML attribute, though it's safe as it is in a
+quoted one.
+*/
+pub fn url_component(s: &str) -> UrlComponent<'_> {
+    UrlComponent(s)
+}
+
+/// Write `value` to `out`, escaped for HTML text.
+pub(crate) fn push_html<D: Display + ?Sized>(out: &mut String, value: &D) {
+    let text = value.to_string();
+    // Writing to a `String` can't fail.
+    _ = write!(out, "{}", html(&text));
+}
@@ -0,0 +1,135 @@
+/*!
+A type-keyed map for attaching arbitrary data to a `Request`.
+*/
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/*
+What we need from values stored in `Extensions`: downcasting, plus
+cloning through a `Box` so that `Request` can stay `Clone`.
+*/
+trait Extension: Any + Send + Sync {
+    fn clone_box(&self) -> Box<dyn Extension>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Clone + Send + Sync + 'static> Extension for T {
+    fn clone_box(&self) -> Box<dyn Extension> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Clone for Box<dyn Extension> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+/**
+A map holding at most one value of each type, for passing data computed
+while pre-processing a request (the authenticated user, the negotiated
+locale, a request ID) along to the code that handles it.
+
+Values must be `Clone + Send + Sync` so the `Request` holding them still is.
+
+```rust
+use dumb_cgi::testing::MockRequest;
+
+#[derive(Clone, Debug, PartialEq)]
+struct RequestId(u64);
+
+let mut req = MockRequest::get("/").build().unwrap();
+req.extensions_mut().insert(RequestId(42));
+
+// ...later...
+assert_eq!(req.extensions().get::<RequestId>(), Some(&RequestId(42)));
+assert!(req.extensions().get::<String>().is_none());
+```
+*/
+#[derive(Clone, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Extension>>,
+}
+
+impl Extensions {
+    /// Create an empty map.
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /**
+    Insert `value`, returning the value of the same type that it
+    replaces (if any).
+    */
+    pub fn insert<T: Clone + Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.into_any().downcast().ok())
+            .map(|old| *old)
+    }
+
+    /// Return a reference to the value of type `T`, if there is one.
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|v| (**v).as_any().downcast_ref())
+    }
+
+    /// Return a mutable reference to the value of type `T`, if there is one.
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|v| (**v).as_any_mut().downcast_mut())
+    }
+
+    /// Remove and return the value of type `T`, if there is one.
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|old| old.into_any().downcast().ok())
+            .map(|old| *old)
+    }
+
+    /// Return whether there is a value of type `T`.
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Return the number of values in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Return whether the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Remove every value.
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+impl std::fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
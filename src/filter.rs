@@ -0,0 +1,652 @@
+/*!
+Transforming response bodies after they've been built.
+
+A `Filters` is a list of `Filter`s that are run, in order, over the body
+of a `Response` just before it's sent: filling in placeholders (like
+a CSRF token) left in a template, adding a banner to every page, and so
+on, collapsing the whitespace in HTML pages (see `Minify`), making
+sure they declare their character set (see `Charset`), or copying them
+to a log (see `Tee`). Each filter says which content types it applies
+to; bodies of other types (like images and downloads) pass through
+untouched and uncopied.
+
+```rust
+use dumb_cgi::Response;
+use dumb_cgi::filter::{Banner, Filters, Replace};
+
+let filters = Filters::new()
+    .with(Replace::new("{{csrf}}", "k2Nq8x"))
+    .with(Banner::new("<p class=\"banner\">Test site</p>"));
+
+let r = Response::new(200)
+    .with_content_type("text/html; charset=utf-8")
+    .with_body("<html><body><input name=\"csrf\" value=\"{{csrf}}\"></body></html>");
+let r = filters.apply(r);
+assert_eq!(
+    r.get_body(),
+    b"<html><body><input name=\"csrf\" value=\"k2Nq8x\"><p class=\"banner\">Test site</p></body></html>"
+);
+
+// Not text, so left alone.
+let png = Response::new(200)
+    .with_content_type("image/png")
+    .with_body(b"{{csrf}}".to_vec());
+assert_eq!(filters.apply(png).get_body(), b"{{csrf}}");
+```
+*/
+
+use crate::audit::AuditLog;
+use crate::Response;
+
+/*
+Return the media type part of a `Content-type` value (without any
+parameters), lower-cased.
+*/
+fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/*
+Whether the media type `t` is some kind of text.
+*/
+fn is_text(t: &str) -> bool {
+    t.starts_with("text/")
+        || t.ends_with("+json")
+        || t.ends_with("+xml")
+        || matches!(
+            t,
+            "application/json" | "application/javascript" | "application/xml"
+        )
+}
+
+/*
+Return the position of the first occurrence of `needle` in `haystack` at
+or after `from`.
+*/
+fn find_from(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack[from..]
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|n| n + from)
+}
+
+/**
+Something that transforms response bodies.
+
+Any `Fn(Vec<u8>) -> Vec<u8>` is a `Filter` that applies to every body.
+*/
+pub trait Filter {
+    /**
+    Whether this filter should be run over bodies of the given media type
+    (lower-cased, and without parameters, like `"text/html"`). By default,
+    filters apply to everything.
+    */
+    fn applies_to(&self, media_type: &str) -> bool {
+        let _ = media_type;
+        true
+    }
+
+    /**
+    Return a new `Content-type` for a response this filter applies to
+    (whose body, before filtering, is `body`), or `None` to leave it as it
+    is (the default).
+    */
+    fn content_type(&self, content_type: &str, body: &[u8]) -> Option<String> {
+        let _ = (content_type, body);
+        None
+    }
+
+    /// Return the transformed `body`.
+    fn filter(&self, body: Vec<u8>) -> Vec<u8>;
+}
+
+impl<F: Fn(Vec<u8>) -> Vec<u8>> Filter for F {
+    fn filter(&self, body: Vec<u8>) -> Vec<u8> {
+        self(body)
+    }
+}
+
+/**
+Replaces every occurrence of a placeholder (like `{{csrf}}`) with a value,
+in bodies of text types (any `text/` type, JSON, JavaScript, and XML).
+*/
+#[derive(Debug, Clone)]
+pub struct Replace {
+    placeholder: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl Replace {
+    /// Replace `placeholder` with `value`.
+    pub fn new<P: Into<Vec<u8>>, V: Into<Vec<u8>>>(placeholder: P, value: V) -> Replace {
+        Replace {
+            placeholder: placeholder.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl Filter for Replace {
+    fn applies_to(&self, media_type: &str) -> bool {
+        is_text(media_type)
+    }
+
+    fn filter(&self, body: Vec<u8>) -> Vec<u8> {
+        if self.placeholder.is_empty() {
+            return body;
+        }
+        let mut start = match find_from(&body, &self.placeholder, 0) {
+            Some(n) => n,
+            None => return body,
+        };
+        let mut out = Vec::with_capacity(body.len());
+        let mut copied = 0;
+        loop {
+            out.extend_from_slice(&body[copied..start]);
+            out.extend_from_slice(&self.value);
+            copied = start + self.placeholder.len();
+            match find_from(&body, &self.placeholder, copied) {
+                Some(n) => start = n,
+                None => break,
+            }
+        }
+        out.extend_from_slice(&body[copied..]);
+        out
+    }
+}
+
+/**
+Adds a fragment of HTML to the end of every HTML page, just before the
+closing `</body>` tag (or at the very end, if there isn't one).
+*/
+#[derive(Debug, Clone)]
+pub struct Banner {
+    html: Vec<u8>,
+}
+
+impl Banner {
+    /// Add `html` to every page.
+    pub fn new<H: Into<Vec<u8>>>(html: H) -> Banner {
+        Banner { html: html.into() }
+    }
+}
+
+impl Filter for Banner {
+    fn applies_to(&self, media_type: &str) -> bool {
+        media_type == "text/html"
+    }
+
+    fn filter(&self, body: Vec<u8>) -> Vec<u8> {
+        let at = body
+            .windows(7)
+            .rposition(|w| w.eq_ignore_ascii_case(b"</body>"))
+            .unwrap_or(body.len());
+        let mut out = Vec::with_capacity(body.len() + self.html.len());
+        out.extend_from_slice(&body[..at]);
+        out.extend_from_slice(&self.html);
+        out.extend_from_slice(&body[at..]);
+        out
+    }
+}
+
+/*
+Elements whose contents are left exactly as they are by `Minify`.
+*/
+const RAW_ELEMENTS: [&[u8]; 4] = [b"pre", b"script", b"style", b"textarea"];
+
+/*
+If `html[at..]` starts the opening tag of one of the `RAW_ELEMENTS`,
+return its name.
+*/
+fn raw_element_at(html: &[u8], at: usize) -> Option<&'static [u8]> {
+    let rest = &html[at + 1..];
+    RAW_ELEMENTS.iter().copied().find(|name| {
+        rest.len() > name.len()
+            && rest[..name.len()].eq_ignore_ascii_case(name)
+            && matches!(rest[name.len()], b'>' | b'/' | b' ' | b'\t' | b'\r' | b'\n')
+    })
+}
+
+/*
+Return the position just past the closing tag of the raw element `name`
+whose contents start at `from` (or the end of `html`, if it's never
+closed).
+*/
+fn raw_element_end(html: &[u8], name: &[u8], from: usize) -> usize {
+    let mut pos = from;
+    while let Some(n) = find_from(html, b"</", pos) {
+        let rest = &html[n + 2..];
+        if rest.len() >= name.len() && rest[..name.len()].eq_ignore_ascii_case(name) {
+            return match find_from(html, b">", n) {
+                Some(gt) => gt + 1,
+                None => html.len(),
+            };
+        }
+        pos = n + 2;
+    }
+    html.len()
+}
+
+/**
+Collapses each run of whitespace in HTML pages to a single character (a
+newline if the run had one in it, a space otherwise), which shrinks
+indented, template-generated pages a good deal without changing how they
+look.
+
+The contents of `<pre>`, `<script>`, `<style>`, and `<textarea>` elements
+are left alone.
+
+```rust
+# use dumb_cgi::filter::{Filter, Minify};
+let html = b"<ul>\n    <li>One</li>\n    <li>Two  words</li>\n</ul>\n<pre>  keep\n    this</pre>";
+assert_eq!(
+    Minify.filter(html.to_vec()),
+    b"<ul>\n<li>One</li>\n<li>Two words</li>\n</ul>\n<pre>  keep\n    this</pre>"
+);
+```
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Minify;
+
+impl Filter for Minify {
+    fn applies_to(&self, media_type: &str) -> bool {
+        media_type == "text/html"
+    }
+
+    fn filter(&self, body: Vec<u8>) -> Vec<u8> {
+        let mut out = Vec::with_capacity(body.len());
+        let mut pos = 0;
+        while pos < body.len() {
+            let b = body[pos];
+            if b.is_ascii_whitespace() {
+                let run_end = body[pos..]
+                    .iter()
+                    .position(|b| !b.is_ascii_whitespace())
+                    .map_or(body.len(), |n| n + pos);
+                let newline = body[pos..run_end].contains(&b'\n');
+                out.push(if newline { b'\n' } else { b' ' });
+                pos = run_end;
+            } else if b == b'<' {
+                match raw_element_at(&body, pos) {
+                    Some(name) => {
+                        let end = raw_element_end(&body, name, pos + 1 + name.len());
+                        out.extend_from_slice(&body[pos..end]);
+                        pos = end;
+                    }
+                    None => {
+                        out.push(b);
+                        pos += 1;
+                    }
+                }
+            } else {
+                out.push(b);
+                pos += 1;
+            }
+        }
+        out
+    }
+}
+
+/*
+Return the position just past the opening tag `<name ...>` in `html`, if
+there is one.
+*/
+fn after_opening_tag(html: &[u8], name: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while let Some(n) = find_from(html, b"<", pos) {
+        let rest = &html[n + 1..];
+        if rest.len() > name.len()
+            && rest[..name.len()].eq_ignore_ascii_case(name)
+            && matches!(rest[name.len()], b'>' | b' ' | b'\t' | b'\r' | b'\n')
+        {
+            return find_from(html, b">", n).map(|gt| gt + 1);
+        }
+        pos = n + 1;
+    }
+    None
+}
+
+/*
+The character set the first `<meta>` tag in `html` that declares one
+declares (lower-cased; empty if it can't be made out), either as
+`<meta charset="...">` or in an `http-equiv` `content="...; charset=..."`.
+*/
+fn declared_charset(html: &[u8]) -> Option<String> {
+    let mut pos = 0;
+    while let Some(n) = find_from(html, b"<", pos) {
+        let end = find_from(html, b">", n).unwrap_or(html.len());
+        let tag = html[n..end].to_ascii_lowercase();
+        if tag.starts_with(b"<meta") {
+            if let Some(at) = find_from(&tag, b"charset", 0) {
+                let value = tag[at + b"charset".len()..]
+                    .iter()
+                    .skip_while(|&&b| b == b' ' || b == b'=' || b == b'"' || b == b'\'')
+                    .take_while(|&&b| !matches!(b, b'"' | b'\'' | b';' | b' ' | b'/'))
+                    .map(|&b| b as char)
+                    .collect();
+                return Some(value);
+            }
+        }
+        pos = n + 1;
+    }
+    None
+}
+
+/**
+Makes sure HTML pages say they're UTF-8, both by adding
+`; charset=utf-8` to the `Content-type` (unless it already names a
+charset) and by adding a `<meta charset="utf-8">` tag just inside the
+`<head>` (or `<html>`, or at the very start) of the page (unless it
+already has one).
+
+A page whose `<meta>` tag declares some other charset is left alone, and
+its `Content-type` doesn't get one either: browsers believe the header
+over the tag, so adding `utf-8` there would garble the page.
+
+```rust
+use dumb_cgi::Response;
+use dumb_cgi::filter::{Charset, Filters};
+
+let r = Response::new(200)
+    .with_content_type("text/html")
+    .with_body("<html><head><title>Caf\u{e9}</title></head></html>");
+let r = Filters::new().with(Charset).apply(r);
+assert_eq!(r.get_content_type(), "text/html; charset=utf-8");
+assert_eq!(
+    r.get_body(),
+    "<html><head><meta charset=\"utf-8\"><title>Caf\u{e9}</title></head></html>".as_bytes()
+);
+```
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Charset;
+
+impl Filter for Charset {
+    fn applies_to(&self, media_type: &str) -> bool {
+        media_type == "text/html"
+    }
+
+    fn content_type(&self, content_type: &str, body: &[u8]) -> Option<String> {
+        if content_type.to_ascii_lowercase().contains("charset=") {
+            return None;
+        }
+        match declared_charset(body).as_deref() {
+            None | Some("utf-8") | Some("utf8") => {
+                Some(format!("{}; charset=utf-8", content_type.trim_end()))
+            }
+            Some(_) => None,
+        }
+    }
+
+    fn filter(&self, body: Vec<u8>) -> Vec<u8> {
+        if declared_charset(&body).is_some() {
+            return body;
+        }
+        const META: &[u8] = b"<meta charset=\"utf-8\">";
+        let at = after_opening_tag(&body, b"head")
+            .or_else(|| after_opening_tag(&body, b"html"))
+            .or_else(|| after_opening_tag(&body, b"!doctype"))
+            .unwrap_or(0);
+        let mut out = Vec::with_capacity(body.len() + META.len());
+        out.extend_from_slice(&body[..at]);
+        out.extend_from_slice(META);
+        out.extend_from_slice(&body[at..]);
+        out
+    }
+}
+
+type Redaction = Box<dyn Fn(&str) -> String>;
+type TeeSink = Box<dyn Fn(&str)>;
+
+/**
+A filter that leaves the body alone, but copies (the start of) it
+somewhere, usually a log, for finding out what a production program
+actually sent a client that's having trouble.
+
+The copy is decoded as UTF-8 (lossily), cut off after a number of bytes,
+and passed through any redactions (which should remove passwords, tokens,
+and the like) before it's handed over.
+
+```rust
+use std::sync::{Arc, Mutex};
+use dumb_cgi::filter::{Filters, Tee};
+use dumb_cgi::Response;
+
+let logged = Arc::new(Mutex::new(Vec::new()));
+let sink = logged.clone();
+let filters = Filters::new().with(
+    Tee::new(40, move |excerpt: &str| sink.lock().unwrap().push(excerpt.to_owned()))
+        .with_redacted_field("token"),
+);
+
+let r = Response::new(200)
+    .with_content_type("application/json")
+    .with_body(r#"{"user": "dan", "token": "s3cr3t", "items": [1, 2, 3, 4, 5, 6]}"#);
+let r = filters.apply(r);
+assert!(r.get_body().ends_with(b"6]}"));
+assert_eq!(
+    logged.lock().unwrap()[0],
+    r#"{"user": "dan", "token": "[redacted]", "item... (23 more bytes)"#
+);
+```
+*/
+pub struct Tee {
+    max_bytes: usize,
+    redactions: Vec<Redaction>,
+    sink: TeeSink,
+}
+
+impl std::fmt::Debug for Tee {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tee")
+            .field("max_bytes", &self.max_bytes)
+            .field("redactions", &self.redactions.len())
+            .finish()
+    }
+}
+
+impl Tee {
+    /// Pass the first `max_bytes` bytes of each body to `sink`.
+    pub fn new<F: Fn(&str) + 'static>(max_bytes: usize, sink: F) -> Tee {
+        Tee {
+            max_bytes,
+            redactions: Vec::new(),
+            sink: Box::new(sink),
+        }
+    }
+
+    /**
+    Append the first `max_bytes` bytes of each body to `log`, as a record
+    of `actor` sending it. Failing to write to the log doesn't stop the
+    response from being sent.
+    */
+    pub fn to_audit_log<A: Into<String>>(max_bytes: usize, log: AuditLog, actor: A) -> Tee {
+        let actor = actor.into();
+        Tee::new(max_bytes, move |excerpt| {
+            _ = log.append(&actor, &format!("response body: {}", excerpt));
+        })
+    }
+
+    /// Builder-pattern method for passing each copy through `redact`
+    /// before it's handed over (after any redactions added earlier).
+    pub fn with_redaction<F: Fn(&str) -> String + 'static>(self, redact: F) -> Tee {
+        let mut new = self;
+        new.redactions.push(Box::new(redact));
+        new
+    }
+
+    /**
+    Builder-pattern method for replacing the value of every field named
+    `name` in the copy with `[redacted]`, whether it's a JSON string
+    (`"name": "value"`) or a form field (`name=value`).
+    */
+    pub fn with_redacted_field<N: Into<String>>(self, name: N) -> Tee {
+        let name = name.into();
+        self.with_redaction(move |text| redact_field(text, &name))
+    }
+}
+
+impl Filter for Tee {
+    fn filter(&self, body: Vec<u8>) -> Vec<u8> {
+        let cut = body.len().min(self.max_bytes);
+        let mut excerpt = String::from_utf8_lossy(&body[..cut]).into_owned();
+        for redact in self.redactions.iter() {
+            excerpt = redact(&excerpt);
+        }
+        if cut < body.len() {
+            excerpt.push_str(&format!("... ({} more bytes)", body.len() - cut));
+        }
+        (self.sink)(&excerpt);
+        body
+    }
+}
+
+const REDACTED: &str = "[redacted]";
+
+/*
+Replace the values of the JSON string fields and form fields named `name`
+in `text` with `REDACTED`.
+*/
+fn redact_field(text: &str, name: &str) -> String {
+    let json_key = format!("\"{}\"", name);
+    let form_key = format!("{}=", name);
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    loop {
+        let json = rest.find(&json_key).map(|n| (n, true));
+        let form = find_form_field(rest, &form_key).map(|n| (n, false));
+        let (start, is_json) = match (json, form) {
+            (Some(j), Some(f)) => std::cmp::min(j, f),
+            (Some(m), None) | (None, Some(m)) => m,
+            (None, None) => break,
+        };
+        let key_end = start
+            + if is_json {
+                json_key.len()
+            } else {
+                form_key.len()
+            };
+        out.push_str(&rest[..key_end]);
+        rest = &rest[key_end..];
+        let value = if is_json {
+            json_string_value(rest)
+        } else {
+            Some((0, rest.find('&').unwrap_or(rest.len())))
+        };
+        if let Some((prefix, len)) = value {
+            out.push_str(&rest[..prefix]);
+            out.push_str(REDACTED);
+            rest = &rest[prefix + len..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/*
+Where the form field starting `form_key` (`name=`) is in `text`, if it's
+there: at the start, or after a `&` or `?`.
+*/
+fn find_form_field(text: &str, form_key: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(n) = text[from..].find(form_key) {
+        let at = from + n;
+        if at == 0 || matches!(text.as_bytes()[at - 1], b'&' | b'?') {
+            return Some(at);
+        }
+        from = at + form_key.len();
+    }
+    None
+}
+
+/*
+If `after_key` (what follows a JSON object key) is a colon and a string,
+return how far in the string's contents start, and how long they are (up
+to the closing quote, or the end of `after_key` if it's been cut off).
+*/
+fn json_string_value(after_key: &str) -> Option<(usize, usize)> {
+    let quoted = after_key
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start()
+        .strip_prefix('"')?;
+    let prefix = after_key.len() - quoted.len();
+    let mut chars = quoted.char_indices();
+    while let Some((n, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => return Some((prefix, n)),
+            _ => {}
+        }
+    }
+    Some((prefix, quoted.len()))
+}
+
+/**
+A list of `Filter`s to run over response bodies, in the order they were
+added.
+*/
+#[derive(Default)]
+pub struct Filters {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl std::fmt::Debug for Filters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Filters")
+            .field("len", &self.filters.len())
+            .finish()
+    }
+}
+
+impl Filters {
+    /// Return an empty list of filters.
+    pub fn new() -> Filters {
+        Filters::default()
+    }
+
+    /// Add a filter to the end of the list.
+    pub fn push<F: Filter + 'static>(&mut self, filter: F) {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Builder-pattern method for adding a filter to the end of the list.
+    pub fn with<F: Filter + 'static>(self, filter: F) -> Filters {
+        let mut new = self;
+        new.push(filter);
+        new
+    }
+
+    /**
+    Run each filter that applies to the response's content type over its
+    body, in order, and return the response.
+    */
+    pub fn apply(&self, response: Response) -> Response {
+        let mut response = response;
+        if response.get_body().is_empty() {
+            return response;
+        }
+        for filter in self.filters.iter() {
+            let content_type = match response.get_content_type() {
+                "" => response.get_header("content-type").unwrap_or(""),
+                content_type => content_type,
+            }
+            .to_owned();
+            if !filter.applies_to(&media_type(&content_type)) {
+                continue;
+            }
+            if let Some(content_type) = filter.content_type(&content_type, response.get_body()) {
+                response.set_content_type(content_type);
+            }
+            response.map_body(|body| filter.filter(body));
+        }
+        response
+    }
+}
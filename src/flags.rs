@@ -0,0 +1,348 @@
+/*!
+Feature flags with percentage rollouts.
+
+Flags are read from a simple text file, one flag per line:
+
+```text
+# Comments start with '#'.
+new_ui = 25%
+fast_search = on
+old_layout = off
+```
+
+A flag with a percentage is enabled for that fraction of users. Which
+users is decided by a hash of the flag name and a stable identifier for
+the user, so each user consistently sees the same thing, and raising the
+percentage only ever adds users.
+
+The identifier for a request is, in order of preference:
+
+  1. a `RolloutId` in the request's extensions (set this from your login
+     or session handling)
+  2. the value of the cookie set with `Flags::with_cookie()`
+  3. the `REMOTE_ADDR` environment variable
+
+Requests with none of these only see flags that are fully on.
+
+For experiments with more than two arms, `bucket()` assigns each user a
+variant and remembers it in a cookie.
+
+```rust
+use dumb_cgi::{flags::{Flags, RolloutId}, testing::MockRequest};
+
+let flags = Flags::parse("new_ui = 50%\nfast_search = on").unwrap();
+
+let mut req = MockRequest::get("/").build().unwrap();
+assert!(flags.enabled("fast_search", &req));
+assert!(!flags.enabled("no_such_flag", &req));
+
+req.extensions_mut().insert(RolloutId("user-1234".to_owned()));
+// Always the same answer for the same user.
+assert_eq!(flags.enabled("new_ui", &req), flags.enabled_for("new_ui", "user-1234"));
+```
+*/
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::cookie::{Cookie, SameSite};
+use crate::hash::sha256;
+use crate::{Error, Request};
+
+/// Rollouts are computed in hundredths of a percent.
+const SCALE: u32 = 10_000;
+
+fn flags_error(details: String) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details,
+    }
+}
+
+/**
+A stable identifier for the user making a request, used to decide which
+side of a rollout they fall on. Insert one into `Request::extensions_mut()`
+once you know who the user is.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RolloutId(pub String);
+
+/*
+Return which of `buckets` slots `id` falls into for the rollout named
+`name`. Including the name means different flags pick different users.
+*/
+fn rollout_slot(name: &str, id: &str, buckets: u32) -> u32 {
+    let mut input = Vec::with_capacity(name.len() + id.len() + 1);
+    input.extend_from_slice(name.as_bytes());
+    input.push(0);
+    input.extend_from_slice(id.as_bytes());
+    let digest = sha256(&input);
+    let mut n = [0u8; 8];
+    n.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(n) % buckets as u64) as u32
+}
+
+/*
+The stable identifier for the user making `req` (see the module
+documentation).
+*/
+fn request_id<'a>(req: &'a Request, cookie: Option<&str>) -> Option<&'a str> {
+    if let Some(RolloutId(id)) = req.extensions().get::<RolloutId>() {
+        return Some(id);
+    }
+    if let Some(value) = cookie.and_then(|name| req.cookie(name)) {
+        return Some(value);
+    }
+    req.var("REMOTE_ADDR")
+}
+
+/*
+Parse `on`, `off`, `true`, `false`, or a percentage like `12.5%` into
+hundredths of a percent.
+*/
+fn parse_rollout(value: &str) -> Option<u32> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" => return Some(SCALE),
+        "off" | "false" | "no" => return Some(0),
+        _ => {}
+    }
+    let pct: f64 = value.strip_suffix('%')?.trim().parse().ok()?;
+    if !(0.0..=100.0).contains(&pct) {
+        return None;
+    }
+    Some((pct * 100.0).round() as u32)
+}
+
+/**
+A set of feature flags.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Flags {
+    flags: HashMap<String, u32>,
+    cookie: Option<String>,
+}
+
+impl Flags {
+    /// Create an empty set (in which every flag is off).
+    pub fn new() -> Flags {
+        Flags::default()
+    }
+
+    /**
+    Parse the contents of a flags file.
+
+    Returns an error describing the first line that isn't blank, a
+    comment, or `name = on|off|N%`.
+    */
+    pub fn parse(text: &str) -> Result<Flags, Error> {
+        let mut flags = Flags::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = line.split_once('=').and_then(|(name, value)| {
+                let name = name.trim();
+                let rollout = parse_rollout(value.trim())?;
+                (!name.is_empty()).then_some((name, rollout))
+            });
+            match parsed {
+                Some((name, rollout)) => {
+                    flags.flags.insert(name.to_owned(), rollout);
+                }
+                None => {
+                    return Err(flags_error(format!(
+                        "Unable to parse line {} of flags file: {:?}",
+                        n + 1,
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(flags)
+    }
+
+    /// Read and parse the flags file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Flags, Error> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(text) => Flags::parse(&text),
+            Err(e) => Err(flags_error(format!(
+                "Unable to read flags file {}: {}",
+                path.display(),
+                &e
+            ))),
+        }
+    }
+
+    /**
+    Identify users by the value of the cookie `name` (such as a session
+    cookie) when a request has no `RolloutId`.
+    */
+    pub fn with_cookie<T: Into<String>>(self, name: T) -> Flags {
+        let mut new = self;
+        new.cookie = Some(name.into());
+        new
+    }
+
+    /**
+    Set the rollout percentage of the flag `name` (clamped to 0–100).
+    */
+    pub fn set(&mut self, name: &str, percent: f64) {
+        let rollout = (percent.clamp(0.0, 100.0) * 100.0).round() as u32;
+        self.flags.insert(name.to_owned(), rollout);
+    }
+
+    /// Return the rollout percentage of the flag `name`, if it's defined.
+    pub fn percent(&self, name: &str) -> Option<f64> {
+        self.flags.get(name).map(|r| *r as f64 / 100.0)
+    }
+
+    /// Return whether the flag `name` is enabled for the user making `req`.
+    pub fn enabled(&self, name: &str, req: &Request) -> bool {
+        match self.flags.get(name) {
+            None | Some(0) => false,
+            Some(&r) if r >= SCALE => true,
+            Some(_) => match request_id(req, self.cookie.as_deref()) {
+                Some(id) => self.enabled_for(name, id),
+                None => false,
+            },
+        }
+    }
+
+    /// Return whether the flag `name` is enabled for the user identified by `id`.
+    pub fn enabled_for(&self, name: &str, id: &str) -> bool {
+        match self.flags.get(name) {
+            None => false,
+            Some(&r) => rollout_slot(name, id, SCALE) < r,
+        }
+    }
+}
+
+/**
+A user's variant in an experiment, as returned by `bucket()`.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    /// Which variant (from 0 up to the number of variants) the user sees.
+    pub variant: u32,
+    set_cookie: Option<String>,
+}
+
+impl Assignment {
+    /// Return whether the user was just assigned (rather than already having a cookie).
+    pub fn is_new(&self) -> bool {
+        self.set_cookie.is_some()
+    }
+
+    /**
+    Return the headers to add to the response: a `Set-Cookie` header
+    recording a new assignment, and `Vary: Cookie` and
+    `Cache-Control: private` so that caches don't serve one user's variant
+    to everyone.
+    */
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::with_capacity(3);
+        if let Some(cookie) = &self.set_cookie {
+            headers.push(("Set-Cookie", cookie.clone()));
+        }
+        headers.push(("Vary", "Cookie".to_owned()));
+        headers.push(("Cache-Control", "private".to_owned()));
+        headers
+    }
+}
+
+/// How long a variant cookie lasts, in seconds: a year.
+const VARIANT_COOKIE_AGE: u64 = 365 * 24 * 60 * 60;
+
+/*
+The cookie that remembers a user's variant in `experiment`; any
+characters not allowed in a cookie name are replaced.
+*/
+fn experiment_cookie(experiment: &str) -> String {
+    let name: String = experiment
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("ab_{}", name)
+}
+
+/**
+Assign the user making `req` to one of `n_variants` variants of
+`experiment`.
+
+A user who already has a variant cookie for the experiment keeps that
+variant. Otherwise the variant is chosen from the user's stable
+identifier (see the module documentation), or at random for a request
+with none, and `Assignment::headers()` includes a cookie that keeps it
+for a year.
+
+```rust
+use dumb_cgi::{flags::bucket, testing::MockRequest, Response};
+
+let req = MockRequest::get("/")
+    .var("REMOTE_ADDR", "192.0.2.1")
+    .build()
+    .unwrap();
+let assignment = bucket(&req, "checkout_button", 3);
+assert!(assignment.variant < 3);
+
+let mut response = Response::new(200);
+for (name, value) in assignment.headers() {
+    response.add_header(name, value);
+}
+assert_eq!(response.get_header("vary"), Some("Cookie"));
+
+// The next request sends the cookie back, and gets the same variant.
+let cookie = format!("ab_checkout_button={}", assignment.variant);
+let req = MockRequest::get("/").header("Cookie", cookie).build().unwrap();
+let again = bucket(&req, "checkout_button", 3);
+assert_eq!(again.variant, assignment.variant);
+assert!(!again.is_new());
+```
+*/
+pub fn bucket(req: &Request, experiment: &str, n_variants: u32) -> Assignment {
+    let n_variants = n_variants.max(1);
+    let cookie_name = experiment_cookie(experiment);
+
+    if let Some(variant) = req
+        .cookie(&cookie_name)
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|v| *v < n_variants)
+    {
+        return Assignment {
+            variant,
+            set_cookie: None,
+        };
+    }
+
+    let variant = match request_id(req, None) {
+        Some(id) => rollout_slot(experiment, id, n_variants),
+        None => {
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0);
+            let seed = format!("{}.{}", nanos, std::process::id());
+            rollout_slot(experiment, &seed, n_variants)
+        }
+    };
+    Assignment {
+        variant,
+        set_cookie: Some(
+            Cookie::new(cookie_name, variant.to_string())
+                .with_path("/")
+                .with_max_age(Duration::from_secs(VARIANT_COOKIE_AGE))
+                .with_same_site(SameSite::Lax)
+                .to_string(),
+        ),
+    }
+}
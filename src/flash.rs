@@ -0,0 +1,255 @@
+/*!
+One-shot "flash" messages ("Your changes have been saved.") carried to
+the next page the user sees in a signed cookie, so a handler can say
+something to the user across a redirect without any server-side session.
+
+```rust
+use dumb_cgi::flash::{Flash, Flasher};
+use dumb_cgi::testing::MockRequest;
+use dumb_cgi::Response;
+
+let flasher = Flasher::new("server-side secret key");
+
+// In the handler that did something:
+let mut r = Response::new(303).with_header("Location", "/account");
+flasher.set(&mut r, &[Flash::success("Password changed."), Flash::info("Check your email.")]);
+
+// In the one for the next page (the user agent having sent the cookie back):
+let cookie = r.get_cookies()[0].split(';').next().unwrap().to_owned();
+let req = MockRequest::get("/account").header("Cookie", cookie).build().unwrap();
+let mut page = Response::new(200).with_content_type("text/html");
+let messages = flasher.take(&req, &mut page);
+assert_eq!(messages.len(), 2);
+assert_eq!(
+    dumb_cgi::flash::render(&messages),
+    "<div class=\"flash flash-success\" role=\"status\">Password changed.</div>\n\
+     <div class=\"flash flash-info\" role=\"status\">Check your email.</div>\n"
+);
+```
+
+The cookie's signature (HMAC-SHA-256 under the `Flasher`'s key) keeps
+anyone else from putting words in the site's mouth; it isn't encrypted,
+so flash messages shouldn't say anything secret. Browsers limit cookies
+to about 4 KB, which is plenty for a few sentences. (`Response::prg_redirect()`
+and `Request::take_flash()` send messages this way along with a
+Post/Redirect/Get redirect.)
+*/
+
+use std::time::Duration;
+
+use crate::cookie::{Cookie, SameSite, FLASH_MAX_AGE};
+use crate::escape::html;
+use crate::hash::{constant_time_eq, hmac_sha256};
+use crate::{base64, Request, Response};
+
+/// The default name of the cookie the messages are kept in.
+pub const DEFAULT_COOKIE_NAME: &str = "flashes";
+
+/**
+What sort of thing a flash message is saying, for styling it.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Level {
+    /// Something the user asked for worked.
+    Success,
+    /// Something the user should know.
+    Info,
+    /// Something went wrong.
+    Error,
+}
+
+impl Level {
+    /// Return the level's name: `success`, `info`, or `error`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Success => "success",
+            Level::Info => "info",
+            Level::Error => "error",
+        }
+    }
+
+    /// Return the level with the name `name`, if there is one.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "success" => Some(Level::Success),
+            "info" => Some(Level::Info),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+/**
+A flash message.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flash {
+    /// What sort of message it is.
+    pub level: Level,
+    /// The message itself, as plain text.
+    pub message: String,
+}
+
+impl Flash {
+    /// Return a message of level `level`.
+    pub fn new<M: Into<String>>(level: Level, message: M) -> Flash {
+        Flash {
+            level,
+            message: message.into(),
+        }
+    }
+
+    /// Return a `Success` message.
+    pub fn success<M: Into<String>>(message: M) -> Flash {
+        Flash::new(Level::Success, message)
+    }
+
+    /// Return an `Info` message.
+    pub fn info<M: Into<String>>(message: M) -> Flash {
+        Flash::new(Level::Info, message)
+    }
+
+    /// Return an `Error` message.
+    pub fn error<M: Into<String>>(message: M) -> Flash {
+        Flash::new(Level::Error, message)
+    }
+}
+
+/**
+Return `messages` as HTML, one `<div>` per message with the classes
+`flash` and `flash-{level}`, ready to drop into a page (say, as a
+template's `{{{flash}}}`). Messages are escaped; errors are given the
+ARIA role `alert`, so screen readers announce them straight away, and the
+others `status`.
+*/
+pub fn render(messages: &[Flash]) -> String {
+    let mut out = String::new();
+    for m in messages {
+        let role = match m.level {
+            Level::Error => "alert",
+            _ => "status",
+        };
+        out.push_str(&format!(
+            "<div class=\"flash flash-{}\" role=\"{}\">{}</div>\n",
+            m.level.name(),
+            role,
+            html(&m.message)
+        ));
+    }
+    out
+}
+
+/**
+Writes flash messages to signed cookies and reads them back (see the
+module documentation).
+*/
+#[derive(Debug, Clone)]
+pub struct Flasher {
+    key: Vec<u8>,
+    cookie_name: String,
+    secure: bool,
+}
+
+impl Flasher {
+    /// Create a `Flasher` that signs its cookies with `key`.
+    pub fn new<K: Into<Vec<u8>>>(key: K) -> Flasher {
+        Flasher {
+            key: key.into(),
+            cookie_name: DEFAULT_COOKIE_NAME.to_owned(),
+            secure: false,
+        }
+    }
+
+    /// Builder-pattern method for changing the name of the cookie.
+    pub fn with_cookie_name<T: Into<String>>(self, name: T) -> Flasher {
+        let mut new = self;
+        new.cookie_name = name.into();
+        new
+    }
+
+    /// Builder-pattern method for setting whether the cookie is only sent
+    /// over HTTPS.
+    pub fn with_secure(self, secure: bool) -> Flasher {
+        let mut new = self;
+        new.secure = secure;
+        new
+    }
+
+    /// Return whether the cookie is only sent over HTTPS (see
+    /// `.with_secure()`).
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        base64::encode_url(&hmac_sha256(&self.key, payload.as_bytes()))
+    }
+
+    /**
+    Add a cookie to `response` carrying `messages` to the next request
+    (replacing any messages set earlier and not yet taken). With no
+    messages, nothing is added.
+    */
+    pub fn set(&self, response: &mut Response, messages: &[Flash]) {
+        self.set_with(response, messages, self.secure);
+    }
+
+    /*
+    `.set()`, with the cookie `Secure` if `secure` is.
+    */
+    pub(crate) fn set_with(&self, response: &mut Response, messages: &[Flash], secure: bool) {
+        if messages.is_empty() {
+            return;
+        }
+        let payload = messages
+            .iter()
+            .map(|m| {
+                format!(
+                    "{}:{}",
+                    m.level.name(),
+                    base64::encode_url(m.message.as_bytes())
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("|");
+        let value = format!("{}.{}", &payload, self.sign(&payload));
+        response.add_cookie(
+            Cookie::new(&self.cookie_name, value)
+                .with_path("/")
+                .with_max_age(Duration::from_secs(FLASH_MAX_AGE))
+                .with_http_only(true)
+                .with_same_site(SameSite::Lax)
+                .with_secure(secure),
+        );
+    }
+
+    /**
+    Return the messages `req` carries (in the order they were set), and
+    add a cookie to `response` that removes them, so they're only shown
+    once. Messages whose signature doesn't check out are dropped (but
+    still removed).
+    */
+    pub fn take(&self, req: &Request, response: &mut Response) -> Vec<Flash> {
+        let value = match req.cookie(&self.cookie_name) {
+            Some(value) => value,
+            None => return Vec::new(),
+        };
+        response.add_cookie(Cookie::removal(&self.cookie_name).with_path("/"));
+        self.decode(value).unwrap_or_default()
+    }
+
+    fn decode(&self, value: &str) -> Option<Vec<Flash>> {
+        let (payload, sig) = value.rsplit_once('.')?;
+        if !constant_time_eq(self.sign(payload).as_bytes(), sig.as_bytes()) {
+            return None;
+        }
+        payload
+            .split('|')
+            .map(|item| {
+                let (level, message) = item.split_once(':')?;
+                let message = String::from_utf8(base64::decode_url(message)?).ok()?;
+                Some(Flash::new(Level::from_name(level)?, message))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,195 @@
+/*!
+Cleaning up old files.
+
+A CGI program has no long-running process to tidy up after it, so the
+files it leaves behind (session files, spooled uploads, cache entries)
+have to be expired by the requests themselves, or by something run from
+cron. A `Gc` removes files older than a given age from a set of
+directories; `Gc::maybe_run()` does so on only one request in N, so the
+cost is spread thinly.
+
+Only one process collects a given directory at a time; the others skip it.
+
+```rust
+use std::time::Duration;
+use dumb_cgi::gc::Gc;
+
+let dir = std::env::temp_dir().join("dumb_cgi_gc_doctest");
+std::fs::create_dir_all(&dir).unwrap();
+std::fs::write(dir.join("old-session"), "data").unwrap();
+
+let gc = Gc::new().with_dir(&dir, Duration::ZERO);
+// (In a request handler, `gc.maybe_run(100)` would be more usual.)
+let report = gc.run();
+assert_eq!(report.removed, 1);
+assert!(report.errors.is_empty());
+# std::fs::remove_dir_all(&dir).unwrap();
+```
+*/
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::hash::sha256;
+use crate::lockfile::LockGuard;
+
+/// Name of the lock file a `Gc` keeps in each directory it's collecting.
+pub(crate) const LOCK_NAME: &str = ".dumb_cgi_gc.lock";
+
+/**
+What a garbage collection run did.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// How many files were removed.
+    pub removed: usize,
+    /// How many bytes those files took up.
+    pub bytes: u64,
+    /// Directories skipped because another process was collecting them.
+    pub skipped: Vec<PathBuf>,
+    /// Descriptions of anything that couldn't be read or removed.
+    pub errors: Vec<String>,
+}
+
+impl GcReport {
+    /// Add the results of `other` to this report.
+    pub fn merge(&mut self, other: GcReport) {
+        self.removed += other.removed;
+        self.bytes += other.bytes;
+        self.skipped.extend(other.skipped);
+        self.errors.extend(other.errors);
+    }
+}
+
+/**
+A set of directories to expire old files from.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Gc {
+    dirs: Vec<(PathBuf, Duration)>,
+}
+
+/*
+Return `true` about once in every `n` calls (in different processes).
+*/
+fn one_in(n: u32) -> bool {
+    if n <= 1 {
+        return true;
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let seed = format!("{}.{}", nanos, std::process::id());
+    let digest = sha256(seed.as_bytes());
+    let mut x = [0u8; 4];
+    x.copy_from_slice(&digest[..4]);
+    u32::from_be_bytes(x) % n == 0
+}
+
+impl Gc {
+    /// Create a collector with no directories.
+    pub fn new() -> Gc {
+        Gc::default()
+    }
+
+    /**
+    Builder-pattern method for adding a directory whose files (including
+    those in its subdirectories) should be removed once they haven't been
+    modified for `max_age`.
+    */
+    pub fn with_dir<P: Into<PathBuf>>(self, dir: P, max_age: Duration) -> Gc {
+        let mut new = self;
+        new.dirs.push((dir.into(), max_age));
+        new
+    }
+
+    /**
+    Collect every directory now. A directory that doesn't exist is
+    treated as empty.
+    */
+    pub fn run(&self) -> GcReport {
+        let mut report = GcReport::default();
+        for (dir, max_age) in self.dirs.iter() {
+            if !dir.is_dir() {
+                continue;
+            }
+            let lock_path = dir.join(LOCK_NAME);
+            let _lock = match LockGuard::try_acquire(&lock_path) {
+                Ok(Some(lock)) => lock,
+                Ok(None) => {
+                    report.skipped.push(dir.clone());
+                    continue;
+                }
+                Err(e) => {
+                    report
+                        .errors
+                        .push(format!("{}: unable to lock: {}", lock_path.display(), &e));
+                    continue;
+                }
+            };
+            let cutoff = SystemTime::now()
+                .checked_sub(*max_age)
+                .unwrap_or(UNIX_EPOCH);
+            collect(dir, cutoff, &mut report);
+        }
+        report
+    }
+
+    /**
+    Run a collection on (about) one call in every `n`, returning its
+    report if it ran. Call this from each request to keep directories
+    tidy without cron.
+    */
+    pub fn maybe_run(&self, n: u32) -> Option<GcReport> {
+        one_in(n).then(|| self.run())
+    }
+}
+
+/*
+Remove files in `dir` (recursively) last modified before `cutoff`. Symbolic
+links are removed (if old) but never followed.
+*/
+fn collect(dir: &Path, cutoff: SystemTime, report: &mut GcReport) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.errors.push(format!("{}: {}", dir.display(), &e));
+            return;
+        }
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.errors.push(format!("{}: {}", dir.display(), &e));
+                continue;
+            }
+        };
+        let path = entry.path();
+        if entry.file_name() == LOCK_NAME {
+            continue;
+        }
+        let meta = match std::fs::symlink_metadata(&path) {
+            Ok(meta) => meta,
+            // Probably removed by someone else in the meantime.
+            Err(_) => continue,
+        };
+        if meta.is_dir() {
+            collect(&path, cutoff, report);
+            continue;
+        }
+        let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+        if modified > cutoff {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                report.removed += 1;
+                report.bytes += meta.len();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => report.errors.push(format!("{}: {}", path.display(), &e)),
+        }
+    }
+}
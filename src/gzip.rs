@@ -0,0 +1,464 @@
+/*!
+A small, dependency-free gzip (and zlib) compressor, for compressing
+response bodies (see `Response::compressed_for()`), and a
+`CompressionPolicy` for choosing which bodies are worth compressing.
+
+```rust
+use dumb_cgi::gzip;
+
+let text = "all work and no play makes jack a dull boy. ".repeat(50);
+let packed = gzip::compress(text.as_bytes());
+assert!(packed.len() < text.len() / 10);
+// A gzip stream ends with the CRC-32 and length of what it holds.
+let n = packed.len();
+assert_eq!(packed[n - 8..n - 4], gzip::crc32(text.as_bytes()).to_le_bytes());
+assert_eq!(packed[n - 4..], (text.len() as u32).to_le_bytes());
+```
+
+The compressor finds repeats with hash chains and writes them with
+DEFLATE's fixed Huffman codes. That gets most of the way to what `gzip -6`
+does on the text CGI programs usually send (HTML, JSON, CSS), at a small
+fraction of the code; it doesn't try to compete on images or other
+already-compressed data, which aren't worth compressing anyway.
+
+This module is only available with the `compression` feature enabled.
+*/
+
+/// The furthest back a DEFLATE match can refer.
+const WINDOW: usize = 32 * 1024;
+/// The shortest and longest matches DEFLATE can encode.
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions to try for each match.
+const MAX_CHAIN: usize = 128;
+const HASH_BITS: u32 = 15;
+/// An empty hash chain link.
+const NONE: usize = usize::MAX;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/**
+Return the CRC-32 (as used by gzip, zip, and PNG) of `data`.
+
+```rust
+# use dumb_cgi::gzip::crc32;
+assert_eq!(crc32(b"123456789"), 0xcbf43926);
+```
+*/
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/*
+Writes bits into bytes least-significant bit first, as DEFLATE wants.
+*/
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    n: u32,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> BitWriter {
+        BitWriter { out, acc: 0, n: 0 }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        self.acc |= (value as u64) << self.n;
+        self.n += count;
+        while self.n >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n -= 8;
+        }
+    }
+
+    /*
+    Huffman codes are defined most-significant bit first, so they go in
+    backwards.
+    */
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn literal(&mut self, sym: u32) {
+        match sym {
+            0..=143 => self.code(0x30 + sym, 8),
+            144..=255 => self.code(0x190 + sym - 144, 9),
+            256..=279 => self.code(sym - 256, 7),
+            _ => self.code(0xC0 + sym - 280, 8),
+        }
+    }
+
+    fn copy(&mut self, len: usize, dist: usize) {
+        let idx = LEN_BASE
+            .iter()
+            .rposition(|&b| b as usize <= len)
+            .unwrap_or(0);
+        self.literal(257 + idx as u32);
+        self.bits((len - LEN_BASE[idx] as usize) as u32, LEN_EXTRA[idx] as u32);
+
+        let idx = DIST_BASE
+            .iter()
+            .rposition(|&b| b as usize <= dist)
+            .unwrap_or(0);
+        self.code(idx as u32, 5);
+        self.bits(
+            (dist - DIST_BASE[idx] as usize) as u32,
+            DIST_EXTRA[idx] as u32,
+        );
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let h = ((data[pos] as u32) << 10) ^ ((data[pos + 1] as u32) << 5) ^ (data[pos + 2] as u32);
+    (h.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/*
+Record that the three bytes at `pos` can be found there, for later matches.
+*/
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash(data, pos);
+        prev[pos] = head[h];
+        head[h] = pos;
+    }
+}
+
+/*
+Compress `data` as a single fixed-Huffman DEFLATE block, appended to `out`.
+*/
+fn deflate(data: &[u8], out: Vec<u8>) -> Vec<u8> {
+    let mut head = vec![NONE; 1 << HASH_BITS];
+    let mut prev = vec![NONE; data.len()];
+    let mut w = BitWriter::new(out);
+    // BFINAL = 1, BTYPE = 01 (fixed codes).
+    w.bits(0b011, 3);
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let max = MAX_MATCH.min(data.len() - pos);
+            let mut cand = head[hash(data, pos)];
+            let mut chain = 0;
+            while cand != NONE && pos - cand <= WINDOW && chain < MAX_CHAIN {
+                let len = data[cand..]
+                    .iter()
+                    .zip(&data[pos..pos + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    (best_len, best_dist) = (len, pos - cand);
+                    if len == max {
+                        break;
+                    }
+                }
+                cand = prev[cand];
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            w.copy(best_len, best_dist);
+            for p in pos..pos + best_len {
+                insert(data, p, &mut head, &mut prev);
+            }
+            pos += best_len;
+        } else {
+            w.literal(data[pos] as u32);
+            insert(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+
+    w.literal(256);
+    w.finish()
+}
+
+/**
+Return `data` compressed in gzip format (RFC 1952), as sent with
+`Content-Encoding: gzip`.
+*/
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // Magic, CM = deflate, no flags, no mtime, no XFL, OS unknown.
+    let header = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    let mut out = deflate(data, header);
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/**
+Return `data` compressed in zlib format (RFC 1950), as sent with
+`Content-Encoding: deflate`.
+*/
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // CM = deflate with a 32K window, no dictionary, FCHECK to make the
+    // pair a multiple of 31.
+    let mut out = deflate(data, vec![0x78, 0x01]);
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/*
+The checksum that ends a zlib stream.
+*/
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is as many as can be summed before `b` could overflow.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/*
+Return the q-value `accept_encoding` gives `coding` (or its `x-` alias),
+falling back to what it gives `*`, or `None` if it mentions neither.
+*/
+fn quality(accept_encoding: &str, coding: &str) -> Option<f32> {
+    let mut star = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name == coding || name.strip_prefix("x-") == Some(coding) {
+            return Some(q);
+        } else if name == "*" {
+            star = Some(q);
+        }
+    }
+    star
+}
+
+/**
+Return whether a request's `Accept-Encoding` header value allows a
+gzipped response: it names `gzip` (or the old `x-gzip`), or `*`, without
+ruling it out with `q=0`.
+
+```rust
+# use dumb_cgi::gzip;
+assert!(gzip::accepts("gzip, deflate, br"));
+assert!(gzip::accepts("br;q=1.0, *;q=0.5"));
+assert!(!gzip::accepts("gzip;q=0, *"));
+assert!(!gzip::accepts("identity"));
+```
+*/
+pub fn accepts(accept_encoding: &str) -> bool {
+    quality(accept_encoding, "gzip").is_some_and(|q| q > 0.0)
+}
+
+/**
+A content coding this module can compress with.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Coding {
+    /// `gzip`, which every browser takes.
+    Gzip,
+    /// `deflate` (zlib format), slightly smaller than `gzip` for the
+    /// same data.
+    Deflate,
+}
+
+impl Coding {
+    /// Return the coding's name, as used in `Content-Encoding`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+
+    /// Return `data` compressed with this coding.
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Coding::Gzip => compress(data),
+            Coding::Deflate => zlib(data),
+        }
+    }
+}
+
+impl std::fmt::Display for Coding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/**
+Which response bodies to compress, and how.
+
+Compressing a body a few hundred bytes long saves less than it costs (a
+gzip stream has 18 bytes of overhead on its own), and images, video,
+archives, and fonts are already compressed, so the default policy only
+compresses bodies of at least 1024 bytes, of textual types: all `text/`,
+JSON, JavaScript, XML (and `+json` and `+xml` types, like SVG and Atom),
+and WebAssembly. It prefers `gzip` to `deflate`.
+
+```rust
+# use dumb_cgi::gzip::{Coding, CompressionPolicy};
+let policy = CompressionPolicy::new()
+    .with_min_size(256)
+    .with_type("application/x-ndjson")
+    .with_codings([Coding::Deflate, Coding::Gzip]);
+
+assert!(policy.allows("text/html; charset=utf-8", 300));
+assert!(policy.allows("application/x-ndjson", 300));
+assert!(policy.allows("image/svg+xml", 300));
+assert!(!policy.allows("image/png", 300_000));
+assert!(!policy.allows("text/plain", 100));
+
+assert_eq!(policy.choose("gzip, deflate"), Some(Coding::Deflate));
+assert_eq!(policy.choose("gzip, deflate;q=0"), Some(Coding::Gzip));
+assert_eq!(policy.choose("br"), None);
+```
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionPolicy {
+    min_size: usize,
+    types: Vec<String>,
+    codings: Vec<Coding>,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> CompressionPolicy {
+        let types = [
+            "text/",
+            "application/json",
+            "application/javascript",
+            "application/xml",
+            "application/xhtml+xml",
+            "application/wasm",
+            "+json",
+            "+xml",
+        ];
+        CompressionPolicy {
+            min_size: 1024,
+            types: types.iter().map(|&t| t.to_owned()).collect(),
+            codings: vec![Coding::Gzip, Coding::Deflate],
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Return the default policy (see above).
+    pub fn new() -> CompressionPolicy {
+        CompressionPolicy::default()
+    }
+
+    /// Builder-pattern method for setting the smallest body (in bytes)
+    /// worth compressing.
+    pub fn with_min_size(self, bytes: usize) -> CompressionPolicy {
+        let mut new = self;
+        new.min_size = bytes;
+        new
+    }
+
+    /**
+    Builder-pattern method for adding a content type to compress. A type
+    ending in `/` (like `text/`) covers every type under it, and one
+    starting with `+` (like `+json`) every type with that suffix.
+    */
+    pub fn with_type<T: Into<String>>(self, content_type: T) -> CompressionPolicy {
+        let mut new = self;
+        new.types.push(content_type.into().to_ascii_lowercase());
+        new
+    }
+
+    /// Builder-pattern method for replacing the content types to compress
+    /// (written as for `.with_type()`).
+    pub fn with_types<I, T>(self, types: I) -> CompressionPolicy
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut new = self;
+        new.types = types
+            .into_iter()
+            .map(|t| t.into().to_ascii_lowercase())
+            .collect();
+        new
+    }
+
+    /// Builder-pattern method for setting the codings to use, most
+    /// preferred first. With none, nothing is compressed.
+    pub fn with_codings<I: IntoIterator<Item = Coding>>(self, codings: I) -> CompressionPolicy {
+        let mut new = self;
+        new.codings = codings.into_iter().collect();
+        new
+    }
+
+    /// Return whether a body of type `content_type`, `len` bytes long,
+    /// should be compressed.
+    pub fn allows(&self, content_type: &str, len: usize) -> bool {
+        if len < self.min_size || self.codings.is_empty() {
+            return false;
+        }
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        self.types.iter().any(|t| {
+            if t.ends_with('/') {
+                essence.starts_with(t.as_str())
+            } else if t.starts_with('+') {
+                essence.ends_with(t.as_str())
+            } else {
+                essence == *t
+            }
+        })
+    }
+
+    /**
+    Return the most preferred of the policy's codings that the
+    `Accept-Encoding` header value `accept_encoding` allows, if any.
+    (The policy's order is used rather than the client's q-values; every
+    browser asks for all of these equally anyway.)
+    */
+    pub fn choose(&self, accept_encoding: &str) -> Option<Coding> {
+        self.codings
+            .iter()
+            .copied()
+            .find(|c| quality(accept_encoding, c.name()).is_some_and(|q| q > 0.0))
+    }
+}
@@ -0,0 +1,435 @@
+/*!
+Small, dependency-free implementations of the hash functions (and the
+constructions built on them) that other parts of this crate need.
+
+These are straight-forward transcriptions of the relevant specifications;
+they are not particularly fast, and have not been hardened against
+side-channel attacks beyond using a constant-time comparison where it
+matters. They are exposed publicly mainly because they're handy to have
+around in a CGI program.
+*/
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const SHA256_H: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Size (in bytes) of a SHA-256 digest.
+pub const SHA256_LEN: usize = 32;
+
+/// Size (in bytes) of the blocks SHA-256 (and HMAC-SHA-256) operate on.
+const SHA256_BLOCK: usize = 64;
+
+/**
+An incremental SHA-256 hasher.
+
+```rust
+# use dumb_cgi::hash::{Sha256, sha256};
+let mut h = Sha256::new();
+h.update(b"ab");
+h.update(b"c");
+assert_eq!(h.finish(), sha256(b"abc"));
+```
+*/
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Sha256::new()
+    }
+}
+
+impl Sha256 {
+    /// Create a new hasher with no data fed into it.
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: SHA256_H,
+            buffer: Vec::with_capacity(SHA256_BLOCK),
+            length: 0,
+        }
+    }
+
+    /// Feed more bytes into the hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        let mut data = data;
+
+        if !self.buffer.is_empty() {
+            let wanted = SHA256_BLOCK - self.buffer.len();
+            let n = wanted.min(data.len());
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buffer.len() == SHA256_BLOCK {
+                let block = std::mem::take(&mut self.buffer);
+                self.compress(&block);
+                self.buffer = block;
+                self.buffer.clear();
+            }
+        }
+
+        let mut chunks = data.chunks_exact(SHA256_BLOCK);
+        for block in chunks.by_ref() {
+            self.compress(block);
+        }
+        self.buffer.extend_from_slice(chunks.remainder());
+    }
+
+    /// Consume the hasher and return the digest of all the data fed into it.
+    pub fn finish(mut self) -> [u8; SHA256_LEN] {
+        let bit_len = self.length.wrapping_mul(8);
+        let mut tail = std::mem::take(&mut self.buffer);
+        tail.push(0x80);
+        while tail.len() % SHA256_BLOCK != 56 {
+            tail.push(0);
+        }
+        tail.extend_from_slice(&bit_len.to_be_bytes());
+        for block in tail.chunks_exact(SHA256_BLOCK) {
+            self.compress(block);
+        }
+
+        let mut out = [0u8; SHA256_LEN];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (n, chunk) in block.chunks_exact(4).enumerate() {
+            w[n] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for n in 16..64 {
+            let s0 = w[n - 15].rotate_right(7) ^ w[n - 15].rotate_right(18) ^ (w[n - 15] >> 3);
+            let s1 = w[n - 2].rotate_right(17) ^ w[n - 2].rotate_right(19) ^ (w[n - 2] >> 10);
+            w[n] = w[n - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[n - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for n in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[n])
+                .wrapping_add(w[n]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+/**
+Return the SHA-256 digest of `data`.
+
+```rust
+# use dumb_cgi::hash::{sha256, to_hex};
+assert_eq!(
+    to_hex(&sha256(b"abc")),
+    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+);
+```
+*/
+pub fn sha256(data: &[u8]) -> [u8; SHA256_LEN] {
+    let mut h = Sha256::new();
+    h.update(data);
+    h.finish()
+}
+
+/**
+Return the HMAC-SHA-256 (as per
+[RFC 2104](https://www.rfc-editor.org/rfc/rfc2104)) of `data` using `key`.
+*/
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; SHA256_LEN] {
+    let mut block_key = [0u8; SHA256_BLOCK];
+    if key.len() > SHA256_BLOCK {
+        block_key[..SHA256_LEN].copy_from_slice(&sha256(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(&block_key.map(|b| b ^ 0x36));
+    inner.update(data);
+    let inner = inner.finish();
+
+    let mut outer = Sha256::new();
+    outer.update(&block_key.map(|b| b ^ 0x5c));
+    outer.update(&inner);
+    outer.finish()
+}
+
+/**
+Derive `out.len()` bytes of key material from `password` and `salt` using
+PBKDF2 (as per [RFC 8018](https://www.rfc-editor.org/rfc/rfc8018)) with
+HMAC-SHA-256 as the pseudorandom function.
+*/
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) {
+    for (n, chunk) in out.chunks_mut(SHA256_LEN).enumerate() {
+        let mut msg = Vec::with_capacity(salt.len() + 4);
+        msg.extend_from_slice(salt);
+        msg.extend_from_slice(&(n as u32 + 1).to_be_bytes());
+
+        let mut u = hmac_sha256(password, &msg);
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac_sha256(password, &u);
+            for (t, u) in t.iter_mut().zip(u.iter()) {
+                *t ^= u;
+            }
+        }
+        chunk.copy_from_slice(&t[..chunk.len()]);
+    }
+}
+
+/*
+Apply Merkle–Damgård padding (as used by MD5, SHA-1, and SHA-256) to
+`data`, with the bit length appended either big- or little-endian.
+*/
+fn md_pad(data: &[u8], big_endian: bool) -> Vec<u8> {
+    let bit_len = (data.len() as u64).wrapping_mul(8);
+    let mut v = Vec::with_capacity(data.len() + 72);
+    v.extend_from_slice(data);
+    v.push(0x80);
+    while v.len() % 64 != 56 {
+        v.push(0);
+    }
+    if big_endian {
+        v.extend_from_slice(&bit_len.to_be_bytes());
+    } else {
+        v.extend_from_slice(&bit_len.to_le_bytes());
+    }
+    v
+}
+
+/// Size (in bytes) of a SHA-1 digest.
+pub const SHA1_LEN: usize = 20;
+
+/**
+Return the SHA-1 digest of `data`.
+
+SHA-1 is no longer considered collision-resistant; it's here because some
+older formats (like `{SHA}` htpasswd entries and TOTP codes) require it.
+
+```rust
+# use dumb_cgi::hash::{sha1, to_hex};
+assert_eq!(to_hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+```
+*/
+pub fn sha1(data: &[u8]) -> [u8; SHA1_LEN] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    for block in md_pad(data, true).chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (n, chunk) in block.chunks_exact(4).enumerate() {
+            w[n] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for n in 16..80 {
+            w[n] = (w[n - 3] ^ w[n - 8] ^ w[n - 14] ^ w[n - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (n, w) in w.iter().enumerate() {
+            let (f, k) = match n {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; SHA1_LEN];
+    for (chunk, word) in out.chunks_exact_mut(4).zip(state.iter()) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+/**
+Return the HMAC-SHA-1 (as per
+[RFC 2104](https://www.rfc-editor.org/rfc/rfc2104)) of `data` using `key`.
+*/
+pub fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; SHA1_LEN] {
+    let mut block_key = [0u8; 64];
+    if key.len() > block_key.len() {
+        block_key[..SHA1_LEN].copy_from_slice(&sha1(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = block_key.map(|b| b ^ 0x36).to_vec();
+    inner.extend_from_slice(data);
+    let mut outer = block_key.map(|b| b ^ 0x5c).to_vec();
+    outer.extend_from_slice(&sha1(&inner));
+    sha1(&outer)
+}
+
+const MD5_K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+/// Size (in bytes) of an MD5 digest.
+pub const MD5_LEN: usize = 16;
+
+/**
+Return the MD5 digest of `data`.
+
+MD5 is thoroughly broken as a cryptographic hash; it's here because some
+older password file formats (like `$apr1$` htpasswd entries) require it.
+
+```rust
+# use dumb_cgi::hash::{md5, to_hex};
+assert_eq!(to_hex(&md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+```
+*/
+pub fn md5(data: &[u8]) -> [u8; MD5_LEN] {
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    for block in md_pad(data, false).chunks_exact(64) {
+        let mut m = [0u32; 16];
+        for (n, chunk) in block.chunks_exact(4).enumerate() {
+            m[n] = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = state;
+        for n in 0..64 {
+            let (f, g) = match n / 16 {
+                0 => ((b & c) | (!b & d), n),
+                1 => ((d & b) | (!d & c), (5 * n + 1) % 16),
+                2 => (b ^ c ^ d, (3 * n + 5) % 16),
+                _ => (c ^ (b | !d), (7 * n) % 16),
+            };
+            let shift = MD5_SHIFTS[(n / 16) * 4 + n % 4];
+            let f = f
+                .wrapping_add(a)
+                .wrapping_add(MD5_K[n])
+                .wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(shift));
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; MD5_LEN];
+    for (chunk, word) in out.chunks_exact_mut(4).zip(state.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+/**
+Compare two byte slices in time that depends only on their lengths (and not
+on where they first differ).
+
+Use this when comparing secrets (like password hashes or message
+authentication codes) to values supplied by the user agent.
+*/
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/*
+Fill `buf` from the operating system's random number source
+(`/dev/urandom`), for the salts, ids, and tokens other modules make.
+*/
+pub(crate) fn fill_random(buf: &mut [u8]) -> Result<(), crate::Error> {
+    use std::io::Read;
+
+    let res = std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(buf));
+    res.map_err(|e| crate::Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!("Unable to read random bytes: {}", &e),
+    })
+}
+
+/// Return a lower-case hexadecimal representation of `bytes`.
+pub fn to_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8] = b"0123456789abcdef";
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes.iter() {
+        s.push(DIGITS[(b >> 4) as usize] as char);
+        s.push(DIGITS[(b & 0xf) as usize] as char);
+    }
+    s
+}
+
+/// Attempt to decode a string of hexadecimal digits into bytes.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = hex.as_bytes();
+    let mut v = Vec::with_capacity(bytes.len() / 2);
+    for pair in bytes.chunks_exact(2) {
+        let hi = (pair[0] as char).to_digit(16)?;
+        let lo = (pair[1] as char).to_digit(16)?;
+        v.push((hi * 16 + lo) as u8);
+    }
+    Some(v)
+}
@@ -0,0 +1,715 @@
+/*!
+Message catalogs for translating user-facing text, without gettext.
+
+Catalogs are read from a subset of the gettext `.po` format: `msgid`,
+`msgid_plural`, `msgstr`, and `msgstr[n]` entries (with multi-line strings
+and the usual backslash escapes). Comments, `msgctxt` lines, and the
+header entry are ignored, and untranslated (empty) entries fall back to
+the original text. Plural forms are chosen using built-in rules for the
+catalog's language rather than the `Plural-Forms` header.
+
+A `Catalogs` holds one `Catalog` per language and picks the best one for
+a request's `Accept-Language` header, and `Catalog::locale()` gives the
+matching conventions for formatting numbers and dates.
+
+```rust
+use dumb_cgi::{i18n::{Catalog, Catalogs}, t, testing::MockRequest};
+
+let de = Catalog::parse("de", r#"
+msgid "Hello, {name}!"
+msgstr "Hallo, {name}!"
+
+msgid "one file"
+msgid_plural "{n} files"
+msgstr[0] "eine Datei"
+msgstr[1] "{n} Dateien"
+"#).unwrap();
+
+let mut catalogs = Catalogs::new("en");
+catalogs.insert(de);
+
+let req = MockRequest::get("/")
+    .header("Accept-Language", "de-CH, de;q=0.9, en;q=0.5")
+    .build()
+    .unwrap();
+let catalog = catalogs.for_request(&req);
+
+assert_eq!(catalog.language(), "de");
+assert_eq!(t!(catalog, "Hello, {name}!", name = "Dan"), "Hallo, Dan!");
+assert_eq!(
+    t!(catalog, "one file", "{n} files", 3, n = 3),
+    "3 Dateien"
+);
+// Text with no translation comes back unchanged.
+assert_eq!(t!(catalog, "Goodbye"), "Goodbye");
+```
+*/
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::{Error, Request};
+
+fn catalog_error(details: String) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details,
+    }
+}
+
+/*
+Lower-case a language tag and use hyphens as separators, so `pt_BR` and
+`pt-br` are the same language.
+*/
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().replace('_', "-").to_lowercase()
+}
+
+fn primary_subtag(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or(tag)
+}
+
+/**
+Return which plural form (as an index into a `.po` entry's `msgstr[n]`
+values) the language `lang` uses for a count of `n`.
+
+Rules are built in for common languages; anything unrecognized is assumed
+to be English-like (singular for 1, plural otherwise).
+
+```rust
+# use dumb_cgi::i18n::plural_index;
+assert_eq!(plural_index("en", 1), 0);
+assert_eq!(plural_index("en", 0), 1);
+assert_eq!(plural_index("fr", 0), 0);
+assert_eq!(plural_index("ja", 5), 0);
+assert_eq!(plural_index("ru", 21), 0);
+assert_eq!(plural_index("ru", 23), 1);
+assert_eq!(plural_index("ru", 11), 2);
+```
+*/
+pub fn plural_index(lang: &str, n: u64) -> usize {
+    let lang = normalize_tag(lang);
+    let few = |n: u64| (2..=4).contains(&(n % 10)) && !(12..=14).contains(&(n % 100));
+    match lang.as_str() {
+        "pt-br" => (n > 1) as usize,
+        _ => match primary_subtag(&lang) {
+            "ja" | "zh" | "ko" | "vi" | "th" | "id" | "ms" => 0,
+            "fr" => (n > 1) as usize,
+            "ru" | "uk" | "be" | "sr" | "hr" | "bs" => {
+                if n % 10 == 1 && n % 100 != 11 {
+                    0
+                } else if few(n) {
+                    1
+                } else {
+                    2
+                }
+            }
+            "pl" => {
+                if n == 1 {
+                    0
+                } else if few(n) {
+                    1
+                } else {
+                    2
+                }
+            }
+            "cs" | "sk" => match n {
+                1 => 0,
+                2..=4 => 1,
+                _ => 2,
+            },
+            _ => (n != 1) as usize,
+        },
+    }
+}
+
+/**
+Replace each `{name}` in `template` with the corresponding value from
+`args`. Placeholders with no matching argument are left alone, and `{{`
+and `}}` produce literal braces.
+
+```rust
+# use dumb_cgi::i18n::interpolate;
+assert_eq!(
+    interpolate("{who} has {n} {{new}} messages", &[("who", &"Dan"), ("n", &3)]),
+    "Dan has 3 {new} messages"
+);
+```
+*/
+pub fn interpolate(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(idx) = rest.find(['{', '}']) {
+        out.push_str(&rest[..idx]);
+        let tail = &rest[idx..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        if tail.starts_with('{') {
+            if let Some(end) = tail.find('}') {
+                let name = &tail[1..end];
+                if let Some((_, value)) = args.iter().find(|(k, _)| *k == name) {
+                    out.push_str(&value.to_string());
+                    rest = &tail[end + 1..];
+                    continue;
+                }
+            }
+        }
+        out.push_str(&tail[..1]);
+        rest = &tail[1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/*
+Undo the C-style escapes allowed in `.po` strings, given the contents of
+a quoted string (without the quotes).
+*/
+fn unescape_po(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/*
+Pull the contents out of a `"quoted string"`.
+*/
+fn po_string(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        Some(unescape_po(&s[1..s.len() - 1]))
+    } else {
+        None
+    }
+}
+
+/*
+Which part of a `.po` entry a continuation line belongs to.
+*/
+#[derive(Clone, Copy)]
+enum Field {
+    None,
+    Ignored,
+    Id,
+    IdPlural,
+    Str(usize),
+}
+
+#[derive(Default)]
+struct Entry {
+    id: Option<String>,
+    id_plural: Option<String>,
+    strs: Vec<String>,
+}
+
+/**
+The translated messages for a single language.
+*/
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    lang: String,
+    messages: HashMap<String, Vec<String>>,
+}
+
+impl Catalog {
+    /// Create an empty catalog for the language `lang` (e.g. `"de"` or `"pt-BR"`).
+    pub fn new(lang: &str) -> Catalog {
+        Catalog {
+            lang: normalize_tag(lang),
+            messages: HashMap::new(),
+        }
+    }
+
+    /**
+    Parse the contents of a `.po` file as the catalog for `lang`.
+
+    Returns an error describing the first line it can't make sense of.
+    */
+    pub fn parse(lang: &str, text: &str) -> Result<Catalog, Error> {
+        let mut catalog = Catalog::new(lang);
+        let mut entry = Entry::default();
+        let mut field = Field::None;
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let bad_line = || {
+                catalog_error(format!(
+                    "Unable to parse line {} of catalog for {}",
+                    n + 1,
+                    lang
+                ))
+            };
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('"') {
+                let s = po_string(line).ok_or_else(bad_line)?;
+                match field {
+                    Field::Id => entry.id.get_or_insert_with(String::new).push_str(&s),
+                    Field::IdPlural => entry.id_plural.get_or_insert_with(String::new).push_str(&s),
+                    Field::Str(idx) => entry.strs[idx].push_str(&s),
+                    Field::Ignored => {}
+                    Field::None => return Err(bad_line()),
+                }
+                continue;
+            }
+
+            let (keyword, rest) = line.split_once(char::is_whitespace).ok_or_else(bad_line)?;
+            let s = po_string(rest).ok_or_else(bad_line)?;
+            field = match keyword {
+                "msgctxt" => Field::Ignored,
+                "msgid" => {
+                    catalog.add_entry(std::mem::take(&mut entry));
+                    entry.id = Some(s);
+                    Field::Id
+                }
+                "msgid_plural" => {
+                    entry.id_plural = Some(s);
+                    Field::IdPlural
+                }
+                "msgstr" => {
+                    entry.strs = vec![s];
+                    Field::Str(0)
+                }
+                _ => {
+                    let idx: usize = keyword
+                        .strip_prefix("msgstr[")
+                        .and_then(|k| k.strip_suffix(']'))
+                        .and_then(|k| k.parse().ok())
+                        .ok_or_else(bad_line)?;
+                    if entry.strs.len() <= idx {
+                        entry.strs.resize(idx + 1, String::new());
+                    }
+                    entry.strs[idx] = s;
+                    Field::Str(idx)
+                }
+            };
+        }
+        catalog.add_entry(entry);
+
+        Ok(catalog)
+    }
+
+    /// Read and parse the `.po` file at `path` as the catalog for `lang`.
+    pub fn load<P: AsRef<Path>>(lang: &str, path: P) -> Result<Catalog, Error> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(text) => Catalog::parse(lang, &text),
+            Err(e) => Err(catalog_error(format!(
+                "Unable to read catalog file {}: {}",
+                path.display(),
+                &e
+            ))),
+        }
+    }
+
+    fn add_entry(&mut self, entry: Entry) {
+        if let Some(id) = entry.id {
+            // Skip the header entry and anything untranslated.
+            if !id.is_empty() && entry.strs.iter().any(|s| !s.is_empty()) {
+                self.messages.insert(id, entry.strs);
+            }
+        }
+    }
+
+    /// The (normalized) language tag of this catalog.
+    pub fn language(&self) -> &str {
+        &self.lang
+    }
+
+    /// Add (or replace) the translation of `key`.
+    pub fn insert<K, V>(&mut self, key: K, translation: V)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.messages.insert(key.into(), vec![translation.into()]);
+    }
+
+    /**
+    Add (or replace) the plural forms of `key`, in the order this
+    language's rules number them (see `plural_index()`).
+    */
+    pub fn insert_plural<K: Into<String>>(&mut self, key: K, forms: Vec<String>) {
+        self.messages.insert(key.into(), forms);
+    }
+
+    /// Return the translation of `key`, or `key` itself if there isn't one.
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        match self.messages.get(key).and_then(|forms| forms.first()) {
+            Some(s) if !s.is_empty() => s,
+            _ => key,
+        }
+    }
+
+    /**
+    Return the appropriate form of a message for a count of `n`, or
+    `singular` or `plural` (English rules) if there is no translation.
+    */
+    pub fn plural<'a>(&'a self, singular: &'a str, plural: &'a str, n: u64) -> &'a str {
+        if let Some(forms) = self.messages.get(singular) {
+            let idx = plural_index(&self.lang, n).min(forms.len().saturating_sub(1));
+            if let Some(s) = forms.get(idx).filter(|s| !s.is_empty()) {
+                return s;
+            }
+        }
+        if n == 1 {
+            singular
+        } else {
+            plural
+        }
+    }
+
+    /// Translate `key`, then fill in its `{placeholders}` from `args`.
+    pub fn format(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        interpolate(self.get(key), args)
+    }
+
+    /// Return the number and date formatting conventions for this catalog's language.
+    pub fn locale(&self) -> Locale {
+        Locale::for_language(&self.lang)
+    }
+}
+
+/**
+Translate a message using a `Catalog`, filling in any `{placeholders}`.
+
+```rust
+# use dumb_cgi::{i18n::Catalog, t};
+let mut catalog = Catalog::new("de");
+catalog.insert("Hello, {name}!", "Hallo, {name}!");
+
+assert_eq!(t!(catalog, "Hello, {name}!", name = "Dan"), "Hallo, Dan!");
+```
+
+Pluralized messages take the singular and plural text and the count:
+
+```rust
+# use dumb_cgi::{i18n::Catalog, t};
+let catalog = Catalog::new("en");
+assert_eq!(t!(catalog, "one file", "{n} files", 1), "one file");
+assert_eq!(t!(catalog, "one file", "{n} files", 2, n = 2), "2 files");
+```
+*/
+#[macro_export]
+macro_rules! t {
+    ($catalog:expr, $key:expr) => {
+        $catalog.get($key).to_owned()
+    };
+    ($catalog:expr, $key:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $catalog.format(
+            $key,
+            &[$((stringify!($name), &$value as &dyn ::std::fmt::Display)),+],
+        )
+    };
+    ($catalog:expr, $singular:expr, $plural:expr, $n:expr) => {
+        $catalog.plural($singular, $plural, $n as u64).to_owned()
+    };
+    ($catalog:expr, $singular:expr, $plural:expr, $n:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::interpolate(
+            $catalog.plural($singular, $plural, $n as u64),
+            &[$((stringify!($name), &$value as &dyn ::std::fmt::Display)),+],
+        )
+    };
+}
+
+/**
+A set of catalogs, one per language, with a default language to fall
+back on.
+*/
+#[derive(Debug, Clone)]
+pub struct Catalogs {
+    default: String,
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl Catalogs {
+    /**
+    Create a set containing only an empty catalog for `default_lang` (the
+    language the untranslated text is written in).
+    */
+    pub fn new(default_lang: &str) -> Catalogs {
+        let default = Catalog::new(default_lang);
+        let mut catalogs = HashMap::new();
+        let lang = default.lang.clone();
+        catalogs.insert(lang.clone(), default);
+        Catalogs {
+            default: lang,
+            catalogs,
+        }
+    }
+
+    /**
+    Load every `<lang>.po` file in the directory `dir` (e.g. `de.po`,
+    `pt_BR.po`).
+    */
+    pub fn load_dir<P: AsRef<Path>>(default_lang: &str, dir: P) -> Result<Catalogs, Error> {
+        let dir = dir.as_ref();
+        let read_error = |e: std::io::Error| {
+            catalog_error(format!(
+                "Unable to read catalog directory {}: {}",
+                dir.display(),
+                &e
+            ))
+        };
+
+        let mut catalogs = Catalogs::new(default_lang);
+        for ent in std::fs::read_dir(dir).map_err(read_error)? {
+            let path = ent.map_err(read_error)?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("po") {
+                continue;
+            }
+            if let Some(lang) = path.file_stem().and_then(|s| s.to_str()) {
+                catalogs.insert(Catalog::load(lang, &path)?);
+            }
+        }
+        Ok(catalogs)
+    }
+
+    /// Add (or replace) the catalog for its language.
+    pub fn insert(&mut self, catalog: Catalog) {
+        self.catalogs.insert(catalog.lang.clone(), catalog);
+    }
+
+    /// Return the catalog for exactly `lang`, if there is one.
+    pub fn get(&self, lang: &str) -> Option<&Catalog> {
+        self.catalogs.get(&normalize_tag(lang))
+    }
+
+    /// Return the default language's catalog.
+    pub fn default_catalog(&self) -> &Catalog {
+        &self.catalogs[&self.default]
+    }
+
+    /**
+    Choose the best catalog for the value of an `Accept-Language` header.
+
+    Languages are tried in order of preference (q-value); each matches a
+    catalog for exactly that tag, or failing that a catalog for its
+    primary language (so `de-CH` will use a `de` catalog), or failing
+    that any regional variant of it (so `pt` will use a `pt-BR` catalog).
+    If nothing matches, the default catalog is returned.
+    */
+    pub fn negotiate(&self, accept_language: &str) -> &Catalog {
+        let mut prefs: Vec<(String, f32)> = accept_language
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let tag = normalize_tag(parts.next()?);
+                let q = parts
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty() && q > 0.0).then_some((tag, q))
+            })
+            .collect();
+        // A stable sort keeps the header's order among equal q-values.
+        prefs.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut langs: Vec<&String> = self.catalogs.keys().collect();
+        langs.sort();
+
+        for (tag, _) in prefs.iter() {
+            if tag == "*" {
+                return self.default_catalog();
+            }
+            if let Some(c) = self.catalogs.get(tag) {
+                return c;
+            }
+            let primary = primary_subtag(tag);
+            if let Some(c) = self.catalogs.get(primary) {
+                return c;
+            }
+            if let Some(lang) = langs.iter().find(|l| primary_subtag(l) == primary) {
+                return &self.catalogs[*lang];
+            }
+        }
+        self.default_catalog()
+    }
+
+    /// Choose the best catalog for `req`'s `Accept-Language` header.
+    pub fn for_request(&self, req: &Request) -> &Catalog {
+        match req.header("accept-language") {
+            Some(accept) => self.negotiate(accept),
+            None => self.default_catalog(),
+        }
+    }
+}
+
+/**
+The order of the parts of a numeric date.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateOrder {
+    /// Day, month, year (`31/12/2024`).
+    Dmy,
+    /// Month, day, year (`12/31/2024`).
+    Mdy,
+    /// Year, month, day (`2024-12-31`).
+    Ymd,
+}
+
+/**
+Conventions for writing numbers, dates, and times in a language.
+
+This covers the simple cases (separators and ordering) for common
+languages; it is not a replacement for full CLDR data.
+
+```rust
+use dumb_cgi::i18n::Locale;
+
+let de = Locale::for_language("de-DE");
+assert_eq!(de.format_decimal(-1234567.891, 2), "-1.234.567,89");
+assert_eq!(de.format_date(2024, 12, 31), "31.12.2024");
+
+let us = Locale::for_language("en-US");
+assert_eq!(us.format_integer(1234567), "1,234,567");
+assert_eq!(us.format_date(2024, 12, 31), "12/31/2024");
+assert_eq!(us.format_time(13, 5), "1:05 PM");
+```
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale {
+    /// Separates the integer and fractional parts of a number.
+    pub decimal_separator: char,
+    /// Separates groups of three digits (if digits are grouped at all).
+    pub group_separator: Option<char>,
+    /// The order of the day, month, and year in a numeric date.
+    pub date_order: DateOrder,
+    /// Separates the parts of a numeric date.
+    pub date_separator: char,
+    /// Whether times use a 12-hour clock with AM/PM.
+    pub twelve_hour: bool,
+}
+
+impl Locale {
+    /**
+    Return the conventions for the language tag `lang` (e.g. `"fr"` or
+    `"en-GB"`). Unrecognized languages get US English conventions.
+    */
+    pub fn for_language(lang: &str) -> Locale {
+        use DateOrder::*;
+
+        let lang = normalize_tag(lang);
+        let (decimal, group, order, date_sep, twelve_hour) = match lang.as_str() {
+            "en-gb" | "en-ie" | "en-au" | "en-nz" | "en-in" => ('.', Some(','), Dmy, '/', false),
+            "en-ca" | "fr-ca" => ('.', Some(','), Ymd, '-', false),
+            "de-ch" => ('.', Some('\''), Dmy, '.', false),
+            _ => match primary_subtag(&lang) {
+                "de" | "da" | "nb" | "no" | "fi" | "tr" => (',', Some('.'), Dmy, '.', false),
+                "fr" => (',', Some('\u{202f}'), Dmy, '/', false),
+                "es" | "it" | "pt" | "el" => (',', Some('.'), Dmy, '/', false),
+                "nl" => (',', Some('.'), Dmy, '-', false),
+                "ru" | "uk" | "be" | "pl" | "cs" | "sk" => (',', Some('\u{a0}'), Dmy, '.', false),
+                "sv" | "lt" => (',', Some('\u{a0}'), Ymd, '-', false),
+                "ja" | "zh" => ('.', Some(','), Ymd, '/', false),
+                "ko" => ('.', Some(','), Ymd, '.', true),
+                _ => ('.', Some(','), Mdy, '/', true),
+            },
+        };
+
+        Locale {
+            decimal_separator: decimal,
+            group_separator: group,
+            date_order: order,
+            date_separator: date_sep,
+            twelve_hour,
+        }
+    }
+
+    /*
+    Insert group separators into a string of ASCII digits.
+    */
+    fn group(&self, digits: &str) -> String {
+        let sep = match self.group_separator {
+            Some(sep) => sep,
+            None => return digits.to_owned(),
+        };
+        let mut out = String::with_capacity(digits.len() + digits.len() / 3 * sep.len_utf8());
+        for (n, c) in digits.chars().enumerate() {
+            if n > 0 && (digits.len() - n).is_multiple_of(3) {
+                out.push(sep);
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    /// Format an integer with digit grouping.
+    pub fn format_integer(&self, n: i64) -> String {
+        let digits = n.unsigned_abs().to_string();
+        let sign = if n < 0 { "-" } else { "" };
+        format!("{}{}", sign, self.group(&digits))
+    }
+
+    /**
+    Format a number rounded to `places` digits after the decimal point,
+    with digit grouping. Infinities and NaN are written as Rust writes
+    them.
+    */
+    pub fn format_decimal(&self, n: f64, places: usize) -> String {
+        if !n.is_finite() {
+            return n.to_string();
+        }
+        let text = format!("{:.*}", places, n.abs());
+        let (int_part, frac_part) = match text.split_once('.') {
+            Some((i, f)) => (i, Some(f)),
+            None => (text.as_str(), None),
+        };
+        let negative = n < 0.0 && text.bytes().any(|b| matches!(b, b'1'..=b'9'));
+
+        let mut out = String::with_capacity(text.len() + text.len() / 3);
+        if negative {
+            out.push('-');
+        }
+        out.push_str(&self.group(int_part));
+        if let Some(frac) = frac_part {
+            out.push(self.decimal_separator);
+            out.push_str(frac);
+        }
+        out
+    }
+
+    /// Format a numeric date (`month` and `day` starting at 1).
+    pub fn format_date(&self, year: i32, month: u32, day: u32) -> String {
+        let sep = self.date_separator;
+        match self.date_order {
+            DateOrder::Dmy => format!("{:02}{}{:02}{}{}", day, sep, month, sep, year),
+            DateOrder::Mdy => format!("{}{}{}{}{}", month, sep, day, sep, year),
+            DateOrder::Ymd => format!("{}{}{:02}{}{:02}", year, sep, month, sep, day),
+        }
+    }
+
+    /// Format a time of day (`hour` from 0 to 23).
+    pub fn format_time(&self, hour: u32, minute: u32) -> String {
+        if self.twelve_hour {
+            let suffix = if hour < 12 { "AM" } else { "PM" };
+            let hour = match hour % 12 {
+                0 => 12,
+                h => h,
+            };
+            format!("{}:{:02} {}", hour, minute, suffix)
+        } else {
+            format!("{:02}:{:02}", hour, minute)
+        }
+    }
+}
@@ -0,0 +1,331 @@
+/*!
+Writing JSON responses.
+
+A `Json` is a JSON value, built from Rust values with `From`/`.into()`
+(or the `.with()` builder for objects), and written out with its
+`Display` implementation or `.to_string_with()`.
+
+```rust
+use dumb_cgi::json::Json;
+use dumb_cgi::Response;
+
+let user = Json::object()
+    .with("name", "Dan")
+    .with("admin", false)
+    .with("groups", vec![Json::from("staff"), Json::from("wheel")]);
+assert_eq!(
+    user.to_string(),
+    r#"{"name":"Dan","admin":false,"groups":["staff","wheel"]}"#
+);
+
+let r = Response::new(200).with_json(&user);
+assert_eq!(r.get_content_type(), "application/json");
+```
+
+Object fields are written in the order they were added, unless the
+`JsonFormat` says to sort them. Sorting makes the same data always come
+out as the same bytes, even when it came from a `HashMap` (whose order
+changes from one run of the program to the next), which keeps ETags (and
+so caches) from changing when the data hasn't:
+
+```rust
+use std::collections::HashMap;
+use dumb_cgi::json::{Json, JsonFormat};
+
+let counts: HashMap<String, i64> =
+    HashMap::from([("b".to_owned(), 2), ("c".to_owned(), 3), ("a".to_owned(), 1)]);
+let stable = JsonFormat::new().with_sorted_keys(true);
+assert_eq!(Json::from(counts).to_string_with(&stable), r#"{"a":1,"b":2,"c":3}"#);
+```
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::{Query, Request};
+
+/**
+A JSON value.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    /// `null`.
+    Null,
+    /// `true` or `false`.
+    Bool(bool),
+    /// A whole number (written without a decimal point).
+    Int(i64),
+    /// A number that might not be whole. Infinities and NaN (which JSON
+    /// can't represent) are written as `null`.
+    Float(f64),
+    /// A string.
+    String(String),
+    /// An array of values.
+    Array(Vec<Json>),
+    /// An object's fields, in order.
+    Object(Vec<(String, Json)>),
+}
+
+/**
+How to write a `Json` value out.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonFormat {
+    /// Whether to write object fields sorted by name, rather than in the
+    /// order they were added.
+    pub sorted_keys: bool,
+    /// Whether to write the value over several lines, indented, for
+    /// people to read, rather than as compactly as possible.
+    pub pretty: bool,
+}
+
+impl JsonFormat {
+    /// Return the default format: compact, with fields in order.
+    pub fn new() -> JsonFormat {
+        JsonFormat::default()
+    }
+
+    /// Builder-pattern method for setting whether object fields are
+    /// sorted by name.
+    pub fn with_sorted_keys(self, sorted_keys: bool) -> JsonFormat {
+        let mut new = self;
+        new.sorted_keys = sorted_keys;
+        new
+    }
+
+    /// Builder-pattern method for setting whether the value is written
+    /// indented, for people to read.
+    pub fn with_pretty(self, pretty: bool) -> JsonFormat {
+        let mut new = self;
+        new.pretty = pretty;
+        new
+    }
+
+    /**
+    Builder-pattern method for writing indented output if `req` asks for
+    it with a `pretty` query string field (`?pretty=1`, or `true`, `yes`,
+    `on`, or empty), and compact output otherwise, so someone looking at
+    an endpoint's output in a browser can ask for a readable version.
+
+    ```rust
+    # use dumb_cgi::json::{Json, JsonFormat};
+    # use dumb_cgi::testing::MockRequest;
+    let value = Json::object().with("ok", true);
+
+    let req = MockRequest::get("/status").query("pretty=1").build().unwrap();
+    let format = JsonFormat::new().for_request(&req);
+    assert_eq!(value.to_string_with(&format), "{\n  \"ok\": true\n}\n");
+
+    let req = MockRequest::get("/status").build().unwrap();
+    let format = JsonFormat::new().for_request(&req);
+    assert_eq!(value.to_string_with(&format), "{\"ok\":true}");
+    ```
+    */
+    pub fn for_request(self, req: &Request) -> JsonFormat {
+        let pretty = match req.query() {
+            Query::Some(fields) => fields.get("pretty").is_some_and(|v| {
+                matches!(
+                    v.to_ascii_lowercase().as_str(),
+                    "" | "1" | "true" | "yes" | "on"
+                )
+            }),
+            _ => false,
+        };
+        self.with_pretty(pretty)
+    }
+}
+
+impl Json {
+    /// Return an empty object.
+    pub fn object() -> Json {
+        Json::Object(Vec::new())
+    }
+
+    /**
+    Builder-pattern method for adding the field `name` to an object (or
+    replacing the value of one already there). Does nothing to values
+    that aren't objects.
+    */
+    pub fn with<N: Into<String>, V: Into<Json>>(self, name: N, value: V) -> Json {
+        let mut new = self;
+        if let Json::Object(fields) = &mut new {
+            let (name, value) = (name.into(), value.into());
+            match fields.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, v)) => *v = value,
+                None => fields.push((name, value)),
+            }
+        }
+        new
+    }
+
+    /// Return the value of the field `name`, if this is an object with one.
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Write the value out as `format` says.
+    pub fn to_string_with(&self, format: &JsonFormat) -> String {
+        let mut out = String::new();
+        write_value(&mut out, self, format, 0);
+        if format.pretty {
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_string_with(&JsonFormat::default()))
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // Writing to a `String` can't fail.
+            c if (c as u32) < 0x20 => _ = write!(out, "\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/*
+In pretty output, start a new line indented for `depth`.
+*/
+fn new_line(out: &mut String, format: &JsonFormat, depth: usize) {
+    if format.pretty {
+        out.push('\n');
+        for _ in 0..depth {
+            out.push_str("  ");
+        }
+    }
+}
+
+fn write_value(out: &mut String, value: &Json, format: &JsonFormat, depth: usize) {
+    match value {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Json::Int(n) => _ = write!(out, "{}", n),
+        Json::Float(x) if x.is_finite() => _ = write!(out, "{}", x),
+        Json::Float(_) => out.push_str("null"),
+        Json::String(s) => write_string(out, s),
+        Json::Array(items) if items.is_empty() => out.push_str("[]"),
+        Json::Array(items) => {
+            out.push('[');
+            for (n, item) in items.iter().enumerate() {
+                if n > 0 {
+                    out.push(',');
+                }
+                new_line(out, format, depth + 1);
+                write_value(out, item, format, depth + 1);
+            }
+            new_line(out, format, depth);
+            out.push(']');
+        }
+        Json::Object(fields) if fields.is_empty() => out.push_str("{}"),
+        Json::Object(fields) => {
+            let mut fields: Vec<&(String, Json)> = fields.iter().collect();
+            if format.sorted_keys {
+                fields.sort_by(|a, b| a.0.cmp(&b.0));
+            }
+            out.push('{');
+            for (n, (name, value)) in fields.into_iter().enumerate() {
+                if n > 0 {
+                    out.push(',');
+                }
+                new_line(out, format, depth + 1);
+                write_string(out, name);
+                out.push_str(if format.pretty { ": " } else { ":" });
+                write_value(out, value, format, depth + 1);
+            }
+            new_line(out, format, depth);
+            out.push('}');
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(n: i64) -> Json {
+        Json::Int(n)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Json {
+        Json::Int(n.into())
+    }
+}
+
+impl From<u32> for Json {
+    fn from(n: u32) -> Json {
+        Json::Int(n.into())
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        match i64::try_from(n) {
+            Ok(n) => Json::Int(n),
+            Err(_) => Json::Float(n as f64),
+        }
+    }
+}
+
+impl From<f64> for Json {
+    fn from(x: f64) -> Json {
+        Json::Float(x)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::String(s.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::String(s)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(items: Vec<T>) -> Json {
+        Json::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Json>> From<BTreeMap<String, T>> for Json {
+    fn from(map: BTreeMap<String, T>) -> Json {
+        Json::Object(map.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+impl<T: Into<Json>, S> From<HashMap<String, T, S>> for Json {
+    fn from(map: HashMap<String, T, S>) -> Json {
+        Json::Object(map.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
@@ -4,7 +4,7 @@ the web server to a CGI program into a single, easy-to-use struct.
 
 ```rust
 use std::io::Write;
-use dumb_cgi::{Request, Query, Body, EmptyResponse};
+use dumb_cgi::{Request, Query, Body, Response};
 
 // This example is wrapped in a main function that returns a
 // `std::io::Result<()>` in order to leverage the `?` to cut down
@@ -14,9 +14,9 @@ fn main() -> std::io::Result<()> {
     // Gather all request data from the environment and stdin.
     let req = Request::new().unwrap();
 
-    // Instantiate a new response object, and give it a `Content-type` so that
-    // the body can be written to.
-    let mut response = EmptyResponse::new(200)
+    // Instantiate a new response object, and give it a `Content-type` for
+    // the body we're about to write.
+    let mut response = Response::new(200)
         .with_content_type("text/plain");
 
     // Write info about the environment to the response body.
@@ -86,13 +86,15 @@ maintenance). In pursuit of these, some trade-offs have been made.
     (and the environment) that it needs. This is simpler to both use
     and maintain.
 
-  * `dumb_cgi` lossily converts everything except request bodies (and the
-    "body" portions of `multipart/form-data` body parts) into UTF-8.
-    This means not supporting certain strictly-compliant requests and
-    possibly not correctly receiving environment variables on some systems,
-    but is easier to both use and maintain. (If you _do_ need to deal
-    with non-UTF-8 environment variables or header values, `dumb_cgi` is
-    too dumb for your use case.)
+  * `dumb_cgi` hands out everything except request bodies (and the
+    "body" portions of `multipart/form-data` body parts) as UTF-8 text.
+    Environment variables are kept as the system passed them and only
+    converted when they're read; header values are converted as the
+    request is read. Anything that isn't valid UTF-8 is converted
+    lossily, and the request records a `Warning::NonUtf8Var` or
+    `Warning::NonUtf8Header` so you can tell it happened, but the
+    original bytes aren't available. This means not supporting certain
+    strictly-compliant requests, but is easier to both use and maintain.
 
   * `dumb_cgi`'s target is server-side CGI programs; it supports _reading_
     requests (not writing them), and _writing_ responses (not reading them).
@@ -105,6 +107,22 @@ pulls in the [`log`](https://crates.io/crates/log) and
 only for debugging `dumb_cgi` during its development. Consumers of this crate
 shouldn't need this feature.
 
+The `auth` feature enables the `auth` module, which provides password
+hashing, a user file reader, and cookie-based login and logout; the `totp`
+module, for one-time-password second factors; and the `lockout` module,
+for throttling password guessing. It adds no dependencies.
+
+The `compression` feature enables the `gzip` module, for compressing
+response bodies, and `Response::compressed_for()` and
+`Response::compressed_with()`, which compress a response when the client
+accepts it. It adds no dependencies.
+
+The `serde` feature pulls in [`serde`](https://crates.io/crates/serde)
+(through `serde_core`) and implements `Serialize` and `Deserialize` for
+`Request` (and `Error`), so a failing request can be dumped to disk in
+any serde format and replayed later; see `Request::to_capture()` for
+what's recorded.
+
 */
 use std::fmt::{Display, Formatter};
 
@@ -114,6 +132,63 @@ pub use request::*;
 mod response;
 pub use response::*;
 
+mod extensions;
+pub use extensions::*;
+
+mod status;
+pub use status::*;
+
+pub mod multipart;
+pub use multipart::{MultipartBuffer, MultipartReader, PartRange, PartReader, StreamedPart};
+
+pub mod assets;
+pub mod audit;
+pub mod base64;
+pub mod cache;
+mod cachefile;
+pub mod canonical;
+pub mod charset;
+pub mod config;
+pub mod cookie;
+pub mod cors;
+pub mod escape;
+pub mod filter;
+pub mod flags;
+pub mod flash;
+pub mod gc;
+pub mod hash;
+pub mod i18n;
+pub mod json;
+mod lockfile;
+pub mod maintenance;
+pub mod mime;
+pub mod rotate;
+pub mod security;
+pub mod selftest;
+pub mod store;
+pub mod submit;
+pub mod template;
+pub mod tenant;
+pub mod testing;
+pub mod time;
+pub mod timing;
+pub mod wizard;
+
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(feature = "auth")]
+mod htpasswd;
+#[cfg(feature = "auth")]
+pub mod lockout;
+#[cfg(feature = "auth")]
+pub mod totp;
+
+#[cfg(feature = "compression")]
+pub mod gzip;
+
+#[cfg(feature = "serde")]
+mod capture;
+
 #[cfg(test)]
 mod test;
 /**
@@ -126,12 +201,12 @@ might help in debugging or troubleshooting.
 Also, an `Error` can be turned directly into an HTTP response.
 
 ```rust
-# use dumb_cgi::{Request, EmptyResponse, Error};
+# use dumb_cgi::{Request, Response, Error};
 // Request::new() will return an `Error` if it can't read/parse all the
 // necessary information supplied by the webserver about the request.
 
 let response = match Request::new() {
-    Ok(_) => EmptyResponse::new(200)
+    Ok(_) => Response::new(200)
                 .with_content_type("text/plain")
                 .with_body("Your request was read successfully."),
     Err(e) => e.to_response(),
@@ -141,7 +216,7 @@ response.respond().unwrap();
 ```
 
 */
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Error {
     /// Recommended HTTP response code to use if sending an error response
     /// due to this error.
@@ -159,8 +234,8 @@ impl Error {
     Consumes this error and returns an HTTP response appropriate to send
     back to the user agent.
     */
-    pub fn to_response(self) -> FullResponse {
-        EmptyResponse::new(self.code)
+    pub fn to_response(self) -> Response {
+        Response::new(self.code)
             .with_content_type("text/plain")
             .with_body(self.message)
     }
@@ -173,3 +248,174 @@ impl Display for Error {
 }
 
 impl std::error::Error for Error {}
+
+/**
+A collection of `Error`s, for reporting every problem with a request at
+once (every invalid form field, every skipped multipart part) instead of
+just the first.
+
+An `Errors` can be turned into a single response listing all the
+problems.
+
+```rust
+# use dumb_cgi::{Error, Errors};
+let mut errors = Errors::new();
+for (name, value) in [("age", "x"), ("name", "Bob"), ("count", "-")] {
+    if name != "name" && value.parse::<u32>().is_err() {
+        errors.push(Error {
+            code: 400,
+            message: format!("{} must be a number.", name),
+            details: format!("field {:?}: {:?} isn't a number", name, value),
+        });
+    }
+}
+assert_eq!(errors.len(), 2);
+
+let response = errors.to_response();
+assert_eq!(response.get_status(), 400);
+assert_eq!(response.get_body(), b"age must be a number.\ncount must be a number.\n");
+```
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Errors(Vec<Error>);
+
+impl Errors {
+    /// Return an empty collection.
+    pub fn new() -> Errors {
+        Errors::default()
+    }
+
+    /// Add an error to the collection.
+    pub fn push(&mut self, err: Error) {
+        self.0.push(err);
+    }
+
+    /// Return whether there are no errors.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Return the number of errors.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Iterate over the errors in the order they were added.
+    pub fn iter(&self) -> std::slice::Iter<'_, Error> {
+        self.0.iter()
+    }
+
+    /**
+    Return `Ok(value)` if there are no errors, and the errors otherwise,
+    for ending a run of checks.
+    */
+    pub fn into_result<T>(self, value: T) -> Result<T, Errors> {
+        if self.0.is_empty() {
+            Ok(value)
+        } else {
+            Err(self)
+        }
+    }
+
+    /*
+    The response code for all the errors together: `400` if they're all
+    the client's fault, `500` if any of them isn't.
+    */
+    fn code(&self) -> u16 {
+        if self.0.iter().all(|e| (400..500).contains(&e.code)) {
+            400
+        } else {
+            500
+        }
+    }
+
+    /**
+    Combine the errors into a single `Error`, whose message has each
+    error's message on a line of its own, and whose details have each
+    error's details, separated by semicolons.
+    */
+    pub fn to_error(&self) -> Error {
+        let mut message = String::new();
+        for e in self.0.iter() {
+            message.push_str(&e.message);
+            message.push('\n');
+        }
+        let details: Vec<&str> = self.0.iter().map(|e| e.details.as_str()).collect();
+        Error {
+            code: self.code(),
+            message,
+            details: details.join("; "),
+        }
+    }
+
+    /**
+    Consumes this collection and returns an HTTP response listing every
+    error's message (see `Error::to_response()`).
+    */
+    pub fn to_response(self) -> Response {
+        self.to_error().to_response()
+    }
+}
+
+impl From<Error> for Errors {
+    fn from(err: Error) -> Errors {
+        Errors(vec![err])
+    }
+}
+
+impl From<Vec<Error>> for Errors {
+    fn from(errs: Vec<Error>) -> Errors {
+        Errors(errs)
+    }
+}
+
+/// So the errors from `MultipartForm::skipped_parts()` can be collected.
+impl From<&[Error]> for Errors {
+    fn from(errs: &[Error]) -> Errors {
+        Errors(errs.to_vec())
+    }
+}
+
+impl FromIterator<Error> for Errors {
+    fn from_iter<I: IntoIterator<Item = Error>>(iter: I) -> Errors {
+        Errors(iter.into_iter().collect())
+    }
+}
+
+impl Extend<Error> for Errors {
+    fn extend<I: IntoIterator<Item = Error>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+impl IntoIterator for Errors {
+    type Item = Error;
+    type IntoIter = std::vec::IntoIter<Error>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Errors {
+    type Item = &'a Error;
+    type IntoIter = std::slice::Iter<'a, Error>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl Display for Errors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        for (n, e) in self.0.iter().enumerate() {
+            if n > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Errors {}
@@ -0,0 +1,122 @@
+/*!
+Exclusive locks between processes, held on lock files.
+
+Used by every module that does a read-modify-write on files other
+processes might be changing at the same time (`audit`, `lockout`,
+`store`, `gc`, `rotate`, and `maintenance`).
+
+The lock is the operating system's advisory lock on the open file (see
+`File::try_lock()`), so it's released when the holder drops it or dies;
+the file's existence means nothing on its own, and an abandoned one is
+simply locked again by the next process to come along.
+
+Lock files are removed when they're released, so they don't pile up. A
+process that opened the file just before it was removed would lock a
+file nobody else can see any more, so after locking, the file is checked
+to still be the one at the lock's path, and if it isn't, the attempt
+starts over.
+*/
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long to wait for another process to release the lock.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/*
+An exclusive lock on the file at a path; the file is removed (and the
+lock released) when this is dropped.
+*/
+pub(crate) struct LockGuard {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    path: PathBuf,
+    // Held open for the lock; closing it releases the lock.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    file: File,
+}
+
+impl LockGuard {
+    /*
+    Take the lock, waiting up to `LOCK_TIMEOUT` for another process to
+    release it.
+    */
+    pub(crate) fn acquire(path: PathBuf) -> std::io::Result<LockGuard> {
+        let start = SystemTime::now();
+        loop {
+            match LockGuard::try_acquire(&path)? {
+                Some(guard) => return Ok(guard),
+                None if start.elapsed().unwrap_or_default() > LOCK_TIMEOUT => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "timed out waiting for lock",
+                    ));
+                }
+                None => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+    }
+
+    /*
+    Take the lock if nobody else holds it, or return `None`.
+    */
+    pub(crate) fn try_acquire(path: &Path) -> std::io::Result<Option<LockGuard>> {
+        loop {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => return Ok(None),
+                Err(TryLockError::Error(e)) => return Err(e),
+            }
+            // The holder we waited on may have removed the file between
+            // our opening and locking it; if so, this lock guards nothing.
+            if is_same_file(&file, path)? {
+                return Ok(Some(LockGuard {
+                    path: path.to_owned(),
+                    file,
+                }));
+            }
+        }
+    }
+}
+
+/*
+Whether `file` is (still) the file at `path`.
+*/
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let held = file.metadata()?;
+    match std::fs::metadata(path) {
+        Ok(found) => Ok(held.dev() == found.dev() && held.ino() == found.ino()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/*
+Elsewhere, lock files are never removed (see `Drop` below), so the file
+at `path` can't have been replaced.
+*/
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, _path: &Path) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        // Removed while still locked, so nobody can lock it in between;
+        // anyone already waiting on it will notice it's gone. If it's
+        // been replaced, the new one is somebody else's, so it stays.
+        // (Windows won't remove a file that's open, so there it stays too.)
+        #[cfg(unix)]
+        if is_same_file(&self.file, &self.path).unwrap_or(false) {
+            _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+/*!
+Tracking of failed login attempts, with exponential backoff, so password
+guessing can't be done at full speed.
+
+This module is only available with the `auth` feature enabled. Since each
+CGI request is a separate process, the attempt counts are kept in small
+files in a directory (one per user/address pair).
+
+```rust
+use dumb_cgi::lockout::Lockout;
+
+let dir = std::env::temp_dir().join("dumb_cgi_lockout_doctest");
+let lockout = Lockout::new(&dir).with_threshold(2);
+
+lockout.record_failure("dan", "10.0.0.1").unwrap();
+assert!(lockout.check("dan", "10.0.0.1").is_ok());
+lockout.record_failure("dan", "10.0.0.1").unwrap();
+// Two failures: locked out (for a little while).
+assert_eq!(lockout.check("dan", "10.0.0.1").unwrap_err().code, 429);
+// Different address: not locked out.
+assert!(lockout.check("dan", "10.0.0.2").is_ok());
+
+lockout.record_success("dan", "10.0.0.1").unwrap();
+assert!(lockout.check("dan", "10.0.0.1").is_ok());
+# std::fs::remove_dir_all(&dir).unwrap();
+```
+
+Checking with `.check()` and then recording the outcome leaves a gap:
+guesses sent in parallel can all pass the check before the first
+failure is recorded. `.attempt()` checks and counts the attempt in one
+step, so a login should be guarded like this:
+
+```rust
+use dumb_cgi::lockout::Lockout;
+
+# let dir = std::env::temp_dir().join("dumb_cgi_lockout_attempt_doctest");
+let lockout = Lockout::new(&dir).with_threshold(1);
+
+let attempt = lockout.attempt("dan", "10.0.0.1").unwrap();
+let password_ok = false;
+if password_ok {
+    attempt.succeeded().unwrap();
+}
+// Dropped without succeeding: it stays counted as a failure.
+assert_eq!(lockout.attempt("dan", "10.0.0.1").unwrap_err().code, 429);
+# std::fs::remove_dir_all(&dir).unwrap();
+```
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::auth::now_secs;
+use crate::hash::{sha256, to_hex};
+use crate::lockfile::LockGuard;
+use crate::Error;
+
+/**
+A file-backed tracker of failed login attempts per user and remote address.
+
+After `threshold` consecutive failures, further attempts are refused for
+`base_delay` seconds; each further failure doubles the delay, up to
+`max_delay`. Failures older than `window` seconds are forgotten.
+*/
+#[derive(Debug, Clone)]
+pub struct Lockout {
+    dir: PathBuf,
+    threshold: u32,
+    base_delay: u64,
+    max_delay: u64,
+    window: u64,
+}
+
+/**
+A login attempt reserved with `Lockout::attempt()`.
+
+It's counted as a failure from the start; call `.succeeded()` if the
+login works, which clears the failures. Dropping it any other way leaves
+the failure counted.
+*/
+#[must_use = "dropping an attempt counts it as a failure"]
+#[derive(Debug)]
+pub struct Attempt<'a> {
+    lockout: &'a Lockout,
+    user: String,
+    addr: String,
+}
+
+impl Attempt<'_> {
+    /// Record that the attempt succeeded, clearing the failures.
+    pub fn succeeded(self) -> Result<(), Error> {
+        self.lockout.record_success(&self.user, &self.addr)
+    }
+}
+
+/*
+What's stored in each tracking file.
+*/
+#[derive(Debug, Clone, Copy, Default)]
+struct Attempts {
+    failures: u32,
+    last_failure: u64,
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!("Error accessing lockout file {}: {}", path.display(), &e),
+    }
+}
+
+impl Lockout {
+    /**
+    Create a new tracker that keeps its files in `dir` (which will be
+    created if necessary).
+
+    Defaults are a threshold of 5 failures, a base delay of 30 seconds, a
+    maximum delay of one hour, and a window of one day.
+    */
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Lockout {
+        Lockout {
+            dir: dir.into(),
+            threshold: 5,
+            base_delay: 30,
+            max_delay: 60 * 60,
+            window: 60 * 60 * 24,
+        }
+    }
+
+    /// Builder-pattern method for setting how many failures are allowed
+    /// before attempts start being refused.
+    pub fn with_threshold(self, failures: u32) -> Lockout {
+        let mut new = self;
+        new.threshold = failures.max(1);
+        new
+    }
+
+    /// Builder-pattern method for setting the initial lockout delay (in
+    /// seconds).
+    pub fn with_base_delay(self, seconds: u64) -> Lockout {
+        let mut new = self;
+        new.base_delay = seconds;
+        new
+    }
+
+    /// Builder-pattern method for setting the longest lockout delay (in
+    /// seconds).
+    pub fn with_max_delay(self, seconds: u64) -> Lockout {
+        let mut new = self;
+        new.max_delay = seconds;
+        new
+    }
+
+    /// Builder-pattern method for setting how long (in seconds) after the
+    /// last failure the failure count is reset.
+    pub fn with_window(self, seconds: u64) -> Lockout {
+        let mut new = self;
+        new.window = seconds;
+        new
+    }
+
+    fn path_for(&self, user: &str, addr: &str) -> PathBuf {
+        let key = format!("{}\0{}", user, addr);
+        self.dir.join(&to_hex(&sha256(key.as_bytes()))[..32])
+    }
+
+    /*
+    Lock the tracking file at `path`, so a count can be read and written
+    back without another request's update landing in between (which
+    would let parallel guesses each count as the first).
+    */
+    fn lock(&self, path: &Path) -> Result<LockGuard, Error> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| io_error(&self.dir, e))?;
+        let lock_path = path.with_extension("lock");
+        LockGuard::acquire(lock_path.clone()).map_err(|e| io_error(&lock_path, e))
+    }
+
+    fn read(&self, path: &Path) -> Attempts {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Attempts::default(),
+        };
+        let mut fields = text.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some(f), Some(t)) => Attempts {
+                failures: f.parse().unwrap_or(0),
+                last_failure: t.parse().unwrap_or(0),
+            },
+            _ => Attempts::default(),
+        }
+    }
+
+    fn write(&self, path: &Path, attempts: Attempts) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| io_error(&self.dir, e))?;
+        // Write to a temporary file and rename it into place, so concurrent
+        // requests never see a half-written file.
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        let text = format!("{} {}\n", attempts.failures, attempts.last_failure);
+        std::fs::write(&tmp, text).map_err(|e| io_error(&tmp, e))?;
+        std::fs::rename(&tmp, path).map_err(|e| io_error(path, e))
+    }
+
+    fn delay_for(&self, failures: u32) -> u64 {
+        if failures < self.threshold {
+            return 0;
+        }
+        let doublings = (failures - self.threshold).min(32);
+        self.base_delay
+            .saturating_mul(1u64 << doublings)
+            .min(self.max_delay)
+    }
+
+    /*
+    How many more seconds `attempts` keep the user locked out.
+    */
+    fn remaining_for(&self, attempts: Attempts) -> u64 {
+        let now = now_secs();
+        if now.saturating_sub(attempts.last_failure) > self.window {
+            return 0;
+        }
+        let unlock = attempts
+            .last_failure
+            .saturating_add(self.delay_for(attempts.failures));
+        unlock.saturating_sub(now)
+    }
+
+    /*
+    The error for `user` being locked out from `addr` if `attempts` lock
+    them out.
+    */
+    fn check_attempts(&self, user: &str, addr: &str, attempts: Attempts) -> Result<(), Error> {
+        match self.remaining_for(attempts) {
+            0 => Ok(()),
+            n => Err(Error {
+                code: 429,
+                message: "Too many failed login attempts. Try again later.".to_owned(),
+                details: format!("{} from {} locked out for {} more seconds", user, addr, n),
+            }),
+        }
+    }
+
+    /*
+    Add a failure (now) to `path`'s count; the caller holds the lock.
+    */
+    fn add_failure(&self, path: &Path, attempts: Attempts) -> Result<(), Error> {
+        let mut attempts = attempts;
+        let now = now_secs();
+        if now.saturating_sub(attempts.last_failure) > self.window {
+            attempts.failures = 0;
+        }
+        attempts.failures = attempts.failures.saturating_add(1);
+        attempts.last_failure = now;
+        self.write(path, attempts)
+    }
+
+    /**
+    Return how many more seconds `user` is locked out from `addr` (zero if
+    attempts are currently allowed).
+    */
+    pub fn remaining(&self, user: &str, addr: &str) -> u64 {
+        self.remaining_for(self.read(&self.path_for(user, addr)))
+    }
+
+    /**
+    Return an error (with a 429 status code) if `user` is currently locked
+    out from `addr`.
+
+    This only looks; to guard a login, use `.attempt()`.
+    */
+    pub fn check(&self, user: &str, addr: &str) -> Result<(), Error> {
+        let attempts = self.read(&self.path_for(user, addr));
+        self.check_attempts(user, addr, attempts)
+    }
+
+    /**
+    Start a login attempt by `user` from `addr`: return an error (with a
+    429 status code) if they're locked out, and otherwise count the
+    attempt as a failure until the returned `Attempt` says it succeeded.
+
+    The check and the count happen under one lock, so however many
+    attempts are made at once, no more get through than `.check()` and
+    `.record_failure()` one after another would allow.
+    */
+    pub fn attempt(&self, user: &str, addr: &str) -> Result<Attempt<'_>, Error> {
+        let path = self.path_for(user, addr);
+        let _lock = self.lock(&path)?;
+        let attempts = self.read(&path);
+        self.check_attempts(user, addr, attempts)?;
+        self.add_failure(&path, attempts)?;
+        Ok(Attempt {
+            lockout: self,
+            user: user.to_owned(),
+            addr: addr.to_owned(),
+        })
+    }
+
+    /// Record a failed login attempt by `user` from `addr`.
+    pub fn record_failure(&self, user: &str, addr: &str) -> Result<(), Error> {
+        let path = self.path_for(user, addr);
+        let _lock = self.lock(&path)?;
+        let attempts = self.read(&path);
+        self.add_failure(&path, attempts)
+    }
+
+    /// Record a successful login by `user` from `addr`, clearing any
+    /// failures.
+    pub fn record_success(&self, user: &str, addr: &str) -> Result<(), Error> {
+        let path = self.path_for(user, addr);
+        let _lock = self.lock(&path)?;
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(io_error(&path, e)),
+        }
+    }
+}
@@ -0,0 +1,335 @@
+/*!
+Periodic housekeeping, run from cron or an admin-only endpoint.
+
+`run_all()` reads what to do from a `Config`:
+
+```text
+[gc]
+# name = directory, then the age after which files are removed
+sessions = /var/lib/mysite/sessions 1d
+uploads = /var/spool/mysite 6h
+
+[rotate]
+# name = log file, then the size at which it's rotated and how many
+# old copies to keep
+audit = /var/lib/mysite/audit.log 10M 5
+
+[cache]
+# name = directory, then the total size to prune it down to (oldest
+# files first)
+config = /var/cache/mysite 50M
+```
+
+Ages are a number followed by `s`, `m`, `h`, or `d` (or just seconds);
+sizes are a number of bytes, optionally followed by `K`, `M`, or `G`.
+
+```rust
+use dumb_cgi::{config::Config, maintenance};
+
+let dir = std::env::temp_dir().join("dumb_cgi_maintenance_doctest");
+std::fs::create_dir_all(&dir).unwrap();
+std::fs::write(dir.join("expired"), "data").unwrap();
+
+let mut config = Config::new();
+config.set("gc.scratch", format!("{} 0s", dir.display()));
+let report = maintenance::run_all(&config).unwrap();
+assert_eq!(report.gc.removed, 1);
+
+// From an admin endpoint, the report can be sent back as the response.
+let response = report.to_response();
+# std::fs::remove_dir_all(&dir).unwrap();
+```
+*/
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::gc::{Gc, GcReport, LOCK_NAME};
+use crate::lockfile::LockGuard;
+use crate::rotate::Rotation;
+use crate::{Error, Response};
+
+fn bad_config(key: &str, value: &str, expected: &str) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!(
+            "Invalid maintenance configuration {} = {:?}: expected {}",
+            key, value, expected
+        ),
+    }
+}
+
+/**
+Parse an age like `90`, `30s`, `15m`, `6h`, or `2d`.
+
+```rust
+# use std::time::Duration;
+# use dumb_cgi::maintenance::parse_age;
+assert_eq!(parse_age("6h"), Some(Duration::from_secs(6 * 60 * 60)));
+assert_eq!(parse_age("90"), Some(Duration::from_secs(90)));
+assert_eq!(parse_age("soon"), None);
+```
+*/
+pub fn parse_age(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (n, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, ""),
+    };
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return None,
+    };
+    let n: u64 = n.parse().ok()?;
+    Some(Duration::from_secs(n.checked_mul(multiplier)?))
+}
+
+/**
+Parse a size like `512`, `64K`, `10M`, or `1G` (powers of 1024).
+
+```rust
+# use dumb_cgi::maintenance::parse_size;
+assert_eq!(parse_size("10M"), Some(10 * 1024 * 1024));
+assert_eq!(parse_size("512"), Some(512));
+assert_eq!(parse_size("big"), None);
+```
+*/
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (n, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return None,
+    };
+    n.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/*
+Split a value like `/some/path 10M 5` into the path and the remaining
+fields. (Paths containing spaces aren't supported.)
+*/
+fn split_value(value: &str) -> (&str, Vec<&str>) {
+    let mut fields = value.split_whitespace();
+    let path = fields.next().unwrap_or("");
+    (path, fields.collect())
+}
+
+/**
+Rotate the log file at `path` if it has grown past `max_bytes`, keeping
+at most `keep` old copies (see the `rotate` module). Returns whether it
+was rotated.
+*/
+pub fn rotate_file(path: &Path, max_bytes: u64, keep: u32) -> Result<bool, Error> {
+    Rotation::new()
+        .with_max_bytes(max_bytes)
+        .with_keep(keep)
+        .rotate(path)
+}
+
+/**
+Remove the least recently modified files in `dir` (and its
+subdirectories) until the files there take up no more than `max_bytes`.
+Returns what was removed, in the same form as a garbage collection.
+*/
+pub fn prune_dir(dir: &Path, max_bytes: u64) -> GcReport {
+    let mut report = GcReport::default();
+    if !dir.is_dir() {
+        return report;
+    }
+    let _lock = match LockGuard::try_acquire(&dir.join(LOCK_NAME)) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            report.skipped.push(dir.to_owned());
+            return report;
+        }
+        Err(e) => {
+            report
+                .errors
+                .push(format!("{}: unable to lock: {}", dir.display(), &e));
+            return report;
+        }
+    };
+
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = Vec::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(d) = dirs.pop() {
+        let entries = match std::fs::read_dir(&d) {
+            Ok(entries) => entries,
+            Err(e) => {
+                report.errors.push(format!("{}: {}", d.display(), &e));
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            if entry.file_name() == LOCK_NAME {
+                continue;
+            }
+            let path = entry.path();
+            match std::fs::symlink_metadata(&path) {
+                Ok(meta) if meta.is_dir() => dirs.push(path),
+                Ok(meta) => {
+                    let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+                    files.push((modified, meta.len(), path));
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    files.sort();
+    for (_, len, path) in files {
+        if total <= max_bytes {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                total -= len;
+                report.removed += 1;
+                report.bytes += len;
+            }
+            Err(e) => report.errors.push(format!("{}: {}", path.display(), &e)),
+        }
+    }
+    report
+}
+
+/**
+What `run_all()` did.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Files expired from the `[gc]` directories.
+    pub gc: GcReport,
+    /// Log files from the `[rotate]` section that were rotated.
+    pub rotated: Vec<PathBuf>,
+    /// Files pruned from the `[cache]` directories.
+    pub pruned: GcReport,
+    /// Descriptions of anything that went wrong along the way.
+    pub errors: Vec<String>,
+}
+
+impl MaintenanceReport {
+    /// Return whether everything was done without errors.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty() && self.gc.errors.is_empty() && self.pruned.errors.is_empty()
+    }
+
+    /**
+    Return the report as a plain text response (with a 500 status code if
+    anything went wrong), for an admin endpoint.
+    */
+    pub fn to_response(&self) -> Response {
+        let code = if self.is_ok() { 200 } else { 500 };
+        Response::new(code)
+            .with_content_type("text/plain")
+            .with_body(self.to_string())
+    }
+}
+
+impl std::fmt::Display for MaintenanceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        writeln!(out, "gc.removed: {}", self.gc.removed)?;
+        writeln!(out, "gc.bytes: {}", self.gc.bytes)?;
+        for dir in self.gc.skipped.iter() {
+            writeln!(out, "gc.skipped: {}", dir.display())?;
+        }
+        for path in self.rotated.iter() {
+            writeln!(out, "rotated: {}", path.display())?;
+        }
+        writeln!(out, "cache.removed: {}", self.pruned.removed)?;
+        writeln!(out, "cache.bytes: {}", self.pruned.bytes)?;
+        for dir in self.pruned.skipped.iter() {
+            writeln!(out, "cache.skipped: {}", dir.display())?;
+        }
+        let errors = self
+            .gc
+            .errors
+            .iter()
+            .chain(self.pruned.errors.iter())
+            .chain(self.errors.iter());
+        for e in errors {
+            writeln!(out, "error: {}", e)?;
+        }
+        f.write_str(&out)
+    }
+}
+
+/**
+Do all the housekeeping described in `config` (see the module
+documentation).
+
+The whole configuration is checked before anything is done, and an error
+returned if any of it is invalid. Problems while working (a file that
+can't be removed, say) don't stop the rest of the work; they're recorded
+in the report.
+*/
+pub fn run_all(config: &Config) -> Result<MaintenanceReport, Error> {
+    let mut gc = Gc::new();
+    let mut rotations: Vec<(PathBuf, u64, u32)> = Vec::new();
+    let mut caches: Vec<(PathBuf, u64)> = Vec::new();
+
+    for (key, value) in config.iter() {
+        let (path, fields) = split_value(value);
+        if key.starts_with("gc.") {
+            match (path, fields.as_slice()) {
+                (p, [age]) if !p.is_empty() => {
+                    let age = parse_age(age).ok_or_else(|| bad_config(key, value, "DIR AGE"))?;
+                    gc = gc.with_dir(p, age);
+                }
+                _ => return Err(bad_config(key, value, "DIR AGE")),
+            }
+        } else if key.starts_with("rotate.") {
+            let expected = "FILE SIZE [KEEP]";
+            let (size, keep) = match fields.as_slice() {
+                [size] => (*size, "5"),
+                [size, keep] => (*size, *keep),
+                _ => return Err(bad_config(key, value, expected)),
+            };
+            let size = parse_size(size).ok_or_else(|| bad_config(key, value, expected))?;
+            let keep = keep.parse().map_err(|_| bad_config(key, value, expected))?;
+            if path.is_empty() {
+                return Err(bad_config(key, value, expected));
+            }
+            rotations.push((PathBuf::from(path), size, keep));
+        } else if key.starts_with("cache.") {
+            match (path, fields.as_slice()) {
+                (p, [size]) if !p.is_empty() => {
+                    let size =
+                        parse_size(size).ok_or_else(|| bad_config(key, value, "DIR SIZE"))?;
+                    caches.push((PathBuf::from(p), size));
+                }
+                _ => return Err(bad_config(key, value, "DIR SIZE")),
+            }
+        }
+    }
+
+    let mut report = MaintenanceReport {
+        gc: gc.run(),
+        ..MaintenanceReport::default()
+    };
+    for (path, size, keep) in rotations {
+        match rotate_file(&path, size, keep) {
+            Ok(true) => report.rotated.push(path),
+            Ok(false) => {}
+            Err(e) => report.errors.push(e.details),
+        }
+    }
+    for (dir, size) in caches {
+        report.pruned.merge(prune_dir(&dir, size));
+    }
+    Ok(report)
+}
@@ -0,0 +1,83 @@
+/*!
+Guessing a file's content type from its extension.
+
+```rust
+use dumb_cgi::mime;
+
+assert_eq!(mime::from_extension("png"), Some("image/png"));
+assert_eq!(mime::from_path("static/Site.CSS"), Some("text/css; charset=utf-8"));
+assert_eq!(mime::from_path("README"), None);
+```
+
+Text types include `; charset=utf-8`, on the assumption that a program's
+own files are UTF-8. Anything not in the table should be sent as
+`DEFAULT`.
+*/
+
+use std::path::Path;
+
+/// The content type for files of unknown type.
+pub const DEFAULT: &str = "application/octet-stream";
+
+/**
+Return the content type of files with the extension `ext` (with or
+without a leading `.`, in any case), if it's one of the common ones the
+table knows.
+*/
+pub fn from_extension(ext: &str) -> Option<&'static str> {
+    let ext = ext.strip_prefix('.').unwrap_or(ext).to_ascii_lowercase();
+    let content_type = match ext.as_str() {
+        // Text
+        "html" | "htm" => "text/html; charset=utf-8",
+        "xhtml" => "application/xhtml+xml",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" | "text" => "text/plain; charset=utf-8",
+        "md" | "markdown" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "ics" => "text/calendar; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "xml" => "application/xml",
+        "rss" => "application/rss+xml",
+        "atom" => "application/atom+xml",
+        // Images
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "ico" => "image/x-icon",
+        // Fonts
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        // Audio and video
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ogv" => "video/ogg",
+        // Documents and archives
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "wasm" => "application/wasm",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+/// Return the content type of the file at `path`, from its extension
+/// (see `from_extension()`).
+pub fn from_path<P: AsRef<Path>>(path: P) -> Option<&'static str> {
+    from_extension(path.as_ref().extension()?.to_str()?)
+}
@@ -0,0 +1,794 @@
+/*!
+Reading `multipart/form-data` bodies from anywhere: all at once with
+`parse()`, a piece at a time with `MultipartReader`, or in place with
+`MultipartBuffer`.
+
+The types here are also re-exported at the crate root.
+*/
+
+use std::collections::HashMap;
+use std::io::{Read, Take, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::request::{
+    create_unique, disposition_filename, disposition_name, slicey_find, split_multipart,
+    temp_upload_name, Finder,
+};
+use crate::{
+    multipart_boundary, parse_header_line, parse_multipart_with_limits, Body, Error, MediaType,
+    MultipartForm, MultipartLimits, MultipartPart, Request, SavedFile, TempUpload,
+};
+
+/// Default size of a `MultipartReader`'s buffer.
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+fn read_error(e: std::io::Error) -> Error {
+    Error {
+        code: 500,
+        message: "Unable to read request body.".to_owned(),
+        details: format!("Error reading request body: {}", &e),
+    }
+}
+
+fn malformed(details: &str) -> Error {
+    Error {
+        code: 400,
+        message: "Invalid multipart body.".to_owned(),
+        details: format!("Error reading multipart body: {}", details),
+    }
+}
+
+/**
+Read a whole multipart body from `reader` and parse it, independently of
+any `Request` (for multipart data from a file, a message queue, or a test
+fixture, say). `boundary` is the boundary parameter from its content type
+(see `multipart_boundary()`).
+
+Parts with malformed headers are kept, or with `limits.strict` skipped
+(see `MultipartForm::skipped_parts()`), as when parsing a request's body;
+a body without any boundary, or one that exceeds `limits`, is an error.
+
+```rust
+use dumb_cgi::{multipart, MultipartLimits};
+
+let body = "--xyz\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            Holiday\r\n\
+            --xyz--\r\n";
+let form = multipart::parse(body.as_bytes(), "xyz", &MultipartLimits::default()).unwrap();
+assert_eq!(form.get("title").unwrap().body, b"Holiday");
+```
+*/
+pub fn parse<R: Read>(
+    mut reader: R,
+    boundary: &str,
+    limits: &MultipartLimits,
+) -> Result<MultipartForm, Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(read_error)?;
+    match parse_multipart_with_limits(&bytes, boundary, limits) {
+        Body::Multipart(form) => Ok(form),
+        Body::Err(e) => Err(e),
+        body => unreachable!("multipart parsing returned {:?}", body),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the first boundary.
+    Preamble,
+    /// Just after a boundary, before the `--` or newline that follows it.
+    Boundary,
+    /// In the body of a part.
+    Body,
+    /// After the final boundary.
+    Done,
+}
+
+/**
+An incremental `multipart/form-data` parser, which reads a body from a
+`Read` and hands out its parts one at a time, each with a reader for its
+body.
+
+Only a fixed-size buffer (64 KiB by default) is held in memory, so
+uploads can be copied to disk (or wherever) no matter how large they are.
+The whole header block of each part has to fit in the buffer.
+
+```rust
+use std::io::Read;
+use dumb_cgi::MultipartReader;
+
+let body = "--xyz\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            Holiday\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"beach.jpg\"\r\n\
+            \r\n\
+            JPEG data\r\n\
+            --xyz--\r\n";
+
+let mut reader = MultipartReader::new(body.as_bytes(), "xyz");
+
+let mut part = reader.next_part().unwrap().unwrap();
+assert_eq!(part.name().as_deref(), Some("title"));
+let mut title = String::new();
+part.read_to_string(&mut title).unwrap();
+assert_eq!(title, "Holiday");
+
+let mut part = reader.next_part().unwrap().unwrap();
+assert_eq!(part.filename().as_deref(), Some("beach.jpg"));
+// Copy the upload wherever it needs to go.
+let mut saved: Vec<u8> = Vec::new();
+std::io::copy(&mut part, &mut saved).unwrap();
+assert_eq!(saved, b"JPEG data");
+
+assert!(reader.next_part().unwrap().is_none());
+```
+*/
+pub struct MultipartReader<R> {
+    source: R,
+    /// Searches for the delimiter between parts: a newline, two hyphens,
+    /// and the boundary.
+    delimiter: Finder,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    eof: bool,
+    state: State,
+    limits: MultipartLimits,
+    /// How many parts have been started.
+    parts: usize,
+    /// How many bytes of the current part's body have been read.
+    part_bytes: usize,
+}
+
+impl<R: Read> MultipartReader<R> {
+    /**
+    Create a reader for a multipart body with the given `boundary` (see
+    `multipart_boundary()`), read from `source`.
+    */
+    pub fn new(source: R, boundary: &str) -> MultipartReader<R> {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+        // The body starts with a boundary but no newline; pretending there
+        // was one means every boundary looks the same.
+        let mut buf = vec![0; DEFAULT_BUFFER_SIZE];
+        buf[..2].copy_from_slice(b"\r\n");
+        MultipartReader {
+            source,
+            delimiter: Finder::new(&delimiter),
+            buf,
+            start: 0,
+            end: 2,
+            eof: false,
+            state: State::Preamble,
+            limits: MultipartLimits::default(),
+            parts: 0,
+            part_bytes: 0,
+        }
+    }
+
+    /**
+    Builder-pattern method for setting the `MultipartLimits` to enforce
+    (instead of the defaults). A limit being exceeded is returned as an
+    error from `next_part()` or from reading a part's body.
+    */
+    pub fn with_limits(self, limits: MultipartLimits) -> MultipartReader<R> {
+        let mut new = self;
+        new.limits = limits;
+        new
+    }
+
+    /**
+    Builder-pattern method for setting the size of the buffer. It will
+    be made at least large enough to hold a few boundaries.
+    */
+    pub fn with_buffer_size(self, bytes: usize) -> MultipartReader<R> {
+        let mut new = self;
+        let size = bytes
+            .max(4 * new.delimiter.needle().len())
+            .max(new.end - new.start);
+        new.buf.copy_within(new.start..new.end, 0);
+        new.end -= new.start;
+        new.start = 0;
+        new.buf.resize(size, 0);
+        new.buf.shrink_to_fit();
+        new
+    }
+
+    /*
+    Move the unconsumed bytes to the front of the buffer and read more
+    after them. Returns `false` if there's no room or nothing more to read.
+    */
+    fn fill(&mut self) -> Result<bool, Error> {
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.eof || self.end == self.buf.len() {
+            return Ok(false);
+        }
+        loop {
+            match self.source.read(&mut self.buf[self.end..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    return Ok(false);
+                }
+                Ok(n) => {
+                    self.end += n;
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(read_error(e)),
+            }
+        }
+    }
+
+    fn find(&self, needle: &[u8]) -> Option<usize> {
+        slicey_find(&self.buf[self.start..self.end], needle)
+    }
+
+    fn find_delimiter(&self) -> Option<usize> {
+        self.delimiter.find(&self.buf[self.start..self.end])
+    }
+
+    /*
+    Read up to `out.len()` bytes of the current part's body, returning 0
+    at its end (and moving on to the boundary that ends it).
+    */
+    fn read_body(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+        if self.state != State::Body || out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let available = match self.find_delimiter() {
+                Some(0) => {
+                    self.start += self.delimiter.needle().len();
+                    self.state = State::Boundary;
+                    return Ok(0);
+                }
+                Some(n) => n,
+                // Anything that might be the start of a delimiter has to
+                // wait until we can see the rest of it.
+                None => (self.end - self.start).saturating_sub(self.delimiter.needle().len() - 1),
+            };
+            if available > 0 {
+                let n = available.min(out.len());
+                self.part_bytes += n;
+                if self.part_bytes > self.limits.max_part_size {
+                    return Err(self.limits.part_too_large());
+                }
+                out[..n].copy_from_slice(&self.buf[self.start..self.start + n]);
+                self.start += n;
+                return Ok(n);
+            }
+            if !self.fill()? {
+                return Err(malformed("body ended inside a part"));
+            }
+        }
+    }
+
+    /*
+    Discard everything up to and including the first delimiter.
+    */
+    fn skip_preamble(&mut self) -> Result<(), Error> {
+        loop {
+            if let Some(n) = self.find_delimiter() {
+                self.start += n + self.delimiter.needle().len();
+                self.state = State::Boundary;
+                return Ok(());
+            }
+            let keep = self.delimiter.needle().len() - 1;
+            self.start = self.end.saturating_sub(keep).max(self.start);
+            if !self.fill()? {
+                return Err(malformed("no boundary found"));
+            }
+        }
+    }
+
+    /*
+    Read a line (without its newline) from the buffer, or `None` if a
+    whole line won't fit.
+    */
+    fn read_line(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            if let Some(n) = self.find(b"\r\n") {
+                let line = self.buf[self.start..self.start + n].to_vec();
+                self.start += n + 2;
+                return Ok(Some(line));
+            }
+            if !self.fill()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /**
+    Return the next part of the body, or `None` after the last one.
+
+    Any of the previous part's body that hasn't been read is skipped.
+    */
+    pub fn next_part(&mut self) -> Result<Option<PartReader<'_, R>>, Error> {
+        let mut scratch = [0u8; 4096];
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::Preamble => self.skip_preamble()?,
+                State::Body => while self.read_body(&mut scratch)? > 0 {},
+                State::Boundary => break,
+            }
+        }
+
+        while self.end - self.start < 2 {
+            if !self.fill()? {
+                return Err(malformed("body ended after a boundary"));
+            }
+        }
+        if &self.buf[self.start..self.start + 2] == b"--" {
+            self.state = State::Done;
+            return Ok(None);
+        }
+        // Whatever follows a boundary (which should only be whitespace) up
+        // to the end of the line is ignored.
+        if self.read_line()?.is_none() {
+            return Err(malformed("boundary line too long"));
+        }
+
+        if self.parts == self.limits.max_parts {
+            return Err(self.limits.too_many_parts());
+        }
+        self.parts += 1;
+
+        let mut headers: HashMap<String, String> = HashMap::new();
+        let mut header_bytes: usize = 0;
+        loop {
+            let line = match self.read_line()? {
+                Some(line) => line,
+                None => return Err(malformed("part headers too long or unterminated")),
+            };
+            header_bytes += line.len() + 2;
+            if header_bytes > self.limits.max_header_bytes {
+                return Err(self.limits.headers_too_large());
+            }
+            if line.is_empty() {
+                break;
+            }
+            match parse_header_line(&line) {
+                Some((k, v)) => {
+                    headers.insert(k, v);
+                }
+                None => {
+                    return Err(malformed(&format!(
+                        "malformed header line \"{}\"",
+                        String::from_utf8_lossy(&line)
+                    )))
+                }
+            }
+        }
+
+        self.state = State::Body;
+        self.part_bytes = 0;
+        Ok(Some(PartReader {
+            reader: self,
+            headers,
+        }))
+    }
+}
+
+impl<R: Read> MultipartReader<R> {
+    /**
+    Read the rest of the body, streaming each part's body into the sink
+    `sink_for` returns for it (given the part's headers), or into memory if
+    it returns `None`. Returns the parts in order, with the sinks, so they
+    can be finished off (a hasher finalized, say).
+
+    ```rust
+    use std::collections::HashMap;
+    use dumb_cgi::{MultipartReader, StreamedPart};
+
+    let body = "--xyz\r\n\
+                Content-Disposition: form-data; name=\"title\"\r\n\
+                \r\n\
+                Holiday\r\n\
+                --xyz\r\n\
+                Content-Disposition: form-data; name=\"photo\"; filename=\"beach.jpg\"\r\n\
+                \r\n\
+                JPEG data\r\n\
+                --xyz--\r\n";
+
+    let mut reader = MultipartReader::new(body.as_bytes(), "xyz");
+    // Uploads go to the sink (a file, usually); other fields stay in memory.
+    let parts = reader
+        .stream_to_sinks(|headers: &HashMap<String, String>| {
+            let is_file = headers
+                .get("content-disposition")
+                .map_or(false, |cd| cd.contains("filename="));
+            Ok(is_file.then(Vec::<u8>::new))
+        })
+        .unwrap();
+
+    match &parts[0] {
+        StreamedPart::Buffered(part) => assert_eq!(part.body, b"Holiday"),
+        _ => panic!("should be in memory"),
+    }
+    match &parts[1] {
+        StreamedPart::Sunk { sink, size, .. } => {
+            assert_eq!(*size, 9);
+            assert_eq!(sink, b"JPEG data");
+        }
+        _ => panic!("should be in the sink"),
+    }
+    ```
+    */
+    pub fn stream_to_sinks<W, F>(&mut self, mut sink_for: F) -> Result<Vec<StreamedPart<W>>, Error>
+    where
+        W: Write,
+        F: FnMut(&HashMap<String, String>) -> Result<Option<W>, Error>,
+    {
+        let mut parts = Vec::new();
+        while let Some(mut part) = self.next_part()? {
+            match sink_for(&part.headers)? {
+                Some(mut sink) => {
+                    let size = part.write_body_to(&mut sink).map_err(sink_error)?;
+                    sink.flush().map_err(sink_error)?;
+                    parts.push(StreamedPart::Sunk {
+                        headers: std::mem::take(&mut part.headers),
+                        size,
+                        sink,
+                    });
+                }
+                None => parts.push(StreamedPart::Buffered(part.into_part()?)),
+            }
+        }
+        Ok(parts)
+    }
+}
+
+/*
+Turn an error from copying a part to a sink back into an `Error`: either
+the one from reading the body (which `PartReader` wraps), or one from
+writing to the sink.
+*/
+fn sink_error(e: std::io::Error) -> Error {
+    if let Some(inner) = e.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+        return inner.clone();
+    }
+    Error {
+        code: 500,
+        message: "Unable to store uploaded data.".to_owned(),
+        details: format!("Error writing multipart part body: {}", &e),
+    }
+}
+
+/**
+A part read by `MultipartReader::stream_to_sinks()`.
+*/
+#[derive(Debug)]
+pub enum StreamedPart<W> {
+    /// A part whose body was read into memory.
+    Buffered(MultipartPart),
+    /// A part whose body was written to a sink.
+    Sunk {
+        /// The part's headers, with names lower-cased (as in `MultipartPart`).
+        headers: HashMap<String, String>,
+        /// How many bytes of body were written to the sink.
+        size: u64,
+        sink: W,
+    },
+}
+
+impl<W> StreamedPart<W> {
+    /// Return the part's headers.
+    pub fn headers(&self) -> &HashMap<String, String> {
+        match self {
+            StreamedPart::Buffered(part) => &part.headers,
+            StreamedPart::Sunk { headers, .. } => headers,
+        }
+    }
+
+    /// Return the form field name of this part (see `MultipartPart::name()`).
+    pub fn name(&self) -> Option<String> {
+        disposition_name(self.headers())
+    }
+
+    /// Return the client-supplied filename of this part, if it's a file
+    /// upload (see `MultipartPart::filename()`).
+    pub fn filename(&self) -> Option<String> {
+        disposition_filename(self.headers())
+    }
+}
+
+impl<R: Read> MultipartReader<Take<R>> {
+    /**
+    Create a reader for the body of `req`, which should have been made
+    with `Request::without_body()`, reading from `source` (stdin, usually).
+
+    Returns an error if the request doesn't have a `multipart/form-data`
+    body.
+    */
+    pub fn for_request(req: &Request, source: R) -> Result<MultipartReader<Take<R>>, Error> {
+        let (boundary, len) = request_boundary(req)?;
+        Ok(MultipartReader::new(source.take(len), boundary))
+    }
+}
+
+/*
+Return the multipart boundary and the length of the body of `req`.
+*/
+fn request_boundary(req: &Request) -> Result<(&str, u64), Error> {
+    let not_multipart = |details: &str| Error {
+        code: 400,
+        message: "Expected a multipart/form-data body.".to_owned(),
+        details: details.to_owned(),
+    };
+    let content_type = req
+        .header("content-type")
+        .ok_or_else(|| not_multipart("No Content-type header"))?;
+    let boundary = multipart_boundary(content_type)
+        .ok_or_else(|| not_multipart("Content-type is not multipart with a boundary"))?;
+    let len: u64 = req
+        .header("content-length")
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| not_multipart("Missing or invalid Content-length header"))?;
+    Ok((boundary, len))
+}
+
+/**
+One part of a multipart body, as returned by `MultipartReader::next_part()`.
+
+Its headers are all read; read its body with the `Read` implementation.
+*/
+pub struct PartReader<'a, R> {
+    reader: &'a mut MultipartReader<R>,
+    /// The part's headers, with names lower-cased (as in `MultipartPart`).
+    pub headers: HashMap<String, String>,
+}
+
+impl<R: Read> PartReader<'_, R> {
+    /// Return the form field name of this part (see `MultipartPart::name()`).
+    pub fn name(&self) -> Option<String> {
+        disposition_name(&self.headers)
+    }
+
+    /// Return the client-supplied filename of this part, if it's a file
+    /// upload (see `MultipartPart::filename()`).
+    pub fn filename(&self) -> Option<String> {
+        disposition_filename(&self.headers)
+    }
+
+    /// Return this part's parsed `content-type` header, if it has a valid one.
+    pub fn content_type(&self) -> Option<MediaType> {
+        MediaType::parse(self.headers.get("content-type")?)
+    }
+
+    /**
+    Copy the rest of this part's body to `sink`, returning the number of
+    bytes copied. A limit being exceeded or the body being malformed is
+    returned as an `InvalidData` error wrapping the crate's `Error`.
+    */
+    pub fn write_body_to<W: Write>(&mut self, sink: &mut W) -> std::io::Result<u64> {
+        std::io::copy(self, sink)
+    }
+
+    /**
+    Copy the rest of this part's body to a temporary file in `dir`, which
+    is removed when the returned `TempUpload` is dropped unless it's
+    persisted (see `MultipartPart::save_temp()`).
+    */
+    pub fn save_temp<P: AsRef<Path>>(&mut self, dir: P) -> Result<TempUpload, Error> {
+        let (path, mut f) = create_unique(dir.as_ref(), &temp_upload_name())?;
+        match self.write_body_to(&mut f) {
+            Ok(size) => Ok(TempUpload::new(SavedFile {
+                path,
+                size,
+                filename: self.filename(),
+            })),
+            Err(e) => {
+                _ = std::fs::remove_file(&path);
+                Err(sink_error(e))
+            }
+        }
+    }
+
+    /// Read the rest of this part's body into memory, returning the whole part.
+    pub fn into_part(mut self) -> Result<MultipartPart, Error> {
+        let mut body = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.reader.read_body(&mut chunk)? {
+                0 => break,
+                n => body.extend_from_slice(&chunk[..n]),
+            }
+        }
+        Ok(MultipartPart {
+            headers: std::mem::take(&mut self.headers),
+            body,
+        })
+    }
+}
+
+impl<R: Read> Read for PartReader<'_, R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        self.reader
+            .read_body(out)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+/**
+One part of a multipart body held in a `MultipartBuffer`: its headers, and
+where in the buffer its body is.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartRange {
+    /// The part's headers, with names lower-cased (as in `MultipartPart`).
+    pub headers: HashMap<String, String>,
+    /// The offsets of the part's body in the buffer.
+    pub body: Range<usize>,
+}
+
+impl PartRange {
+    /// Return the form field name of this part (see `MultipartPart::name()`).
+    pub fn name(&self) -> Option<String> {
+        disposition_name(&self.headers)
+    }
+
+    /// Return the client-supplied filename of this part, if it's a file
+    /// upload (see `MultipartPart::filename()`).
+    pub fn filename(&self) -> Option<String> {
+        disposition_filename(&self.headers)
+    }
+
+    /// Return this part's parsed `content-type` header, if it has a valid one.
+    pub fn content_type(&self) -> Option<MediaType> {
+        MediaType::parse(self.headers.get("content-type")?)
+    }
+}
+
+/**
+A parsed multipart body that keeps the body's bytes in one buffer, and
+hands out slices of it, rather than copying each part's body into a
+`Vec` of its own (as `MultipartForm` does). For large uploads this halves
+the memory used.
+
+The limits and the handling of malformed parts are the same as for
+`parse_multipart_with_limits()`.
+
+```rust
+use dumb_cgi::MultipartBuffer;
+
+let body = b"--xyz\r\n\
+             Content-Disposition: form-data; name=\"title\"\r\n\
+             \r\n\
+             Holiday\r\n\
+             --xyz\r\n\
+             Content-Disposition: form-data; name=\"photo\"; filename=\"beach.jpg\"\r\n\
+             \r\n\
+             JPEG data\r\n\
+             --xyz--\r\n";
+
+let form = MultipartBuffer::parse(body.to_vec(), "xyz").unwrap();
+assert_eq!(form.get("title"), Some(&b"Holiday"[..]));
+
+let photo = &form.parts()[1];
+assert_eq!(photo.filename().as_deref(), Some("beach.jpg"));
+assert_eq!(form.body_of(photo), b"JPEG data");
+```
+*/
+#[derive(Debug, Clone)]
+pub struct MultipartBuffer {
+    bytes: Vec<u8>,
+    parts: Vec<PartRange>,
+    skipped: Vec<Error>,
+    preamble: Range<usize>,
+    epilogue: Range<usize>,
+}
+
+impl MultipartBuffer {
+    /**
+    Parse `bytes` as a multipart body with the given `boundary` (see
+    `multipart_boundary()`), with the default `MultipartLimits`.
+    */
+    pub fn parse(bytes: Vec<u8>, boundary: &str) -> Result<MultipartBuffer, Error> {
+        MultipartBuffer::parse_with_limits(bytes, boundary, &MultipartLimits::default())
+    }
+
+    /// Like `parse()`, but with `limits` instead of the default `MultipartLimits`.
+    pub fn parse_with_limits(
+        bytes: Vec<u8>,
+        boundary: &str,
+        limits: &MultipartLimits,
+    ) -> Result<MultipartBuffer, Error> {
+        let split = split_multipart(&bytes, boundary, limits, &mut Vec::new())?;
+        Ok(MultipartBuffer {
+            bytes,
+            parts: split.parts,
+            skipped: split.skipped,
+            preamble: split.preamble,
+            epilogue: split.epilogue,
+        })
+    }
+
+    /**
+    Read and parse the body of `req`, which should have been made with
+    `Request::without_body()`, from `source` (stdin, usually).
+    */
+    pub fn for_request<R: Read>(
+        req: &Request,
+        source: R,
+        limits: &MultipartLimits,
+    ) -> Result<MultipartBuffer, Error> {
+        let (boundary, len) = request_boundary(req)?;
+        let mut bytes = Vec::with_capacity(len.min(usize::MAX as u64) as usize);
+        source
+            .take(len)
+            .read_to_end(&mut bytes)
+            .map_err(read_error)?;
+        if (bytes.len() as u64) < len {
+            return Err(malformed("body shorter than its Content-length"));
+        }
+        MultipartBuffer::parse_with_limits(bytes, boundary, limits)
+    }
+
+    /// Return the parts, in the order they appeared in the body.
+    pub fn parts(&self) -> &[PartRange] {
+        &self.parts
+    }
+
+    /// Return the body of `part` (which should be one of this buffer's parts).
+    pub fn body_of(&self, part: &PartRange) -> &[u8] {
+        &self.bytes[part.body.clone()]
+    }
+
+    /// Iterate over the parts along with their bodies.
+    pub fn iter(&self) -> impl Iterator<Item = (&PartRange, &[u8])> {
+        self.parts.iter().map(|p| (p, self.body_of(p)))
+    }
+
+    /// Return the body of the first part with the form field name `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.parts
+            .iter()
+            .find(|p| p.name().as_deref() == Some(name))
+            .map(|p| self.body_of(p))
+    }
+
+    /// Iterate over the bodies of every part with the form field name
+    /// `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> {
+        self.parts
+            .iter()
+            .filter(move |p| p.name().as_deref() == Some(name))
+            .map(|p| self.body_of(p))
+    }
+
+    /// Return the errors for any parts that were skipped because they were
+    /// malformed, being strict (see `MultipartForm::skipped_parts()`).
+    pub fn skipped_parts(&self) -> &[Error] {
+        &self.skipped
+    }
+
+    /// Return anything before the first boundary (see `MultipartForm::preamble()`).
+    pub fn preamble(&self) -> &[u8] {
+        &self.bytes[self.preamble.clone()]
+    }
+
+    /// Return anything after the final boundary (see `MultipartForm::epilogue()`).
+    pub fn epilogue(&self) -> &[u8] {
+        &self.bytes[self.epilogue.clone()]
+    }
+
+    /// Return the whole body.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Return the whole body, discarding the parsed parts.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
@@ -4,10 +4,21 @@ available to a CGI program, together with all the attendant constants and
 functions required to generate it.
 */
 
-use std::collections::HashMap;
-use std::io::Read;
-
-use crate::Error;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::{IsTerminal, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::charset::{decode_text, Charset};
+use crate::flash::{Flash, Flasher};
+use crate::multipart::PartRange;
+use crate::timing::Timings;
+use crate::{base64, Error, Extensions, Response};
 
 const MULTIPART_CONTENT_TYPE: &str = "multipart/form-data";
 const MULTIPART_BOUNDARY: &str = "boundary=";
@@ -16,17 +27,91 @@ const HTTP_NEWLINE: &[u8] = "\r\n".as_bytes();
 /// an HTTP header being passed on to the script.
 const HTTP_PREFIX: &str = "HTTP_";
 
+/// First line of a request capture (see `Request::to_capture()`).
+const CAPTURE_MAGIC: &str = "dumb_cgi capture 1";
+
+/*
+The parts of a request that a capture records, shared by the text format
+of `Request::to_capture()` and the `serde` feature's `Serialize` impl.
+*/
+pub(crate) struct Captured<'a> {
+    pub(crate) vars: Vec<(&'a str, &'a str)>,
+    pub(crate) headers: Vec<(&'a str, String)>,
+    pub(crate) body: Option<Vec<u8>>,
+    pub(crate) error: Option<&'a Error>,
+}
+
 const PLUS: u8 = b'+';
 const PERCENT: u8 = b'%';
 const SPACE: u8 = b' ';
 
 /*
+Decode the `%XX` escape starting at `bytes[idx]`.
+*/
+fn decode_escape(bytes: &[u8], idx: usize) -> Result<u8, String> {
+    match bytes.get(idx + 1..idx + 3) {
+        Some(substr) => match std::str::from_utf8(substr) {
+            Ok(txt) => match u8::from_str_radix(txt, 16) {
+                Ok(n) => Ok(n),
+                Err(e) => Err(format!("Error %-decoding at index {}: {}", idx, &e)),
+            },
+            Err(e) => Err(format!("Error %-decoding at index {}: {}", idx, &e)),
+        },
+        None => Err("Query string ended during escape sequence.".to_owned()),
+    }
+}
+
+/**
 Attempt to decode a %-encoded string (like in a CGI query string,
 which is exactly what this function is used for).
+
+`+` characters are decoded as spaces. On failure, the returned `String`
+describes what went wrong.
+
+```rust
+# use dumb_cgi::url_decode;
+assert_eq!(url_decode("dumb+cgi%21").unwrap(), "dumb cgi!");
+assert!(url_decode("100%").is_err());
+```
 */
-fn url_decode(qstr: &str) -> Result<String, String> {
-    let bytes = qstr.as_bytes();
+pub fn url_decode(qstr: &str) -> Result<String, String> {
     let mut rbytes: Vec<u8> = Vec::with_capacity(qstr.len());
+    url_decode_into(qstr, &mut rbytes)?;
+
+    rbytes.shrink_to_fit();
+    match String::from_utf8(rbytes) {
+        Ok(s) => Ok(s),
+        Err(e) => {
+            let estr = format!("%-decoded query string not UTF-8: {}", &e);
+            Err(estr)
+        }
+    }
+}
+
+/**
+Like `url_decode()`, but appends the decoded bytes to `dest` instead of
+allocating a new `String`, so one buffer can be reused for many values.
+
+The decoded bytes aren't checked to be UTF-8. On failure, `dest` is left
+as it was.
+
+```rust
+# use dumb_cgi::url_decode_into;
+let mut buf = Vec::new();
+for value in ["a+b", "100%25"] {
+    buf.clear();
+    url_decode_into(value, &mut buf).unwrap();
+    // ...use buf...
+}
+assert_eq!(buf, b"100%");
+assert!(url_decode_into("%4", &mut buf).is_err());
+assert_eq!(buf, b"100%");
+```
+*/
+pub fn url_decode_into(src: &str, dest: &mut Vec<u8>) -> Result<(), String> {
+    let bytes = src.as_bytes();
+    let original_len = dest.len();
+    dest.reserve(bytes.len());
     let mut idx: usize = 0;
 
     while idx < bytes.len() {
@@ -34,48 +119,99 @@ fn url_decode(qstr: &str) -> Result<String, String> {
         // to be less than the length of `bytes`.
         let &b = unsafe { bytes.get_unchecked(idx) };
         if b == PLUS {
-            rbytes.push(SPACE);
+            dest.push(SPACE);
             idx += 1;
         } else if b == PERCENT {
-            let (start, end) = (idx + 1, idx + 3);
-            match bytes.get(start..end) {
-                Some(substr) => match std::str::from_utf8(substr) {
-                    Ok(txt) => match u8::from_str_radix(txt, 16) {
-                        Ok(n) => {
-                            rbytes.push(n);
-                            idx += 3;
-                        }
-                        Err(e) => {
-                            let estr = format!("Error %-decoding at index {}: {}", idx, &e);
-                            return Err(estr);
-                        }
-                    },
-                    Err(e) => {
-                        let estr = format!("Error %-decoding at index {}: {}", idx, &e);
-                        return Err(estr);
-                    }
-                },
-                None => {
-                    let estr = "Query string ended during escape sequence.".to_owned();
-                    return Err(estr);
+            match decode_escape(bytes, idx) {
+                Ok(n) => {
+                    dest.push(n);
+                    idx += 3;
+                }
+                Err(e) => {
+                    dest.truncate(original_len);
+                    return Err(e);
                 }
             }
         } else {
-            rbytes.push(b);
+            dest.push(b);
             idx += 1;
         }
     }
 
-    rbytes.shrink_to_fit();
-    match String::from_utf8(rbytes) {
-        Ok(s) => Ok(s),
-        Err(e) => {
-            let estr = format!("%-decoded query string not UTF-8: {}", &e);
-            Err(estr)
+    Ok(())
+}
+
+/**
+Decode `s` in place, without allocating.
+
+Decoding never makes a string longer, so this just shuffles the bytes
+of `s` down. On failure `s` is left empty (part of it will already have
+been overwritten).
+
+```rust
+# use dumb_cgi::url_decode_in_place;
+let mut s = "dumb+cgi%21".to_owned();
+url_decode_in_place(&mut s).unwrap();
+assert_eq!(s, "dumb cgi!");
+
+let mut bad = "%FF".to_owned();
+assert!(url_decode_in_place(&mut bad).is_err());
+assert!(bad.is_empty());
+```
+*/
+pub fn url_decode_in_place(s: &mut String) -> Result<(), String> {
+    if !s.bytes().any(|b| b == PLUS || b == PERCENT) {
+        return Ok(());
+    }
+
+    let mut bytes = std::mem::take(s).into_bytes();
+    let (mut read, mut write) = (0, 0);
+    while read < bytes.len() {
+        let b = match bytes[read] {
+            PLUS => {
+                read += 1;
+                SPACE
+            }
+            PERCENT => {
+                let n = decode_escape(&bytes, read)?;
+                read += 3;
+                n
+            }
+            b => {
+                read += 1;
+                b
+            }
+        };
+        bytes[write] = b;
+        write += 1;
+    }
+    bytes.truncate(write);
+
+    match String::from_utf8(bytes) {
+        Ok(decoded) => {
+            *s = decoded;
+            Ok(())
         }
+        Err(e) => Err(format!("%-decoded query string not UTF-8: {}", &e)),
     }
 }
 
+/**
+Percent-encode `s` for use in a query string or URL path segment.
+
+Everything except ASCII letters, digits, and `-._~` is encoded (including
+spaces, which become `%20`). See also `escape::url_component()`, for
+writing it without building a `String` first.
+
+```rust
+# use dumb_cgi::url_encode;
+assert_eq!(url_encode("dumb cgi/1.0?"), "dumb%20cgi%2F1.0%3F");
+```
+*/
+pub fn url_encode(s: &str) -> String {
+    crate::escape::url_component(s).to_string()
+}
+
 /*
 Return the offset of the beginning of `needle` in `haystack` (or `None`
 if it's not there).
@@ -84,21 +220,98 @@ This is essentially analagous to the
 [`str.find()`](https://doc.rust-lang.org/std/primitive.str.html#method.find)
 method with another `str` as the argument, and really should be a standard
 `slice` method.
+
+It's meant for short needles (like newlines) that are searched for many
+times; the first byte is scanned for, and only its occurrences compared.
+Long needles searched for repeatedly (like multipart boundaries) should
+use a `Finder` instead.
 */
-fn slicey_find<T: Eq>(haystack: &[T], needle: &[T]) -> Option<usize> {
-    // slice::windows() panics if asked for windows of length 0,
-    // so let's just return early and avoid that situation.
-    if needle.is_empty() {
-        return None;
+pub(crate) fn slicey_find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let (&first, rest) = needle.split_first()?;
+    let last_start = haystack.len().checked_sub(needle.len())?;
+    let mut pos = 0;
+    while pos <= last_start {
+        let n = haystack[pos..=last_start]
+            .iter()
+            .position(|&b| b == first)?;
+        pos += n;
+        if &haystack[pos + 1..pos + needle.len()] == rest {
+            return Some(pos);
+        }
+        pos += 1;
     }
+    None
+}
 
-    for (n, w) in haystack.windows(needle.len()).enumerate() {
-        if w == needle {
-            return Some(n);
+/*
+A substring searcher for a fixed needle, using the Boyer-Moore-Horspool
+algorithm: on a mismatch it skips ahead by up to the needle's length, so
+the longer the needle (a multipart boundary is usually 30-70 bytes) the
+less of the haystack it looks at.
+*/
+#[derive(Debug, Clone)]
+pub(crate) struct Finder {
+    needle: Vec<u8>,
+    /// How far to shift the window when its last byte is a given byte.
+    skip: [usize; 256],
+}
+
+impl Finder {
+    pub(crate) fn new(needle: &[u8]) -> Finder {
+        let mut skip = [needle.len().max(1); 256];
+        if let Some((_, init)) = needle.split_last() {
+            for (n, &b) in init.iter().enumerate() {
+                skip[b as usize] = needle.len() - 1 - n;
+            }
+        }
+        Finder {
+            needle: needle.to_vec(),
+            skip,
         }
     }
 
-    None
+    pub(crate) fn needle(&self) -> &[u8] {
+        &self.needle
+    }
+
+    /*
+    Return the index of the first occurrence of the needle in `haystack`.
+    */
+    pub(crate) fn find(&self, haystack: &[u8]) -> Option<usize> {
+        let m = self.needle.len();
+        if m < 4 {
+            return slicey_find(haystack, &self.needle);
+        }
+        let last = self.needle[m - 1];
+        let mut pos = 0;
+        while pos + m <= haystack.len() {
+            let b = haystack[pos + m - 1];
+            if b == last && haystack[pos..pos + m - 1] == self.needle[..m - 1] {
+                return Some(pos);
+            }
+            pos += self.skip[b as usize];
+        }
+        None
+    }
+}
+
+/*
+The form field name from a multipart part's `content-disposition` header
+(see `MultipartPart::name()`).
+*/
+pub(crate) fn disposition_name(headers: &HashMap<String, String>) -> Option<String> {
+    header_param(headers.get("content-disposition")?, "name")
+}
+
+/*
+The filename from a multipart part's `content-disposition` header (see
+`MultipartPart::filename()`).
+*/
+pub(crate) fn disposition_filename(headers: &HashMap<String, String>) -> Option<String> {
+    let cd = headers.get("content-disposition")?;
+    header_param(cd, "filename*")
+        .and_then(|v| decode_ext_value(&v))
+        .or_else(|| header_param(cd, "filename"))
 }
 
 /**
@@ -109,19 +322,866 @@ converted to UTF-8. Names have been lower-cased and stripped of surrounding
 whitespace. Values have had their _leading_ whitespace stripped, but any
 trailing whitespace has been left intact.
 */
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MultipartPart {
     pub headers: HashMap<String, String>,
     pub body: Vec<u8>,
 }
 
+/**
+Return the value of the parameter `key` (case-insensitively) from a
+header value like `form-data; name="field"; filename="a.txt"`.
+
+Quoted values have their quotes removed and backslash escapes undone.
+
+```rust
+# use dumb_cgi::header_param;
+let cd = r#"form-data; name="say \"hi\""; size=12"#;
+assert_eq!(header_param(cd, "name").as_deref(), Some("say \"hi\""));
+assert_eq!(header_param(cd, "SIZE").as_deref(), Some("12"));
+assert_eq!(header_param(cd, "form-data"), None);
+```
+*/
+pub fn header_param(value: &str, key: &str) -> Option<String> {
+    header_params(value)
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v)
+}
+
+/*
+Split the `; name=value` parameters off a header value (skipping the
+value itself and any parameters without an `=`), unquoting any quoted
+values. Names are returned as they appear.
+*/
+fn header_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = match value.find(';') {
+        Some(idx) => &value[idx + 1..],
+        None => return params,
+    };
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        if rest.is_empty() {
+            return params;
+        }
+        let name_end = rest.find(['=', ';']).unwrap_or(rest.len());
+        let name = rest[..name_end].trim();
+        rest = &rest[name_end..];
+
+        let after = match rest.strip_prefix('=') {
+            None => continue,
+            Some(after) => after.trim_start(),
+        };
+        let param_value = if let Some(quoted) = after.strip_prefix('"') {
+            let mut unquoted = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((idx, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            unquoted.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = idx + 1;
+                        break;
+                    }
+                    c => unquoted.push(c),
+                }
+            }
+            rest = &quoted[end..];
+            unquoted
+        } else {
+            let end = after.find(';').unwrap_or(after.len());
+            rest = &after[end..];
+            after[..end].trim_end().to_owned()
+        };
+        params.push((name.to_owned(), param_value));
+    }
+}
+
+/**
+A parsed media type, like `text/html; charset=utf-8`.
+
+The type and subtype are lower-cased; parameter names are lower-cased,
+and parameter values are unquoted but otherwise left alone.
+
+```rust
+# use dumb_cgi::MediaType;
+let mt = MediaType::parse("Text/HTML; Charset=\"UTF-8\"").unwrap();
+assert_eq!(mt.essence(), "text/html");
+assert_eq!(mt.param("charset"), Some("UTF-8"));
+assert!(MediaType::parse("not a media type").is_none());
+```
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    /// The top-level type (the `text` in `text/html`).
+    pub main: String,
+    /// The subtype (the `html` in `text/html`).
+    pub sub: String,
+    /// `(name, value)` parameters, in order.
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    /// Parse a `Content-Type`-style header value.
+    pub fn parse(value: &str) -> Option<MediaType> {
+        let essence = value.split(';').next()?.trim();
+        let (main, sub) = essence.split_once('/')?;
+        let valid = |s: &str| {
+            !s.is_empty()
+                && s.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b))
+        };
+        if !valid(main) || !valid(sub) {
+            return None;
+        }
+        Some(MediaType {
+            main: main.to_ascii_lowercase(),
+            sub: sub.to_ascii_lowercase(),
+            params: header_params(value)
+                .into_iter()
+                .map(|(k, v)| (k.to_ascii_lowercase(), v))
+                .collect(),
+        })
+    }
+
+    /// Return the type and subtype without parameters, like `text/html`.
+    pub fn essence(&self) -> String {
+        format!("{}/{}", &self.main, &self.sub)
+    }
+
+    /// Return the value of the parameter `name` (case-insensitively).
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl std::fmt::Display for MediaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", &self.main, &self.sub)?;
+        for (k, v) in self.params.iter() {
+            let is_token = !v.is_empty()
+                && v.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+~`|'*%".contains(&b));
+            if is_token {
+                write!(f, "; {}={}", k, v)?;
+            } else {
+                write!(
+                    f,
+                    "; {}=\"{}\"",
+                    k,
+                    v.replace('\\', "\\\\").replace('"', "\\\"")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+Decode an RFC 5987 extended parameter value, like
+`UTF-8'en'%E2%82%AC%20rates`. Only the UTF-8 and ISO-8859-1 charsets are
+supported; anything else (or a malformed value) returns `None`.
+*/
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut pieces = value.splitn(3, '\'');
+    let charset = pieces.next()?;
+    let _language = pieces.next()?;
+    let encoded = pieces.next()?.as_bytes();
+
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut idx = 0;
+    while idx < encoded.len() {
+        if encoded[idx] == PERCENT {
+            let hex = std::str::from_utf8(encoded.get(idx + 1..idx + 3)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            idx += 3;
+        } else {
+            bytes.push(encoded[idx]);
+            idx += 1;
+        }
+    }
+
+    if charset.eq_ignore_ascii_case("utf-8") {
+        String::from_utf8(bytes).ok()
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.into_iter().map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+impl MultipartPart {
+    /**
+    Return the form field name from this part's `content-disposition`
+    header.
+
+    ```rust
+    # use dumb_cgi::{testing::MockRequest, Body};
+    let body = "--xyz\r\n\
+                Content-Disposition: form-data; name=\"upload\"; filename=\"a b.txt\"\r\n\
+                \r\n\
+                file contents\r\n\
+                --xyz--\r\n";
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=xyz")
+        .body(body)
+        .build()
+        .unwrap();
+
+    if let Body::Multipart(parts) = req.body() {
+        assert_eq!(parts[0].name().as_deref(), Some("upload"));
+        assert_eq!(parts[0].filename().as_deref(), Some("a b.txt"));
+    } else {
+        panic!("body should be multipart");
+    }
+    ```
+    */
+    pub fn name(&self) -> Option<String> {
+        disposition_name(&self.headers)
+    }
+
+    /**
+    Return the client-supplied filename from this part's
+    `content-disposition` header, if this part is a file upload.
+
+    An extended `filename*` parameter (as per
+    [RFC 6266](https://www.rfc-editor.org/rfc/rfc6266), used for non-ASCII
+    filenames) is preferred if present and decodable, falling back to
+    plain `filename`.
+
+    This is whatever the client sent; don't use it as a path without
+    sanitizing it.
+
+    ```rust
+    # use std::collections::HashMap;
+    # use dumb_cgi::MultipartPart;
+    let part = MultipartPart {
+        headers: HashMap::from([(
+            "content-disposition".to_owned(),
+            "form-data; name=\"f\"; filename=\"naive.txt\"; \
+             filename*=UTF-8''na%C3%AFve.txt".to_owned(),
+        )]),
+        body: Vec::new(),
+    };
+    assert_eq!(part.filename().as_deref(), Some("naïve.txt"));
+    ```
+    */
+    pub fn filename(&self) -> Option<String> {
+        disposition_filename(&self.headers)
+    }
+
+    /**
+    Return this part's parsed `content-type` header, if it has a valid one.
+    (Parts without one should be treated as `text/plain`.)
+    */
+    pub fn content_type(&self) -> Option<MediaType> {
+        MediaType::parse(self.headers.get("content-type")?)
+    }
+
+    /**
+    Decode this part's body as text, using the charset declared in its
+    `content-type` header (or UTF-8 if none is declared).
+
+    The charsets `Charset` knows are supported. An unsupported charset,
+    or a body that isn't valid in the declared charset, is an error.
+
+    ```rust
+    # use std::collections::HashMap;
+    # use dumb_cgi::MultipartPart;
+    let part = MultipartPart {
+        headers: HashMap::from([(
+            "content-type".to_owned(),
+            "text/plain; charset=iso-8859-1".to_owned(),
+        )]),
+        body: b"caf\xe9".to_vec(),
+    };
+    assert_eq!(part.text().unwrap(), "café");
+    ```
+    */
+    pub fn text(&self) -> Result<String, Error> {
+        self.text_or(Charset::Utf8)
+    }
+
+    /**
+    Like `text()`, but decoding the body from `default` if the part
+    doesn't declare a charset (browsers don't, for ordinary fields; see
+    `Request::form_charset()`).
+    */
+    pub fn text_or(&self, default: Charset) -> Result<String, Error> {
+        let bad_text = |details: String| Error {
+            code: 400,
+            message: "Invalid form data.".to_owned(),
+            details,
+        };
+        let charset = match self
+            .content_type()
+            .and_then(|mt| mt.param("charset").map(str::to_owned))
+        {
+            Some(label) => Charset::from_label(&label)
+                .ok_or_else(|| bad_text(format!("Unsupported charset {:?}", &label)))?,
+            None => default,
+        };
+        charset
+            .decode(&self.body)
+            .map_err(|e| bad_text(format!("Multipart body {}", &e)))
+    }
+
+    /**
+    Write this part's body to `sink` (a file, a hasher, or anything else
+    that implements `Write`), returning the number of bytes written.
+
+    ```rust
+    # use std::collections::HashMap;
+    # use dumb_cgi::MultipartPart;
+    let part = MultipartPart { headers: HashMap::new(), body: b"data".to_vec() };
+    let mut sink: Vec<u8> = Vec::new();
+    assert_eq!(part.write_body_to(&mut sink).unwrap(), 4);
+    assert_eq!(sink, b"data");
+    ```
+    */
+    pub fn write_body_to<W: Write>(&self, sink: &mut W) -> std::io::Result<u64> {
+        sink.write_all(&self.body)?;
+        Ok(self.body.len() as u64)
+    }
+
+    /**
+    Write this part's body to a new file in `dir`, named after the
+    client-supplied filename (sanitized with `sanitize_filename()`), or
+    `upload` if there isn't one.
+
+    An existing file is never overwritten; if the name is taken, a number
+    is added to it (`photo-1.jpg`, `photo-2.jpg`, ...).
+
+    ```rust
+    # use std::collections::HashMap;
+    # use dumb_cgi::MultipartPart;
+    let dir = std::env::temp_dir().join("dumb_cgi_save_doctest");
+    # _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let part = MultipartPart {
+        headers: HashMap::from([(
+            "content-disposition".to_owned(),
+            "form-data; name=\"f\"; filename=\"../../etc/passwd\"".to_owned(),
+        )]),
+        body: b"not really".to_vec(),
+    };
+
+    let saved = part.save_to(&dir).unwrap();
+    assert_eq!(saved.path, dir.join("passwd"));
+    assert_eq!(saved.size, 10);
+    let again = part.save_to(&dir).unwrap();
+    assert_eq!(again.path, dir.join("passwd-1"));
+    # std::fs::remove_dir_all(&dir).unwrap();
+    ```
+    */
+    pub fn save_to<P: AsRef<Path>>(&self, dir: P) -> Result<SavedFile, Error> {
+        let name = sanitize_filename(&self.filename().unwrap_or_default());
+        let (path, mut f) = create_unique(dir.as_ref(), &name)?;
+        if let Err(e) = f.write_all(&self.body) {
+            _ = std::fs::remove_file(&path);
+            return Err(save_error(&path, e));
+        }
+        Ok(SavedFile {
+            path,
+            size: self.body.len() as u64,
+            filename: self.filename(),
+        })
+    }
+
+    /**
+    Like `save_to()`, but the file is given a temporary name, and removed
+    when the returned `TempUpload` is dropped, unless it's persisted.
+    */
+    pub fn save_temp<P: AsRef<Path>>(&self, dir: P) -> Result<TempUpload, Error> {
+        let (path, mut f) = create_unique(dir.as_ref(), &temp_upload_name())?;
+        if let Err(e) = f.write_all(&self.body) {
+            _ = std::fs::remove_file(&path);
+            return Err(save_error(&path, e));
+        }
+        Ok(TempUpload::new(SavedFile {
+            path,
+            size: self.body.len() as u64,
+            filename: self.filename(),
+        }))
+    }
+}
+
+/*
+Create a new file named `name` in `dir`, or, if that's taken, with a
+number added to the name (`photo-1.jpg`, `photo-2.jpg`, ...).
+*/
+pub(crate) fn create_unique(dir: &Path, name: &str) -> Result<(PathBuf, std::fs::File), Error> {
+    let (stem, ext) = match name.rfind('.') {
+        Some(idx) if idx > 0 => (&name[..idx], &name[idx..]),
+        _ => (name, ""),
+    };
+    for n in 0..1000 {
+        let path = match n {
+            0 => dir.join(name),
+            n => dir.join(format!("{}-{}{}", stem, n, ext)),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(f) => return Ok((path, f)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(save_error(&path, e)),
+        }
+    }
+    Err(save_error(
+        &dir.join(name),
+        std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "too many files with this name",
+        ),
+    ))
+}
+
+/*
+The name a temporary upload file is first tried with.
+*/
+pub(crate) fn temp_upload_name() -> String {
+    format!("dumb_cgi-upload-{}.tmp", std::process::id())
+}
+
+fn save_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: 500,
+        message: "Unable to save uploaded file.".to_owned(),
+        details: format!("Error writing uploaded file {}: {}", path.display(), &e),
+    }
+}
+
+/**
+Where an uploaded file was saved by `MultipartPart::save_to()`.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedFile {
+    /// Path of the new file.
+    pub path: PathBuf,
+    /// Number of bytes written.
+    pub size: u64,
+    /// The filename the client sent (unsanitized), if any.
+    pub filename: Option<String>,
+}
+
+/**
+An uploaded file saved under a temporary name, which is removed when this
+is dropped unless `persist()` or `persist_to()` is called first.
+
+A process that crashes (or is killed) doesn't get to remove its temporary
+files; point a `gc::Gc` at the directory to clear those up.
+
+```rust
+# use std::collections::HashMap;
+# use dumb_cgi::MultipartPart;
+let dir = std::env::temp_dir().join("dumb_cgi_temp_upload_doctest");
+# _ = std::fs::remove_dir_all(&dir);
+std::fs::create_dir_all(&dir).unwrap();
+let part = MultipartPart {
+    headers: HashMap::from([(
+        "content-disposition".to_owned(),
+        "form-data; name=\"f\"; filename=\"notes.txt\"".to_owned(),
+    )]),
+    body: b"some notes".to_vec(),
+};
+
+let upload = part.save_temp(&dir).unwrap();
+let temp_path = upload.path().to_owned();
+assert!(temp_path.exists());
+drop(upload);
+// Not persisted, so it's gone.
+assert!(!temp_path.exists());
+
+let upload = part.save_temp(&dir).unwrap();
+let saved = upload.persist_to(dir.join("notes.txt")).unwrap();
+assert_eq!(std::fs::read(&saved.path).unwrap(), b"some notes");
+# std::fs::remove_dir_all(&dir).unwrap();
+```
+*/
+#[derive(Debug)]
+pub struct TempUpload {
+    file: SavedFile,
+    persisted: bool,
+}
+
+impl TempUpload {
+    /// Take charge of removing an already-saved file.
+    pub fn new(file: SavedFile) -> TempUpload {
+        TempUpload {
+            file,
+            persisted: false,
+        }
+    }
+
+    /// Return the path of the temporary file.
+    pub fn path(&self) -> &Path {
+        &self.file.path
+    }
+
+    /// Return the number of bytes in the file.
+    pub fn size(&self) -> u64 {
+        self.file.size
+    }
+
+    /// Return the filename the client sent (unsanitized), if any.
+    pub fn filename(&self) -> Option<&str> {
+        self.file.filename.as_deref()
+    }
+
+    /// Keep the file where it is, returning where that is.
+    pub fn persist(self) -> SavedFile {
+        let mut upload = self;
+        upload.persisted = true;
+        upload.file.clone()
+    }
+
+    /**
+    Move the file to `dest` (which must be on the same filesystem) and
+    keep it there. If it can't be moved, it's still removed on drop.
+    */
+    pub fn persist_to<P: AsRef<Path>>(self, dest: P) -> Result<SavedFile, Error> {
+        let dest = dest.as_ref();
+        std::fs::rename(&self.file.path, dest).map_err(|e| save_error(dest, e))?;
+        let mut file = self.persist();
+        file.path = dest.to_owned();
+        Ok(file)
+    }
+}
+
+impl Drop for TempUpload {
+    fn drop(&mut self) {
+        if !self.persisted {
+            _ = std::fs::remove_file(&self.file.path);
+        }
+    }
+}
+
+/**
+Turn a client-supplied filename into one that's safe to create in a
+directory of your choosing.
+
+Only the last component of a path (with either kind of slash) is kept;
+control characters and characters that are troublesome in filenames on
+some systems are replaced with `_`; leading dots (which would make a
+hidden file, or `..`), trailing dots (which Windows drops), and
+surrounding whitespace are removed; and the result is cut to at most 200
+bytes. An empty result becomes `upload`, and a name Windows reserves for
+a device (like `CON`, `NUL`, or `COM1`, with or without an extension)
+gets a `_` in front.
+
+```rust
+# use dumb_cgi::sanitize_filename;
+assert_eq!(sanitize_filename("C:\\Users\\dan\\My Photo.jpg"), "My Photo.jpg");
+assert_eq!(sanitize_filename("/etc/passwd"), "passwd");
+assert_eq!(sanitize_filename(".."), "upload");
+assert_eq!(sanitize_filename(". ."), "upload");
+assert_eq!(sanitize_filename(".htaccess"), "htaccess");
+assert_eq!(sanitize_filename("a<b>:c?.txt"), "a_b__c_.txt");
+assert_eq!(sanitize_filename("notes.txt. ."), "notes.txt");
+assert_eq!(sanitize_filename("nul.tar.gz"), "_nul.tar.gz");
+```
+*/
+pub fn sanitize_filename(name: &str) -> String {
+    const MAX_LEN: usize = 200;
+    let last = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let mut clean = String::with_capacity(last.len());
+    for c in last.chars() {
+        let c = if c.is_control() || "<>:\"|?*".contains(c) {
+            '_'
+        } else {
+            c
+        };
+        if clean.len() + c.len_utf8() > MAX_LEN {
+            break;
+        }
+        clean.push(c);
+    }
+    let dot_or_space = |c: char| c == '.' || c.is_whitespace();
+    let clean = clean
+        .trim_start_matches(dot_or_space)
+        .trim_end_matches(dot_or_space);
+    if clean.is_empty() {
+        "upload".to_owned()
+    } else if is_windows_device_name(clean) {
+        let mut prefixed = format!("_{}", clean);
+        while prefixed.len() > MAX_LEN {
+            prefixed.pop();
+        }
+        prefixed.trim_end_matches(dot_or_space).to_owned()
+    } else {
+        clean.to_owned()
+    }
+}
+
+/*
+Whether Windows would take the file `name` for a device, whatever its
+extension: `CON`, `PRN`, `AUX`, `NUL`, `CONIN$`, `CONOUT$`, and `COM` and
+`LPT` followed by a digit from 1 to 9 (or a superscript 1, 2, or 3), in
+any case.
+*/
+fn is_windows_device_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    let stem = stem.to_ascii_uppercase();
+    if matches!(
+        stem.as_str(),
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$"
+    ) {
+        return true;
+    }
+    ["COM", "LPT"].iter().any(|port| {
+        let mut n = stem.strip_prefix(port).unwrap_or("").chars();
+        matches!(
+            (n.next(), n.next()),
+            (Some('1'..='9' | '\u{b9}' | '\u{b2}' | '\u{b3}'), None)
+        )
+    })
+}
+
+/**
+The parts of a `multipart/form-data` body, in the order they were sent.
+
+This dereferences to a slice of `MultipartPart`s, so it can be indexed
+and iterated over directly, and also has methods for looking up parts by
+their form field names.
+
+```rust
+# use dumb_cgi::{testing::MockRequest, Body};
+let body = "--xyz\r\n\
+            Content-Disposition: form-data; name=\"tag\"\r\n\
+            \r\n\
+            red\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"tag\"\r\n\
+            \r\n\
+            blue\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"a.jpg\"\r\n\
+            \r\n\
+            JPEG data\r\n\
+            --xyz--\r\n";
+let req = MockRequest::post("/")
+    .header("Content-Type", "multipart/form-data; boundary=xyz")
+    .body(body)
+    .build()
+    .unwrap();
+
+let form = match req.body() {
+    Body::Multipart(form) => form,
+    _ => panic!("body should be multipart"),
+};
+assert_eq!(form.len(), 3);
+assert_eq!(form.get("tag").unwrap().body, b"red");
+let tags: Vec<&[u8]> = form.get_all("tag").map(|p| p.body.as_slice()).collect();
+assert_eq!(tags, [b"red".as_slice(), b"blue"]);
+assert_eq!(form.files().count(), 1);
+assert_eq!(form.fields().count(), 2);
+```
+*/
+#[derive(Debug, Clone, Default)]
+pub struct MultipartForm {
+    parts: Vec<MultipartPart>,
+    skipped: Vec<Error>,
+    preamble: Vec<u8>,
+    epilogue: Vec<u8>,
+}
+
+impl MultipartForm {
+    /// Return the first part with the form field name `name`.
+    pub fn get(&self, name: &str) -> Option<&MultipartPart> {
+        self.parts
+            .iter()
+            .find(|part| part.name().as_deref() == Some(name))
+    }
+
+    /// Iterate over every part with the form field name `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a MultipartPart> {
+        self.parts
+            .iter()
+            .filter(move |part| part.name().as_deref() == Some(name))
+    }
+
+    /**
+    Return the parts grouped by form field name, with the names in the
+    order they first appeared, and each group's parts in order. Parts
+    without a name are left out.
+
+    This is handy for `<input type="file" multiple>`, which sends each
+    file as a separate part with the same name.
+
+    ```rust
+    # use dumb_cgi::{parse_multipart, Body};
+    let body = b"--xyz\r\nContent-Disposition: form-data; name=\"photos\"; filename=\"a.jpg\"\r\n\r\nA\r\n\
+                 --xyz\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nTrip\r\n\
+                 --xyz\r\nContent-Disposition: form-data; name=\"photos\"; filename=\"b.jpg\"\r\n\r\nB\r\n\
+                 --xyz--\r\n";
+    let form = match parse_multipart(body, "xyz") {
+        Body::Multipart(form) => form,
+        _ => panic!("should have parsed"),
+    };
+    let groups = form.grouped();
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].0, "photos");
+    let names: Vec<String> = groups[0].1.iter().filter_map(|p| p.filename()).collect();
+    assert_eq!(names, ["a.jpg", "b.jpg"]);
+    assert_eq!(groups[1].0, "title");
+    ```
+    */
+    pub fn grouped(&self) -> Vec<(String, Vec<&MultipartPart>)> {
+        let mut groups: Vec<(String, Vec<&MultipartPart>)> = Vec::new();
+        for part in self.parts.iter() {
+            let name = match part.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            match groups.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, group)) => group.push(part),
+                None => groups.push((name, vec![part])),
+            }
+        }
+        groups
+    }
+
+    /// Iterate over the parts that are file uploads (that have a filename).
+    pub fn files(&self) -> impl Iterator<Item = &MultipartPart> {
+        self.parts.iter().filter(|part| part.filename().is_some())
+    }
+
+    /// Iterate over the parts that are ordinary fields (not file uploads).
+    pub fn fields(&self) -> impl Iterator<Item = &MultipartPart> {
+        self.parts.iter().filter(|part| part.filename().is_none())
+    }
+
+    /**
+    Return an error for each part of the body that couldn't be parsed (and
+    so isn't in the form), in the order they appeared. Only a body parsed
+    with `MultipartLimits::strict` has any (see `parse_multipart()`).
+
+    ```rust
+    # use dumb_cgi::{parse_multipart_with_limits, Body, MultipartLimits};
+    let body = b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+                 --xyz\r\nnot a header\r\n\r\n2\r\n--xyz--\r\n";
+    let strict = MultipartLimits::new().with_strict(true);
+    let form = match parse_multipart_with_limits(body, "xyz", &strict) {
+        Body::Multipart(form) => form,
+        _ => panic!("should have parsed"),
+    };
+    assert_eq!(form.len(), 1);
+    assert_eq!(form.skipped_parts().len(), 1);
+    assert_eq!(form.skipped_parts()[0].code, 400);
+    ```
+    */
+    pub fn skipped_parts(&self) -> &[Error] {
+        &self.skipped
+    }
+
+    /**
+    Save every file upload in the form to `dir` (see
+    `MultipartPart::save_to()`), returning where each was saved.
+
+    If one can't be saved, the files already saved are removed and the
+    error returned.
+    */
+    pub fn save_files<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<SavedFile>, Error> {
+        let mut saved: Vec<SavedFile> = Vec::new();
+        for part in self.files() {
+            match part.save_to(dir.as_ref()) {
+                Ok(file) => saved.push(file),
+                Err(e) => {
+                    for file in saved.iter() {
+                        _ = std::fs::remove_file(&file.path);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(saved)
+    }
+
+    /**
+    Return the bytes before the first boundary (not including the newline
+    that precedes it). This is usually empty, and ignored by anything
+    following the standard, but some clients put information there.
+
+    ```rust
+    # use dumb_cgi::{parse_multipart, Body};
+    let body = b"sent by LegacyClient 2.1\r\n\
+                 --xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+                 --xyz--\r\nchecksum 1234";
+    let form = match parse_multipart(body, "xyz") {
+        Body::Multipart(form) => form,
+        _ => panic!("should have parsed"),
+    };
+    assert_eq!(form.preamble(), b"sent by LegacyClient 2.1");
+    assert_eq!(form.epilogue(), b"checksum 1234");
+    ```
+    */
+    pub fn preamble(&self) -> &[u8] {
+        &self.preamble
+    }
+
+    /**
+    Return the bytes after the closing boundary (not including the newline
+    that follows it). Like the preamble, this is usually empty.
+    */
+    pub fn epilogue(&self) -> &[u8] {
+        &self.epilogue
+    }
+
+    /// Consume the form and return its parts.
+    pub fn into_parts(self) -> Vec<MultipartPart> {
+        self.parts
+    }
+}
+
+impl std::ops::Deref for MultipartForm {
+    type Target = [MultipartPart];
+
+    fn deref(&self) -> &[MultipartPart] {
+        &self.parts
+    }
+}
+
+impl From<Vec<MultipartPart>> for MultipartForm {
+    fn from(parts: Vec<MultipartPart>) -> MultipartForm {
+        MultipartForm {
+            parts,
+            ..MultipartForm::default()
+        }
+    }
+}
+
+impl IntoIterator for MultipartForm {
+    type Item = MultipartPart;
+    type IntoIter = std::vec::IntoIter<MultipartPart>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.parts.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MultipartForm {
+    type Item = &'a MultipartPart;
+    type IntoIter = std::slice::Iter<'a, MultipartPart>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.parts.iter()
+    }
+}
+
 /**
 Type of body detected in the request.
 
 This is not detected from the request method, but rather from the presence
 (and values) of the `content-length` and `content-type` headers.
 */
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Body {
     /// The request has no `content-length` header.
     None,
@@ -129,9 +1189,9 @@ pub enum Body {
     /// is something _other_ than `multipart/form-data.`
     Some(Vec<u8>),
     /// The request has a `content-length` header, and the `content-type`
-    /// _is_ `multipart/form-data`. This will contain a vector of
+    /// _is_ `multipart/form-data`. This will contain the
     /// successfully-parsed body parts.
-    Multipart(Vec<MultipartPart>),
+    Multipart(MultipartForm),
     /// There was an error at some point in the process of determining the
     /// type of or reading/parsing the body.
     Err(Error),
@@ -143,7 +1203,7 @@ Type of query string detected in the request.
 This is not detected from the request method, but rather the presence
 and content of the `QUERY_STRING` environment variable.
 */
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Query {
     /// No `QUERY_STRING` environment variable.
     None,
@@ -161,128 +1221,832 @@ pub enum Query {
 }
 
 /**
-Struct holding details about your CGI environment and the request
-that has been made to your program.
+Something questionable (but not fatal) that was encountered while reading
+and parsing a request.
+
+These are collected by `Request::new()` and are available through
+`Request::warnings()`; they might explain why some data isn't where you
+expected it. Recording a warning doesn't change how the request is
+parsed.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The named query string field appeared more than once; only the last
+    /// value was kept.
+    DuplicateQueryKey(String),
+    /// A part of a multipart body was skipped because its headers couldn't
+    /// be parsed (which only happens with `MultipartLimits::strict`). The
+    /// contained string describes the problem.
+    SkippedMultipartPart(String),
+    /// The value of the named header wasn't valid UTF-8, and has been
+    /// lossily converted.
+    NonUtf8Header(String),
+    /// The value of the named environment variable wasn't valid UTF-8, and
+    /// has been lossily converted.
+    NonUtf8Var(String),
+    /// The named header was sent more than once (in the environment, as
+    /// variables like `HTTP_X_TAG` and `HTTP_x_tag`, or in the headers of a
+    /// multipart part); only one value was kept.
+    CollidedHeader(String),
+    /// Stdin was a terminal, so no body was read (see `TerminalPolicy`).
+    TerminalStdin,
+    /// A multipart body's lines ended with bare LFs instead of CRLFs, so
+    /// none of its parts could be read (see `parse_multipart()`).
+    BareLfLineEndings,
+    /// A multipart body had no closing boundary (it was probably cut off),
+    /// so whatever followed its last boundary wasn't read as a part.
+    UnterminatedMultipart,
+    /// A part of a multipart body had malformed headers, and was kept with
+    /// the headers before the problem (see `parse_multipart()`). The
+    /// contained string describes the problem.
+    MalformedPartHeaders(String),
+}
+
+impl Warning {
+    /**
+    Return a short, fixed name for the kind of warning, without the
+    details, for counting warnings in logs or metrics.
+
+    ```rust
+    # use dumb_cgi::Warning;
+    let w = Warning::DuplicateQueryKey("page".to_owned());
+    assert_eq!(w.kind(), "duplicate-query-key");
+    ```
+    */
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Warning::DuplicateQueryKey(_) => "duplicate-query-key",
+            Warning::SkippedMultipartPart(_) => "skipped-multipart-part",
+            Warning::NonUtf8Header(_) => "non-utf8-header",
+            Warning::NonUtf8Var(_) => "non-utf8-var",
+            Warning::CollidedHeader(_) => "collided-header",
+            Warning::TerminalStdin => "terminal-stdin",
+            Warning::BareLfLineEndings => "bare-lf-line-endings",
+            Warning::UnterminatedMultipart => "unterminated-multipart",
+            Warning::MalformedPartHeaders(_) => "malformed-part-headers",
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::DuplicateQueryKey(k) => {
+                write!(f, "duplicate query key \"{}\" overwritten", k)
+            }
+            Warning::SkippedMultipartPart(e) => {
+                write!(f, "multipart part skipped due to bad headers: {}", e)
+            }
+            Warning::NonUtf8Header(k) => write!(f, "header \"{}\" value was not valid UTF-8", k),
+            Warning::NonUtf8Var(k) => {
+                write!(
+                    f,
+                    "environment variable \"{}\" value was not valid UTF-8",
+                    k
+                )
+            }
+            Warning::CollidedHeader(k) => {
+                write!(f, "header \"{}\" sent more than once; one value kept", k)
+            }
+            Warning::TerminalStdin => write!(f, "stdin is a terminal; no body read"),
+            Warning::BareLfLineEndings => {
+                write!(f, "multipart body has bare LF line endings; no parts read")
+            }
+            Warning::UnterminatedMultipart => {
+                write!(
+                    f,
+                    "multipart body has no closing boundary; last part not read"
+                )
+            }
+            Warning::MalformedPartHeaders(e) => {
+                write!(f, "multipart part kept despite bad headers: {}", e)
+            }
+        }
+    }
+}
+
+/**
+Struct holding details about your CGI environment and the request
+that has been made to your program.
+*/
+#[derive(Debug, Clone)]
+pub struct Request {
+    vars: HashMap<String, EnvValue>,
+    headers: HashMap<String, String>,
+    query: Query,
+    body: Body,
+    warnings: Vec<Warning>,
+    extensions: Extensions,
+    timings: Timings,
+    method_override: Option<String>,
+}
+
+/**
+The pieces of a `Request`, as returned by `Request::into_parts()`.
+*/
+#[derive(Debug, Clone)]
+pub struct Parts {
+    /// Environment variables (with upper-case names).
+    pub vars: HashMap<String, String>,
+    /// Request headers (with lower-case, hyphenated names).
+    pub headers: HashMap<String, String>,
+    /// The decoded query string.
+    pub query: Query,
+    /// The request body.
+    pub body: Body,
+    /// Non-fatal problems encountered while reading the request.
+    pub warnings: Vec<Warning>,
+    /// Data attached to the request after it was read.
+    pub extensions: Extensions,
+}
+
+/*
+The value of an environment variable, kept as it came from the OS and
+only converted to UTF-8 (lossily, and then only if it isn't already
+valid UTF-8) when it's asked for.
+*/
+#[derive(Debug, Clone)]
+struct EnvValue {
+    raw: OsString,
+    lossy: OnceLock<String>,
+}
+
+impl EnvValue {
+    fn new(raw: OsString) -> EnvValue {
+        EnvValue {
+            raw,
+            lossy: OnceLock::new(),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self.raw.to_str() {
+            Some(s) => s,
+            None => self
+                .lossy
+                .get_or_init(|| self.raw.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+/**
+An iterator over environment variables or headers that yields
+`(&'str, &'str)` tuples.
+
+This is returned by the `Request::vars()` and `Request::headers()` methods,
+for iterating over environment variables and request headers, respectively.
+*/
+pub struct Vars<'a>(VarsIter<'a>);
+
+enum VarsIter<'a> {
+    Env(std::collections::hash_map::Iter<'a, String, EnvValue>),
+    Strings(std::collections::hash_map::Iter<'a, String, String>),
+}
+
+impl<'a> Iterator for Vars<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            VarsIter::Env(iter) => iter.next().map(|(k, v)| (k.as_str(), v.as_str())),
+            VarsIter::Strings(iter) => iter.next().map(|(k, v)| (k.as_str(), v.as_str())),
+        }
+    }
+}
+
+/**
+Given a slice of bytes, attempt to parse it as an HTTP header-style line
+and return a `(name, value)` tuple.
+
+Both `name` and `value` will be lossily converted to UTF-8. The `name` will
+then have surrounding whitespace trimmed and be forced to lower-case; the
+`value` will have _leading_ whitespace trimmed but otherwise left as-is.
+
+```rust
+# use dumb_cgi::parse_header_line;
+let (name, value) = parse_header_line(b"Content-Type: text/plain").unwrap();
+assert_eq!(name, "content-type");
+assert_eq!(value, "text/plain");
+assert!(parse_header_line(b"not a header").is_none());
+```
+*/
+pub fn parse_header_line(bytes: &[u8]) -> Option<(String, String)> {
+    const COLON: u8 = b':';
+    let sep_idx = match bytes.iter().position(|b| *b == COLON) {
+        Some(n) => n,
+        None => {
+            return None;
+        }
+    };
+    let key = String::from_utf8_lossy(&bytes[..sep_idx])
+        .trim()
+        .to_lowercase();
+    let val = String::from_utf8_lossy(&bytes[(sep_idx + 1)..])
+        .trim_start()
+        .to_string();
+    Some((key, val))
+}
+
+/*
+Return the index of the next newline (after `current_position`) in `bytes`
+that is immediately followed by the boundary. This should be the first
+byte after the end of the multipart/form-data body chunk that begins on or
+after `current_position`. `delimiter` searches for a newline followed by
+the boundary.
+*/
+fn find_next_multipart_chunk_end(
+    bytes: &[u8],
+    current_position: usize,
+    delimiter: &Finder,
+) -> Option<usize> {
+    delimiter
+        .find(&bytes[current_position..])
+        .map(|n| current_position + n)
+}
+
+/**
+Caps on what the multipart parser will accept, so a malicious body can't
+make it do unbounded work.
+
+Exceeding `max_parts` or `max_part_size` makes the whole body a
+`Body::Err` with a 413 (Payload Too Large) status; exceeding
+`max_header_bytes` makes it a `Body::Err` with a 400 status.
+
+The defaults are 1,000 parts, no limit on the size of a part (beyond the
+length of the body itself), and 16 KiB of headers per part.
+
+Setting `strict` makes a body that doesn't end with a closing boundary
+(`--boundary--`), like one cut off part way through an upload, a
+`Body::Err` with a 400 status, rather than a form with whatever parts were
+complete, and leaves parts with malformed headers out of the form rather
+than keeping them (see `parse_multipart()`). (A `MultipartReader` is
+always strict.)
+
+```rust
+# use dumb_cgi::{parse_multipart_with_limits, Body, MultipartLimits};
+let limits = MultipartLimits::new().with_max_parts(1);
+let body = b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+             --xyz\r\nContent-Disposition: form-data; name=\"b\"\r\n\r\n2\r\n--xyz--\r\n";
+match parse_multipart_with_limits(body, "xyz", &limits) {
+    Body::Err(e) => assert_eq!(e.code, 413),
+    _ => panic!("should have hit the limit"),
+}
+```
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultipartLimits {
+    /// Most parts a body may have.
+    pub max_parts: usize,
+    /// Most bytes the body of any one part may have.
+    pub max_part_size: usize,
+    /// Most bytes the headers of any one part may take up.
+    pub max_header_bytes: usize,
+    /// Whether a body must end with a closing boundary, and parts with
+    /// malformed headers are left out.
+    pub strict: bool,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> MultipartLimits {
+        MultipartLimits {
+            max_parts: 1000,
+            max_part_size: usize::MAX,
+            max_header_bytes: 16 * 1024,
+            strict: false,
+        }
+    }
+}
+
+impl MultipartLimits {
+    /// Return the default limits.
+    pub fn new() -> MultipartLimits {
+        MultipartLimits::default()
+    }
+
+    /// Builder-pattern method for setting the most parts a body may have.
+    pub fn with_max_parts(self, n: usize) -> MultipartLimits {
+        let mut new = self;
+        new.max_parts = n;
+        new
+    }
+
+    /// Builder-pattern method for setting the most bytes the body of any one
+    /// part may have.
+    pub fn with_max_part_size(self, bytes: usize) -> MultipartLimits {
+        let mut new = self;
+        new.max_part_size = bytes;
+        new
+    }
+
+    /// Builder-pattern method for setting the most bytes the headers of any
+    /// one part may take up.
+    pub fn with_max_header_bytes(self, bytes: usize) -> MultipartLimits {
+        let mut new = self;
+        new.max_header_bytes = bytes;
+        new
+    }
+
+    /**
+    Builder-pattern method for setting whether a body without a closing
+    boundary is an error, and parts with malformed headers are left out.
+
+    ```rust
+    # use dumb_cgi::{parse_multipart_with_limits, Body, MultipartLimits};
+    let cut_off = b"--xyz\r\nContent-Disposition: form-data; name=\"f\"\r\n\r\nhalf a fi";
+    let limits = MultipartLimits::new().with_strict(true);
+    match parse_multipart_with_limits(cut_off, "xyz", &limits) {
+        Body::Err(e) => assert_eq!(e.code, 400),
+        _ => panic!("should have been rejected"),
+    }
+    ```
+    */
+    pub fn with_strict(self, strict: bool) -> MultipartLimits {
+        let mut new = self;
+        new.strict = strict;
+        new
+    }
+
+    pub(crate) fn too_many_parts(&self) -> Error {
+        Error {
+            code: 413,
+            message: "Too many parts in multipart body.".to_owned(),
+            details: format!("multipart body has more than {} parts", self.max_parts),
+        }
+    }
+
+    pub(crate) fn part_too_large(&self) -> Error {
+        Error {
+            code: 413,
+            message: "Multipart body part too large.".to_owned(),
+            details: format!(
+                "multipart body part larger than {} bytes",
+                self.max_part_size
+            ),
+        }
+    }
+
+    pub(crate) fn headers_too_large(&self) -> Error {
+        Error {
+            code: 400,
+            message: "Multipart body part headers too large.".to_owned(),
+            details: format!(
+                "multipart body part headers longer than {} bytes",
+                self.max_header_bytes
+            ),
+        }
+    }
+
+    pub(crate) fn bare_lf(&self) -> Error {
+        Error {
+            code: 400,
+            message: "Invalid multipart body line endings.".to_owned(),
+            details: "multipart body has bare LF line endings instead of CRLF".to_owned(),
+        }
+    }
+
+    pub(crate) fn unterminated(&self) -> Error {
+        Error {
+            code: 400,
+            message: "Incomplete multipart body.".to_owned(),
+            details: "multipart body has no closing boundary".to_owned(),
+        }
+    }
+}
+
+/**
+What to do with a request that has an `Expect: 100-continue` header.
+
+A client sending one is asking for permission before it sends the body,
+but a CGI program can't send the `100 Continue` interim response through
+most servers (the server either sends it itself, or doesn't); all the
+program can do is read the body, or refuse it without reading it.
+
+```rust
+# use dumb_cgi::{testing::MockRequest, Body, ContinuePolicy, RequestOptions};
+let opts = RequestOptions::new().with_expect_continue(ContinuePolicy::RejectOver(1024));
+let req = MockRequest::post("/upload")
+    .header("Expect", "100-continue")
+    .body(vec![0u8; 4096])
+    .options(opts)
+    .build()
+    .unwrap();
+assert!(req.expects_continue());
+match req.body() {
+    Body::Err(e) => assert_eq!(e.code, 417),
+    _ => panic!("body should have been refused"),
+}
+```
 */
-#[derive(Debug)]
-pub struct Request {
-    vars: HashMap<String, String>,
-    headers: HashMap<String, String>,
-    query: Query,
-    body: Body,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContinuePolicy {
+    /// Read the body as if the header weren't there (the default).
+    #[default]
+    Read,
+    /// Refuse every body the client is waiting for permission to send.
+    Reject,
+    /// Refuse bodies whose `Content-Length` is more than this many bytes.
+    RejectOver(usize),
 }
 
-/**
-An iterator over a `HashMap<String, String>` that yields
-`(&'str, &'str)` tuples.
+impl ContinuePolicy {
+    /*
+    Return the `417 Expectation Failed` error to use as the body if a body
+    of `body_len` bytes should be refused.
+    */
+    fn refusal(&self, body_len: usize) -> Option<Error> {
+        let refuse = match *self {
+            ContinuePolicy::Read => false,
+            ContinuePolicy::Reject => true,
+            ContinuePolicy::RejectOver(max) => body_len > max,
+        };
+        refuse.then(|| Error {
+            code: 417,
+            message: "Expectation failed.".to_owned(),
+            details: format!(
+                "refused {}-byte body sent with Expect: 100-continue",
+                body_len
+            ),
+        })
+    }
+}
 
-This is returned by the `Request::vars()` and `Request::headers()` methods,
-for iterating over environment variables and request headers, respectively.
+/**
+Options for how `Request::new_with_options()` reads a request.
 */
-pub struct Vars<'a>(std::collections::hash_map::Iter<'a, String, String>);
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+    /// Limits on parsing a `multipart/form-data` body.
+    pub multipart: MultipartLimits,
+    /// What to do when the client sent `Expect: 100-continue`.
+    pub expect_continue: ContinuePolicy,
+    /// Most bytes of memory reading the request may use (see
+    /// `with_memory_budget()`); `None` (the default) is no limit.
+    pub memory_budget: Option<usize>,
+    /// What to do when stdin is a terminal (see `TerminalPolicy`).
+    pub on_terminal: TerminalPolicy,
+    /// Whether to record how long each phase of reading the request takes
+    /// (see the `timing` module); off by default.
+    pub timings: bool,
+}
 
-impl<'a> Iterator for Vars<'a> {
-    type Item = (&'a str, &'a str);
+impl RequestOptions {
+    /// Return the default options.
+    pub fn new() -> RequestOptions {
+        RequestOptions::default()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|(k, v)| (k.as_str(), v.as_str()))
+    /// Builder-pattern method for setting the multipart limits.
+    pub fn with_multipart_limits(self, limits: MultipartLimits) -> RequestOptions {
+        let mut new = self;
+        new.multipart = limits;
+        new
     }
-}
 
-/*
-Given a slice of bytes, attempt to parse it as an HTTP header-style line
-and return a `(name, value)` tuple.
+    /// Builder-pattern method for setting what to do with
+    /// `Expect: 100-continue`.
+    pub fn with_expect_continue(self, policy: ContinuePolicy) -> RequestOptions {
+        let mut new = self;
+        new.expect_continue = policy;
+        new
+    }
 
-Both `name` and `value` will be lossily converted to UTF-8. The `name` will
-then have surrounding whitespace trimmed and be forced to lower-case; the
-`value` will have _leading_ whitespace trimmed but otherwise left as-is.
+    /**
+    Builder-pattern method for limiting the memory reading the request
+    may use to `bytes`: the decoded query string, the body, and the parts
+    of a multipart body all count against it. Whatever would go over is
+    a 413 (Content Too Large) error instead, as the `Query::Err` or
+    `Body::Err`; a body that's too large isn't read at all.
+
+    This is one limit on the whole request, rather than one per place
+    (like `MultipartLimits`), which still apply.
+
+    ```rust
+    # use dumb_cgi::{testing::MockRequest, Body, RequestOptions};
+    let opts = RequestOptions::new().with_memory_budget(1024);
+    let req = MockRequest::post("/upload")
+        .body(vec![0u8; 4096])
+        .options(opts)
+        .build()
+        .unwrap();
+    match req.body() {
+        Body::Err(e) => assert_eq!(e.code, 413),
+        _ => panic!("body should have been over budget"),
+    }
+    ```
+    */
+    pub fn with_memory_budget(self, bytes: usize) -> RequestOptions {
+        let mut new = self;
+        new.memory_budget = Some(bytes);
+        new
+    }
+
+    /// Builder-pattern method for setting what to do when stdin is a
+    /// terminal.
+    pub fn with_on_terminal(self, policy: TerminalPolicy) -> RequestOptions {
+        let mut new = self;
+        new.on_terminal = policy;
+        new
+    }
+
+    /// Builder-pattern method for setting whether to record timings (see
+    /// the `timing` module).
+    pub fn with_timings(self, timings: bool) -> RequestOptions {
+        let mut new = self;
+        new.timings = timings;
+        new
+    }
+}
+
+/**
+What `Request::new()` does when stdin is a terminal, which means the
+program is being run by hand (say, to debug it) rather than by a web
+server, and there's no body to read: reading one would just wait for
+someone to type it.
+
+```rust,no_run
+# use dumb_cgi::{Request, RequestOptions, TerminalPolicy};
+let opts = RequestOptions::new().with_on_terminal(TerminalPolicy::Reject);
+match Request::new_with_options(opts) {
+    Ok(req) => { /* ... */ }
+    Err(e) => eprintln!("{}", e.details),
+}
+```
 */
-fn match_header(bytes: &[u8]) -> Option<(String, String)> {
-    const COLON: u8 = b':';
-    let sep_idx = match bytes.iter().position(|b| *b == COLON) {
-        Some(n) => n,
-        None => {
-            return None;
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TerminalPolicy {
+    /**
+    Don't read a body (even if `CONTENT_LENGTH` says there is one), and
+    add a `Warning::TerminalStdin` to the request's warnings (the
+    default). Nothing is written to stderr; print `Request::warnings()`
+    to see it.
+    */
+    #[default]
+    SkipBody,
+    /// Read the body from the terminal as usual, for typing one in.
+    Read,
+    /// Make `Request::new()` return an error.
+    Reject,
+}
+
+impl TerminalPolicy {
+    /*
+    Whether to read the body from a terminal, or the error to return
+    instead of a request.
+    */
+    pub(crate) fn read_body(&self) -> Result<bool, Error> {
+        match self {
+            TerminalPolicy::SkipBody => Ok(false),
+            TerminalPolicy::Read => Ok(true),
+            TerminalPolicy::Reject => Err(Error {
+                code: 500,
+                message: "Internal server error.".to_owned(),
+                details: "stdin is a terminal; not running as a CGI program".to_owned(),
+            }),
         }
-    };
-    let key = String::from_utf8_lossy(&bytes[..sep_idx])
-        .trim()
-        .to_lowercase();
-    let val = String::from_utf8_lossy(&bytes[(sep_idx + 1)..])
-        .trim_start()
-        .to_string();
-    Some((key, val))
+    }
 }
 
 /*
-Return the index of the next newline (after `current_position`) in `bytes`
-that is immediately followed by `boundary`. This should be the first byte
-after the end of the multipart/form-data body chunk that begins on or
-after `current_position`.
+What's left of `RequestOptions::memory_budget` as a request is read.
 */
-fn find_next_multipart_chunk_end(
-    bytes: &[u8],
-    current_position: usize,
-    boundary: &[u8],
-) -> Option<usize> {
-    let mut pos = current_position;
-    let mut subslice = &bytes[pos..];
-    while let Some(n) = slicey_find(subslice, HTTP_NEWLINE) {
-        let post_newline_idx = pos + n + HTTP_NEWLINE.len();
-        if bytes.len() > post_newline_idx {
-            subslice = &bytes[post_newline_idx..];
-            if subslice.starts_with(boundary) {
-                return Some(pos + n);
+#[derive(Debug)]
+struct MemoryBudget {
+    limit: Option<usize>,
+    used: usize,
+}
+
+impl MemoryBudget {
+    fn new(limit: Option<usize>) -> MemoryBudget {
+        MemoryBudget { limit, used: 0 }
+    }
+
+    /*
+    Take `bytes` (for `what`) from the budget, or return a 413 error
+    (and take nothing) if there isn't enough left.
+    */
+    fn draw(&mut self, bytes: usize, what: &str) -> Result<(), Error> {
+        let used = self.used.saturating_add(bytes);
+        match self.limit {
+            Some(limit) if used > limit => Err(Error {
+                code: 413,
+                message: "Request too large.".to_owned(),
+                details: format!(
+                    "{} needs {} bytes, over the {}-byte request memory budget ({} used)",
+                    what, bytes, limit, self.used
+                ),
+            }),
+            _ => {
+                self.used = used;
+                Ok(())
             }
-            pos = post_newline_idx;
         }
     }
-    None
+}
+
+/*
+Why `read_multipart_chunk()` failed: either the chunk was malformed (and,
+being strict, is skipped), or it broke one of the limits (and the whole
+body is rejected).
+*/
+enum ChunkError {
+    Malformed(String),
+    Limit(Error),
 }
 
 /*
 Takes a reference to a chunk of a multipart body that falls between two
-boundaries, and returns that information in a `MultipartPart` struct.
+boundaries, and returns its headers and the offset (within the chunk) at
+which its body starts.
 */
-fn read_multipart_chunk(chunk: &[u8]) -> Result<MultipartPart, String> {
+fn read_multipart_chunk(
+    chunk: &[u8],
+    limits: &MultipartLimits,
+    warnings: &mut Vec<Warning>,
+) -> Result<(HashMap<String, String>, usize), ChunkError> {
     let mut position: usize = 0;
     let mut headers: HashMap<String, String> = HashMap::new();
+    let mut found_blank_line = false;
+    let mut malformed: Option<String> = None;
 
     while let Some(n) = slicey_find(&chunk[position..], HTTP_NEWLINE) {
         let next_pos = position + n;
-        if let Some((k, v)) = match_header(&chunk[position..next_pos]) {
-            headers.insert(k, v);
-            position = next_pos + HTTP_NEWLINE.len();
-        } else {
-            position = next_pos + HTTP_NEWLINE.len();
+        let line = &chunk[position..next_pos];
+        position = next_pos + HTTP_NEWLINE.len();
+        if position > limits.max_header_bytes {
+            return Err(ChunkError::Limit(limits.headers_too_large()));
+        }
+        if line.is_empty() {
+            found_blank_line = true;
             break;
         }
+        match parse_header_line(line) {
+            Some((k, v)) => {
+                if std::str::from_utf8(line).is_err() {
+                    warnings.push(Warning::NonUtf8Header(k.clone()));
+                }
+                if headers.contains_key(&k) {
+                    warnings.push(Warning::CollidedHeader(k.clone()));
+                }
+                headers.insert(k, v);
+            }
+            None => {
+                malformed = Some(format!(
+                    "malformed header line \"{}\"",
+                    String::from_utf8_lossy(line)
+                ));
+                break;
+            }
+        }
+    }
+
+    if !found_blank_line && malformed.is_none() {
+        if limits.strict && chunk.len() > limits.max_header_bytes {
+            return Err(ChunkError::Limit(limits.headers_too_large()));
+        }
+        malformed = Some("no blank line after part headers".to_owned());
+    }
+    if let Some(problem) = malformed {
+        if limits.strict {
+            return Err(ChunkError::Malformed(problem));
+        }
+        // Kept as it always was: the headers end at the bad line, and the
+        // body starts after it (or after the last line break).
+        warnings.push(Warning::MalformedPartHeaders(problem));
     }
 
-    let body: Vec<u8> = chunk[position..].to_vec();
+    if chunk.len() - position > limits.max_part_size {
+        return Err(ChunkError::Limit(limits.part_too_large()));
+    }
 
-    Ok(MultipartPart { headers, body })
+    Ok((headers, position))
 }
 
-/*
+/**
 Takes a reference to the body of a multipart/form-data request and
 attempts to return a `Body::Multipart` variant.
 
-This function (and the multipart body chunking code in particular) is
-kind of a rats' nest of conditionals, so this function's interior
-commentary errs on the side of excessiveness.
+`boundary` is the value of the `boundary` parameter from the request's
+`Content-type` header (see `multipart_boundary()`), _without_ the two
+hyphens that precede it in the body. This doesn't touch the environment or
+stdin, so it can be used on multipart data from anywhere.
+
+```rust
+# use dumb_cgi::{parse_multipart, Body};
+let body = b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--xyz--\r\n";
+match parse_multipart(body, "xyz") {
+    Body::Multipart(parts) => assert_eq!(parts[0].body, b"1"),
+    _ => panic!("should have parsed"),
+}
+```
+
+A part whose headers are malformed (a line before the blank line that
+isn't a `Name: value` header, or no blank line at all) is kept: its
+headers end at the bad line, and its body starts after it, or after the
+last line break if there was no blank line. `Request::new()` records a
+`Warning::MalformedPartHeaders` for it. With `MultipartLimits::strict`,
+such a part is instead left out of the form, with an error in
+`MultipartForm::skipped_parts()`.
+
+The spec requires CRLF line endings. A body whose lines end in bare LFs
+has no parts; `Request::new()` records a `Warning::BareLfLineEndings` for
+it (or, with `MultipartLimits::strict`, makes it a `Body::Err`).
+Likewise, a body with no closing boundary (one that was cut off) loses
+whatever followed its last boundary, and gets a
+`Warning::UnterminatedMultipart` (or, when strict, is a `Body::Err`).
+*/
+pub fn parse_multipart(body_bytes: &[u8], boundary: &str) -> Body {
+    parse_multipart_with_limits(body_bytes, boundary, &MultipartLimits::default())
+}
+
+/**
+Like `parse_multipart()`, but with `limits` instead of the default
+`MultipartLimits`.
+*/
+pub fn parse_multipart_with_limits(
+    body_bytes: &[u8],
+    boundary: &str,
+    limits: &MultipartLimits,
+) -> Body {
+    parse_multipart_with_warnings(
+        body_bytes,
+        boundary,
+        limits,
+        &mut Vec::new(),
+        &mut MemoryBudget::new(None),
+    )
+}
+
+/*
+The guts of `parse_multipart()`, which also records any `Warning`s.
+*/
+fn parse_multipart_with_warnings(
+    body_bytes: &[u8],
+    boundary: &str,
+    limits: &MultipartLimits,
+    warnings: &mut Vec<Warning>,
+    budget: &mut MemoryBudget,
+) -> Body {
+    let split = split_multipart(body_bytes, boundary, limits, warnings).and_then(|split| {
+        let headers: usize = split
+            .parts
+            .iter()
+            .flat_map(|p| p.headers.iter())
+            .map(|(k, v)| k.len() + v.len())
+            .sum();
+        let bodies: usize = split.parts.iter().map(|p| p.body.len()).sum();
+        let copied = headers + bodies + split.preamble.len() + split.epilogue.len();
+        budget.draw(copied, "multipart form")?;
+        Ok(split)
+    });
+    match split {
+        Ok(split) => Body::Multipart(MultipartForm {
+            parts: split
+                .parts
+                .into_iter()
+                .map(|p| MultipartPart {
+                    headers: p.headers,
+                    body: body_bytes[p.body].to_vec(),
+                })
+                .collect(),
+            skipped: split.skipped,
+            preamble: body_bytes[split.preamble].to_vec(),
+            epilogue: body_bytes[split.epilogue].to_vec(),
+        }),
+        Err(e) => Body::Err(e),
+    }
+}
+
+/*
+The pieces of a multipart body, as ranges of the body's bytes.
+*/
+#[derive(Debug, Default)]
+pub(crate) struct SplitMultipart {
+    pub(crate) parts: Vec<PartRange>,
+    pub(crate) skipped: Vec<Error>,
+    pub(crate) preamble: Range<usize>,
+    pub(crate) epilogue: Range<usize>,
+}
+
+/*
+Find the parts of a multipart body without copying any of them.
 */
-fn read_multipart_body(body_bytes: &[u8], boundary: &str) -> Body {
+pub(crate) fn split_multipart(
+    body_bytes: &[u8],
+    boundary: &str,
+    limits: &MultipartLimits,
+    warnings: &mut Vec<Warning>,
+) -> Result<SplitMultipart, Error> {
+    // This function (and the multipart body chunking code in particular) is
+    // kind of a rats' nest of conditionals, so this function's interior
+    // commentary errs on the side of excessiveness.
+
     #[cfg(feature = "log")]
     {
-        log::debug!(
-            "read_multipart_body() called\n    boundary: \"{}\"",
-            boundary
-        );
+        log::debug!("parse_multipart() called\n    boundary: \"{}\"", boundary);
         log::debug!("  {} body bytes", body_bytes.len());
     }
 
-    let mut parts: Vec<MultipartPart> = Vec::new();
+    let mut parts: Vec<PartRange> = Vec::new();
 
     // As per RFC 7578, the `boundary=...` value found in the `CONTENT_TYPE`
     // header will appear in the body with two hyphens prepended, so
@@ -294,22 +2058,31 @@ fn read_multipart_body(body_bytes: &[u8], boundary: &str) -> Body {
         b
     };
     let boundary_bytes = &prepended_boundary.as_bytes();
+    let delimiter = {
+        let mut d = HTTP_NEWLINE.to_vec();
+        d.extend_from_slice(boundary_bytes);
+        Finder::new(&d)
+    };
 
-    // This will hold subslices of `body_bytes`, each of which will contain
+    // This will hold ranges of `body_bytes`, each of which will contain
     // the raw bytes of one "part" of the multipart body.
-    let mut chunks: Vec<&[u8]> = Vec::new();
+    let mut chunks: Vec<Range<usize>> = Vec::new();
 
     /*
     Thus follows the multipart body chunking code. It grovels through the body
     of a multipart/form-data request (`body_bytes`), identifying the beginning
-    and end of each part, and pushing the corresponding slice of bytes (a
-    subslice of `body_bytes`) onto the `chunks` vector.
+    and end of each part, and pushing the corresponding range of bytes (of
+    `body_bytes`) onto the `chunks` vector.
     */
 
     // First we set our initial position just after the first occurrence of
     // the boundary byte sequence.
-    let mut position = match slicey_find(body_bytes, boundary_bytes) {
+    let preamble: &[u8];
+    let mut position = match Finder::new(boundary_bytes).find(body_bytes) {
         Some(n) => {
+            preamble = body_bytes[..n]
+                .strip_suffix(HTTP_NEWLINE)
+                .unwrap_or(&body_bytes[..n]);
             // If the boundary is found in the body, check to ensure there is
             // more body left after the end of the boundary (so we don't)
             // panic in our subsequent subslicing.
@@ -322,21 +2095,43 @@ fn read_multipart_body(body_bytes: &[u8], boundary: &str) -> Body {
                     // If so, set our starting position to be immediately
                     // after the newline.
                     nl_end_idx
+                } else if body_bytes[end_idx] == b'\n' {
+                    // Lines ending in bare LFs (which the spec doesn't
+                    // allow, but some hand-rolled clients send) hide every
+                    // delimiter, so there are no parts to find; say so
+                    // rather than returning a silently empty form.
+                    if limits.strict {
+                        return Err(limits.bare_lf());
+                    }
+                    warnings.push(Warning::BareLfLineEndings);
+                    return Ok(SplitMultipart::default());
                 } else {
                     // If the boundary _isn't_ immediately followed by a
-                    // newline, just return a `Body::Multipart` with an empty
-                    // vector of parts.`
+                    // newline, just return an empty vector of parts (unless
+                    // being strict, and it isn't a closing boundary).
                     //
                     // *** Should this be an error instead?
-                    return Body::Multipart(parts);
+                    if !body_bytes[end_idx..].starts_with(b"--") {
+                        if limits.strict {
+                            return Err(limits.unterminated());
+                        }
+                        warnings.push(Warning::UnterminatedMultipart);
+                    }
+                    return Ok(SplitMultipart::default());
                 }
             } else {
                 // If there isn't any more body after the first occurrence of
-                // the boundary, just return a `Body::Multipart` with an
-                // empty vector of parts.
+                // the boundary, just return an empty vector of parts (with
+                // the same exception).
                 //
                 // *** Should this be an error instead?
-                return Body::Multipart(parts);
+                if !body_bytes[end_idx..].starts_with(b"--") {
+                    if limits.strict {
+                        return Err(limits.unterminated());
+                    }
+                    warnings.push(Warning::UnterminatedMultipart);
+                }
+                return Ok(SplitMultipart::default());
             }
         }
         None => {
@@ -347,7 +2142,7 @@ fn read_multipart_body(body_bytes: &[u8], boundary: &str) -> Body {
                 message: "Not a valid multipart/form-data body.".to_owned(),
                 details: "multipart body missing boundary string".to_owned(),
             };
-            return Body::Err(err);
+            return Err(err);
         }
     };
 
@@ -356,12 +2151,14 @@ fn read_multipart_body(body_bytes: &[u8], boundary: &str) -> Body {
 
     // Now we find subesequent occurrences of a newline pattern immediately
     // followed by a boundary.
-    while let Some(next_position) =
-        find_next_multipart_chunk_end(body_bytes, position, boundary_bytes)
+    while let Some(next_position) = find_next_multipart_chunk_end(body_bytes, position, &delimiter)
     {
         // Declare a chunk that goes from the previous `position` up to (but
         // not including) the newline, and push it onto the vector of chunks.
-        let chunk = &body_bytes[position..next_position];
+        let chunk = position..next_position;
+        if chunks.len() == limits.max_parts {
+            return Err(limits.too_many_parts());
+        }
         chunks.push(chunk);
 
         // If the boundary is then immediately followed by another newline,
@@ -383,36 +2180,96 @@ fn read_multipart_body(body_bytes: &[u8], boundary: &str) -> Body {
         }
     }
 
+    // If the last boundary found isn't followed by "--", the body was cut
+    // off (or is otherwise missing its closing boundary), and whatever
+    // followed that boundary is lost.
+    let closed = !chunks.is_empty() && body_bytes[position..].starts_with(b"--");
+    if !closed {
+        if limits.strict {
+            return Err(limits.unterminated());
+        }
+        warnings.push(Warning::UnterminatedMultipart);
+    }
+
+    // Anything after a proper closing boundary (and the newline after
+    // that) is the epilogue.
+    let epilogue: &[u8] = match body_bytes.get(position..) {
+        Some(rest) if !chunks.is_empty() => match rest.strip_prefix(b"--") {
+            Some(rest) => rest.strip_prefix(HTTP_NEWLINE).unwrap_or(rest),
+            None => &[],
+        },
+        _ => &[],
+    };
+
     #[cfg(feature = "log")]
     log::debug!("  read {} multipart chunks", &chunks.len());
 
     /*
     Now all the chunks have been found, it's time to process each one into
-    a `MultipartPart` struct which contains a map of headers and a vector
-    of bytes for the individual parts' body.
+    a `PartRange` struct which contains a map of headers and the range
+    of bytes of the individual parts' body.
     */
-    for chunk in chunks.iter() {
-        match read_multipart_chunk(chunk) {
-            Err(_) => {
+    let mut skipped: Vec<Error> = Vec::new();
+    for (n, chunk) in chunks.into_iter().enumerate() {
+        match read_multipart_chunk(&body_bytes[chunk.clone()], limits, warnings) {
+            Err(ChunkError::Limit(e)) => return Err(e),
+            Err(ChunkError::Malformed(e)) => {
                 // If there is an error with a given multipart chunk, it is
-                // just ignored. There is not a simple way to indicate errors
-                // in individual chunks to the consumer of this library.
+                // skipped, but the error is kept with the form (and a
+                // warning is recorded).
+                skipped.push(Error {
+                    code: 400,
+                    message: "Invalid multipart body part.".to_owned(),
+                    details: format!("Error reading multipart part {}: {}", n, &e),
+                });
+                warnings.push(Warning::SkippedMultipartPart(e));
             }
-            Ok(mpp) => parts.push(mpp),
+            Ok((headers, offset)) => parts.push(PartRange {
+                headers,
+                body: (chunk.start + offset)..chunk.end,
+            }),
         }
     }
 
-    Body::Multipart(parts)
+    // Both of these are subslices of `body_bytes`: the preamble at the
+    // start, the epilogue at the end.
+    Ok(SplitMultipart {
+        parts,
+        skipped,
+        preamble: 0..preamble.len(),
+        epilogue: (body_bytes.len() - epilogue.len())..body_bytes.len(),
+    })
+}
+
+/*
+Whether the `Expect` header asks for `100-continue`.
+*/
+fn expects_continue(headers: &HashMap<String, String>) -> bool {
+    headers.get("expect").is_some_and(|v| {
+        v.split(',')
+            .any(|e| e.trim().eq_ignore_ascii_case("100-continue"))
+    })
 }
 
 /*
-Huff from stdin and process if appropriate to return a `Body` enum.
+Huff from `source` (usually stdin) and process if appropriate to return a
+`Body` enum.
 */
-fn read_body(body_len: usize, content_type: Option<&str>) -> Body {
+fn read_body<R: Read>(
+    body_len: usize,
+    content_type: Option<&str>,
+    mut source: R,
+    limits: &MultipartLimits,
+    warnings: &mut Vec<Warning>,
+    timings: &mut Timings,
+    budget: &mut MemoryBudget,
+) -> Body {
+    if let Err(e) = budget.draw(body_len, "request body") {
+        return Body::Err(e);
+    }
     let mut body_bytes: Vec<u8> = vec![0; body_len];
-    let stdin = std::io::stdin();
-    let mut stdin_lock = stdin.lock();
-    if let Err(e) = stdin_lock.read_exact(&mut body_bytes) {
+    let read_result = timings.time("body-read", || source.read_exact(&mut body_bytes));
+    if let Err(e) = read_result {
         let err = Error {
             code: 500,
             message: "Unable to read request body.".to_owned(),
@@ -422,11 +2279,11 @@ fn read_body(body_len: usize, content_type: Option<&str>) -> Body {
     }
 
     if let Some(content_type) = content_type {
-        if let Some(n) = content_type.find(MULTIPART_CONTENT_TYPE) {
-            let next_idx = n + MULTIPART_CONTENT_TYPE.len();
-            if let Some(n) = content_type[next_idx..].find(MULTIPART_BOUNDARY) {
-                let next_idx = next_idx + n + MULTIPART_BOUNDARY.len();
-                return read_multipart_body(&body_bytes, &content_type[next_idx..]);
+        if content_type.contains(MULTIPART_CONTENT_TYPE) {
+            if let Some(boundary) = multipart_boundary(content_type) {
+                return timings.time("body-parse", || {
+                    parse_multipart_with_warnings(&body_bytes, boundary, limits, warnings, budget)
+                });
             } else {
                 let err = Error {
                     code: 400,
@@ -440,100 +2297,609 @@ fn read_body(body_len: usize, content_type: Option<&str>) -> Body {
                 };
                 return Body::Err(err);
             }
-        }
+        }
+    }
+
+    Body::Some(body_bytes)
+}
+
+/**
+Extract the value of the `boundary` parameter from a
+`multipart/form-data` `Content-type` header value, if present.
+
+Surrounding quotes (which are allowed by the spec) are removed.
+
+```rust
+# use dumb_cgi::multipart_boundary;
+assert_eq!(
+    multipart_boundary("multipart/form-data; boundary=\"a b\"; charset=utf-8"),
+    Some("a b")
+);
+assert_eq!(multipart_boundary("multipart/form-data; boundary=xyz"), Some("xyz"));
+assert_eq!(multipart_boundary("multipart/form-data"), None);
+```
+*/
+pub fn multipart_boundary(content_type: &str) -> Option<&str> {
+    let n = content_type.find(MULTIPART_CONTENT_TYPE)?;
+    let rest = &content_type[(n + MULTIPART_CONTENT_TYPE.len())..];
+    let n = rest.find(MULTIPART_BOUNDARY)?;
+    let value = &rest[(n + MULTIPART_BOUNDARY.len())..];
+    let value = match value.strip_prefix('"') {
+        Some(quoted) => &quoted[..quoted.find('"')?],
+        None => value.split(';').next().unwrap_or(value).trim_end(),
+    };
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/**
+Attempt to return the form data that's been URL percent-encoded
+and chunked into `&`-separated `name=value` pairs in the query
+string.
+
+This is what `Request::new()` does with the value of the `QUERY_STRING`
+environment variable, but it can be used on any string (for example, an
+`application/x-www-form-urlencoded` request body).
+
+```rust
+# use dumb_cgi::{parse_query_string, Query};
+match parse_query_string("a=1&b=two+words") {
+    Query::Some(map) => assert_eq!(map.get("b").unwrap(), "two words"),
+    _ => panic!("should have parsed"),
+}
+```
+*/
+pub fn parse_query_string(qstr: &str) -> Query {
+    parse_query_string_with_warnings(qstr, &mut Vec::new())
+}
+
+/*
+The guts of `parse_query_string()`, which also records any `Warning`s.
+*/
+fn parse_query_string_with_warnings(qstr: &str, warnings: &mut Vec<Warning>) -> Query {
+    let pairs = match Query::parse_borrowed(qstr) {
+        Ok(pairs) => pairs,
+        Err(e) => return Query::Err(e),
+    };
+
+    let mut qmap: HashMap<String, String> = HashMap::with_capacity(pairs.len());
+    for (name, value) in pairs {
+        let name = name.into_owned();
+        if qmap.contains_key(&name) {
+            warnings.push(Warning::DuplicateQueryKey(name.clone()));
+        }
+        qmap.insert(name, value.into_owned());
+    }
+
+    Query::Some(qmap)
+}
+
+/*
+Percent-decode `s`, only allocating if there's actually something to decode.
+*/
+fn url_decode_cow(s: &str) -> Result<Cow<'_, str>, String> {
+    if s.bytes().any(|b| b == PLUS || b == PERCENT) {
+        url_decode(s).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(s))
+    }
+}
+
+/// Decoded `(name, value)` pairs returned by `Query::parse_borrowed()`.
+pub type BorrowedPairs<'a> = Vec<(Cow<'a, str>, Cow<'a, str>)>;
+
+impl Query {
+    /**
+    Split a query string (or `application/x-www-form-urlencoded` body)
+    into decoded `(name, value)` pairs, in order and keeping duplicates.
+
+    Names and values without any `%` escapes or `+`s are borrowed from
+    `qstr` rather than copied, so this is cheaper than
+    `parse_query_string()` when you only need to look at the data.
+
+    ```rust
+    # use std::borrow::Cow;
+    # use dumb_cgi::Query;
+    let pairs = Query::parse_borrowed("a=1&b=two+words&a=3").unwrap();
+    assert_eq!(pairs.len(), 3);
+    assert!(matches!(pairs[0].1, Cow::Borrowed("1")));
+    assert_eq!(pairs[1].1, "two words");
+    assert_eq!(pairs[2], (Cow::Borrowed("a"), Cow::Borrowed("3")));
+
+    assert!(Query::parse_borrowed("a=%zz").is_err());
+    ```
+    */
+    pub fn parse_borrowed(qstr: &str) -> Result<BorrowedPairs<'_>, Error> {
+        let mut pairs = Vec::new();
+
+        for nvp in qstr.split('&') {
+            let (coded_name, coded_value) = match nvp.split_once('=') {
+                Some(pair) => pair,
+                None => {
+                    return Err(Error {
+                        code: 400,
+                        message: "Invalid query string.".to_owned(),
+                        details: format!("Chunk \"{}\" not a name=vlaue pair.", nvp),
+                    });
+                }
+            };
+            let name = url_decode_cow(coded_name).map_err(|e| Error {
+                code: 400,
+                message: "Invalid query string.".to_owned(),
+                details: format!(
+                    "Error decoding name in chunk \"{}={}\": {}",
+                    coded_name, coded_value, &e
+                ),
+            })?;
+            let value = url_decode_cow(coded_value).map_err(|e| Error {
+                code: 400,
+                message: "Invalid query string.".to_owned(),
+                details: format!(
+                    "Error decoding value in chunk \"{}={}\": {}",
+                    coded_name, coded_value, &e
+                ),
+            })?;
+            pairs.push((name, value));
+        }
+
+        Ok(pairs)
+    }
+
+    /**
+    Split a query string (or `application/x-www-form-urlencoded` body)
+    into `(name, value)` pairs, in order and keeping duplicates, decoding
+    the percent-decoded bytes from `charset` rather than UTF-8.
+
+    ```rust
+    # use dumb_cgi::{charset::Charset, Query};
+    let pairs = Query::parse_with_charset("name=Ren%E9e", Charset::Latin1).unwrap();
+    assert_eq!(pairs[0].1, "Renée");
+    ```
+    */
+    pub fn parse_with_charset(
+        qstr: &str,
+        charset: Charset,
+    ) -> Result<Vec<(String, String)>, Error> {
+        let bad_query = |details: String| Error {
+            code: 400,
+            message: "Invalid query string.".to_owned(),
+            details,
+        };
+        let decode = |coded: &str, buf: &mut Vec<u8>| -> Result<String, String> {
+            buf.clear();
+            url_decode_into(coded, buf)?;
+            charset.decode(buf)
+        };
+
+        let mut pairs = Vec::new();
+        let mut buf = Vec::new();
+        for nvp in qstr.split('&') {
+            let (coded_name, coded_value) = nvp
+                .split_once('=')
+                .ok_or_else(|| bad_query(format!("Chunk \"{}\" not a name=value pair.", nvp)))?;
+            let name = decode(coded_name, &mut buf).map_err(|e| {
+                bad_query(format!("Error decoding name in chunk \"{}\": {}", nvp, &e))
+            })?;
+            let value = decode(coded_value, &mut buf).map_err(|e| {
+                bad_query(format!("Error decoding value in chunk \"{}\": {}", nvp, &e))
+            })?;
+            pairs.push((name, value));
+        }
+        Ok(pairs)
+    }
+
+    /**
+    Parse a query string (or `application/x-www-form-urlencoded` body)
+    the way many JavaScript form serializers and server frameworks expect,
+    where `a[]=1&a[]=2` is a list and `user[name]=x` is a field of a map:
+
+    ```rust
+    # use dumb_cgi::{FormValue, Query};
+    let form = Query::parse_nested(
+        "tag[]=fish&tag[]=chips&user[name]=Dan&user[langs][]=rust&page=2"
+    ).unwrap();
+
+    assert_eq!(form["page"].as_str(), Some("2"));
+    assert_eq!(form["tag"].as_list().unwrap().len(), 2);
+    assert_eq!(form["user"].get("name").and_then(FormValue::as_str), Some("Dan"));
+    assert_eq!(
+        form["user"]["langs"],
+        FormValue::List(vec![FormValue::Value("rust".to_owned())])
+    );
+    ```
+
+    A `[]` followed by a name (`items[][id]=1&items[][qty]=2`) adds to the
+    last map in the list, unless it already has that name, in which case
+    it starts a new one. Names with unbalanced brackets are taken as they
+    are, and as with `parse_query_string()`, the last of several values
+    for the same name wins.
+
+    Returns an error if the same name is used for different kinds of
+    values (`a=1&a[]=2`), or names are nested more than 16 deep.
+    */
+    pub fn parse_nested(qstr: &str) -> Result<BTreeMap<String, FormValue>, Error> {
+        let mut root = FormValue::Map(BTreeMap::new());
+        for (name, value) in Query::parse_borrowed(qstr)? {
+            let keys = split_nested_name(&name);
+            if keys.len() > MAX_NESTING + 1 {
+                return Err(nested_error(format!(
+                    "Name \"{}\" nested more than {} deep.",
+                    &name, MAX_NESTING
+                )));
+            }
+            if !place_nested(&mut root, &keys, value.into_owned()) {
+                return Err(nested_error(format!(
+                    "Name \"{}\" conflicts with an earlier one.",
+                    &name
+                )));
+            }
+        }
+        match root {
+            FormValue::Map(map) => Ok(map),
+            _ => unreachable!("the root is always a map"),
+        }
+    }
+}
+
+/// The deepest `Query::parse_nested()` will nest names.
+const MAX_NESTING: usize = 16;
+
+fn nested_error(details: String) -> Error {
+    Error {
+        code: 400,
+        message: "Invalid query string.".to_owned(),
+        details,
+    }
+}
+
+/**
+A value from a form parsed with `Query::parse_nested()`.
+
+Indexing a `FormValue` with a name (`value["name"]`) panics if it isn't
+a map with that name in it; `.get()` returns an `Option` instead.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormValue {
+    /// A plain `name=value` value.
+    Value(String),
+    /// The values of a `name[]` field, in order.
+    List(Vec<FormValue>),
+    /// The fields of a `name[key]` field.
+    Map(BTreeMap<String, FormValue>),
+}
+
+impl FormValue {
+    /// Return the value, if this is a plain value.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            FormValue::Value(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Return the list of values, if this is a list.
+    pub fn as_list(&self) -> Option<&[FormValue]> {
+        match self {
+            FormValue::List(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Return the map of names to values, if this is a map.
+    pub fn as_map(&self) -> Option<&BTreeMap<String, FormValue>> {
+        match self {
+            FormValue::Map(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    /// Return the value of the field `name`, if this is a map with one.
+    pub fn get(&self, name: &str) -> Option<&FormValue> {
+        self.as_map()?.get(name)
+    }
+}
+
+impl std::ops::Index<&str> for FormValue {
+    type Output = FormValue;
+
+    fn index(&self, name: &str) -> &FormValue {
+        self.get(name)
+            .unwrap_or_else(|| panic!("no form field \"{}\"", name))
+    }
+}
+
+/*
+One step of a nested form field name: `[]`, or a name (the leading one,
+or a `[name]`).
+*/
+#[derive(Debug, PartialEq, Eq)]
+enum NestedKey<'a> {
+    Push,
+    Name(&'a str),
+}
+
+/*
+Split a field name like `user[langs][]` into its steps. A name that
+isn't of that form (no leading name, or unbalanced brackets) is a single
+step, taken as it is.
+*/
+fn split_nested_name(name: &str) -> Vec<NestedKey<'_>> {
+    let whole = vec![NestedKey::Name(name)];
+    let (base, mut rest) = match name.find('[') {
+        Some(0) | None => return whole,
+        Some(n) => name.split_at(n),
+    };
+    let mut keys = vec![NestedKey::Name(base)];
+    while !rest.is_empty() {
+        let inner = match rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+            Some((inner, after)) if !inner.contains('[') => {
+                rest = after;
+                inner
+            }
+            _ => return whole,
+        };
+        keys.push(if inner.is_empty() {
+            NestedKey::Push
+        } else {
+            NestedKey::Name(inner)
+        });
     }
+    keys
+}
 
-    Body::Some(body_bytes)
+/// An empty container of the kind the step `key` goes into.
+fn nested_container(key: &NestedKey) -> FormValue {
+    match key {
+        NestedKey::Push => FormValue::List(Vec::new()),
+        NestedKey::Name(_) => FormValue::Map(BTreeMap::new()),
+    }
 }
 
 /*
-Attempt to return the form data that's been URL percent-encoded
-and chunked into `&`-separated `name=value` pairs in the query
-string.
+Put `value` in `node` at the place named by `keys`, creating containers
+on the way; returns `false` if something of the wrong kind is in the way.
 */
-fn parse_query_string(qstr: &str) -> Query {
-    let mut qmap: HashMap<String, String> = HashMap::new();
-
-    for nvp in qstr.split('&') {
-        match nvp.split_once('=') {
-            Some((coded_name, coded_value)) => {
-                let name = match url_decode(coded_name) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        let err = Error {
-                            code: 400,
-                            message: "Invalid query string.".to_owned(),
-                            details: format!(
-                                "Error decoding name in chunk \"{}={}\": {}",
-                                coded_name, coded_value, &e
-                            ),
-                        };
-                        return Query::Err(err);
-                    }
-                };
-                let value = match url_decode(coded_value) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        let err = Error {
-                            code: 400,
-                            message: "Invalid query string.".to_owned(),
-                            details: format!(
-                                "Error decoding value in chunk \"{}={}\": {}",
-                                coded_name, coded_value, &e
-                            ),
-                        };
-                        return Query::Err(err);
-                    }
-                };
-
-                qmap.insert(name, value);
+fn place_nested(node: &mut FormValue, keys: &[NestedKey], value: String) -> bool {
+    let (key, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => return false,
+    };
+    match (node, key) {
+        (FormValue::Map(map), NestedKey::Name(name)) => match rest.first() {
+            // A plain value only replaces another plain value, never a
+            // map or list made by an earlier `name[...]` field.
+            None => match map.get(*name) {
+                Some(FormValue::Map(_) | FormValue::List(_)) => false,
+                _ => {
+                    map.insert((*name).to_owned(), FormValue::Value(value));
+                    true
+                }
+            },
+            Some(next) => {
+                let child = map
+                    .entry((*name).to_owned())
+                    .or_insert_with(|| nested_container(next));
+                place_nested(child, rest, value)
             }
+        },
+        (FormValue::List(list), NestedKey::Push) => match rest.first() {
             None => {
-                let err = Error {
-                    code: 400,
-                    message: "Invalid query string.".to_owned(),
-                    details: format!("Chunk \"{}\" not a name=vlaue pair.", nvp),
+                list.push(FormValue::Value(value));
+                true
+            }
+            Some(next) => {
+                let fits_last = match (list.last(), next) {
+                    (Some(FormValue::Map(m)), NestedKey::Name(n)) => !m.contains_key(*n),
+                    _ => false,
                 };
-                return Query::Err(err);
+                if !fits_last {
+                    list.push(nested_container(next));
+                }
+                match list.last_mut() {
+                    Some(last) => place_nested(last, rest, value),
+                    None => false,
+                }
             }
-        }
+        },
+        _ => false,
     }
+}
 
-    Query::Some(qmap)
+/*
+The value of the last `name=value` pair for `name` in `qstr`, for
+`Request::param()`. Like the query string map, the last value wins; each
+pair is decoded on its own, so one bad pair only loses itself.
+*/
+fn last_pair_value(qstr: &str, name: &str) -> Option<String> {
+    qstr.split('&')
+        .rev()
+        .filter_map(|nvp| Query::parse_borrowed(nvp).ok())
+        .flatten()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned())
 }
 
 impl Request {
+    /**
+    Gather all the information about the request from the environment,
+    reading the body (if any) from stdin.
+    */
     pub fn new() -> Result<Request, Error> {
+        Request::gather(true, RequestOptions::default())
+    }
+
+    /**
+    Like `Request::new()`, but parsing a multipart body with `limits`
+    instead of the default `MultipartLimits`.
+    */
+    pub fn new_with_limits(limits: MultipartLimits) -> Result<Request, Error> {
+        Request::gather(true, RequestOptions::new().with_multipart_limits(limits))
+    }
+
+    /**
+    Like `Request::new()`, but reading the request according to `options`
+    instead of the default `RequestOptions`.
+    */
+    pub fn new_with_options(options: RequestOptions) -> Result<Request, Error> {
+        Request::gather(true, options)
+    }
+
+    /**
+    Gather the information about the request from the environment, but
+    leave the body (if any) unread on stdin; the returned request's body
+    is always `Body::None`.
+
+    This is for reading large bodies a piece at a time, as with a
+    `MultipartReader`, rather than all at once.
+
+    ```rust,no_run
+    use dumb_cgi::{MultipartReader, Request};
+
+    let req = Request::without_body().unwrap();
+    let mut reader = MultipartReader::for_request(&req, std::io::stdin()).unwrap();
+    while let Some(mut part) = reader.next_part().unwrap() {
+        // ...
+    }
+    ```
+    */
+    pub fn without_body() -> Result<Request, Error> {
+        Request::gather(false, RequestOptions::default())
+    }
+
+    /*
+    Where `new()` and `without_body()` do their work.
+    */
+    fn gather(read_body: bool, options: RequestOptions) -> Result<Request, Error> {
         #[cfg(feature = "log")]
         log::debug!("Request::new() called");
 
-        let mut vars: HashMap<String, String> = HashMap::new();
+        let started = Instant::now();
+        let env: Vec<(OsString, OsString)> = std::env::vars_os().collect();
+        let gathered = started.elapsed();
+        let stdin = std::io::stdin();
+        let mut req = if read_body {
+            let on_terminal = stdin.is_terminal();
+            Request::from_stdin_env(env, stdin.lock(), on_terminal, &options)?
+        } else {
+            Request::from_env_with(env, None::<std::io::StdinLock>, &options)?
+        };
+        req.timings.set_started(started);
+        req.timings.record("env", gathered);
+        Ok(req)
+    }
+
+    /*
+    `from_env_with()`, reading the body from `source` unless it's a
+    terminal (`on_terminal`) and `options.on_terminal` says not to, in
+    which case the request gets a `Warning::TerminalStdin` instead.
+    */
+    pub(crate) fn from_stdin_env<I, K, V, R>(
+        env: I,
+        source: R,
+        on_terminal: bool,
+        options: &RequestOptions,
+    ) -> Result<Request, Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<OsString>,
+        V: Into<OsString>,
+        R: Read,
+    {
+        let read_body = !on_terminal || options.on_terminal.read_body()?;
+        let mut req = Request::from_env_with(env, read_body.then_some(source), options)?;
+        if !read_body {
+            req.warnings.push(Warning::TerminalStdin);
+        }
+        Ok(req)
+    }
+
+    /*
+    Build a `Request` from a set of `(NAME, value)` environment variable
+    pairs, reading the body (if the environment says there is one) from
+    `source`.
+
+    This is where `Request::new()` does all its work; it's separate so
+    that requests can also be constructed from a fake environment (see
+    the `testing` module).
+    */
+    pub(crate) fn from_env<I, K, V, R>(env: I, source: R) -> Result<Request, Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<OsString>,
+        V: Into<OsString>,
+        R: Read,
+    {
+        Request::from_env_with(env, Some(source), &RequestOptions::default())
+    }
+
+    /*
+    `from_env()`, but reading the body according to `options`, and if
+    `source` is `None` the body is left unread (and the request's body is
+    `Body::None`).
+    */
+    pub(crate) fn from_env_with<I, K, V, R>(
+        env: I,
+        source: Option<R>,
+        options: &RequestOptions,
+    ) -> Result<Request, Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<OsString>,
+        V: Into<OsString>,
+        R: Read,
+    {
+        let mut timings = if options.timings {
+            Timings::new()
+        } else {
+            Timings::disabled()
+        };
+        let env_start = Instant::now();
+        let mut warnings: Vec<Warning> = Vec::new();
+        let mut vars: HashMap<String, EnvValue> = HashMap::new();
         let mut headers: HashMap<String, String> = HashMap::new();
 
-        for (k, v) in std::env::vars_os().map(|(os_k, os_v)| {
-            let str_k = String::from(os_k.to_string_lossy());
-            let str_v = String::from(os_v.to_string_lossy());
-            (str_k, str_v)
-        }) {
+        for (k, v) in env {
+            let (k, v): (OsString, OsString) = (k.into(), v.into());
+            let k = k.to_string_lossy();
             if let Some(var_name) = k.strip_prefix(HTTP_PREFIX) {
                 let lower_k = var_name.replace('_', "-").to_lowercase();
+                let v = match v.into_string() {
+                    Ok(v) => v,
+                    Err(v) => {
+                        warnings.push(Warning::NonUtf8Header(lower_k.clone()));
+                        v.to_string_lossy().into_owned()
+                    }
+                };
                 #[cfg(feature = "log")]
                 log::debug!("  \"{}\" -> \"{}\", value: \"{}\"", &k, &lower_k, &v);
-                headers.insert(lower_k, v);
+                if headers.insert(lower_k.clone(), v).is_some() {
+                    warnings.push(Warning::CollidedHeader(lower_k));
+                }
             } else {
                 let upper_k = k.to_uppercase();
+                if v.to_str().is_none() {
+                    warnings.push(Warning::NonUtf8Var(upper_k.clone()));
+                }
                 #[cfg(feature = "log")]
-                log::debug!("  \"{}\" -> \"{}\", value: \"{}\"", &k, &upper_k, &v);
-                vars.insert(upper_k, v);
+                log::debug!("  \"{}\" -> \"{}\", value: {:?}", &k, &upper_k, &v);
+                vars.insert(upper_k, EnvValue::new(v));
             }
         }
 
+        let mut budget = MemoryBudget::new(options.memory_budget);
         let query = match vars.get("QUERY_STRING") {
-            Some(qstr) => parse_query_string(qstr),
+            Some(qstr) => match budget.draw(qstr.as_str().len(), "query string") {
+                Ok(()) => parse_query_string_with_warnings(qstr.as_str(), &mut warnings),
+                Err(e) => Query::Err(e),
+            },
             None => Query::None,
         };
+        timings.record_since("env", env_start);
 
-        let body = if let Some(len_str) = headers.get("content-length") {
+        let body = if let (Some(source), Some(len_str)) = (source, headers.get("content-length")) {
             match len_str.parse::<usize>() {
                 Err(e) => {
                     let err = Error {
@@ -546,9 +2912,22 @@ impl Request {
                     };
                     Body::Err(err)
                 }
-                Ok(body_len) => {
-                    read_body(body_len, headers.get("content-type").map(|x| x.as_str()))
-                }
+                Ok(body_len) => match options
+                    .expect_continue
+                    .refusal(body_len)
+                    .filter(|_| expects_continue(&headers))
+                {
+                    Some(err) => Body::Err(err),
+                    None => read_body(
+                        body_len,
+                        headers.get("content-type").map(|x| x.as_str()),
+                        source,
+                        &options.multipart,
+                        &mut warnings,
+                        &mut timings,
+                        &mut budget,
+                    ),
+                },
             }
         } else {
             Body::None
@@ -559,6 +2938,10 @@ impl Request {
             headers,
             query,
             body,
+            warnings,
+            extensions: Extensions::new(),
+            timings,
+            method_override: None,
         })
     }
 
@@ -587,8 +2970,8 @@ impl Request {
     Return an iterator over all of the `("VARIABLE", "value")` pairs of
     environment variables passed to the CGI program.
     */
-    pub fn vars(&self) -> Vars {
-        Vars(self.vars.iter())
+    pub fn vars(&self) -> Vars<'_> {
+        Vars(VarsIter::Env(self.vars.iter()))
     }
 
     /**
@@ -615,12 +2998,315 @@ impl Request {
         self.headers.get(&modded).map(|v| v.as_str())
     }
 
+    /**
+    Return whether the client sent `Expect: 100-continue`, meaning it was
+    waiting for permission before sending the body.
+
+    Whether the body was read anyway depends on the `ContinuePolicy` in
+    the `RequestOptions` the request was read with; by default it is.
+    */
+    pub fn expects_continue(&self) -> bool {
+        expects_continue(&self.headers)
+    }
+
     /**
     Return an iterator over all the `("header-name", "value")` pairs of
     the request headers that have been exposed to the CGI program.
     */
-    pub fn headers(&self) -> Vars {
-        Vars(self.headers.iter())
+    pub fn headers(&self) -> Vars<'_> {
+        Vars(VarsIter::Strings(self.headers.iter()))
+    }
+
+    /**
+    Return the value of the cookie named `name` if the request's `Cookie`
+    header contains one.
+
+    Cookie names are case-sensitive. If the same name appears more than
+    once, the first value is returned.
+
+    ```
+    # use dumb_cgi::Request;
+    let r = Request::new().unwrap();
+
+    println!("{:?}", r.cookie("session"));
+    // None, unless the user agent sent a `Cookie: session=...` header.
+    ```
+    */
+    pub fn cookie<'a>(&'a self, name: &str) -> Option<&'a str> {
+        let header = self.headers.get("cookie")?;
+        for pair in header.split(';') {
+            if let Some((k, v)) = pair.split_once('=') {
+                if k.trim() == name {
+                    let v = v.trim();
+                    return Some(
+                        v.strip_prefix('"')
+                            .and_then(|v| v.strip_suffix('"'))
+                            .unwrap_or(v),
+                    );
+                }
+            }
+        }
+        None
+    }
+
+    /**
+    Return the flash messages left by `Response::prg_redirect()` (or
+    anything else using `flasher`), and add a cookie to `response` that
+    removes them, so they're only shown once; the same as
+    `flasher.take(self, response)`.
+    */
+    pub fn take_flash(&self, flasher: &Flasher, response: &mut Response) -> Vec<Flash> {
+        flasher.take(self, response)
+    }
+
+    /**
+    Return the value of the form parameter `name`, wherever it was sent,
+    the way classic CGI libraries treat `GET` and `POST` parameters alike.
+
+    Places are checked in this order, and the first value found is
+    returned:
+
+      1. the query string
+      2. an `application/x-www-form-urlencoded` body
+      3. the non-file fields of a `multipart/form-data` body (converted
+         lossily to UTF-8)
+
+    Within each place, a name given more than once takes its last value,
+    as in the query string map. Parts of the request that failed to parse
+    are skipped, down to a single undecodable `name=value` pair: even when
+    one bad pair makes `query()` a `Query::Err`, the query string's other
+    fields are still found here.
+
+    ```rust
+    # use dumb_cgi::testing::MockRequest;
+    let req = MockRequest::post("/search")
+        .query("page=2")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("q=dumb+cgi&page=5")
+        .build()
+        .unwrap();
+
+    assert_eq!(req.param("q").as_deref(), Some("dumb cgi"));
+    // The query string wins.
+    assert_eq!(req.param("page").as_deref(), Some("2"));
+    assert_eq!(req.param("missing"), None);
+    ```
+    */
+    pub fn param(&self, name: &str) -> Option<String> {
+        let from_query = match &self.query {
+            Query::Some(map) => map.get(name).cloned(),
+            Query::Err(_) => self
+                .var("QUERY_STRING")
+                .and_then(|qstr| last_pair_value(qstr, name)),
+            Query::None => None,
+        };
+        from_query.or_else(|| self.body_param(name))
+    }
+
+    /*
+    The form field `name` from the body only (see `param()`).
+    */
+    fn body_param(&self, name: &str) -> Option<String> {
+        match &self.body {
+            Body::Some(bytes) => {
+                if !self.is_urlencoded() {
+                    return None;
+                }
+                last_pair_value(&String::from_utf8_lossy(bytes), name)
+            }
+            Body::Multipart(form) => form
+                .fields()
+                .filter(|part| part.name().as_deref() == Some(name))
+                .last()
+                .map(|part| String::from_utf8_lossy(&part.body).into_owned()),
+            _ => None,
+        }
+    }
+
+    /// Whether the body is `application/x-www-form-urlencoded`.
+    fn is_urlencoded(&self) -> bool {
+        self.header("content-type")
+            .and_then(|ct| ct.split(';').next())
+            .map(|mime| {
+                mime.trim()
+                    .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+            })
+            .unwrap_or(false)
+    }
+
+    /**
+    Work out which charset the request's form data was sent in: the one
+    named by a `_charset_` field (in the body, then the query string),
+    or else the `charset` of the request's `Content-type`, or else
+    `hint` (the charset of the page the form was on, or its
+    `accept-charset`). Unknown charset names are ignored.
+
+    See the `charset` module for an example.
+    */
+    pub fn form_charset(&self, hint: Charset) -> Charset {
+        // `_charset_` values are plain ASCII names, so these are decoded
+        // without knowing the charset.
+        let field = |qstr: &str| {
+            qstr.split('&')
+                .filter_map(|nvp| nvp.split_once('='))
+                .find(|(name, _)| *name == "_charset_")
+                .and_then(|(_, value)| url_decode(value).ok())
+        };
+        let from_body = match &self.body {
+            Body::Some(bytes) if self.is_urlencoded() => field(&String::from_utf8_lossy(bytes)),
+            Body::Multipart(form) => form
+                .get("_charset_")
+                .map(|part| String::from_utf8_lossy(&part.body).into_owned()),
+            _ => None,
+        };
+        let from_content_type = || {
+            let mt = MediaType::parse(self.header("content-type")?)?;
+            mt.param("charset").map(str::to_owned)
+        };
+        from_body
+            .or_else(|| field(self.var("QUERY_STRING")?))
+            .or_else(from_content_type)
+            .and_then(|label| Charset::from_label(&label))
+            .unwrap_or(hint)
+    }
+
+    /**
+    Return the request's form fields (from the query string, and an
+    `application/x-www-form-urlencoded` or `multipart/form-data` body,
+    leaving out file uploads) decoded from the charset `form_charset(hint)`
+    picks. As with `param()`, a field in the query string wins over one
+    with the same name in the body.
+
+    Returns an error if the form data can't be decoded from that charset.
+    */
+    pub fn decoded_form(&self, hint: Charset) -> Result<HashMap<String, String>, Error> {
+        let charset = self.form_charset(hint);
+        let mut form = HashMap::new();
+        match &self.body {
+            Body::Some(bytes) if self.is_urlencoded() => {
+                let body = String::from_utf8_lossy(bytes);
+                form.extend(Query::parse_with_charset(&body, charset)?);
+            }
+            Body::Multipart(multipart) => {
+                for part in multipart.fields() {
+                    if let Some(name) = part.name() {
+                        form.insert(name, part.text_or(charset)?);
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let Some(qstr) = self.var("QUERY_STRING").filter(|q| !q.is_empty()) {
+            form.extend(Query::parse_with_charset(qstr, charset)?);
+        }
+        Ok(form)
+    }
+
+    /**
+    Return the request's (non-multipart) body as text, for parsing as
+    JSON, CSV, or the like: decoded from the `charset` of its
+    `Content-type` (UTF-8 if it doesn't have one), unless it starts with
+    a byte-order mark, which is removed (see `charset::decode_text()`).
+    A request without a body has empty text.
+
+    ```rust
+    # use dumb_cgi::testing::MockRequest;
+    let req = MockRequest::post("/api/items")
+        .header("Content-Type", "application/json")
+        .body(b"\xEF\xBB\xBF{\"name\":\"widget\"}".to_vec())
+        .build()
+        .unwrap();
+    assert_eq!(req.body_text().unwrap(), r#"{"name":"widget"}"#);
+    ```
+
+    A body that can't be decoded, in an unsupported charset, or that's
+    `multipart/form-data` is an error.
+    */
+    pub fn body_text(&self) -> Result<String, Error> {
+        let bad_text = |details: String| Error {
+            code: 400,
+            message: "Invalid request body.".to_owned(),
+            details,
+        };
+        let bytes = match &self.body {
+            Body::None => return Ok(String::new()),
+            Body::Some(bytes) => bytes,
+            Body::Multipart(_) => {
+                return Err(bad_text("Multipart body read as text".to_owned()));
+            }
+            Body::Err(e) => return Err(e.clone()),
+        };
+        let label = self
+            .header("content-type")
+            .and_then(MediaType::parse)
+            .and_then(|mt| mt.param("charset").map(str::to_owned));
+        let charset = match label {
+            Some(label) => Charset::from_label(&label)
+                .ok_or_else(|| bad_text(format!("Unsupported charset {:?}", &label)))?,
+            None => Charset::Utf8,
+        };
+        decode_text(bytes, charset).map_err(|e| bad_text(format!("Request body {}", &e)))
+    }
+
+    /**
+    Return the request method: the `REQUEST_METHOD` variable, unless
+    `apply_method_override()` has replaced it.
+    */
+    pub fn method(&self) -> &str {
+        self.method_override
+            .as_deref()
+            .unwrap_or_else(|| self.original_method())
+    }
+
+    /// Return the `REQUEST_METHOD` the request was actually made with.
+    pub fn original_method(&self) -> &str {
+        self.var("REQUEST_METHOD").unwrap_or("")
+    }
+
+    /**
+    Let a `POST` request stand in for a `PUT`, `PATCH`, or `DELETE`, which
+    HTML forms can't send. This is opt-in; call it before dispatching on
+    `method()`.
+
+    The overriding method is taken from the first of the
+    `X-HTTP-Method-Override`, `X-HTTP-Method`, or `X-Method-Override`
+    headers present, or failing that from a `_method` field in the body
+    (not the query string, so a plain link can't trigger it). Anything
+    other than those three methods is ignored. Returns the new method, if
+    it was overridden; `original_method()` still returns `POST`.
+
+    ```rust
+    # use dumb_cgi::testing::MockRequest;
+    let mut req = MockRequest::post("/items/12")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("_method=delete")
+        .build()
+        .unwrap();
+    assert_eq!(req.apply_method_override(), Some("DELETE"));
+    assert_eq!(req.method(), "DELETE");
+    assert_eq!(req.original_method(), "POST");
+    ```
+    */
+    pub fn apply_method_override(&mut self) -> Option<&str> {
+        const ALLOWED: [&str; 3] = ["PUT", "PATCH", "DELETE"];
+
+        if !self.original_method().eq_ignore_ascii_case("POST") {
+            return None;
+        }
+        let requested = [
+            "x-http-method-override",
+            "x-http-method",
+            "x-method-override",
+        ]
+        .iter()
+        .find_map(|h| self.header(h).map(str::to_owned))
+        .or_else(|| self.body_param("_method"))?;
+        let method = requested.trim().to_ascii_uppercase();
+        if !ALLOWED.contains(&method.as_str()) {
+            return None;
+        }
+        self.method_override = Some(method);
+        self.method_override.as_deref()
     }
 
     /**
@@ -630,10 +3316,345 @@ impl Request {
         &self.query
     }
 
+    /**
+    Parse the query string with `Query::parse_nested()`, for forms sent
+    with `a[]=1&a[]=2`-style names; without a query string, the map is
+    empty.
+
+    ```rust
+    # use dumb_cgi::testing::MockRequest;
+    let req = MockRequest::get("/")
+        .query("ids[]=3&ids[]=5")
+        .build()
+        .unwrap();
+    let query = req.nested_query().unwrap();
+    assert_eq!(query["ids"].as_list().unwrap().len(), 2);
+    ```
+    */
+    pub fn nested_query(&self) -> Result<BTreeMap<String, FormValue>, Error> {
+        match self.var("QUERY_STRING") {
+            Some(qstr) if !qstr.is_empty() => Query::parse_nested(qstr),
+            _ => Ok(BTreeMap::new()),
+        }
+    }
+
     /**
     Return a reference to the request's body.
     */
     pub fn body(&self) -> &Body {
         &self.body
     }
+
+    /**
+    Return any non-fatal problems encountered while reading and parsing
+    the request (duplicate query string keys, skipped multipart parts,
+    non-UTF-8 header values, headers sent more than once, and the like):
+    every place the request wasn't quite well-formed, and the parser had
+    to make do. Counting them by `Warning::kind()` shows how well-behaved
+    a program's clients are.
+
+    ```
+    # use dumb_cgi::Request;
+    let r = Request::new().unwrap();
+
+    for w in r.warnings() {
+        eprintln!("warning: {}", w);
+    }
+    ```
+    */
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /**
+    Return the data attached to this request (see `Extensions`).
+    */
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Return the data attached to this request, for adding to or changing it.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /**
+    Return how long reading and parsing this request took (see the
+    `timing` module), if timings were asked for in its `RequestOptions`.
+    */
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    /// Return this request's timings, for recording phases of your own.
+    pub fn timings_mut(&mut self) -> &mut Timings {
+        &mut self.timings
+    }
+
+    /**
+    Run `handler` on this request, recording how long it takes as the
+    `handler` phase of its timings (see the `timing` module), and return
+    what it returns.
+    */
+    pub fn time_handler<T, F>(&mut self, handler: F) -> T
+    where
+        F: FnOnce(&mut Request) -> T,
+    {
+        let start = Instant::now();
+        let value = handler(self);
+        self.timings.record_since("handler", start);
+        value
+    }
+
+    /**
+    Consume the request and return its body, without copying it.
+
+    ```rust
+    # use dumb_cgi::{testing::MockRequest, Body};
+    let req = MockRequest::post("/").body("some bytes").build().unwrap();
+
+    let bytes = match req.into_body() {
+        Body::Some(bytes) => bytes,
+        _ => panic!("body should be present"),
+    };
+    assert_eq!(bytes, b"some bytes");
+    ```
+    */
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+
+    /// Consume the request and return its decoded query string.
+    pub fn into_query(self) -> Query {
+        self.query
+    }
+
+    /**
+    Consume the request and return all of its pieces, for when you want to
+    take ownership of more than one of them.
+    */
+    pub fn into_parts(self) -> Parts {
+        Parts {
+            vars: self
+                .vars
+                .into_iter()
+                .map(|(k, v)| {
+                    let v = v
+                        .raw
+                        .into_string()
+                        .unwrap_or_else(|v| v.to_string_lossy().into_owned());
+                    (k, v)
+                })
+                .collect(),
+            headers: self.headers,
+            query: self.query,
+            body: self.body,
+            warnings: self.warnings,
+            extensions: self.extensions,
+        }
+    }
+
+    /*
+    What a capture of this request holds: the variables and headers
+    (sorted by name), and either the body (a multipart one re-encoded
+    from its parts, with the `content-length` header to match) or the
+    error reading it.
+    */
+    pub(crate) fn captured(&self) -> Captured<'_> {
+        let mut vars: Vec<(&str, &str)> = self.vars().collect();
+        vars.sort();
+
+        let (body, error) = match &self.body {
+            Body::None => (None, None),
+            Body::Some(bytes) => (Some(bytes.clone()), None),
+            Body::Multipart(parts) => {
+                let boundary = self
+                    .header("content-type")
+                    .and_then(multipart_boundary)
+                    .unwrap_or("dumb-cgi-capture-boundary");
+                let mut bytes: Vec<u8> = Vec::new();
+                for part in parts.iter() {
+                    bytes.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+                    let mut headers: Vec<(&String, &String)> = part.headers.iter().collect();
+                    headers.sort();
+                    for (k, v) in headers.into_iter() {
+                        bytes.extend_from_slice(format!("{}: {}\r\n", k, v).as_bytes());
+                    }
+                    bytes.extend_from_slice(HTTP_NEWLINE);
+                    bytes.extend_from_slice(&part.body);
+                    bytes.extend_from_slice(HTTP_NEWLINE);
+                }
+                bytes.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+                (Some(bytes), None)
+            }
+            Body::Err(e) => (None, Some(e)),
+        };
+
+        let mut headers: Vec<(&str, String)> = self
+            .headers
+            .iter()
+            .map(|(k, v)| match (k.as_str(), &body) {
+                ("content-length", Some(bytes)) => (k.as_str(), bytes.len().to_string()),
+                _ => (k.as_str(), v.clone()),
+            })
+            .collect();
+        headers.sort();
+
+        Captured {
+            vars,
+            headers,
+            body,
+            error,
+        }
+    }
+
+    /*
+    Rebuild a request from the parts of a capture, parsing it exactly as
+    `Request::new()` would have.
+    */
+    pub(crate) fn from_captured<V, H>(
+        vars: V,
+        headers: H,
+        body: Vec<u8>,
+        error: Option<Error>,
+    ) -> Result<Request, Error>
+    where
+        V: IntoIterator<Item = (String, String)>,
+        H: IntoIterator<Item = (String, String)>,
+    {
+        let env = vars.into_iter().chain(headers.into_iter().map(|(k, v)| {
+            let name = format!("{}{}", HTTP_PREFIX, k.replace('-', "_").to_uppercase());
+            (name, v)
+        }));
+        match error {
+            None => Request::from_env(env, body.as_slice()),
+            Some(e) => {
+                // Any body reading this does will fail in its own way; the
+                // captured error replaces it.
+                let mut req = Request::from_env(env, std::io::empty())?;
+                req.body = Body::Err(e);
+                Ok(req)
+            }
+        }
+    }
+
+    /**
+    Serialize everything needed to reconstruct this request into a
+    plain-text "capture" (suitable for writing to a file), which can be
+    turned back into a `Request` with `Request::from_capture()`.
+
+    The capture holds the environment variables, headers, and body
+    (names and values all base64-encoded). A multipart body is re-encoded
+    from its parsed parts, so it replays to the same parts (though not
+    necessarily the exact same bytes). If reading the body failed, the
+    error is recorded instead, and replaying the capture produces the
+    same error. Extensions and timings are not captured.
+
+    (With the `serde` feature, a `Request` can also be serialized with
+    any serde format, and deserialized back into a request the same way.)
+
+    ```rust
+    # use dumb_cgi::{testing::MockRequest, Request};
+    let req = MockRequest::post("/form")
+        .header("Content-Type", "text/plain")
+        .body("hello")
+        .build()
+        .unwrap();
+
+    let capture = req.to_capture();
+    let replayed = Request::from_capture(&capture).unwrap();
+    assert_eq!(replayed.header("content-type"), Some("text/plain"));
+    assert_eq!(replayed.to_capture(), capture);
+    ```
+    */
+    pub fn to_capture(&self) -> String {
+        let captured = self.captured();
+        let mut s = String::from(CAPTURE_MAGIC);
+        s.push('\n');
+        for (k, v) in captured.vars.iter() {
+            s.push_str(&format!(
+                "var\t{}\t{}\n",
+                base64::encode(k.as_bytes()),
+                base64::encode(v.as_bytes())
+            ));
+        }
+        if let Some(e) = captured.error {
+            s.push_str(&format!(
+                "error\t{}\t{}\t{}\n",
+                e.code,
+                base64::encode(e.message.as_bytes()),
+                base64::encode(e.details.as_bytes())
+            ));
+        }
+        for (k, v) in captured.headers.iter() {
+            s.push_str(&format!(
+                "header\t{}\t{}\n",
+                base64::encode(k.as_bytes()),
+                base64::encode(v.as_bytes())
+            ));
+        }
+        if let Some(bytes) = &captured.body {
+            s.push_str(&format!("body\t{}\n", base64::encode(bytes)));
+        }
+        s
+    }
+
+    /**
+    Reconstruct a `Request` from a capture produced by
+    `Request::to_capture()`, parsing it exactly as `Request::new()` would
+    have.
+
+    Returns an `Error` if `capture` isn't a valid capture.
+    */
+    pub fn from_capture(capture: &str) -> Result<Request, Error> {
+        fn bad_capture(details: String) -> Error {
+            Error {
+                code: 500,
+                message: "Invalid request capture.".to_owned(),
+                details,
+            }
+        }
+        fn decode_field(field: &str, n: usize) -> Result<String, Error> {
+            match base64::decode(field).map(String::from_utf8) {
+                Some(Ok(s)) => Ok(s),
+                _ => Err(bad_capture(format!("line {}: invalid base64 value", n + 1))),
+            }
+        }
+
+        // Numbered from the start of the capture, header line included.
+        let mut lines = capture.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(CAPTURE_MAGIC) {
+            return Err(bad_capture("missing capture header line".to_owned()));
+        }
+
+        let mut vars: Vec<(String, String)> = Vec::new();
+        let mut headers: Vec<(String, String)> = Vec::new();
+        let mut body: Vec<u8> = Vec::new();
+        let mut error: Option<Error> = None;
+        for (n, line) in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["var", k, v] => vars.push((decode_field(k, n)?, decode_field(v, n)?)),
+                ["header", k, v] => headers.push((decode_field(k, n)?, decode_field(v, n)?)),
+                ["body", b] => match base64::decode(b) {
+                    Some(bytes) => body = bytes,
+                    None => return Err(bad_capture(format!("line {}: invalid body", n + 1))),
+                },
+                ["error", code, message, details] => {
+                    let code = code.parse::<u16>().map_err(|e| {
+                        bad_capture(format!("line {}: invalid error code: {}", n + 1, &e))
+                    })?;
+                    error = Some(Error {
+                        code,
+                        message: decode_field(message, n)?,
+                        details: decode_field(details, n)?,
+                    });
+                }
+                [""] => {}
+                _ => return Err(bad_capture(format!("line {}: unrecognized", n + 1))),
+            }
+        }
+
+        Request::from_captured(vars, headers, body, error)
+    }
 }
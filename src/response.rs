@@ -1,18 +1,33 @@
 /*!
-The two response types, `EmptyResponse` and `FullResponse`, to help build
-(and deliver) responses to CGI requests.
+The `Response` type, to help build (and deliver) responses to CGI
+requests.
 */
 
-use std::collections::{hash_map::Entry, HashMap};
-use std::io::Write;
+use std::fs::File;
+use std::io::{Read, StdoutLock, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cache::CacheControl;
+use crate::charset::UTF8_BOM;
+use crate::cookie::Cookie;
+use crate::flash::{Flash, Flasher};
+use crate::hash::{sha256, to_hex};
+use crate::json::{Json, JsonFormat};
+use crate::mime;
+use crate::security::{Csp, SecurityHeaders};
+use crate::time::{http_date, parse_http_date};
+use crate::timing::Timings;
+use crate::{reason_phrase, Request, StatusCode};
 
 /*
 Internal value used to store `Response` header name-value pairs.
 
 When a header is added to a `Response` (with one of several methods), a
-lower-cased version of the passed name is used as a hash key, and the
-unchanged version of the name is stored in a `HeaderValue`, along with the
-value, of course. This is mainly to prevent the user from specifying
+lower-cased version of the passed name is kept as a key for looking it up,
+and the unchanged version of the name is stored alongside it, along with
+the value, of course. This is mainly to prevent the user from specifying
 an incorrect value for `Content-length` (it can be easily overwritten
 in the call to `.respond()` because the key is guaranteed to be the
 all-lower-case `"content-length"`). It also prevents multiple header
@@ -34,213 +49,144 @@ Header-name: value0, value1
 ```
 
 So multiple insertions of the same header will map the former form to
-the latter form.
+the latter form. The exception is headers (like `Set-Cookie`) whose values
+can't be combined; those are marked `separate` and sent one per line.
 
 This is also important, because according to that same RFC, the order
-in which these multiple values occur might matter. Because `dumb_cgi`
-sends response headers by iterating through a `HashMap` (which does
-_not_ guarantee any ordering), this rearrangement also guarantees
-multiple values appear in the same order they are added.
+in which these multiple values occur might matter; they appear in the
+same order they are added.
 */
 #[derive(Debug, Clone)]
 struct HeaderValue {
+    key: String,
     name: String,
     value: String,
+    separate: bool,
 }
 
-/**
-A response with no body.
-
-Both `EmptyResponse::new()` and `FullResponse::new()` create values of this
-type. Until the `.with_content_type()` method is called (consuming its
-receiver and returning a `FullResponse`), a response may not have a body
-added or bytes written to its body.
-*/
-#[derive(Debug)]
-pub struct EmptyResponse {
-    status: u16,
-    headers: HashMap<String, HeaderValue>,
+/* The HTTP version of an NPH response's status line. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HttpVersion {
+    Http10,
+    Http11,
 }
 
-impl EmptyResponse {
-    /**
-    Create a new, headerless, empty response with the given HTTP status code.
-
-    Headers can be set, and a body can be added, using the builder pattern:
-
-    ```rust
-    # use dumb_cgi::EmptyResponse;
-    // Responding to a CORS preflight request
-    let r = EmptyResponse::new(204)
-        .with_header("Access-Control-Allow-Methods", "GET, POST")
-        .with_header("Access-Control-Allow-Origin", "https://this-origin.net")
-        .with_header("Access-Control-Allow-Headers", "Content-type");
-    ```
-    */
-    pub fn new(status: u16) -> EmptyResponse {
-        EmptyResponse {
-            status,
-            headers: HashMap::new(),
+/*
+The headers of a response, kept in the order they were first added, so
+they're written out in that order.
+*/
+#[derive(Debug, Clone, Default)]
+struct HeaderList(Vec<HeaderValue>);
+
+impl HeaderList {
+    fn add(&mut self, name: String, value: String) {
+        if name.eq_ignore_ascii_case("set-cookie") {
+            self.add_line(name, value);
+            return;
         }
-    }
-
-    /**
-    Adds a response header.
-
-    Adding multiple headers with the same name will concatenate the added
-    values in a comma-separated list:
-
-    ```rust
-    # use dumb_cgi::EmptyResponse;
-    let mut r = EmptyResponse::new(200);
-    r.add_header("Custom-header", "value0");
-    r.add_header("Custom-header", "value1");
-
-    assert_eq!(r.get_header("Custom-header"), Some("value0, value1"));
-    ```
-    */
-    pub fn add_header<N, V>(&mut self, name: N, value: V)
-    where
-        N: Into<String>,
-        V: Into<String>,
-    {
-        let name = name.into();
-        let value = value.into();
-        let name_key = (&name).to_lowercase();
-        match self.headers.entry(name_key) {
-            Entry::Occupied(mut oe) => {
-                let old = oe.get_mut();
-                (*old).value.push_str(", ");
-                (*old).value.push_str(&value);
-            }
-            Entry::Vacant(ve) => {
-                let header = HeaderValue { name, value };
-                ve.insert(header);
+        let key = name.to_lowercase();
+        match self.0.iter_mut().find(|h| !h.separate && h.key == key) {
+            Some(old) => {
+                old.value.push_str(", ");
+                old.value.push_str(&value);
             }
+            None => self.0.push(HeaderValue {
+                key,
+                name,
+                value,
+                separate: false,
+            }),
         }
     }
 
-    /**
-    Builder pattern method for adding a header value.
-
-    Works similarly to `.add_header()`:
-
-    ```rust
-    # use dumb_cgi::EmptyResponse;
-    let r = EmptyResponse::new(200)
-        .with_header("Custom-header", "value0")
-        .with_header("Custom-header", "value1");
-
-    assert_eq!(r.get_header("custom-header"), Some("value0, value1"));
-    ```
-    */
-    pub fn with_header<N, V>(self, name: N, value: V) -> EmptyResponse
-    where
-        N: Into<String>,
-        V: Into<String>,
-    {
-        let mut new = self;
-        new.add_header(name, value);
-        new
+    fn add_line(&mut self, name: String, value: String) {
+        self.0.push(HeaderValue {
+            key: name.to_lowercase(),
+            name,
+            value,
+            separate: true,
+        });
     }
 
-    /**
-    Adds a `Content-type` header to this request, turning it into a
-    `FullResponse`, which can have a body.
-
-    Any `content-type` header explicitly set with the `.with_header()` or
-    `.add_header()` methods will be overwritten and replaced with this
-    value when the request is sent.
-
-    ```rust
-    # use dumb_cgi::EmptyResponse;
-    let r = EmptyResponse::new(400)
-        .with_content_type("test/plain")
-        .with_body("Your request must contain a \"Content=type\" header.");
-    ````
+    /*
+    Replace the (combined) value of the header `name`, keeping its place,
+    or add it at the end if it isn't there.
     */
-    pub fn with_content_type<T>(self, content_type: T) -> FullResponse
-    where
-        T: Into<String>,
-    {
-        FullResponse {
-            status: self.status,
-            headers: self.headers,
-            content_type: content_type.into(),
-            body: Vec::new(),
+    fn set(&mut self, name: &str, value: String) {
+        let key = name.to_lowercase();
+        match self.0.iter_mut().find(|h| !h.separate && h.key == key) {
+            Some(old) => old.value = value,
+            None => self.0.push(HeaderValue {
+                key,
+                name: name.to_owned(),
+                value,
+                separate: false,
+            }),
         }
     }
 
-    /// Return the HTTP status code associated with this response.
-    pub fn get_status(&self) -> u16 {
-        self.status
+    /*
+    Keep only the headers whose (lower-cased) names `keep` returns true
+    for.
+    */
+    fn retain<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        self.0.retain(|h| keep(&h.key));
     }
 
-    /// Change the HTTP status code associated with this response.
-    pub fn set_status(&mut self, new_status: u16) {
-        self.status = new_status;
+    fn get(&self, name: &str) -> Option<&str> {
+        let key = name.to_lowercase();
+        self.0
+            .iter()
+            .find(|h| !h.separate && h.key == key)
+            .map(|h| h.value.as_str())
     }
 
-    /// Return the header value associated with the header `name` (if set).
-    pub fn get_header<T: AsRef<str>>(&self, name: T) -> Option<&str> {
-        let name = name.as_ref().to_lowercase();
-        self.headers.get(&name).map(|s| s.value.as_str())
+    fn get_lines(&self, name: &str) -> Vec<&str> {
+        let key = name.to_lowercase();
+        self.0
+            .iter()
+            .filter(|h| h.key == key)
+            .map(|h| h.value.as_str())
+            .collect()
     }
 
-    /**
-    Write this response to stdout. This consumes the value.
-
-    ```rust
-    # use dumb_cgi::EmptyResponse;
-    let r = EmptyResponse::new(204)
-        .with_header("Status-Message", "success")
-        .with_header("Cache-Control", "no-store");
-
-    r.respond().unwrap();
-    ```
+    /*
+    Write `first_line` (the `Status` header, or an NPH status line), then
+    the headers in the order they were added (skipping any `Status`), then
+    the blank line that ends the headers.
     */
-    pub fn respond(mut self) -> std::io::Result<()> {
-        let status_str = format!("{}", &self.status);
-        let status_header = HeaderValue {
-            name: "Status".to_owned(),
-            value: status_str,
-        };
-        _ = self.headers.insert("status".to_owned(), status_header);
-
-        let stdout = std::io::stdout();
-        let mut out = stdout.lock();
-        for (_, header) in self.headers.iter() {
-            write!(&mut out, "{}: {}\r\n", &header.name, &header.value)?;
+    fn write_to<W: Write + ?Sized>(&self, out: &mut W, first_line: &str) -> std::io::Result<()> {
+        write!(out, "{}\r\n", first_line)?;
+        for header in self.0.iter().filter(|h| h.key != "status") {
+            write!(out, "{}: {}\r\n", &header.name, &header.value)?;
         }
-
-        write!(&mut out, "\r\n")
+        write!(out, "\r\n")
     }
 }
 
 /**
-A response with a body, instantiated by calling `.with_content_type()`
-on an `EmptyResponse`.
-
-Note that there is no `FullResponse::new()` associated function, and that
-the only way to get a `FullResponse` is by adding a content type to an
-`EmptyResponse` with the `.with_content_type()` method.
+An HTTP response: a status code, headers, and (optionally) a body.
 
 ```rust
-# use dumb_cgi::EmptyResponse;
-let r = EmptyResponse::new(200)                 // an `EmptyResponse` upon instantiation
-    .with_header("Cache-Control", "no-store")  // still an `EmptyResponse`
-    .with_content_type("text/json")            // now a `FullResponse`
-    .with_body("{\"status\":\"updated\"}");
+# use dumb_cgi::Response;
+let r = Response::new(200)
+    .with_header("Cache-Control", "no-store")
+    .with_body("{\"status\":\"updated\"}")
+    .with_content_type("application/json");
 
 r.respond().unwrap();
 ```
 
-`FullResponse` also implements `std::io::Write` for writing to the body:
+A body can be added, and its content type set, in either order. A body
+sent without a content type (set with `.with_content_type()` or as a
+header) is sent as `application/octet-stream`.
+
+`Response` also implements `std::io::Write` for writing to the body:
 
 ```rust
-# use dumb_cgi::EmptyResponse;
+# use dumb_cgi::Response;
 # use std::io::Write;
-let mut r = EmptyResponse::new(200)
+let mut r = Response::new(200)
     .with_content_type("text/plain");
 
 let status = r.get_status();
@@ -251,48 +197,197 @@ write!(&mut r, "The status is {}.", &status).unwrap();
 r.respond().unwrap();
 ```
 */
-
-#[derive(Debug)]
-pub struct FullResponse {
+#[derive(Debug, Clone)]
+pub struct Response {
     status: u16,
-    headers: HashMap<String, HeaderValue>,
+    status_text: Option<String>,
+    /* In NPH mode, the protocol version to put in the status line. */
+    nph: Option<HttpVersion>,
+    http10: bool,
+    headers: HeaderList,
     body: Vec<u8>,
-    content_type: String,
+    reader: Option<Arc<ReaderBody>>,
+    content_type: Option<String>,
+}
+
+/*
+A body to be copied from a reader when the response is sent. Clones of
+a response share the reader, which can only be read once.
+*/
+struct ReaderBody {
+    reader: Mutex<Option<Box<dyn Read + Send>>>,
+    len: Option<u64>,
 }
 
-impl FullResponse {
+impl std::fmt::Debug for ReaderBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReaderBody")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl ReaderBody {
+    /*
+    Copy the body to `out`, returning an error if the reader has already
+    been used up, or gives out before `len` bytes.
+    */
+    fn copy_to<W: Write + ?Sized>(&self, out: &mut W) -> std::io::Result<()> {
+        let taken = match self.reader.lock() {
+            Ok(mut reader) => reader.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        let mut reader =
+            taken.ok_or_else(|| std::io::Error::other("response body reader already used"))?;
+        match self.len {
+            None => std::io::copy(&mut reader, out).map(|_| ()),
+            Some(len) => {
+                let copied = std::io::copy(&mut reader.take(len), out)?;
+                if copied < len {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "response body reader ended after {} of {} bytes",
+                            copied, len
+                        ),
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/**
+The name of a `Response` that doesn't (yet) have a body.
+
+Responses used to come in two types, one without a body, which turned
+into one with a body when given a content type; now there's just
+`Response`, and this name remains so older code keeps working.
+*/
+pub type EmptyResponse = Response;
+
+/**
+The name of a `Response` that has a body; see `EmptyResponse`.
+*/
+pub type FullResponse = Response;
+
+impl Response {
+    /**
+    Create a new, headerless, empty response with the given HTTP status code
+    (a `u16` or a `Status`).
+
+    Headers can be set, and a body can be added, using the builder pattern:
+
+    ```rust
+    # use dumb_cgi::Response;
+    // Responding to a CORS preflight request
+    let r = Response::new(204)
+        .with_header("Access-Control-Allow-Methods", "GET, POST")
+        .with_header("Access-Control-Allow-Origin", "https://this-origin.net")
+        .with_header("Access-Control-Allow-Headers", "Content-type");
+    ```
+    */
+    pub fn new<S: StatusCode>(status: S) -> Response {
+        Response {
+            status: status.status_code(),
+            status_text: None,
+            nph: None,
+            http10: false,
+            headers: HeaderList::default(),
+            body: Vec::new(),
+            reader: None,
+            content_type: None,
+        }
+    }
+
+    /// Return a `ResponseBuilder`, starting with a `200 OK` response.
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder::new()
+    }
+
+    /**
+    Builder-pattern method for setting the `Content-type` of the body.
+
+    Any `content-type` header explicitly set with the `.with_header()` or
+    `.add_header()` methods will be overwritten and replaced with this
+    value when the response is sent.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let r = Response::new(400)
+        .with_content_type("text/plain")
+        .with_body("Your request must contain a \"Content-type\" header.");
+    ````
+    */
+    pub fn with_content_type<T>(self, content_type: T) -> Response
+    where
+        T: Into<String>,
+    {
+        let mut new = self;
+        new.content_type = Some(content_type.into());
+        new
+    }
+
     /**
     Adds a response header.
 
     Adding multiple headers with the same name will concatenate the added
-    values in a comma-separated list:
+    values in a comma-separated list (except for `Set-Cookie` headers,
+    which can't be combined, and are added as with `.add_header_line()`):
 
     ```rust
-    # use dumb_cgi::EmptyResponse;
-    let mut r = EmptyResponse::new(200).with_content_type("text/plain");
+    # use dumb_cgi::Response;
+    let mut r = Response::new(200).with_content_type("text/plain");
     r.add_header("Custom-header", "value0");
     r.add_header("Custom-header", "value1");
 
     assert_eq!(r.get_header("Custom-header"), Some("value0, value1"));
     ```
+
+    The status isn't a header of its own (it's sent first, whatever order
+    headers were added in), so a `Status` header sets it instead, as
+    `.set_status()` and `.set_status_text()` do. A value that doesn't
+    start with a three-digit code makes the response a 500, as a web
+    server would.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let mut r = Response::new(200);
+    r.add_header("Status", "404 Gone Fishing");
+    assert_eq!(r.get_status(), 404);
+    assert_eq!(r.get_status_text(), Some("Gone Fishing"));
+    assert_eq!(r.get_header("Status"), None);
+    ```
     */
     pub fn add_header<N, V>(&mut self, name: N, value: V)
     where
         N: Into<String>,
         V: Into<String>,
     {
-        let name = name.into();
-        let value = value.into();
-        let name_key = (&name).to_lowercase();
-        match self.headers.entry(name_key) {
-            Entry::Occupied(mut oe) => {
-                let old = oe.get_mut();
-                (*old).value.push_str(", ");
-                (*old).value.push_str(&value);
+        let (name, value) = (name.into(), value.into());
+        if name.eq_ignore_ascii_case("status") {
+            self.set_status_header(&value);
+        } else {
+            self.headers.add(name, value);
+        }
+    }
+
+    /*
+    Set the status and reason phrase from the value of a `Status` header.
+    */
+    fn set_status_header(&mut self, value: &str) {
+        match parse_status_header(value) {
+            Some((status, reason)) => {
+                self.status = status;
+                self.status_text = None;
+                if !reason.is_empty() {
+                    self.set_status_text(reason);
+                }
             }
-            Entry::Vacant(ve) => {
-                let header = HeaderValue { name, value };
-                ve.insert(header);
+            None => {
+                self.status = 500;
+                self.status_text = None;
             }
         }
     }
@@ -303,8 +398,8 @@ impl FullResponse {
     Works similarly to `.add_header()`:
 
     ```rust
-    # use dumb_cgi::EmptyResponse;
-    let r = EmptyResponse::new(200)
+    # use dumb_cgi::Response;
+    let r = Response::new(200)
         .with_content_type("test/plain")
         .with_header("Custom-header", "value0")
         .with_header("Custom-header", "value1");
@@ -312,7 +407,7 @@ impl FullResponse {
     assert_eq!(r.get_header("custom-header"), Some("value0, value1"));
     ```
     */
-    pub fn with_header<N, V>(self, name: N, value: V) -> FullResponse
+    pub fn with_header<N, V>(self, name: N, value: V) -> Response
     where
         N: Into<String>,
         V: Into<String>,
@@ -328,8 +423,8 @@ impl FullResponse {
     This replaces any current body value with `new_body`:
 
     ```rust
-    # use dumb_cgi::EmptyResponse;
-    let r = EmptyResponse::new(200)
+    # use dumb_cgi::Response;
+    let r = Response::new(200)
         .with_content_type("text/plain")
         .with_body("This is the first body.")
         .with_body("This is the second body.");
@@ -337,88 +432,1452 @@ impl FullResponse {
     assert_eq!(r.get_body(), "This is the second body.".as_bytes());
     ```
     */
-    pub fn with_body<T: Into<Vec<u8>>>(self, new_body: T) -> FullResponse {
+    pub fn with_body<T: Into<Vec<u8>>>(self, new_body: T) -> Response {
         let mut new = self;
         new.body = new_body.into();
+        new.reader = None;
         new
     }
 
-    /// Return the HTTP status code associated with this response.
-    pub fn get_status(&self) -> u16 {
-        self.status
+    /**
+    Builder-pattern method for starting the body with a UTF-8 byte-order
+    mark (unless it already does), which Excel needs to open a CSV file
+    as UTF-8 rather than mangling every non-ASCII character. Call it
+    after setting the body (it goes before a body reader's contents, too).
+
+    ```rust
+    # use dumb_cgi::Response;
+    let r = Response::new(200)
+        .with_content_type("text/csv; charset=utf-8")
+        .with_header("Content-Disposition", "attachment; filename=\"names.csv\"")
+        .with_body("name\nRen\u{e9}e\n")
+        .with_utf8_bom();
+    assert_eq!(r.get_body(), b"\xEF\xBB\xBFname\nRen\xC3\xA9e\n");
+    ```
+    */
+    pub fn with_utf8_bom(self) -> Response {
+        let mut new = self;
+        if !new.body.starts_with(UTF8_BOM) {
+            new.body.splice(0..0, UTF8_BOM.iter().copied());
+        }
+        new
     }
 
-    /// Change the HTTP status code associated with this response.
-    pub fn set_status(&mut self, new_status: u16) {
-        self.status = new_status;
+    /**
+    Builder-pattern method for setting the body to `value`, as JSON (with
+    the `Content-type` `application/json`). This replaces any current
+    body.
+
+    ```rust
+    # use dumb_cgi::{json::Json, Response};
+    let r = Response::new(200).with_json(&Json::object().with("ok", true));
+    assert_eq!(r.get_body(), br#"{"ok":true}"#);
+    ```
+    */
+    pub fn with_json(self, value: &Json) -> Response {
+        self.with_json_formatted(value, &JsonFormat::default())
     }
 
-    /// Return the header value associated with the header `name` (if set).
-    pub fn get_header<T: AsRef<str>>(&self, name: T) -> Option<&str> {
-        let name = name.as_ref().to_lowercase();
-        self.headers.get(&name).map(|s| s.value.as_str())
+    /**
+    Like `.with_json()`, but written indented if `req` asks for it with
+    `?pretty=1` (see `JsonFormat::for_request()`).
+    */
+    pub fn with_json_for(self, req: &Request, value: &Json) -> Response {
+        self.with_json_formatted(value, &JsonFormat::new().for_request(req))
     }
 
-    /// Return a reference to the current body payload.
-    pub fn get_body(&self) -> &[u8] {
-        &self.body
+    /**
+    Like `.with_json()`, but writing `value` as `format` says (say, with
+    its object fields sorted, so the same data always makes the same
+    body, and so the same `ETag`).
+    */
+    pub fn with_json_formatted(self, value: &Json, format: &JsonFormat) -> Response {
+        self.with_content_type("application/json")
+            .with_body(value.to_string_with(format))
     }
 
     /**
-    Write this response to stdout. This consumes the value.
+    Builder-pattern method for compressing the body, if `req`'s
+    `Accept-Encoding` says the client can take it and the default
+    `gzip::CompressionPolicy` says it's worth it (a textual type, at least
+    1024 bytes), and setting `Content-Encoding` to match. See
+    `.compressed_with()`.
 
     ```rust
-    # use dumb_cgi::EmptyResponse;
-    let body: &str = "<!doctype html>
-    <html>
-    <head>
-        <meta charset='utf-8'>
-    </head>
-    <body>
-        <h1>Hello, browser!</h1>
-    </body>
-    </html>";
+    # use dumb_cgi::{Response, testing::MockRequest};
+    let page = "<p>Hello, world!</p>\n".repeat(100);
+    let req = MockRequest::get("/")
+        .header("Accept-Encoding", "gzip, deflate, br")
+        .build()
+        .unwrap();
+
+    let r = Response::new(200)
+        .with_content_type("text/html")
+        .with_body(page.clone())
+        .compressed_for(&req);
+    assert_eq!(r.get_header("Content-Encoding"), Some("gzip"));
+    assert_eq!(r.get_header("Vary"), Some("Accept-Encoding"));
+    assert!(r.get_body().len() < page.len() / 10);
+    ```
+    */
+    #[cfg(feature = "compression")]
+    pub fn compressed_for(self, req: &Request) -> Response {
+        self.compressed_with(req, &crate::gzip::CompressionPolicy::default())
+    }
 
-    let r = EmptyResponse::new(200)
-        .with_content_type("text/html")    // this makes it a `FullResponse`
-        .with_body(body);
+    /**
+    Builder-pattern method for compressing the body with the coding
+    `policy` prefers among those `req`'s `Accept-Encoding` allows, if
+    `policy` says the body's type and size are worth compressing, and
+    setting `Content-Encoding` to match. `Vary: Accept-Encoding` is added
+    whenever the policy allows compressing the body, whether or not this
+    client could take it, so caches keep the versions apart.
+
+    The body is left alone if it comes from a reader, already has a
+    `Content-Encoding`, or wouldn't get any smaller. This should be the
+    last thing done to a response before it's sent, since the body can't
+    usefully be changed afterward.
+
+    If the body is compressed and there's a strong `ETag` (like the one
+    from `.with_etag_from_body()`), the coding's name is added to it
+    (`"abc"` becomes `"abc-gzip"`), since the compressed body isn't the
+    same bytes; weak tags are left alone.
 
-    r.respond().unwrap();
+    ```rust
+    # use dumb_cgi::{Response, testing::MockRequest};
+    # use dumb_cgi::gzip::CompressionPolicy;
+    let req = MockRequest::get("/")
+        .header("Accept-Encoding", "gzip")
+        .build()
+        .unwrap();
+    let policy = CompressionPolicy::new().with_min_size(0);
+
+    let r = Response::new(200)
+        .with_content_type("application/json")
+        .with_body("[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]")
+        .compressed_with(&req, &policy);
+    assert_eq!(r.get_header("Content-Encoding"), Some("gzip"));
+
+    // Already compressed.
+    let r = Response::new(200)
+        .with_content_type("image/png")
+        .with_body(vec![0u8; 4096])
+        .compressed_with(&req, &policy);
+    assert_eq!(r.get_header("Content-Encoding"), None);
+    assert_eq!(r.get_header("Vary"), None);
     ```
     */
-    pub fn respond(mut self) -> std::io::Result<()> {
-        let status_str = format!("{}", &self.status);
-        self.add_header("Status".to_owned(), status_str);
-        if !self.body.is_empty() {
-            self.add_header("Content-type".to_owned(), self.content_type.clone());
-            self.add_header("Content-length".to_owned(), format!("{}", self.body.len()));
+    #[cfg(feature = "compression")]
+    pub fn compressed_with(
+        self,
+        req: &Request,
+        policy: &crate::gzip::CompressionPolicy,
+    ) -> Response {
+        let mut new = self;
+        let content_type = new
+            .content_type
+            .as_deref()
+            .or_else(|| new.headers.get("content-type"))
+            .unwrap_or(mime::DEFAULT);
+        if new.reader.is_some()
+            || new.headers.get("content-encoding").is_some()
+            || !policy.allows(content_type, new.body.len())
+        {
+            return new;
         }
+        new.add_vary("Accept-Encoding");
 
-        let stdout = std::io::stdout();
-        let mut out = stdout.lock();
+        let coding = match req.header("accept-encoding").and_then(|a| policy.choose(a)) {
+            Some(coding) => coding,
+            None => return new,
+        };
+        let packed = coding.encode(&new.body);
+        if packed.len() < new.body.len() {
+            new.body = packed;
+            new.headers
+                .set("Content-Encoding", coding.name().to_owned());
+            // The compressed body is a different representation, so it
+            // can't share a strong `ETag` with the uncompressed one.
+            if let Some(etag) = new.headers.get("etag") {
+                if let Some(tag) = etag.strip_suffix('"').filter(|t| t.starts_with('"')) {
+                    let etag = format!("{}-{}\"", tag, coding.name());
+                    new.headers.set("ETag", etag);
+                }
+            }
+        }
+        new
+    }
+
+    /*
+    Add `name` to the `Vary` header, unless it's already there.
+    */
+    pub(crate) fn add_vary(&mut self, name: &str) {
+        let present = self
+            .headers
+            .get("vary")
+            .is_some_and(|v| v.split(',').any(|n| n.trim().eq_ignore_ascii_case(name)));
+        if !present {
+            self.headers.add("Vary".to_owned(), name.to_owned());
+        }
+    }
+
+    /**
+    Builder-pattern method for sending the body from `reader` (a file,
+    say), copying it out a piece at a time when the response is sent
+    rather than holding it all in memory. This replaces any current body.
+
+    If `len` is given, it's sent as the `Content-length`, and exactly that
+    many bytes are copied (sending fails if the reader runs out sooner).
+    Otherwise there's no `Content-length`, and the reader is copied until
+    it runs out.
+
+    The reader can only be read once, so a response with one can only be
+    sent once (clones share the reader). Anything written to the response
+    (with `write!()`) is sent before the reader's contents.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let data = std::io::Cursor::new(b"a long report".to_vec());
+    let r = Response::new(200)
+        .with_content_type("text/plain")
+        .with_body_reader(data, Some(13));
+    assert_eq!(
+        r.to_bytes().unwrap(),
+        b"Status: 200 OK\r\nContent-type: text/plain\r\nContent-length: 13\r\n\r\na long report"
+    );
+    ```
+    */
+    pub fn with_body_reader<R>(self, reader: R, len: Option<u64>) -> Response
+    where
+        R: Read + Send + 'static,
+    {
+        let mut new = self;
+        new.body = Vec::new();
+        new.reader = Some(Arc::new(ReaderBody {
+            reader: Mutex::new(Some(Box::new(reader))),
+            len,
+        }));
+        new
+    }
+
+    /**
+    Return a `200 OK` response that sends the file at `path`, with a
+    `Content-type` guessed from its extension (by `mime::from_path()`)
+    and its `Content-length` and `Last-Modified` date. The file is copied
+    out when the response is sent, rather than read into memory.
 
-        for (_, header) in self.headers.iter() {
-            write!(&mut out, "{}: {}\r\n", &header.name, &header.value)?;
+    A file that doesn't exist (or is a directory) is a 404 error, and one
+    that can't be read is a 403.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let path = std::env::temp_dir().join("dumb_cgi_send_file_doctest.css");
+    std::fs::write(&path, "body { color: #333; }").unwrap();
+
+    let r = Response::send_file(&path).unwrap();
+    assert_eq!(r.get_content_type(), "text/css; charset=utf-8");
+    assert!(r.get_header("last-modified").unwrap().ends_with(" GMT"));
+    let bytes = r.to_bytes().unwrap();
+    assert!(bytes.ends_with(b"Content-length: 21\r\n\r\nbody { color: #333; }"));
+
+    assert_eq!(Response::send_file("/no/such/file").unwrap_err().code, 404);
+    # std::fs::remove_file(&path).unwrap();
+    ```
+    */
+    pub fn send_file<P: AsRef<Path>>(path: P) -> Result<Response, crate::Error> {
+        let path = path.as_ref();
+        let file_error = |e: std::io::Error| {
+            let (code, message) = match e.kind() {
+                std::io::ErrorKind::NotFound => (404, "Not found."),
+                std::io::ErrorKind::PermissionDenied => (403, "Forbidden."),
+                _ => (500, "Internal server error."),
+            };
+            crate::Error {
+                code,
+                message: message.to_owned(),
+                details: format!("Unable to send file {}: {}", path.display(), &e),
+            }
+        };
+
+        let file = File::open(path).map_err(file_error)?;
+        let meta = file.metadata().map_err(file_error)?;
+        if meta.is_dir() {
+            return Err(file_error(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "is a directory",
+            )));
         }
-        write!(&mut out, "\r\n")?;
 
-        if !self.body.is_empty() {
-            out.write_all(&self.body)?;
+        let mut r = Response::new(200)
+            .with_content_type(mime::from_path(path).unwrap_or(mime::DEFAULT))
+            .with_body_reader(file, Some(meta.len()));
+        if let Ok(modified) = meta.modified() {
+            r = r.with_last_modified(modified);
         }
+        Ok(r)
+    }
 
-        Ok(())
+    /**
+    Builder-pattern method for setting the `Cache-Control` header
+    (replacing any already set).
+
+    ```rust
+    # use dumb_cgi::{cache::CacheControl, Response};
+    let r = Response::new(200)
+        .with_cache_control(CacheControl::private().max_age(60).must_revalidate());
+    assert_eq!(r.get_header("Cache-Control"), Some("private, max-age=60, must-revalidate"));
+    ```
+    */
+    pub fn with_cache_control(self, cache_control: CacheControl) -> Response {
+        let mut new = self;
+        new.headers.set("Cache-Control", cache_control.to_string());
+        new
     }
-}
 
-/// `Write` is implemented for `FullResponse` by appending to the `.body`
-/// vector, in exactly the same way it's implemented for `Vec<u8>`.
-impl Write for FullResponse {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.body.extend_from_slice(buf);
-        Ok(buf.len())
+    /**
+    Builder-pattern method for setting the `Server-Timing` header to the
+    phases recorded in `timings` (see the `timing` module), if any were
+    recorded; otherwise this does nothing.
+    */
+    pub fn with_server_timing(self, timings: &Timings) -> Response {
+        if !timings.is_enabled() {
+            return self;
+        }
+        let mut new = self;
+        new.headers.set("Server-Timing", timings.server_timing());
+        new
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
+    /**
+    Builder-pattern method for adding the default set of security headers
+    (those it doesn't have already); see `security::SecurityHeaders` for
+    what they are, and for choosing others.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let r = Response::new(200)
+        .with_header("X-Frame-Options", "DENY")
+        .with_security_headers();
+    assert_eq!(r.get_header("X-Content-Type-Options"), Some("nosniff"));
+    assert_eq!(r.get_header("X-Frame-Options"), Some("DENY"));
+    ```
+    */
+    pub fn with_security_headers(self) -> Response {
+        SecurityHeaders::new().apply(self)
+    }
+
+    /**
+    Builder-pattern method for setting the `Content-Security-Policy` (or,
+    for a report-only policy, `Content-Security-Policy-Report-Only`)
+    header to `csp` (replacing any already set).
+
+    ```rust
+    # use dumb_cgi::security::{nonce, Csp, SELF};
+    # use dumb_cgi::Response;
+    let r = Response::new(200)
+        .with_csp(&Csp::new().script_src([SELF, &nonce("r4nd0m")]).report_only());
+    assert_eq!(
+        r.get_header("Content-Security-Policy-Report-Only"),
+        Some("script-src 'self' 'nonce-r4nd0m'")
+    );
+    ```
+    */
+    pub fn with_csp(self, csp: &Csp) -> Response {
+        let mut new = self;
+        new.headers.set(csp.header_name(), csp.to_string());
+        new
+    }
+
+    /**
+    Builder-pattern method for setting the `Last-Modified` header to
+    `when` (to the second), as an HTTP date. (`.not_modified_if()` checks
+    it against `If-Modified-Since`.)
+
+    ```rust
+    # use std::time::{Duration, UNIX_EPOCH};
+    # use dumb_cgi::Response;
+    let when = UNIX_EPOCH + Duration::from_millis(784_111_777_500);
+    let r = Response::new(200).with_last_modified(when);
+    assert_eq!(r.get_header("Last-Modified"), Some("Sun, 06 Nov 1994 08:49:37 GMT"));
+    ```
+    */
+    pub fn with_last_modified(self, when: SystemTime) -> Response {
+        let mut new = self;
+        let unix = match when.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        new.headers.set("Last-Modified", http_date(unix));
+        new
+    }
+
+    /**
+    Return a `303 See Other` redirect to `location`, leaving `messages`
+    (if there are any) in `flasher`'s signed cookie for the page
+    redirected to to show with `Request::take_flash()` (see the `flash`
+    module).
+
+    This is the Post/Redirect/Get pattern: a form handler answers a
+    successful `POST` with a redirect, so the page the user ends up on
+    was fetched with a `GET`, and reloading it doesn't send the form
+    again. The cookie is `Secure` if `flasher` says so, or if `req` (the
+    `POST`) came in over HTTPS.
+
+    ```rust
+    # use dumb_cgi::{Response, testing::MockRequest};
+    use dumb_cgi::flash::{Flash, Flasher};
+
+    let flasher = Flasher::new("server-side secret key");
+    let post = MockRequest::post("/comments").build().unwrap();
+    let r = Response::prg_redirect(
+        &post,
+        "/comments#latest",
+        &flasher,
+        &[Flash::success("Thanks; your comment is up.")],
+    );
+    assert_eq!(r.get_status(), 303);
+    assert_eq!(r.get_header("Location"), Some("/comments#latest"));
+
+    // The browser follows the redirect, sending the cookie back.
+    let set_cookie = r.get_cookies()[0];
+    let flash = set_cookie.split(';').next().unwrap();
+    let get = MockRequest::get("/comments")
+        .header("Cookie", flash)
+        .build()
+        .unwrap();
+    let mut page = Response::new(200).with_content_type("text/html");
+    assert_eq!(
+        get.take_flash(&flasher, &mut page),
+        [Flash::success("Thanks; your comment is up.")]
+    );
+    // (`page` now also removes the cookie, so the message is shown once.)
+    ```
+    */
+    pub fn prg_redirect(
+        req: &Request,
+        location: &str,
+        flasher: &Flasher,
+        messages: &[Flash],
+    ) -> Response {
+        // The location goes straight into a header.
+        let location: String = location.chars().filter(|c| !c.is_control()).collect();
+        let mut r = Response::new(303).with_header("Location", location);
+        let https = req
+            .var("HTTPS")
+            .is_some_and(|v| v.eq_ignore_ascii_case("on") || v == "1");
+        flasher.set_with(&mut r, messages, flasher.is_secure() || https);
+        r
+    }
+
+    /**
+    Builder-pattern method for setting an `ETag` made from a hash of the
+    body, so the response can be checked with `.not_modified_if()`. The
+    same body always gets the same tag. Does nothing if the body comes
+    from a reader (which can't be hashed without reading it).
+
+    ```rust
+    # use dumb_cgi::Response;
+    let a = Response::new(200).with_body("same").with_etag_from_body();
+    let b = Response::new(200).with_body("same").with_etag_from_body();
+    let c = Response::new(200).with_body("different").with_etag_from_body();
+    assert_eq!(a.get_header("ETag"), b.get_header("ETag"));
+    assert_ne!(a.get_header("ETag"), c.get_header("ETag"));
+    assert!(a.get_header("ETag").unwrap().starts_with('"'));
+    ```
+    */
+    pub fn with_etag_from_body(self) -> Response {
+        let mut new = self;
+        if new.reader.is_none() {
+            let etag = format!("\"{}\"", &to_hex(&sha256(&new.body))[..16]);
+            new.headers.set("ETag", etag);
+        }
+        new
+    }
+
+    /**
+    Builder-pattern method for turning the response into a `304 Not
+    Modified` (with no body) if `req` is a `GET` or `HEAD` whose
+    validators show the client's cached copy is current:
+
+      * an `If-None-Match` listing the response's `ETag` (or `*`), or,
+        if there's no `If-None-Match`,
+      * an `If-Modified-Since` no earlier than its `Last-Modified`.
+
+    The 304 keeps the response's other headers (`ETag`, `Cache-Control`,
+    `Vary`, cookies, and so on), but not the ones describing the body.
+    Responses that aren't successes are left alone.
+
+    ```rust
+    # use dumb_cgi::{Response, testing::MockRequest};
+    let page = || Response::new(200)
+        .with_content_type("text/html")
+        .with_header("Cache-Control", "no-cache")
+        .with_body("<p>Today's news</p>")
+        .with_etag_from_body();
+    let etag = page().get_header("etag").unwrap().to_owned();
+
+    let req = MockRequest::get("/news")
+        .header("If-None-Match", format!("\"older\", {}", &etag))
+        .build()
+        .unwrap();
+    let r = page().not_modified_if(&req);
+    assert_eq!(r.get_status(), 304);
+    assert!(r.get_body().is_empty());
+    assert_eq!(r.get_header("etag"), Some(etag.as_str()));
+    assert_eq!(r.get_header("cache-control"), Some("no-cache"));
+
+    let req = MockRequest::get("/news").build().unwrap();
+    assert_eq!(page().not_modified_if(&req).get_status(), 200);
+    ```
+    */
+    pub fn not_modified_if(self, req: &Request) -> Response {
+        let mut new = self;
+        if !(200..300).contains(&new.status) || !matches!(req.method(), "GET" | "HEAD") {
+            return new;
+        }
+        let current = match req.header("if-none-match") {
+            Some(tags) => new.headers.get("etag").is_some_and(|etag| {
+                // Weak comparison, which is what `If-None-Match` uses.
+                let etag = etag.trim_start_matches("W/");
+                tags.split(',').any(|t| {
+                    let t = t.trim();
+                    t == "*" || t.trim_start_matches("W/") == etag
+                })
+            }),
+            None => {
+                let since = req.header("if-modified-since").and_then(parse_http_date);
+                let modified = new.headers.get("last-modified").and_then(parse_http_date);
+                matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+            }
+        };
+        if current {
+            new.status = 304;
+            new.status_text = None;
+            new.body.clear();
+            new.reader = None;
+            new.content_type = None;
+            new.headers
+                .retain(|key| key == "content-location" || !key.starts_with("content-"));
+        }
+        new
+    }
+
+    /**
+    Adds a response header on a line of its own, even if a header with
+    the same name has already been added, for headers whose values can't
+    be combined into a comma-separated list.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let mut r = Response::new(200).with_content_type("text/plain");
+    r.add_header_line("Link", "</style.css>; rel=preload");
+    r.add_header_line("Link", "</app.js>; rel=preload");
+
+    assert_eq!(
+        r.get_header_lines("link"),
+        ["</style.css>; rel=preload", "</app.js>; rel=preload"]
+    );
+    ```
+    */
+    pub fn add_header_line<N, V>(&mut self, name: N, value: V)
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let (name, value) = (name.into(), value.into());
+        if name.eq_ignore_ascii_case("status") {
+            self.set_status_header(&value);
+        } else {
+            self.headers.add_line(name, value);
+        }
+    }
+
+    /// Builder pattern method for adding a header on a line of its own
+    /// (see `.add_header_line()`).
+    pub fn with_header_line<N, V>(self, name: N, value: V) -> Response
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let mut new = self;
+        new.add_header_line(name, value);
+        new
+    }
+
+    /**
+    Return every value of the header `name`, in the order they were added:
+    the (combined) value set with `.add_header()`, if any, and those added
+    with `.add_header_line()`.
+    */
+    pub fn get_header_lines<T: AsRef<str>>(&self, name: T) -> Vec<&str> {
+        self.headers.get_lines(name.as_ref())
+    }
+
+    /**
+    Add a `Set-Cookie` header for `cookie`. Unlike other headers, each
+    cookie is sent as a separate header line.
+
+    ```rust
+    # use dumb_cgi::{cookie::Cookie, Response};
+    let mut r = Response::new(200).with_content_type("text/plain");
+    r.add_cookie(Cookie::new("a", "1"));
+    r.add_cookie(Cookie::new("b", "2").with_http_only(true));
+    assert_eq!(r.get_cookies(), ["a=1", "b=2; HttpOnly"]);
+    ```
+    */
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        self.add_header_line("Set-Cookie", cookie.to_string());
+    }
+
+    /// Builder-pattern method for adding a cookie (see `.add_cookie()`).
+    pub fn with_cookie(self, cookie: Cookie) -> Response {
+        let mut new = self;
+        new.add_cookie(cookie);
+        new
+    }
+
+    /// Return the `Set-Cookie` header values added with `.add_cookie()`.
+    pub fn get_cookies(&self) -> Vec<&str> {
+        self.get_header_lines("set-cookie")
+    }
+
+    /// Return the HTTP status code associated with this response.
+    pub fn get_status(&self) -> u16 {
+        self.status
+    }
+
+    /// Change the HTTP status code associated with this response.
+    pub fn set_status<S: StatusCode>(&mut self, new_status: S) {
+        self.status = new_status.status_code();
+    }
+
+    /**
+    Builder-pattern method for setting the reason phrase sent after the
+    status code in the `Status` header, instead of the standard one (or
+    nothing, for codes without one). Some gateways pass it on to clients.
+
+    Control characters are removed, so the text can't end the header.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let r = Response::new(299).with_status_text("Custom Thing");
+    assert_eq!(r.get_status_text(), Some("Custom Thing"));
+
+    let bytes = r.to_bytes().unwrap();
+    assert!(bytes.starts_with(b"Status: 299 Custom Thing\r\n"));
+    ```
+    */
+    pub fn with_status_text<T: AsRef<str>>(self, text: T) -> Response {
+        let mut new = self;
+        new.set_status_text(text);
+        new
+    }
+
+    /// Set the reason phrase sent in the `Status` header (see
+    /// `.with_status_text()`).
+    pub fn set_status_text<T: AsRef<str>>(&mut self, text: T) {
+        let text: String = text.as_ref().chars().filter(|c| !c.is_control()).collect();
+        self.status_text = Some(text.trim().to_owned());
+    }
+
+    /// Return the custom reason phrase set with `.with_status_text()`, if any.
+    pub fn get_status_text(&self) -> Option<&str> {
+        self.status_text.as_deref()
+    }
+
+    /**
+    Builder-pattern method for sending the response in "non-parsed
+    header" (NPH) mode, for a program the web server runs as an `nph-`
+    script and passes its output straight to the client: an HTTP status
+    line (`HTTP/1.1 200 OK`) is sent instead of a `Status` header, along
+    with the `Date`, `Content-length`, and `Connection` headers a server
+    would otherwise add.
+
+    The status line says `HTTP/1.1`; use `.with_nph_for()` to answer in
+    the protocol version of the request. `is_nph_script()` says whether
+    the program is being run that way.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let r = Response::new(404)
+        .with_nph(true)
+        .with_content_type("text/plain")
+        .with_body("Gone fishing.");
+    let bytes = r.to_bytes().unwrap();
+    let text = String::from_utf8_lossy(&bytes);
+    assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(text.contains("\r\nDate: "));
+    assert!(text.contains("\r\nConnection: close\r\n"));
+    assert!(text.ends_with("\r\n\r\nGone fishing."));
+    ```
+    */
+    pub fn with_nph(self, nph: bool) -> Response {
+        let mut new = self;
+        new.nph = nph.then_some(HttpVersion::Http11);
+        new
+    }
+
+    /**
+    Builder-pattern method for sending the response in NPH mode (see
+    `.with_nph()`) as the answer to `req`: the status line is in the
+    protocol version `req` came in (according to `SERVER_PROTOCOL`),
+    `HTTP/1.0` or (otherwise) `HTTP/1.1`.
+
+    ```rust
+    # use dumb_cgi::Response;
+    # use dumb_cgi::testing::MockRequest;
+    let req = MockRequest::get("/")
+        .var("SERVER_PROTOCOL", "HTTP/1.0")
+        .build()
+        .unwrap();
+    let r = Response::new(200).with_nph_for(&req);
+    let text = String::from_utf8(r.to_bytes().unwrap()).unwrap();
+    assert!(text.starts_with("HTTP/1.0 200 OK\r\n"));
+    ```
+    */
+    pub fn with_nph_for(self, req: &Request) -> Response {
+        let mut new = self;
+        new.nph = match req.var("SERVER_PROTOCOL") {
+            Some("HTTP/1.0") => Some(HttpVersion::Http10),
+            _ => Some(HttpVersion::Http11),
+        };
+        new
+    }
+
+    /// Return whether the response will be sent in NPH mode (see
+    /// `.with_nph()`).
+    pub fn is_nph(&self) -> bool {
+        self.nph.is_some()
+    }
+
+    /**
+    Builder-pattern method for sending the response in a way HTTP/1.0
+    clients (some embedded devices still are) can handle, if `req` came in
+    over HTTP/1.0 (according to `SERVER_PROTOCOL`); for HTTP/1.1 requests
+    nothing changes. When the response is sent:
+
+      * It always has a `Content-length` (even `0`), since a 1.0 client
+        can't be sent a body in chunks. A body from a reader of unknown
+        length is read into memory first to measure it, and `.start()`
+        holds on to everything written until `BodyWriter::finish()`, so
+        nothing is sent at all if that isn't called.
+      * Any `Transfer-Encoding` header is left out.
+      * Statuses that came after 1.0 are replaced with the nearest older
+        ones: `303 See Other` and `307 Temporary Redirect` with
+        `302 Found`, and `308 Permanent Redirect` with
+        `301 Moved Permanently`.
+      * A `Cache-Control` header is accompanied by the 1.0 headers that
+        say the same thing, if they aren't there already: `Pragma:
+        no-cache` and an `Expires` in the past for `no-cache` or
+        `no-store`, and otherwise an `Expires` date `max-age` seconds
+        away.
+
+    ```rust
+    # use dumb_cgi::Response;
+    # use dumb_cgi::testing::MockRequest;
+    let req = MockRequest::get("/status.txt")
+        .var("SERVER_PROTOCOL", "HTTP/1.0")
+        .build()
+        .unwrap();
+    let r = Response::new(303)
+        .with_header("Location", "/done")
+        .with_header("Cache-Control", "no-store")
+        .http10_compat_for(&req);
+    let text = String::from_utf8(r.to_bytes().unwrap()).unwrap();
+    assert!(text.starts_with("Status: 302 Found\r\n"));
+    assert!(text.contains("\r\nPragma: no-cache\r\n"));
+    assert!(text.contains("\r\nExpires: Thu, 01 Jan 1970 00:00:00 GMT\r\n"));
+    assert!(text.contains("\r\nContent-length: 0\r\n"));
+    ```
+    */
+    pub fn http10_compat_for(self, req: &Request) -> Response {
+        let mut new = self;
+        new.http10 = req.var("SERVER_PROTOCOL") == Some("HTTP/1.0");
+        new
+    }
+
+    /*
+    The status code as it'll be sent: older equivalents of newer
+    redirects, for HTTP/1.0 clients.
+    */
+    fn sent_status(&self) -> u16 {
+        match self.status {
+            303 | 307 if self.http10 => 302,
+            308 if self.http10 => 301,
+            status => status,
+        }
+    }
+
+    /*
+    The first line of the response: the `Status` header, or in NPH mode
+    the HTTP status line.
+    */
+    fn status_line(&self) -> String {
+        let status = self.sent_status();
+        let reason = self
+            .status_text
+            .as_deref()
+            .filter(|r| !r.is_empty() && status == self.status)
+            .or_else(|| reason_phrase(status));
+        let start = match self.nph {
+            Some(HttpVersion::Http10) => "HTTP/1.0",
+            Some(HttpVersion::Http11) => "HTTP/1.1",
+            None => "Status:",
+        };
+        match reason {
+            Some(reason) => format!("{} {} {}", start, status, reason),
+            None => format!("{} {}", start, status),
+        }
+    }
+
+    /**
+    Return the value of the header `name` (if set), combined as described
+    for `.add_header()`.
+
+    Headers sent on lines of their own aren't included, since their
+    values can't be combined: that's every `Set-Cookie` header, and any
+    added with `.add_header_line()`. Use `.get_cookies()` or
+    `.get_header_lines()` for those.
+
+    ```rust
+    # use dumb_cgi::{cookie::Cookie, Response};
+    let r = Response::new(200)
+        .with_header("Cache-Control", "no-store")
+        .with_cookie(Cookie::new("a", "1"));
+    assert_eq!(r.get_header("cache-control"), Some("no-store"));
+    assert_eq!(r.get_header("Set-Cookie"), None);
+    assert_eq!(r.get_cookies(), ["a=1"]);
+    ```
+    */
+    pub fn get_header<T: AsRef<str>>(&self, name: T) -> Option<&str> {
+        self.headers.get(name.as_ref())
+    }
+
+    /// Return a reference to the current body payload.
+    pub fn get_body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Return the content type set with `.with_content_type()`, or `""`
+    /// if there isn't one.
+    pub fn get_content_type(&self) -> &str {
+        self.content_type.as_deref().unwrap_or("")
+    }
+
+    /*
+    Replace the content type, for `filter::Filters`.
+    */
+    pub(crate) fn set_content_type(&mut self, content_type: String) {
+        self.content_type = Some(content_type);
+    }
+
+    /*
+    Set the header `name` to `value`, replacing any value it had.
+    */
+    pub(crate) fn set_header<V: Into<String>>(&mut self, name: &str, value: V) {
+        self.headers.set(name, value.into());
+    }
+
+    /*
+    Replace the body with `f` of it, for `filter::Filters`.
+    */
+    pub(crate) fn map_body<F: FnOnce(Vec<u8>) -> Vec<u8>>(&mut self, f: F) {
+        let body = std::mem::take(&mut self.body);
+        self.body = f(body);
+    }
+
+    /**
+    Write this response to stdout. This consumes the value.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let body: &str = "<!doctype html>
+    <html>
+    <head>
+        <meta charset='utf-8'>
+    </head>
+    <body>
+        <h1>Hello, browser!</h1>
+    </body>
+    </html>";
+
+    let r = Response::new(200)
+        .with_content_type("text/html")
+        .with_body(body);
+
+    r.respond().unwrap();
+    ```
+    */
+    pub fn respond(self) -> std::io::Result<()> {
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        self.respond_to(&mut out)
+    }
+
+    /**
+    Write this response to `out` instead of stdout, exactly as
+    `.respond()` would. This consumes the value.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let mut out: Vec<u8> = Vec::new();
+    Response::new(200)
+        .with_content_type("text/plain")
+        .with_body("hello")
+        .respond_to(&mut out)
+        .unwrap();
+    assert_eq!(
+        out,
+        b"Status: 200 OK\r\nContent-type: text/plain\r\nContent-length: 5\r\n\r\nhello"
+    );
+    ```
+    */
+    pub fn respond_to<W: Write>(self, out: &mut W) -> std::io::Result<()> {
+        self.write_response(out)
+    }
+
+    /**
+    Return the bytes `.respond()` would write (for snapshot tests, or for
+    saving a rendered response to send again later).
+
+    This doesn't consume the response, but a body from
+    `.with_body_reader()` is read to produce the bytes, and can only be
+    read once: afterward, neither this response nor its clones can be
+    sent (or turned into bytes) again. Errors reading it are returned.
+
+    ```rust
+    # use dumb_cgi::Response;
+    let r = Response::new(200)
+        .with_content_type("text/plain")
+        .with_body("hi");
+    assert_eq!(
+        r.to_bytes().unwrap(),
+        b"Status: 200 OK\r\nContent-type: text/plain\r\nContent-length: 2\r\n\r\nhi"
+    );
+    ```
+    */
+    pub fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
+        let mut out: Vec<u8> = Vec::with_capacity(self.body.len() + 256);
+        self.write_response(&mut out)?;
+        Ok(out)
+    }
+
+    /**
+    Send the headers (and any body already added) to stdout right away,
+    and return a `BodyWriter` for sending the rest of the body as it's
+    produced, so the client can start receiving a long page before it's
+    finished. This consumes the value.
+
+    There's no `Content-length` header (unless one was set explicitly),
+    since the length isn't known; the web server either sends the body
+    in chunks or closes the connection at the end of it.
+
+    ```rust
+    # use std::io::Write;
+    # use dumb_cgi::Response;
+    let mut body = Response::new(200)
+        .with_content_type("text/plain")
+        .start()
+        .unwrap();
+    for n in 1..=3 {
+        writeln!(body, "row {}", n).unwrap();
+        body.flush().unwrap();
+    }
+    body.finish().unwrap();
+    ```
+    */
+    pub fn start(self) -> std::io::Result<BodyWriter<StdoutLock<'static>>> {
+        self.start_to(std::io::stdout().lock())
+    }
+
+    /**
+    Like `.start()`, but sending the response to `out` instead of stdout.
+
+    ```rust
+    # use std::io::Write;
+    # use dumb_cgi::Response;
+    let mut body = Response::new(200)
+        .with_content_type("text/csv")
+        .with_body("n,square\n")
+        .start_to(Vec::new())
+        .unwrap();
+    for n in 1..=2 {
+        writeln!(body, "{},{}", n, n * n).unwrap();
+    }
+    let out = body.finish().unwrap();
+    assert_eq!(
+        out,
+        b"Status: 200 OK\r\nContent-type: text/csv\r\n\r\nn,square\n1,1\n2,4\n"
+    );
+    ```
+    */
+    pub fn start_to<W: Write>(self, out: W) -> std::io::Result<BodyWriter<W>> {
+        let mut out = out;
+        if self.http10 {
+            // The length has to be known before anything is sent.
+            let mut body: Vec<u8> = Vec::new();
+            self.write_body(&mut body)?;
+            return Ok(BodyWriter {
+                out,
+                held: Some(HeldResponse {
+                    headers: self.prepared_headers(true),
+                    status_line: self.status_line(),
+                    body,
+                }),
+            });
+        }
+        self.prepared_headers(true)
+            .write_to(&mut out, &self.status_line())?;
+        self.write_body(&mut out)?;
+        out.flush()?;
+        Ok(BodyWriter { out, held: None })
+    }
+
+    /*
+    Where `.respond_to()` and `.to_bytes()` do their work.
+    */
+    fn write_response<W: Write + ?Sized>(&self, out: &mut W) -> std::io::Result<()> {
+        let unknown_length = self.reader.as_ref().is_some_and(|r| r.len.is_none());
+        if self.http10 && unknown_length {
+            let mut body: Vec<u8> = Vec::new();
+            self.write_body(&mut body)?;
+            let mut headers = self.prepared_headers(false);
+            headers.set("Content-length", body.len().to_string());
+            headers.write_to(out, &self.status_line())?;
+            return out.write_all(&body);
+        }
+        self.prepared_headers(false)
+            .write_to(out, &self.status_line())?;
+        self.write_body(out)
+    }
+
+    /// Write the body bytes, then copy the body reader (if there is one).
+    fn write_body<W: Write + ?Sized>(&self, out: &mut W) -> std::io::Result<()> {
+        out.write_all(&self.body)?;
+        match &self.reader {
+            Some(reader) => reader.copy_to(out),
+            None => Ok(()),
+        }
+    }
+
+    /*
+    The headers as they'll be sent: with the `Content-type` and
+    `Content-length` (unless `streaming`, or the body comes from a reader
+    of unknown length) of the body, if there is one, and the headers NPH
+    and HTTP/1.0 compatibility modes need.
+    */
+    fn prepared_headers(&self, streaming: bool) -> HeaderList {
+        let mut headers = self.headers.clone();
+        if streaming || !self.body.is_empty() || self.reader.is_some() {
+            match &self.content_type {
+                Some(content_type) => headers.set("Content-type", content_type.clone()),
+                None if headers.get("content-type").is_none() => {
+                    headers.set("Content-type", "application/octet-stream".to_owned())
+                }
+                None => {}
+            }
+        }
+        if self.nph.is_some() {
+            if headers.get("date").is_none() {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or(0);
+                headers.set("Date", http_date(now));
+            }
+            if headers.get("connection").is_none() {
+                headers.set("Connection", "close".to_owned());
+            }
+        }
+        let length = match &self.reader {
+            _ if streaming => None,
+            None => Some(self.body.len() as u64),
+            Some(reader) => reader.len.map(|len| self.body.len() as u64 + len),
+        };
+        // Responses that can't have a body don't get a length, and
+        // (outside NPH and HTTP/1.0 modes) the server works out that
+        // empty ones are.
+        let can_have_body = !matches!(self.status, 100..=199 | 204 | 304);
+        if let Some(length) = length {
+            if length > 0 || ((self.nph.is_some() || self.http10) && can_have_body) {
+                headers.set("Content-length", format!("{}", length));
+            }
+        }
+        if self.http10 {
+            downgrade_headers(&mut headers);
+        }
+        headers
+    }
+}
+
+/*
+Replace or accompany headers HTTP/1.0 clients don't understand.
+*/
+fn downgrade_headers(headers: &mut HeaderList) {
+    headers.retain(|name| name != "transfer-encoding");
+    let cache_control = match headers.get("cache-control") {
+        Some(cache_control) => cache_control.to_ascii_lowercase(),
+        None => return,
+    };
+    let directives: Vec<&str> = cache_control.split(',').map(str::trim).collect();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let expires = if directives
+        .iter()
+        .any(|d| *d == "no-cache" || *d == "no-store")
+    {
+        if headers.get("pragma").is_none() {
+            headers.set("Pragma", "no-cache".to_owned());
+        }
+        Some(0)
+    } else {
+        directives
+            .iter()
+            .find_map(|d| d.strip_prefix("max-age="))
+            .and_then(|secs| secs.parse::<i64>().ok())
+            .map(|secs| now.saturating_add(secs))
+    };
+    if let Some(expires) = expires {
+        if headers.get("expires").is_none() {
+            headers.set("Expires", http_date(expires));
+        }
+    }
+}
+
+/*
+A response held back until its body is finished, so its length can be
+sent (see `Response::http10_compat_for()`).
+*/
+#[derive(Debug)]
+struct HeldResponse {
+    headers: HeaderList,
+    status_line: String,
+    body: Vec<u8>,
+}
+
+/**
+The body of a response whose headers have already been sent, returned
+by `Response::start()`; whatever is written to it goes straight to the
+client (once flushed).
+
+(Except in HTTP/1.0 compatibility mode, where the whole response is held
+until `.finish()`; see `Response::http10_compat_for()`.)
+*/
+#[derive(Debug)]
+pub struct BodyWriter<W: Write> {
+    out: W,
+    held: Option<HeldResponse>,
+}
+
+impl<W: Write> BodyWriter<W> {
+    /// Flush anything still buffered, and return the underlying writer.
+    pub fn finish(self) -> std::io::Result<W> {
+        let mut out = self.out;
+        if let Some(held) = self.held {
+            let mut headers = held.headers;
+            headers.set("Content-length", held.body.len().to_string());
+            headers.write_to(&mut out, &held.status_line)?;
+            out.write_all(&held.body)?;
+        }
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+impl<W: Write> Write for BodyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.held {
+            Some(held) => held.body.write(buf),
+            None => self.out.write(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match &mut self.held {
+            Some(held) => held.body.write_all(buf),
+            None => self.out.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &self.held {
+            // Nothing can be sent until the length is known.
+            Some(_) => Ok(()),
+            None => self.out.flush(),
+        }
+    }
+}
+
+/**
+Return whether the program is being run as an NPH ("non-parsed header")
+script: whether its file name (from `SCRIPT_NAME`, or failing that the
+path it was run by) starts with `nph-`, which is how web servers decide
+to pass a program's output straight to the client. Responses from such a
+program should be sent with `.with_nph_for()`.
+*/
+pub fn is_nph_script() -> bool {
+    let path = std::env::var("SCRIPT_NAME")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .or_else(|| std::env::args().next());
+    path.as_deref()
+        .and_then(|p| p.rsplit('/').next())
+        .is_some_and(|name| name.starts_with("nph-"))
+}
+
+fn builder_error(details: String) -> crate::Error {
+    crate::Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details,
+    }
+}
+
+/*
+Whether `name` is a valid header name (an RFC 7230 token).
+*/
+fn valid_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/*
+Whether `value` can be sent as a header value (no control characters,
+which could otherwise be used to start another header).
+*/
+fn valid_header_value(value: &str) -> bool {
+    value.bytes().all(|b| b == b'\t' || !b.is_ascii_control())
+}
+
+/*
+Split the value of a `Status` header into its three-digit code and
+(possibly empty) reason phrase.
+*/
+fn parse_status_header(value: &str) -> Option<(u16, &str)> {
+    let value = value.trim();
+    let (code, reason) = value.split_once(' ').unwrap_or((value, ""));
+    if code.len() != 3 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u16 = code.parse().ok()?;
+    (code >= 100).then_some((code, reason.trim()))
+}
+
+/**
+A builder for `Response`s in the style of the `http` crate's, checking
+its inputs as it goes; the first problem (an invalid status code, or a
+header name or value that can't be sent) is returned as an error from
+`.body()`. A `Status` header sets the status, and is an error if its
+value isn't a valid status code and optional reason phrase.
+
+```rust
+use dumb_cgi::Response;
+
+let r = Response::builder()
+    .status(201)
+    .header("Location", "/items/12")
+    .header("Content-Type", "application/json")
+    .body("{\"id\":12}")
+    .unwrap();
+assert_eq!(r.get_status(), 201);
+assert_eq!(r.get_header("location"), Some("/items/12"));
+
+let bad = Response::builder()
+    .header("X-Injected", "a\r\nSet-Cookie: admin=1")
+    .body(Vec::new());
+assert!(bad.is_err());
+```
+*/
+#[derive(Debug)]
+pub struct ResponseBuilder {
+    inner: Result<Response, crate::Error>,
+}
+
+impl Default for ResponseBuilder {
+    fn default() -> ResponseBuilder {
+        ResponseBuilder {
+            inner: Ok(Response::new(200)),
+        }
+    }
+}
+
+impl ResponseBuilder {
+    /// Return a builder for a `200 OK` response with no headers.
+    pub fn new() -> ResponseBuilder {
+        ResponseBuilder::default()
+    }
+
+    /// Set the status code, which must be between 100 and 999.
+    pub fn status<S: StatusCode>(self, status: S) -> ResponseBuilder {
+        let status = status.status_code();
+        self.and_then(|mut r| {
+            if (100..1000).contains(&status) {
+                r.set_status(status);
+                Ok(r)
+            } else {
+                Err(builder_error(format!("invalid status code {}", status)))
+            }
+        })
+    }
+
+    /**
+    Add a header (as `Response::add_header()` does; a `Content-Type`
+    header is also used as the content type).
+    */
+    pub fn header<N, V>(self, name: N, value: V) -> ResponseBuilder
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let (name, value) = (name.into(), value.into());
+        self.and_then(|mut r| {
+            if !valid_header_name(&name) {
+                return Err(builder_error(format!("invalid header name {:?}", &name)));
+            }
+            if !valid_header_value(&value) {
+                return Err(builder_error(format!(
+                    "invalid value for header {}: {:?}",
+                    &name, &value
+                )));
+            }
+            if name.eq_ignore_ascii_case("status") && parse_status_header(&value).is_none() {
+                return Err(builder_error(format!("invalid Status header {:?}", &value)));
+            }
+            if name.eq_ignore_ascii_case("content-type") {
+                r.set_content_type(value);
+            } else {
+                r.add_header(name, value);
+            }
+            Ok(r)
+        })
+    }
+
+    /// Finish the response with `body`, or return the first problem
+    /// with what was given to the builder.
+    pub fn body<B: Into<Vec<u8>>>(self, body: B) -> Result<Response, crate::Error> {
+        self.inner.map(|r| r.with_body(body))
+    }
+
+    fn and_then<F>(self, f: F) -> ResponseBuilder
+    where
+        F: FnOnce(Response) -> Result<Response, crate::Error>,
+    {
+        ResponseBuilder {
+            inner: self.inner.and_then(f),
+        }
+    }
+}
+
+/// `Write` is implemented for `Response` by appending to the `.body`
+/// vector, in exactly the same way it's implemented for `Vec<u8>`.
+impl Write for Response {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.body.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/**
+Anything that can be sent as a response: `Response`, and `Error` (and
+`Errors`), which respond as with `.to_response()`.
+
+This is for functions that might return either kind of response:
+
+```rust
+use dumb_cgi::{Response, Error, Request, Respond};
+
+fn handle(req: &Request) -> Box<dyn Respond> {
+    match req.var("PATH_INFO") {
+        Some("/ping") => Box::new(Response::new(204)),
+        Some("/hello") => Box::new(
+            Response::new(200)
+                .with_content_type("text/plain")
+                .with_body("Hello!"),
+        ),
+        _ => Box::new(Error {
+            code: 404,
+            message: "Not found.".to_owned(),
+            details: "no such page".to_owned(),
+        }),
+    }
+}
+
+let req = dumb_cgi::testing::MockRequest::get("/nope").build().unwrap();
+let response = handle(&req);
+assert_eq!(response.status(), 404);
+response.respond().unwrap();
+```
+*/
+pub trait Respond {
+    /// Return the HTTP status code of the response.
+    fn status(&self) -> u16;
+
+    /// Write the response to `out`.
+    fn write_to(&self, out: &mut dyn Write) -> std::io::Result<()>;
+
+    /// Return the bytes the response would write (see
+    /// `Response::to_bytes()`).
+    fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
+        let mut out: Vec<u8> = Vec::new();
+        self.write_to(&mut out)?;
+        Ok(out)
+    }
+
+    /// Write the response to stdout. This consumes the value.
+    fn respond(self) -> std::io::Result<()>
+    where
+        Self: Sized,
+    {
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        self.write_to(&mut out)
+    }
+}
+
+impl Respond for Response {
+    fn status(&self) -> u16 {
+        self.status
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        self.write_response(out)
+    }
+}
+
+impl Respond for crate::Error {
+    fn status(&self) -> u16 {
+        self.code
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        Respond::write_to(&self.clone().to_response(), out)
+    }
+}
+
+impl Respond for crate::Errors {
+    fn status(&self) -> u16 {
+        self.to_error().code
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        Respond::write_to(&self.to_error(), out)
+    }
+}
+
+impl<T: Respond + ?Sized> Respond for Box<T> {
+    fn status(&self) -> u16 {
+        (**self).status()
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        (**self).write_to(out)
     }
 }
@@ -0,0 +1,169 @@
+/*!
+Rotating log files by size or age.
+
+A CGI program can't easily cooperate with an external log rotator (there
+is no long-running process to tell to reopen its files), so logs written
+by this crate rotate themselves: before writing, the writer checks whether
+the file is due, and if so renames it out of the way (`log` becomes
+`log.1`, `log.1` becomes `log.2`, and so on, up to the number of old
+copies to keep). Renames are atomic, and the log's lock file is held
+throughout, so no writes are lost.
+
+```rust
+use dumb_cgi::{audit::AuditLog, rotate::Rotation};
+
+let path = std::env::temp_dir().join("dumb_cgi_rotate_doctest.log");
+# _ = std::fs::remove_file(&path);
+let log = AuditLog::new(&path)
+    .with_rotation(Rotation::new().with_max_bytes(10 * 1024 * 1024).with_keep(5));
+log.append("dan", "logged in").unwrap();
+# std::fs::remove_file(&path).unwrap();
+```
+*/
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::lockfile::LockGuard;
+use crate::Error;
+
+fn rotate_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!("Error rotating log file {}: {}", path.display(), &e),
+    }
+}
+
+/*
+The path of the `n`th old copy of `path`.
+*/
+pub(crate) fn numbered(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/**
+When to rotate a log file, and how many old copies to keep.
+
+A file is rotated when it grows past `max_bytes`, or when the current
+`period` (counted from the Unix epoch, so a period of one day rotates at
+midnight UTC) is different from the one it was last written in. With
+neither set, files are never rotated.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    max_bytes: Option<u64>,
+    period: Option<Duration>,
+    keep: u32,
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation {
+            max_bytes: None,
+            period: None,
+            keep: 5,
+        }
+    }
+}
+
+impl Rotation {
+    /// Create a rotation policy that never rotates, keeping 5 old copies.
+    pub fn new() -> Rotation {
+        Rotation::default()
+    }
+
+    /// Builder-pattern method for rotating files larger than `bytes`.
+    pub fn with_max_bytes(self, bytes: u64) -> Rotation {
+        let mut new = self;
+        new.max_bytes = Some(bytes);
+        new
+    }
+
+    /// Builder-pattern method for rotating files every `period` (like
+    /// `Duration::from_secs(24 * 60 * 60)` for daily).
+    pub fn with_period(self, period: Duration) -> Rotation {
+        let mut new = self;
+        new.period = Some(period).filter(|p| !p.is_zero());
+        new
+    }
+
+    /// Builder-pattern method for setting how many old copies to keep
+    /// (zero just deletes the file when it's due, which an `AuditLog`
+    /// refuses to do).
+    pub fn with_keep(self, keep: u32) -> Rotation {
+        let mut new = self;
+        new.keep = keep;
+        new
+    }
+
+    /* How many old copies to keep. */
+    pub(crate) fn keep(&self) -> u32 {
+        self.keep
+    }
+
+    /*
+    Return whether the file with `meta` is due for rotation.
+    */
+    fn is_due(&self, meta: &std::fs::Metadata) -> bool {
+        if let Some(max) = self.max_bytes {
+            if meta.len() > max {
+                return true;
+            }
+        }
+        if let Some(period) = self.period {
+            let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            let period = period.as_secs().max(1);
+            let modified = meta.modified().map(secs).unwrap_or(0);
+            if modified / period != secs(SystemTime::now()) / period {
+                return true;
+            }
+        }
+        false
+    }
+
+    /*
+    Rotate `path` if it's due, assuming the caller holds its lock.
+    */
+    pub(crate) fn rotate_locked(&self, path: &Path) -> Result<bool, Error> {
+        let meta = match std::fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(rotate_error(path, e)),
+        };
+        if !self.is_due(&meta) {
+            return Ok(false);
+        }
+
+        if self.keep == 0 {
+            std::fs::remove_file(path).map_err(|e| rotate_error(path, e))?;
+            return Ok(true);
+        }
+        _ = std::fs::remove_file(numbered(path, self.keep));
+        for n in (1..self.keep).rev() {
+            let from = numbered(path, n);
+            if from.exists() {
+                std::fs::rename(&from, numbered(path, n + 1))
+                    .map_err(|e| rotate_error(&from, e))?;
+            }
+        }
+        std::fs::rename(path, numbered(path, 1)).map_err(|e| rotate_error(path, e))?;
+        Ok(true)
+    }
+
+    /**
+    Rotate the file at `path` if it's due, holding its `.lock` file (the
+    same one `AuditLog` uses) while doing so. Returns whether it was
+    rotated.
+    */
+    pub fn rotate(&self, path: &Path) -> Result<bool, Error> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let _lock =
+            LockGuard::acquire(lock_path.clone()).map_err(|e| rotate_error(&lock_path, e))?;
+        self.rotate_locked(path)
+    }
+}
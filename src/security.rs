@@ -0,0 +1,451 @@
+/*!
+The response headers that switch on browsers' defences, set as a group.
+
+```rust
+use dumb_cgi::security::{FrameOptions, SecurityHeaders};
+use dumb_cgi::Response;
+
+// The defaults:
+let r = Response::new(200).with_security_headers();
+assert_eq!(r.get_header("X-Content-Type-Options"), Some("nosniff"));
+assert_eq!(r.get_header("X-Frame-Options"), Some("SAMEORIGIN"));
+assert_eq!(r.get_header("Referrer-Policy"), Some("strict-origin-when-cross-origin"));
+
+// Or a set of one's own, switched on or off header by header:
+let headers = SecurityHeaders::new()
+    .with_frame_options(Some(FrameOptions::Deny))
+    .with_referrer_policy(None)
+    .with_secure(true)
+    .with_hsts_subdomains(true);
+let r = headers.apply(Response::new(200));
+assert_eq!(r.get_header("X-Frame-Options"), Some("DENY"));
+assert_eq!(r.get_header("Referrer-Policy"), None);
+assert_eq!(
+    r.get_header("Strict-Transport-Security"),
+    Some("max-age=31536000; includeSubDomains")
+);
+```
+
+Headers the response already has are left alone, so a page that needs
+to be framed, say, can set its own `X-Frame-Options` first.
+
+The `Content-Security-Policy` header, which says where a page's scripts,
+styles, images, and so on may come from, has a builder of its own, `Csp`:
+
+```rust
+use dumb_cgi::security::{Csp, NONE, SELF};
+use dumb_cgi::Response;
+
+let csp = Csp::new()
+    .default_src([SELF])
+    .script_src([SELF, "https://cdn.example.com"])
+    .img_src([SELF, "data:"])
+    .object_src([NONE])
+    .frame_ancestors([NONE])
+    .upgrade_insecure_requests();
+let r = Response::new(200).with_csp(&csp);
+assert_eq!(
+    r.get_header("Content-Security-Policy"),
+    Some(
+        "default-src 'self'; script-src 'self' https://cdn.example.com; \
+         img-src 'self' data:; object-src 'none'; frame-ancestors 'none'; \
+         upgrade-insecure-requests"
+    )
+);
+```
+*/
+
+use std::fmt::{Display, Formatter};
+
+use crate::Response;
+
+/// How long (in seconds) browsers are told to stick to HTTPS by default:
+/// a year.
+pub const DEFAULT_HSTS_MAX_AGE: u64 = 365 * 24 * 60 * 60;
+
+/**
+Which pages may show a response in a frame (`X-Frame-Options`).
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOptions {
+    /// None at all.
+    Deny,
+    /// Only pages from the same origin.
+    SameOrigin,
+}
+
+impl FrameOptions {
+    /// Return the header value: `DENY` or `SAMEORIGIN`.
+    pub fn value(&self) -> &'static str {
+        match self {
+            FrameOptions::Deny => "DENY",
+            FrameOptions::SameOrigin => "SAMEORIGIN",
+        }
+    }
+}
+
+/**
+A set of security headers to add to responses (see the module
+documentation).
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    nosniff: bool,
+    frame_options: Option<FrameOptions>,
+    referrer_policy: Option<String>,
+    hsts_max_age: Option<u64>,
+    hsts_subdomains: bool,
+    secure: Option<bool>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders {
+            nosniff: true,
+            frame_options: Some(FrameOptions::SameOrigin),
+            referrer_policy: Some("strict-origin-when-cross-origin".to_owned()),
+            hsts_max_age: Some(DEFAULT_HSTS_MAX_AGE),
+            hsts_subdomains: false,
+            secure: None,
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /**
+    Return the default set:
+
+      * `X-Content-Type-Options: nosniff`
+      * `X-Frame-Options: SAMEORIGIN`
+      * `Referrer-Policy: strict-origin-when-cross-origin`
+      * `Strict-Transport-Security: max-age=31536000`, if the request came
+        in over HTTPS (according to the `HTTPS` environment variable)
+    */
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders::default()
+    }
+
+    /// Builder-pattern method for setting whether to stop browsers
+    /// guessing a content type other than the one sent
+    /// (`X-Content-Type-Options: nosniff`).
+    pub fn with_nosniff(self, nosniff: bool) -> SecurityHeaders {
+        let mut new = self;
+        new.nosniff = nosniff;
+        new
+    }
+
+    /// Builder-pattern method for setting `X-Frame-Options`, or (with
+    /// `None`) leaving it out.
+    pub fn with_frame_options(self, frame_options: Option<FrameOptions>) -> SecurityHeaders {
+        let mut new = self;
+        new.frame_options = frame_options;
+        new
+    }
+
+    /// Builder-pattern method for setting `Referrer-Policy` (like
+    /// `no-referrer` or `same-origin`), or (with `None`) leaving it out.
+    pub fn with_referrer_policy(self, policy: Option<&str>) -> SecurityHeaders {
+        let mut new = self;
+        new.referrer_policy = policy.map(str::to_owned);
+        new
+    }
+
+    /// Builder-pattern method for setting how many seconds browsers are
+    /// told to use only HTTPS for the site (`Strict-Transport-Security`),
+    /// or (with `None`) leaving the header out.
+    pub fn with_hsts(self, max_age: Option<u64>) -> SecurityHeaders {
+        let mut new = self;
+        new.hsts_max_age = max_age;
+        new
+    }
+
+    /// Builder-pattern method for setting whether `Strict-Transport-Security`
+    /// covers the site's subdomains too (`includeSubDomains`).
+    pub fn with_hsts_subdomains(self, subdomains: bool) -> SecurityHeaders {
+        let mut new = self;
+        new.hsts_subdomains = subdomains;
+        new
+    }
+
+    /// Builder-pattern method for saying whether responses are sent over
+    /// HTTPS (so get `Strict-Transport-Security`), rather than going by
+    /// the `HTTPS` environment variable.
+    pub fn with_secure(self, secure: bool) -> SecurityHeaders {
+        let mut new = self;
+        new.secure = Some(secure);
+        new
+    }
+
+    /*
+    Whether the request came in over HTTPS. Browsers ignore
+    `Strict-Transport-Security` sent over plain HTTP, and it has no
+    business there anyway.
+    */
+    fn is_secure(&self) -> bool {
+        self.secure.unwrap_or_else(|| {
+            std::env::var("HTTPS").is_ok_and(|v| v.eq_ignore_ascii_case("on") || v == "1")
+        })
+    }
+
+    /**
+    Builder-style method for adding the headers to `response` (except
+    any it already has).
+    */
+    pub fn apply(&self, response: Response) -> Response {
+        let mut r = response;
+        let mut add = |name: &str, value: String| {
+            if r.get_header(name).is_none() {
+                r.set_header(name, value);
+            }
+        };
+        if self.nosniff {
+            add("X-Content-Type-Options", "nosniff".to_owned());
+        }
+        if let Some(frame_options) = self.frame_options {
+            add("X-Frame-Options", frame_options.value().to_owned());
+        }
+        if let Some(policy) = &self.referrer_policy {
+            add("Referrer-Policy", policy.clone());
+        }
+        if let Some(max_age) = self.hsts_max_age.filter(|_| self.is_secure()) {
+            let mut value = format!("max-age={}", max_age);
+            if self.hsts_subdomains {
+                value.push_str("; includeSubDomains");
+            }
+            add("Strict-Transport-Security", value);
+        }
+        r
+    }
+}
+
+/// The CSP source `'self'`: the page's own origin.
+pub const SELF: &str = "'self'";
+/// The CSP source `'none'`: nowhere at all.
+pub const NONE: &str = "'none'";
+/// The CSP source `'unsafe-inline'`: inline `<script>`s, `<style>`s, and
+/// event handler attributes.
+pub const UNSAFE_INLINE: &str = "'unsafe-inline'";
+/// The CSP source `'unsafe-eval'`: `eval()` and its relatives.
+pub const UNSAFE_EVAL: &str = "'unsafe-eval'";
+/// The CSP source `'strict-dynamic'`: scripts loaded by trusted scripts.
+pub const STRICT_DYNAMIC: &str = "'strict-dynamic'";
+
+/*
+The source keywords that have to be quoted; unquoted, each is taken for
+a host name, and the page breaks.
+*/
+const KEYWORDS: [&str; 8] = [
+    "self",
+    "none",
+    "unsafe-inline",
+    "unsafe-eval",
+    "strict-dynamic",
+    "unsafe-hashes",
+    "wasm-unsafe-eval",
+    "report-sample",
+];
+
+/**
+Return the CSP source allowing `<script>`s and `<style>`s with the
+attribute `nonce="{nonce}"`: `'nonce-{nonce}'`. The nonce should be new
+(and random) for every response.
+*/
+pub fn nonce(nonce: &str) -> String {
+    format!("'nonce-{}'", nonce)
+}
+
+/**
+A `Content-Security-Policy`, written by its `Display` implementation (see
+the module documentation).
+
+Directives are written in the order they were first set; setting one
+again replaces its sources. Sources are separated at whitespace, the
+keywords (like `self` and `unsafe-inline`) are quoted if they aren't
+already, and any string with a `;` or `,` in it (which would end the
+directive, or the policy) is left out whole. A directive left with no sources
+allows nothing (`'none'`).
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Csp {
+    directives: Vec<(String, String)>,
+    report_only: bool,
+}
+
+impl Csp {
+    /// Return a policy with no directives.
+    pub fn new() -> Csp {
+        Csp::default()
+    }
+
+    /*
+    Set the directive `name` to `value`, keeping its place if it's
+    already set.
+    */
+    fn set(self, name: &str, value: String) -> Csp {
+        let mut new = self;
+        match new.directives.iter_mut().find(|(n, _)| n == name) {
+            Some(directive) => directive.1 = value,
+            None => new.directives.push((name.to_owned(), value)),
+        }
+        new
+    }
+
+    /**
+    Builder-pattern method for setting the directive `name` (for ones
+    without a method of their own) to the source list `sources`.
+
+    ```rust
+    # use dumb_cgi::security::{Csp, SELF};
+    let csp = Csp::new().directive("prefetch-src", [SELF, "https://api.example.com"]);
+    assert_eq!(csp.to_string(), "prefetch-src 'self' https://api.example.com");
+    ```
+    */
+    pub fn directive<I, T>(self, name: &str, sources: I) -> Csp
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut list: Vec<String> = Vec::new();
+        for source in sources {
+            let source = source.as_ref();
+            if source.contains([';', ',']) || source.chars().any(|c| c.is_control()) {
+                continue;
+            }
+            for token in source.split_whitespace() {
+                if KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(token)) {
+                    list.push(format!("'{}'", token.to_ascii_lowercase()));
+                } else {
+                    list.push(token.to_owned());
+                }
+            }
+        }
+        let value = if list.is_empty() {
+            NONE.to_owned()
+        } else {
+            list.join(" ")
+        };
+        self.set(name, value)
+    }
+
+    /// Builder-pattern method for setting `default-src`, the sources for
+    /// every kind of resource without a directive of its own.
+    pub fn default_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("default-src", sources)
+    }
+
+    /// Builder-pattern method for setting `script-src`.
+    pub fn script_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("script-src", sources)
+    }
+
+    /// Builder-pattern method for setting `style-src`.
+    pub fn style_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("style-src", sources)
+    }
+
+    /// Builder-pattern method for setting `img-src`.
+    pub fn img_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("img-src", sources)
+    }
+
+    /// Builder-pattern method for setting `font-src`.
+    pub fn font_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("font-src", sources)
+    }
+
+    /// Builder-pattern method for setting `connect-src`, the places
+    /// scripts may fetch from.
+    pub fn connect_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("connect-src", sources)
+    }
+
+    /// Builder-pattern method for setting `media-src`.
+    pub fn media_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("media-src", sources)
+    }
+
+    /// Builder-pattern method for setting `object-src` (plugins).
+    pub fn object_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("object-src", sources)
+    }
+
+    /// Builder-pattern method for setting `frame-src`, what the page may
+    /// show in frames.
+    pub fn frame_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("frame-src", sources)
+    }
+
+    /// Builder-pattern method for setting `worker-src`.
+    pub fn worker_src<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("worker-src", sources)
+    }
+
+    /// Builder-pattern method for setting `base-uri`, what a `<base>`
+    /// element may point at.
+    pub fn base_uri<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("base-uri", sources)
+    }
+
+    /// Builder-pattern method for setting `form-action`, where the page's
+    /// forms may be submitted.
+    pub fn form_action<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("form-action", sources)
+    }
+
+    /// Builder-pattern method for setting `frame-ancestors`, which pages
+    /// may show this one in a frame (the successor to `X-Frame-Options`).
+    pub fn frame_ancestors<I: IntoIterator<Item = T>, T: AsRef<str>>(self, sources: I) -> Csp {
+        self.directive("frame-ancestors", sources)
+    }
+
+    /// Builder-pattern method for having browsers fetch the page's
+    /// `http:` resources over HTTPS (`upgrade-insecure-requests`).
+    pub fn upgrade_insecure_requests(self) -> Csp {
+        self.set("upgrade-insecure-requests", String::new())
+    }
+
+    /// Builder-pattern method for setting the URL browsers report
+    /// violations of the policy to (`report-uri`).
+    pub fn report_uri(self, uri: &str) -> Csp {
+        let uri: String = uri
+            .chars()
+            .filter(|c| !c.is_control() && !c.is_whitespace() && *c != ';' && *c != ',')
+            .collect();
+        self.set("report-uri", uri)
+    }
+
+    /// Builder-pattern method for sending the policy as
+    /// `Content-Security-Policy-Report-Only`, so violations are reported
+    /// but nothing is blocked (for trying a policy out).
+    pub fn report_only(self) -> Csp {
+        let mut new = self;
+        new.report_only = true;
+        new
+    }
+
+    /// Return the name of the header the policy is sent in.
+    pub fn header_name(&self) -> &'static str {
+        if self.report_only {
+            "Content-Security-Policy-Report-Only"
+        } else {
+            "Content-Security-Policy"
+        }
+    }
+}
+
+impl Display for Csp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let directives: Vec<String> = self
+            .directives
+            .iter()
+            .map(|(name, value)| {
+                if value.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{} {}", name, value)
+                }
+            })
+            .collect();
+        f.write_str(&directives.join("; "))
+    }
+}
@@ -0,0 +1,282 @@
+/*!
+A `--selftest` mode for checking that a CGI program is ready to run.
+
+A CGI program usually fails in production because of something about
+where it's been installed: a configuration file it can't read, a data
+directory it can't write to. A `SelfTest` lists the things the program
+needs, and `run_if_requested()`, called at the top of `main()`, checks
+them all and prints a report when the program is run by hand (outside a
+CGI environment) with `--selftest`, ideally as the user the web server
+runs it as.
+
+```rust,no_run
+use std::process::ExitCode;
+use dumb_cgi::selftest::SelfTest;
+use dumb_cgi::store::ContentStore;
+
+fn main() -> ExitCode {
+    let selftest = SelfTest::new()
+        .with_config("/etc/mysite.conf")
+        .with_writable_dir("/var/lib/mysite/sessions")
+        .with_store(ContentStore::new("/var/lib/mysite/uploads"));
+    if let Some(code) = selftest.run_if_requested() {
+        return code;
+    }
+
+    // ... handle the request as usual ...
+    ExitCode::SUCCESS
+}
+```
+
+```text
+$ sudo -u www-data ./mysite.cgi --selftest
+ok   config /etc/mysite.conf: 12 keys
+FAIL writable /var/lib/mysite/sessions: Permission denied (os error 13)
+ok   store /var/lib/mysite/uploads: stored and removed a test file
+1 of 3 checks failed
+```
+
+The same checks can be run for a monitoring page with
+`SelfTestReport::to_response()`, which answers in JSON (indented, for
+people to read, with `?pretty=1`). The report names files and
+directories, so keep such a page away from the public.
+*/
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::config::Config;
+use crate::json::Json;
+use crate::store::ContentStore;
+use crate::{Request, Response};
+
+/// The command-line flag that asks for a self-test.
+pub const FLAG: &str = "--selftest";
+
+type Check = Box<dyn Fn() -> Result<String, String>>;
+
+/**
+The list of things a program needs to work.
+*/
+#[derive(Default)]
+pub struct SelfTest {
+    checks: Vec<(String, Check)>,
+}
+
+impl std::fmt::Debug for SelfTest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = self.checks.iter().map(|(name, _)| name.as_str()).collect();
+        f.debug_struct("SelfTest").field("checks", &names).finish()
+    }
+}
+
+impl SelfTest {
+    /// Return a self-test with no checks.
+    pub fn new() -> SelfTest {
+        SelfTest::default()
+    }
+
+    /**
+    Builder-pattern method for adding a check named `name`: a function
+    returning a short description of what it found if all is well, or of
+    what's wrong if not.
+    */
+    pub fn with_check<N, F>(self, name: N, check: F) -> SelfTest
+    where
+        N: Into<String>,
+        F: Fn() -> Result<String, String> + 'static,
+    {
+        let mut new = self;
+        new.checks.push((name.into(), Box::new(check)));
+        new
+    }
+
+    /// Builder-pattern method for checking that the configuration file at
+    /// `path` can be read and parsed.
+    pub fn with_config<P: Into<PathBuf>>(self, path: P) -> SelfTest {
+        let path = path.into();
+        let name = format!("config {}", path.display());
+        self.with_check(name, move || match Config::load(&path) {
+            Ok(config) => Ok(format!("{} keys", config.len())),
+            Err(e) => Err(e.details),
+        })
+    }
+
+    /// Builder-pattern method for checking that files can be created in
+    /// (and removed from) the directory `dir`.
+    pub fn with_writable_dir<P: Into<PathBuf>>(self, dir: P) -> SelfTest {
+        let dir = dir.into();
+        let name = format!("writable {}", dir.display());
+        self.with_check(name, move || check_writable(&dir))
+    }
+
+    /// Builder-pattern method for checking that a file can be stored in
+    /// (and removed from) `store`.
+    pub fn with_store(self, store: ContentStore) -> SelfTest {
+        let name = format!("store {}", store.dir().display());
+        self.with_check(name, move || {
+            let data = format!("dumb_cgi self-test {}", std::process::id());
+            let id = store.put(data.as_bytes()).map_err(|e| e.details)?;
+            let read = store.get(&id).map_err(|e| e.details);
+            store.release(&id).map_err(|e| e.details)?;
+            if read? == data.as_bytes() {
+                Ok("stored and removed a test file".to_owned())
+            } else {
+                Err("test file read back differently".to_owned())
+            }
+        })
+    }
+
+    /// Run every check, in the order they were added.
+    pub fn run(&self) -> SelfTestReport {
+        let results = self
+            .checks
+            .iter()
+            .map(|(name, check)| (name.clone(), check()))
+            .collect();
+        SelfTestReport { results }
+    }
+
+    /**
+    If the program was run with `--selftest` (and not as a CGI program),
+    run every check, print the report to stdout, and return the exit
+    code the program should exit with; otherwise, return `None` (and the
+    program should carry on handling its request).
+    */
+    pub fn run_if_requested(&self) -> Option<ExitCode> {
+        if !requested() {
+            return None;
+        }
+        let report = self.run();
+        print!("{}", &report);
+        Some(if report.is_ok() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        })
+    }
+}
+
+/**
+Return whether the program was run with `--selftest` outside a CGI
+environment (one without `GATEWAY_INTERFACE` set).
+*/
+pub fn requested() -> bool {
+    std::env::var_os("GATEWAY_INTERFACE").is_none() && std::env::args().skip(1).any(|a| a == FLAG)
+}
+
+fn check_writable(dir: &Path) -> Result<String, String> {
+    if !dir.is_dir() {
+        return Err("not a directory".to_owned());
+    }
+    let path = dir.join(format!(".dumb_cgi-selftest-{}", std::process::id()));
+    std::fs::write(&path, b"selftest").map_err(|e| e.to_string())?;
+    std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+    Ok("created and removed a test file".to_owned())
+}
+
+/**
+The results of a `SelfTest`: each check's name, and what it found.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// Each check's name, and either what it found or what went wrong.
+    pub results: Vec<(String, Result<String, String>)>,
+}
+
+impl SelfTestReport {
+    /// Return whether every check passed.
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|(_, r)| r.is_ok())
+    }
+
+    /// Return the names of the checks that failed.
+    pub fn failures(&self) -> Vec<&str> {
+        self.results
+            .iter()
+            .filter(|(_, r)| r.is_err())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /**
+    Return the report as JSON: whether every check passed, and each
+    check's name, whether it passed, and what it found (as `found`) or
+    what went wrong (as `problem`).
+
+    ```rust
+    # use dumb_cgi::selftest::SelfTestReport;
+    let report = SelfTestReport {
+        results: vec![("disk".to_owned(), Err("full".to_owned()))],
+    };
+    assert_eq!(
+        report.to_json().to_string(),
+        r#"{"ok":false,"checks":[{"name":"disk","ok":false,"problem":"full"}]}"#
+    );
+    ```
+    */
+    pub fn to_json(&self) -> Json {
+        let checks: Vec<Json> = self
+            .results
+            .iter()
+            .map(|(name, result)| {
+                let check = Json::object()
+                    .with("name", name.as_str())
+                    .with("ok", result.is_ok());
+                match result {
+                    Ok(found) => check.with("found", found.as_str()),
+                    Err(problem) => check.with("problem", problem.as_str()),
+                }
+            })
+            .collect();
+        Json::object()
+            .with("ok", self.is_ok())
+            .with("checks", checks)
+    }
+
+    /**
+    Return the report as a response to `req`: `200 OK` if every check
+    passed, or `503 Service Unavailable` if not, with `to_json()` as the
+    body, indented if `req` asks for it (see `JsonFormat::for_request()`).
+    It isn't cached.
+
+    ```rust
+    # use dumb_cgi::selftest::SelfTest;
+    # use dumb_cgi::testing::MockRequest;
+    let report = SelfTest::new()
+        .with_check("answer", || Ok("42".to_owned()))
+        .run();
+
+    let req = MockRequest::get("/health").query("pretty=1").build().unwrap();
+    let r = report.to_response(&req);
+    assert_eq!(r.get_status(), 200);
+    assert!(r.get_body().starts_with(b"{\n  \"ok\": true,"));
+    ```
+    */
+    pub fn to_response(&self, req: &Request) -> Response {
+        let status = if self.is_ok() { 200 } else { 503 };
+        Response::new(status)
+            .with_header("Cache-Control", "no-store")
+            .with_json_for(req, &self.to_json())
+    }
+}
+
+impl std::fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        for (name, result) in self.results.iter() {
+            match result {
+                Ok(found) => writeln!(out, "ok   {}: {}", name, found)?,
+                Err(problem) => writeln!(out, "FAIL {}: {}", name, problem)?,
+            }
+        }
+        let failed = self.failures().len();
+        if failed == 0 {
+            writeln!(out, "all {} checks passed", self.results.len())?;
+        } else {
+            writeln!(out, "{} of {} checks failed", failed, self.results.len())?;
+        }
+        f.write_str(&out)
+    }
+}
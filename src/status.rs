@@ -0,0 +1,366 @@
+/*!
+HTTP status codes and their reason phrases.
+*/
+
+use std::fmt::{Display, Formatter};
+
+/**
+The status codes registered with IANA, with their reason phrases.
+
+A `Status` can be used anywhere a response takes a status code; so can
+a plain `u16`.
+
+```rust
+use dumb_cgi::{Response, Status};
+
+let r = Response::new(Status::NotFound);
+assert_eq!(r.get_status(), 404);
+assert_eq!(r.to_bytes().unwrap(), b"Status: 404 Not Found\r\n\r\n");
+
+assert_eq!(Status::from_code(429), Some(Status::TooManyRequests));
+assert_eq!(Status::TooManyRequests.reason(), "Too Many Requests");
+```
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Status {
+    /// 100 Continue
+    Continue = 100,
+    /// 101 Switching Protocols
+    SwitchingProtocols = 101,
+    /// 102 Processing
+    Processing = 102,
+    /// 103 Early Hints
+    EarlyHints = 103,
+    /// 200 OK
+    Ok = 200,
+    /// 201 Created
+    Created = 201,
+    /// 202 Accepted
+    Accepted = 202,
+    /// 203 Non-Authoritative Information
+    NonAuthoritativeInformation = 203,
+    /// 204 No Content
+    NoContent = 204,
+    /// 205 Reset Content
+    ResetContent = 205,
+    /// 206 Partial Content
+    PartialContent = 206,
+    /// 207 Multi-Status
+    MultiStatus = 207,
+    /// 208 Already Reported
+    AlreadyReported = 208,
+    /// 226 IM Used
+    ImUsed = 226,
+    /// 300 Multiple Choices
+    MultipleChoices = 300,
+    /// 301 Moved Permanently
+    MovedPermanently = 301,
+    /// 302 Found
+    Found = 302,
+    /// 303 See Other
+    SeeOther = 303,
+    /// 304 Not Modified
+    NotModified = 304,
+    /// 305 Use Proxy
+    UseProxy = 305,
+    /// 307 Temporary Redirect
+    TemporaryRedirect = 307,
+    /// 308 Permanent Redirect
+    PermanentRedirect = 308,
+    /// 400 Bad Request
+    BadRequest = 400,
+    /// 401 Unauthorized
+    Unauthorized = 401,
+    /// 402 Payment Required
+    PaymentRequired = 402,
+    /// 403 Forbidden
+    Forbidden = 403,
+    /// 404 Not Found
+    NotFound = 404,
+    /// 405 Method Not Allowed
+    MethodNotAllowed = 405,
+    /// 406 Not Acceptable
+    NotAcceptable = 406,
+    /// 407 Proxy Authentication Required
+    ProxyAuthenticationRequired = 407,
+    /// 408 Request Timeout
+    RequestTimeout = 408,
+    /// 409 Conflict
+    Conflict = 409,
+    /// 410 Gone
+    Gone = 410,
+    /// 411 Length Required
+    LengthRequired = 411,
+    /// 412 Precondition Failed
+    PreconditionFailed = 412,
+    /// 413 Content Too Large
+    ContentTooLarge = 413,
+    /// 414 URI Too Long
+    UriTooLong = 414,
+    /// 415 Unsupported Media Type
+    UnsupportedMediaType = 415,
+    /// 416 Range Not Satisfiable
+    RangeNotSatisfiable = 416,
+    /// 417 Expectation Failed
+    ExpectationFailed = 417,
+    /// 421 Misdirected Request
+    MisdirectedRequest = 421,
+    /// 422 Unprocessable Content
+    UnprocessableContent = 422,
+    /// 423 Locked
+    Locked = 423,
+    /// 424 Failed Dependency
+    FailedDependency = 424,
+    /// 425 Too Early
+    TooEarly = 425,
+    /// 426 Upgrade Required
+    UpgradeRequired = 426,
+    /// 428 Precondition Required
+    PreconditionRequired = 428,
+    /// 429 Too Many Requests
+    TooManyRequests = 429,
+    /// 431 Request Header Fields Too Large
+    RequestHeaderFieldsTooLarge = 431,
+    /// 451 Unavailable For Legal Reasons
+    UnavailableForLegalReasons = 451,
+    /// 500 Internal Server Error
+    InternalServerError = 500,
+    /// 501 Not Implemented
+    NotImplemented = 501,
+    /// 502 Bad Gateway
+    BadGateway = 502,
+    /// 503 Service Unavailable
+    ServiceUnavailable = 503,
+    /// 504 Gateway Timeout
+    GatewayTimeout = 504,
+    /// 505 HTTP Version Not Supported
+    HttpVersionNotSupported = 505,
+    /// 506 Variant Also Negotiates
+    VariantAlsoNegotiates = 506,
+    /// 507 Insufficient Storage
+    InsufficientStorage = 507,
+    /// 508 Loop Detected
+    LoopDetected = 508,
+    /// 510 Not Extended
+    NotExtended = 510,
+    /// 511 Network Authentication Required
+    NetworkAuthenticationRequired = 511,
+}
+
+impl Status {
+    /// Every `Status`, in order.
+    pub const ALL: &'static [Status] = &[
+        Status::Continue,
+        Status::SwitchingProtocols,
+        Status::Processing,
+        Status::EarlyHints,
+        Status::Ok,
+        Status::Created,
+        Status::Accepted,
+        Status::NonAuthoritativeInformation,
+        Status::NoContent,
+        Status::ResetContent,
+        Status::PartialContent,
+        Status::MultiStatus,
+        Status::AlreadyReported,
+        Status::ImUsed,
+        Status::MultipleChoices,
+        Status::MovedPermanently,
+        Status::Found,
+        Status::SeeOther,
+        Status::NotModified,
+        Status::UseProxy,
+        Status::TemporaryRedirect,
+        Status::PermanentRedirect,
+        Status::BadRequest,
+        Status::Unauthorized,
+        Status::PaymentRequired,
+        Status::Forbidden,
+        Status::NotFound,
+        Status::MethodNotAllowed,
+        Status::NotAcceptable,
+        Status::ProxyAuthenticationRequired,
+        Status::RequestTimeout,
+        Status::Conflict,
+        Status::Gone,
+        Status::LengthRequired,
+        Status::PreconditionFailed,
+        Status::ContentTooLarge,
+        Status::UriTooLong,
+        Status::UnsupportedMediaType,
+        Status::RangeNotSatisfiable,
+        Status::ExpectationFailed,
+        Status::MisdirectedRequest,
+        Status::UnprocessableContent,
+        Status::Locked,
+        Status::FailedDependency,
+        Status::TooEarly,
+        Status::UpgradeRequired,
+        Status::PreconditionRequired,
+        Status::TooManyRequests,
+        Status::RequestHeaderFieldsTooLarge,
+        Status::UnavailableForLegalReasons,
+        Status::InternalServerError,
+        Status::NotImplemented,
+        Status::BadGateway,
+        Status::ServiceUnavailable,
+        Status::GatewayTimeout,
+        Status::HttpVersionNotSupported,
+        Status::VariantAlsoNegotiates,
+        Status::InsufficientStorage,
+        Status::LoopDetected,
+        Status::NotExtended,
+        Status::NetworkAuthenticationRequired,
+    ];
+
+    /// Return the numeric status code.
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// Return the canonical reason phrase (like `"Not Found"`).
+    pub fn reason(self) -> &'static str {
+        match self {
+            Status::Continue => "Continue",
+            Status::SwitchingProtocols => "Switching Protocols",
+            Status::Processing => "Processing",
+            Status::EarlyHints => "Early Hints",
+            Status::Ok => "OK",
+            Status::Created => "Created",
+            Status::Accepted => "Accepted",
+            Status::NonAuthoritativeInformation => "Non-Authoritative Information",
+            Status::NoContent => "No Content",
+            Status::ResetContent => "Reset Content",
+            Status::PartialContent => "Partial Content",
+            Status::MultiStatus => "Multi-Status",
+            Status::AlreadyReported => "Already Reported",
+            Status::ImUsed => "IM Used",
+            Status::MultipleChoices => "Multiple Choices",
+            Status::MovedPermanently => "Moved Permanently",
+            Status::Found => "Found",
+            Status::SeeOther => "See Other",
+            Status::NotModified => "Not Modified",
+            Status::UseProxy => "Use Proxy",
+            Status::TemporaryRedirect => "Temporary Redirect",
+            Status::PermanentRedirect => "Permanent Redirect",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::PaymentRequired => "Payment Required",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::NotAcceptable => "Not Acceptable",
+            Status::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            Status::RequestTimeout => "Request Timeout",
+            Status::Conflict => "Conflict",
+            Status::Gone => "Gone",
+            Status::LengthRequired => "Length Required",
+            Status::PreconditionFailed => "Precondition Failed",
+            Status::ContentTooLarge => "Content Too Large",
+            Status::UriTooLong => "URI Too Long",
+            Status::UnsupportedMediaType => "Unsupported Media Type",
+            Status::RangeNotSatisfiable => "Range Not Satisfiable",
+            Status::ExpectationFailed => "Expectation Failed",
+            Status::MisdirectedRequest => "Misdirected Request",
+            Status::UnprocessableContent => "Unprocessable Content",
+            Status::Locked => "Locked",
+            Status::FailedDependency => "Failed Dependency",
+            Status::TooEarly => "Too Early",
+            Status::UpgradeRequired => "Upgrade Required",
+            Status::PreconditionRequired => "Precondition Required",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::BadGateway => "Bad Gateway",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::GatewayTimeout => "Gateway Timeout",
+            Status::HttpVersionNotSupported => "HTTP Version Not Supported",
+            Status::VariantAlsoNegotiates => "Variant Also Negotiates",
+            Status::InsufficientStorage => "Insufficient Storage",
+            Status::LoopDetected => "Loop Detected",
+            Status::NotExtended => "Not Extended",
+            Status::NetworkAuthenticationRequired => "Network Authentication Required",
+        }
+    }
+
+    /// Return the `Status` with the numeric code `code`, if there is one.
+    pub fn from_code(code: u16) -> Option<Status> {
+        Status::ALL.iter().copied().find(|s| s.code() == code)
+    }
+
+    /// Return whether this is a `1xx` (informational) status.
+    pub fn is_informational(self) -> bool {
+        (100..200).contains(&self.code())
+    }
+
+    /// Return whether this is a `2xx` (success) status.
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    /// Return whether this is a `3xx` (redirection) status.
+    pub fn is_redirection(self) -> bool {
+        (300..400).contains(&self.code())
+    }
+
+    /// Return whether this is a `4xx` (client error) status.
+    pub fn is_client_error(self) -> bool {
+        (400..500).contains(&self.code())
+    }
+
+    /// Return whether this is a `5xx` (server error) status.
+    pub fn is_server_error(self) -> bool {
+        (500..600).contains(&self.code())
+    }
+}
+
+/// Writes the code and the reason phrase, like `404 Not Found`.
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+impl From<Status> for u16 {
+    fn from(status: Status) -> u16 {
+        status.code()
+    }
+}
+
+/**
+Return the canonical reason phrase for the status code `code`, if it's
+a registered one.
+
+```rust
+# use dumb_cgi::reason_phrase;
+assert_eq!(reason_phrase(503), Some("Service Unavailable"));
+assert_eq!(reason_phrase(299), None);
+```
+*/
+pub fn reason_phrase(code: u16) -> Option<&'static str> {
+    Status::from_code(code).map(Status::reason)
+}
+
+/**
+Something that can be used as a response's status code: a `Status`, or
+a `u16`.
+*/
+pub trait StatusCode {
+    /// Return the numeric status code.
+    fn status_code(self) -> u16;
+}
+
+impl StatusCode for u16 {
+    fn status_code(self) -> u16 {
+        self
+    }
+}
+
+impl StatusCode for Status {
+    fn status_code(self) -> u16 {
+        self.code()
+    }
+}
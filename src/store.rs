@@ -0,0 +1,218 @@
+/*!
+A content-addressed store for uploaded files.
+
+Each file is saved once, under the SHA-256 hash of its contents, no matter
+how many times it's uploaded; the store keeps a count of references to
+each file and deletes it when the last one is released. This is handy
+for programs that keep a lot of attachments (which tend to be uploaded
+over and over) on a small disk.
+
+Files are kept in `dir/ab/abcdef...` (the first two hex digits of the
+hash name a subdirectory), with the reference count alongside in
+`abcdef....refs`.
+
+```rust
+use dumb_cgi::store::ContentStore;
+
+let dir = std::env::temp_dir().join("dumb_cgi_store_doctest");
+let store = ContentStore::new(&dir);
+
+let id = store.put(b"attachment").unwrap();
+let again = store.put(b"attachment").unwrap();
+assert_eq!(id, again);
+assert_eq!(store.refs(&id).unwrap(), 2);
+assert_eq!(store.get(&id).unwrap(), b"attachment");
+
+store.release(&id).unwrap();
+assert!(store.contains(&id));
+store.release(&id).unwrap();
+assert!(!store.contains(&id));
+# std::fs::remove_dir_all(&dir).unwrap();
+```
+*/
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::hash::{sha256, to_hex};
+use crate::lockfile::LockGuard;
+use crate::{Error, MultipartPart};
+
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!("Error accessing stored file {}: {}", path.display(), &e),
+    }
+}
+
+fn not_found(id: &str) -> Error {
+    Error {
+        code: 404,
+        message: "File not found.".to_owned(),
+        details: format!("No stored file with id {:?}", id),
+    }
+}
+
+/*
+Whether `id` looks like something `ContentStore::put()` returns. This
+also keeps ids from user input from naming paths outside the store.
+*/
+fn valid_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/**
+A directory of files stored by content hash, with reference counts.
+*/
+#[derive(Debug, Clone)]
+pub struct ContentStore {
+    dir: PathBuf,
+}
+
+impl ContentStore {
+    /// Create a store that keeps its files in `dir` (which will be created
+    /// if necessary).
+    pub fn new<P: Into<PathBuf>>(dir: P) -> ContentStore {
+        ContentStore { dir: dir.into() }
+    }
+
+    /// Return the directory the store keeps its files in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /*
+    Return the path of the file with `id`, or a 404 error if `id` isn't a
+    valid id.
+    */
+    fn object_path(&self, id: &str) -> Result<PathBuf, Error> {
+        if !valid_id(id) {
+            return Err(not_found(id));
+        }
+        Ok(self.dir.join(&id[..2]).join(id))
+    }
+
+    fn refs_path(object: &Path) -> PathBuf {
+        object.with_extension("refs")
+    }
+
+    fn lock(object: &Path) -> Result<LockGuard, Error> {
+        let path = object.with_extension("lock");
+        LockGuard::acquire(path.clone()).map_err(|e| io_error(&path, e))
+    }
+
+    fn read_refs(object: &Path) -> u64 {
+        std::fs::read_to_string(ContentStore::refs_path(object))
+            .ok()
+            .and_then(|text| text.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    fn write_refs(object: &Path, refs: u64) -> Result<(), Error> {
+        let path = ContentStore::refs_path(object);
+        let tmp = object.with_extension(format!("refs.tmp{}", std::process::id()));
+        std::fs::write(&tmp, format!("{}\n", refs)).map_err(|e| io_error(&tmp, e))?;
+        std::fs::rename(&tmp, &path).map_err(|e| io_error(&path, e))
+    }
+
+    /**
+    Store `data` (if it isn't already stored) and add a reference to it,
+    returning its id: the hex SHA-256 hash of the data.
+    */
+    pub fn put(&self, data: &[u8]) -> Result<String, Error> {
+        let id = to_hex(&sha256(data));
+        let object = self.object_path(&id)?;
+        let subdir = object.parent().unwrap_or(&self.dir);
+        std::fs::create_dir_all(subdir).map_err(|e| io_error(subdir, e))?;
+
+        let _lock = ContentStore::lock(&object)?;
+        let refs = if object.exists() {
+            ContentStore::read_refs(&object)
+        } else {
+            let tmp = object.with_extension(format!("tmp{}", std::process::id()));
+            std::fs::write(&tmp, data).map_err(|e| io_error(&tmp, e))?;
+            std::fs::rename(&tmp, &object).map_err(|e| io_error(&object, e))?;
+            0
+        };
+        ContentStore::write_refs(&object, refs + 1)?;
+        Ok(id)
+    }
+
+    /// Store the body of an uploaded multipart part (see `put()`).
+    pub fn put_part(&self, part: &MultipartPart) -> Result<String, Error> {
+        self.put(&part.body)
+    }
+
+    /// Return whether a file with `id` is stored.
+    pub fn contains(&self, id: &str) -> bool {
+        self.object_path(id).map(|p| p.is_file()).unwrap_or(false)
+    }
+
+    /**
+    Return the path of the stored file with `id`, or a 404 error if there
+    isn't one. Don't modify the file; it may be shared.
+    */
+    pub fn path(&self, id: &str) -> Result<PathBuf, Error> {
+        let object = self.object_path(id)?;
+        if object.is_file() {
+            Ok(object)
+        } else {
+            Err(not_found(id))
+        }
+    }
+
+    /// Read the contents of the stored file with `id`.
+    pub fn get(&self, id: &str) -> Result<Vec<u8>, Error> {
+        let path = self.path(id)?;
+        std::fs::read(&path).map_err(|e| io_error(&path, e))
+    }
+
+    /// Open the stored file with `id` for reading.
+    pub fn open(&self, id: &str) -> Result<File, Error> {
+        let path = self.path(id)?;
+        File::open(&path).map_err(|e| io_error(&path, e))
+    }
+
+    /// Return the number of references to the stored file with `id`.
+    pub fn refs(&self, id: &str) -> Result<u64, Error> {
+        Ok(ContentStore::read_refs(&self.path(id)?))
+    }
+
+    /**
+    Make the stored file with `id` also appear at `dest`, as a hard link
+    (so it takes no more space), or as a copy if a hard link can't be made
+    (say, because `dest` is on another filesystem).
+
+    This doesn't add a reference; the linked file stays even after the
+    stored one is released.
+    */
+    pub fn link_to<P: AsRef<Path>>(&self, id: &str, dest: P) -> Result<(), Error> {
+        let path = self.path(id)?;
+        let dest = dest.as_ref();
+        if std::fs::hard_link(&path, dest).is_ok() {
+            return Ok(());
+        }
+        std::fs::copy(&path, dest)
+            .map(|_| ())
+            .map_err(|e| io_error(dest, e))
+    }
+
+    /**
+    Remove a reference to the stored file with `id`, deleting the file if
+    that was the last one. Returns the number of references left.
+    */
+    pub fn release(&self, id: &str) -> Result<u64, Error> {
+        let object = self.path(id)?;
+        let _lock = ContentStore::lock(&object)?;
+        let refs = ContentStore::read_refs(&object).saturating_sub(1);
+        if refs == 0 {
+            std::fs::remove_file(&object).map_err(|e| io_error(&object, e))?;
+            let refs_path = ContentStore::refs_path(&object);
+            _ = std::fs::remove_file(refs_path);
+        } else {
+            ContentStore::write_refs(&object, refs)?;
+        }
+        Ok(refs)
+    }
+}
@@ -0,0 +1,221 @@
+/*!
+Stopping a form from being submitted twice, by a double click or by
+reloading the page a `POST` led to.
+
+Each time a form is shown, it gets a fresh one-time token in a hidden
+field; `SubmitGuard::check()` accepts each token once, and refuses it
+after that with an error fit to show the user (a 409, "This form has
+already been submitted."). Tokens are signed with a server-side key and
+carry the time they were made, so they can't be made up and they run
+out; the tokens that have been used are remembered as empty files in
+the guard's directory.
+
+This doesn't stop cross-site request forgery (a token can be fetched
+and used by anyone who can load the form); use it alongside whatever
+does.
+
+```rust
+use dumb_cgi::submit::SubmitGuard;
+use dumb_cgi::testing::MockRequest;
+
+let dir = std::env::temp_dir().join("dumb_cgi_submit_doctest");
+let guard = SubmitGuard::new("server-side secret key", &dir);
+
+// When showing the form:
+let input = guard.hidden_input().unwrap();
+assert!(input.starts_with(r#"<input type="hidden" name="submit_token" value=""#));
+let token = guard.token().unwrap();
+
+// When handling it:
+let req = MockRequest::post("/order")
+    .header("Content-Type", "application/x-www-form-urlencoded")
+    .body(format!("item=widget&submit_token={}", &token))
+    .build()
+    .unwrap();
+assert!(guard.check(&req).is_ok());
+// The same submission again:
+let err = guard.check(&req).unwrap_err();
+assert_eq!(err.code, 409);
+assert_eq!(err.message, "This form has already been submitted.");
+# std::fs::remove_dir_all(&dir).unwrap();
+```
+
+Nothing removes the files of used tokens; point a `gc::Gc` at the
+directory, with an age at least the guard's `max_age` (after which the
+tokens are refused as expired anyway).
+*/
+
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::escape::html;
+use crate::hash::{constant_time_eq, fill_random, hmac_sha256, to_hex};
+use crate::{base64, Error, Request};
+
+/// The default name of the hidden form field that holds the token.
+pub const DEFAULT_FIELD: &str = "submit_token";
+
+/// How long (in seconds) a token lasts by default: one day.
+const DEFAULT_MAX_AGE: u64 = 24 * 60 * 60;
+
+/// How far (in seconds) a token's time can be ahead of the clock, for
+/// servers whose clocks disagree a little.
+const CLOCK_SLACK: u64 = 60;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!(
+            "Error accessing submit token file {}: {}",
+            path.display(),
+            &e
+        ),
+    }
+}
+
+/*
+A token that's missing, forged, mangled, or out of date: the user should
+start again from a fresh copy of the form.
+*/
+fn stale_error(details: String) -> Error {
+    Error {
+        code: 400,
+        message: "This form has expired. Please reload the page and try again.".to_owned(),
+        details,
+    }
+}
+
+/**
+Hands out one-time form tokens, and accepts each one once (see the
+module documentation).
+*/
+#[derive(Debug, Clone)]
+pub struct SubmitGuard {
+    key: Vec<u8>,
+    dir: PathBuf,
+    field: String,
+    max_age: u64,
+}
+
+impl SubmitGuard {
+    /**
+    Create a guard that signs tokens with `key` and remembers used ones
+    in `dir` (which will be created if necessary).
+
+    By default, tokens are sent in the field `submit_token` and last for
+    a day.
+    */
+    pub fn new<K: Into<Vec<u8>>, P: Into<PathBuf>>(key: K, dir: P) -> SubmitGuard {
+        SubmitGuard {
+            key: key.into(),
+            dir: dir.into(),
+            field: DEFAULT_FIELD.to_owned(),
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Builder-pattern method for changing the name of the form field
+    /// the token is sent in.
+    pub fn with_field_name<T: Into<String>>(self, name: T) -> SubmitGuard {
+        let mut new = self;
+        new.field = name.into();
+        new
+    }
+
+    /// Builder-pattern method for setting how long (in seconds) a form
+    /// can sit before its token is refused.
+    pub fn with_max_age(self, seconds: u64) -> SubmitGuard {
+        let mut new = self;
+        new.max_age = seconds;
+        new
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        base64::encode_url(&hmac_sha256(&self.key, payload.as_bytes()))
+    }
+
+    /// Return a new token, for one showing of a form.
+    pub fn token(&self) -> Result<String, Error> {
+        let mut nonce = [0u8; 16];
+        fill_random(&mut nonce)?;
+        let payload = format!("{}.{}", now_secs(), to_hex(&nonce));
+        Ok(format!("{}.{}", &payload, self.sign(&payload)))
+    }
+
+    /// Return a hidden `<input>` holding a new token, to go in a form.
+    pub fn hidden_input(&self) -> Result<String, Error> {
+        Ok(format!(
+            "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+            html(&self.field),
+            self.token()?
+        ))
+    }
+
+    /*
+    Check `token`'s signature and age, returning its nonce.
+    */
+    fn verify<'a>(&self, token: &'a str) -> Result<&'a str, Error> {
+        let bad = || stale_error(format!("invalid submit token {:?}", token));
+        let (payload, sig) = token.rsplit_once('.').ok_or_else(bad)?;
+        if !constant_time_eq(self.sign(payload).as_bytes(), sig.as_bytes()) {
+            return Err(bad());
+        }
+        let (issued, nonce) = payload.split_once('.').ok_or_else(bad)?;
+        let issued: u64 = issued.parse().map_err(|_| bad())?;
+        // It'll be a file name, so make sure it's only what `token()` makes.
+        if nonce.len() != 32 || !nonce.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(bad());
+        }
+        let now = now_secs();
+        if issued > now + CLOCK_SLACK || now.saturating_sub(issued) > self.max_age {
+            return Err(stale_error(format!(
+                "submit token from {} is out of date at {}",
+                issued, now
+            )));
+        }
+        Ok(nonce)
+    }
+
+    /**
+    Accept `token` if it's genuine, in date, and hasn't been accepted
+    before, remembering that it has now.
+
+    Otherwise the error is fit to turn straight into a response: a 409
+    for a token that's already been used, or a 400 (asking the user to
+    reload the form) for one that's out of date or not genuine.
+    */
+    pub fn check_token(&self, token: &str) -> Result<(), Error> {
+        let nonce = self.verify(token)?;
+        std::fs::create_dir_all(&self.dir).map_err(|e| io_error(&self.dir, e))?;
+        let path = self.dir.join(nonce);
+        // Creating the file succeeds for exactly one request, however
+        // many arrive at once.
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Err(Error {
+                code: 409,
+                message: "This form has already been submitted.".to_owned(),
+                details: format!("submit token {} used again", nonce),
+            }),
+            Err(e) => Err(io_error(&path, e)),
+        }
+    }
+
+    /// Like `.check_token()`, for the token in `req`'s form field (see
+    /// `Request::param()`).
+    pub fn check(&self, req: &Request) -> Result<(), Error> {
+        match req.param(&self.field) {
+            Some(token) => self.check_token(&token),
+            None => Err(stale_error(format!("no {} field in request", &self.field))),
+        }
+    }
+}
@@ -0,0 +1,279 @@
+/*!
+Simple text templates, with placeholders for values.
+
+`{{name}}` is replaced with the value of `name`, HTML-escaped (see
+`escape::html()`), and `{{{name}}}` with the value as it is. Placeholders
+with no value are replaced with nothing.
+
+```rust
+use dumb_cgi::template::Template;
+
+let page = Template::parse("<h1>{{title}}</h1>\n{{{content}}}").unwrap();
+let html = page.render(&[
+    ("title", &"Fish & Chips"),
+    ("content", &"<p>Delicious.</p>"),
+]);
+assert_eq!(html, "<h1>Fish &amp; Chips</h1>\n<p>Delicious.</p>");
+```
+
+Templates are usually kept in files. A CGI program starts afresh for
+every request, so `Template::load_cached()` keeps a pre-parsed copy next
+to the file, which is used until the file is modified; a long-running
+program can keep templates in memory with a `TemplateCache` instead,
+which reloads them when their files change.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::cachefile::{self, source_stamp, CacheReader, CacheWriter, Stamp};
+use crate::escape::push_html;
+use crate::Error;
+
+/// First bytes of a pre-parsed template cache file.
+const CACHE_MAGIC: &[u8; 8] = b"DCGITPL1";
+
+fn template_error(details: String) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details,
+    }
+}
+
+fn read_error(path: &Path, e: std::io::Error) -> Error {
+    template_error(format!(
+        "Unable to read template {}: {}",
+        path.display(),
+        &e
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Escaped(String),
+    Raw(String),
+}
+
+impl Segment {
+    fn tag(&self) -> u8 {
+        match self {
+            Segment::Text(_) => 0,
+            Segment::Escaped(_) => 1,
+            Segment::Raw(_) => 2,
+        }
+    }
+
+    fn text(&self) -> &str {
+        match self {
+            Segment::Text(s) | Segment::Escaped(s) | Segment::Raw(s) => s,
+        }
+    }
+}
+
+/**
+A parsed template.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /**
+    Parse the template `text`, returning an error if a placeholder isn't
+    closed, or has a name that isn't letters, digits, `_`, `-`, and `.`.
+    */
+    pub fn parse(text: &str) -> Result<Template, Error> {
+        let mut segments = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_owned()));
+            }
+            let after = &rest[start..];
+            let (open, close) = if after.starts_with("{{{") {
+                ("{{{", "}}}")
+            } else {
+                ("{{", "}}")
+            };
+            let inner = &after[open.len()..];
+            let end = inner.find(close).ok_or_else(|| {
+                template_error(format!(
+                    "Unclosed placeholder at byte {}",
+                    text.len() - after.len()
+                ))
+            })?;
+            let name = inner[..end].trim();
+            let valid = !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"_-.".contains(&b));
+            if !valid {
+                return Err(template_error(format!(
+                    "Invalid placeholder name {:?}",
+                    name
+                )));
+            }
+            segments.push(if open == "{{{" {
+                Segment::Raw(name.to_owned())
+            } else {
+                Segment::Escaped(name.to_owned())
+            });
+            rest = &inner[end + close.len()..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_owned()));
+        }
+        Ok(Template { segments })
+    }
+
+    /// Read and parse the template file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Template, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| read_error(path, e))?;
+        Template::parse(&text)
+    }
+
+    /**
+    Load the template file at `path`, using a pre-parsed copy (kept in
+    `path` with `.cache` appended) if one exists and the file hasn't
+    changed since it was made.
+
+    As with `Config::load_cached()`, failing to write the cache isn't an
+    error; it just means the file will be parsed every time.
+    */
+    pub fn load_cached<P: AsRef<Path>>(path: P) -> Result<Template, Error> {
+        let path = path.as_ref();
+        let cache = cachefile::beside(path);
+
+        let meta = std::fs::metadata(path).map_err(|e| read_error(path, e))?;
+        let stamp = source_stamp(&meta);
+        if let Ok(bytes) = std::fs::read(&cache) {
+            if let Some(template) = Template::from_cache_bytes(&bytes, stamp) {
+                return Ok(template);
+            }
+        }
+
+        let template = Template::load(path)?;
+        // The cache is only an optimization, so failing to write it is fine.
+        _ = template.to_cache(stamp).write(&cache);
+        Ok(template)
+    }
+
+    /// Iterate over the names of the template's placeholders.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|s| match s {
+            Segment::Text(_) => None,
+            Segment::Escaped(name) | Segment::Raw(name) => Some(name.as_str()),
+        })
+    }
+
+    /// Fill in the template's placeholders with the values in `vars`.
+    pub fn render(&self, vars: &[(&str, &dyn Display)]) -> String {
+        let mut out = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Escaped(name) => {
+                    if let Some((_, value)) = vars.iter().find(|(k, _)| k == name) {
+                        push_html(&mut out, *value);
+                    }
+                }
+                Segment::Raw(name) => {
+                    if let Some((_, value)) = vars.iter().find(|(k, _)| k == name) {
+                        out.push_str(&value.to_string());
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn to_cache(&self, stamp: Stamp) -> CacheWriter {
+        let mut cache = CacheWriter::new(CACHE_MAGIC, stamp);
+        cache.u32(self.segments.len() as u32);
+        for segment in self.segments.iter() {
+            cache.u8(segment.tag());
+            cache.str(segment.text());
+        }
+        cache
+    }
+
+    /*
+    Decode a cache file, returning `None` if it's malformed or was made
+    from a different version of the source file.
+    */
+    fn from_cache_bytes(bytes: &[u8], stamp: Stamp) -> Option<Template> {
+        let mut cache = CacheReader::new(bytes, CACHE_MAGIC, stamp)?;
+        let count = cache.u32()?;
+        let mut segments = Vec::new();
+        for _ in 0..count {
+            let tag = cache.u8()?;
+            let text = cache.str()?;
+            segments.push(match tag {
+                0 => Segment::Text(text),
+                1 => Segment::Escaped(text),
+                2 => Segment::Raw(text),
+                _ => return None,
+            });
+        }
+        cache.is_done().then_some(Template { segments })
+    }
+}
+
+/**
+Parsed templates kept in memory, for programs that handle more than one
+request (like a FastCGI-style or pre-forking server); each is re-read
+when its file changes.
+
+```rust
+use dumb_cgi::template::TemplateCache;
+
+let path = std::env::temp_dir().join("dumb_cgi_template_cache_doctest.html");
+std::fs::write(&path, "Hello, {{name}}!").unwrap();
+
+let mut cache = TemplateCache::new();
+let template = cache.get(&path).unwrap();
+assert_eq!(template.render(&[("name", &"Dan")]), "Hello, Dan!");
+# std::fs::remove_file(&path).unwrap();
+```
+*/
+#[derive(Debug, Clone, Default)]
+pub struct TemplateCache {
+    templates: HashMap<PathBuf, (Stamp, Arc<Template>)>,
+}
+
+impl TemplateCache {
+    /// Return an empty cache.
+    pub fn new() -> TemplateCache {
+        TemplateCache::default()
+    }
+
+    /**
+    Return the template in the file at `path`, loading it if it isn't in
+    the cache or its file has changed since it was.
+    */
+    pub fn get<P: AsRef<Path>>(&mut self, path: P) -> Result<Arc<Template>, Error> {
+        let path = path.as_ref();
+        let meta = std::fs::metadata(path).map_err(|e| read_error(path, e))?;
+        let stamp = source_stamp(&meta);
+        if let Some((cached, template)) = self.templates.get(path) {
+            if *cached == stamp {
+                return Ok(template.clone());
+            }
+        }
+        let template = Arc::new(Template::load(path)?);
+        self.templates
+            .insert(path.to_owned(), (stamp, template.clone()));
+        Ok(template)
+    }
+
+    /// Forget every cached template.
+    pub fn clear(&mut self) {
+        self.templates.clear();
+    }
+}
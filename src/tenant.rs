@@ -0,0 +1,227 @@
+/*!
+Serving several sites from one CGI program.
+
+A `Tenant` identifies which site a request is for, either by its `Host`
+header or by the first segment of its path, and namespaces the places
+the program keeps things (directories for stores, caches, and sessions;
+log files; cookie names), so sites never see each other's data.
+
+```rust
+use std::path::Path;
+use dumb_cgi::{tenant::Tenant, testing::MockRequest};
+
+let req = MockRequest::get("/")
+    .header("Host", "Shop.Example.com:8080")
+    .build()
+    .unwrap();
+let tenant = Tenant::from_known_host(&req, &["shop.example.com", "blog.example.com"]).unwrap();
+assert_eq!(tenant.id(), "shop.example.com");
+
+let base = Path::new("/var/lib/myapp");
+assert_eq!(tenant.dir(base), base.join("shop.example.com"));
+let store = tenant.store(base.join("uploads"));
+assert_eq!(store.dir(), base.join("uploads/shop.example.com"));
+```
+
+The `Host` header comes from the client, so `Tenant::from_host()` will
+make a tenant of any (valid) name it's given; unless the web server only
+passes on requests for the sites it serves, use
+`Tenant::from_known_host()`, or check the id before creating anything
+for it.
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::audit::AuditLog;
+use crate::store::ContentStore;
+use crate::{Error, Request};
+
+fn bad_tenant(details: String) -> Error {
+    Error {
+        code: 400,
+        message: "Unknown site.".to_owned(),
+        details,
+    }
+}
+
+/**
+The site a request is for.
+
+Ids are lower-case, and contain only ASCII letters, digits, `-`, `_`,
+and `.` (but aren't `.` or `..`), so they're safe to use as directory
+and file names.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tenant {
+    id: String,
+    /// The path prefix the tenant was taken from (like `/acme`), if any.
+    prefix: Option<String>,
+}
+
+impl Tenant {
+    /**
+    Create a tenant with the given `id` (which is lower-cased), or return
+    an error if it isn't a valid id.
+    */
+    pub fn new(id: &str) -> Result<Tenant, Error> {
+        let id = id.to_ascii_lowercase();
+        let valid = !id.is_empty()
+            && id.len() <= 253
+            && id != "."
+            && id != ".."
+            && id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b));
+        if valid {
+            Ok(Tenant { id, prefix: None })
+        } else {
+            Err(bad_tenant(format!("Invalid tenant id {:?}", &id)))
+        }
+    }
+
+    /**
+    Return the tenant named by the request's `Host` header (without any
+    port), falling back to the `SERVER_NAME` variable.
+
+    The header is whatever the client sent; see `from_known_host()`.
+    */
+    pub fn from_host(req: &Request) -> Result<Tenant, Error> {
+        let host = req
+            .header("host")
+            .or_else(|| req.var("SERVER_NAME"))
+            .ok_or_else(|| bad_tenant("Request has no Host header".to_owned()))?;
+        let host = match host.rsplit_once(':') {
+            // Don't mistake the colons in an IPv6 literal for a port.
+            Some((name, port))
+                if !name.ends_with(':') && port.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                name
+            }
+            _ => host,
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        Tenant::new(host.trim_end_matches('.'))
+    }
+
+    /**
+    Like `from_host()`, but return an error (with a 400 status code) if
+    the tenant's id isn't one of `known` (which are compared without
+    regard to case), so a client can't invent new tenants.
+    */
+    pub fn from_known_host<S: AsRef<str>>(req: &Request, known: &[S]) -> Result<Tenant, Error> {
+        let tenant = Tenant::from_host(req)?;
+        if known
+            .iter()
+            .any(|k| k.as_ref().eq_ignore_ascii_case(&tenant.id))
+        {
+            Ok(tenant)
+        } else {
+            Err(bad_tenant(format!("Unknown tenant {:?}", &tenant.id)))
+        }
+    }
+
+    /**
+    Return the tenant named by the first segment of the request's
+    `PATH_INFO` (so `/acme/orders/12` is for `acme`). Use `path_info()` to
+    get the rest of the path.
+
+    ```rust
+    use dumb_cgi::{tenant::Tenant, testing::MockRequest};
+
+    let req = MockRequest::get("/acme/orders/12").build().unwrap();
+    let tenant = Tenant::from_path_prefix(&req).unwrap();
+    assert_eq!(tenant.id(), "acme");
+    assert_eq!(tenant.path_info(&req), "/orders/12");
+    assert_eq!(tenant.url_prefix(), "/acme");
+    ```
+    */
+    pub fn from_path_prefix(req: &Request) -> Result<Tenant, Error> {
+        let path = req.var("PATH_INFO").unwrap_or("");
+        let segment = path.trim_start_matches('/').split('/').next().unwrap_or("");
+        let mut tenant = Tenant::new(segment).map_err(|_| Error {
+            code: 404,
+            message: "Not found.".to_owned(),
+            details: format!("No tenant in path {:?}", path),
+        })?;
+        tenant.prefix = Some(format!("/{}", segment));
+        Ok(tenant)
+    }
+
+    /// Return the tenant's id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /**
+    Return the part of the path that came before the request's path
+    within the tenant (`/acme` for a tenant from `from_path_prefix()`,
+    and empty otherwise), for building links.
+    */
+    pub fn url_prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or("")
+    }
+
+    /**
+    Return the request's `PATH_INFO` with the tenant's prefix (if it came
+    from one) removed; always starts with `/`.
+    */
+    pub fn path_info<'a>(&self, req: &'a Request) -> &'a str {
+        let path = req.var("PATH_INFO").unwrap_or("");
+        let rest = match &self.prefix {
+            Some(prefix) => path
+                .strip_prefix(prefix.as_str())
+                .or_else(|| path.strip_prefix(&prefix[1..]))
+                .unwrap_or(path),
+            None => path,
+        };
+        if rest.is_empty() {
+            "/"
+        } else {
+            rest
+        }
+    }
+
+    /// Return this tenant's subdirectory of `base`.
+    pub fn dir<P: AsRef<Path>>(&self, base: P) -> PathBuf {
+        base.as_ref().join(&self.id)
+    }
+
+    /// Return a `ContentStore` in this tenant's subdirectory of `base`.
+    pub fn store<P: AsRef<Path>>(&self, base: P) -> ContentStore {
+        ContentStore::new(self.dir(base))
+    }
+
+    /**
+    Return an `AuditLog` named `name` in this tenant's subdirectory of
+    `base` (which is created if necessary).
+    */
+    pub fn audit_log<P: AsRef<Path>>(&self, base: P, name: &str) -> Result<AuditLog, Error> {
+        let dir = self.dir(base);
+        std::fs::create_dir_all(&dir).map_err(|e| Error {
+            code: 500,
+            message: "Internal server error.".to_owned(),
+            details: format!("Unable to create directory {}: {}", dir.display(), &e),
+        })?;
+        Ok(AuditLog::new(dir.join(name)))
+    }
+
+    /**
+    Return `name` prefixed with the tenant, for cookies (like session or
+    login cookies) that would otherwise be shared by tenants on the same
+    host.
+
+    The id and `name` are joined with a `~`, which ids can't contain, so
+    no two tenants' cookie names are ever the same.
+
+    ```rust
+    # use dumb_cgi::tenant::Tenant;
+    let a = Tenant::new("a.b").unwrap();
+    let b = Tenant::new("a_b").unwrap();
+    assert_eq!(a.cookie_name("session"), "a.b~session");
+    assert_ne!(a.cookie_name("session"), b.cookie_name("session"));
+    ```
+    */
+    pub fn cookie_name(&self, name: &str) -> String {
+        format!("{}~{}", &self.id, name)
+    }
+}
@@ -1,6 +1,34 @@
+#[cfg(feature = "log")]
 use crate::{Body, EmptyResponse, Query, Request};
+#[cfg(feature = "log")]
 use simplelog::{Config, LevelFilter, WriteLogger};
 
+/*
+A directory of its own for a test, named after it and the process, and
+removed (with everything in it) when dropped, even if the test panics.
+*/
+struct Scratch(std::path::PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Scratch {
+        let dir = std::env::temp_dir().join(format!("dumb_cgi_{}_{}", name, std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Scratch(dir)
+    }
+
+    fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(feature = "log")]
 #[test]
 fn readme_main() {
     // Open the log file.
@@ -128,3 +156,4545 @@ fn readme_main() {
     // response to stdout.
     response.respond().unwrap();
 }
+
+#[test]
+fn sha256_multiblock() {
+    use crate::hash::{sha256, to_hex, Sha256};
+
+    let data = [b'a'; 1000];
+    let expected = "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3";
+    assert_eq!(to_hex(&sha256(&data)), expected);
+
+    // Feed the same data in awkwardly-sized pieces.
+    let mut h = Sha256::new();
+    for chunk in data.chunks(37) {
+        h.update(chunk);
+    }
+    assert_eq!(to_hex(&h.finish()), expected);
+}
+
+#[test]
+fn hmac_sha256_rfc4231() {
+    use crate::hash::{hmac_sha256, to_hex};
+
+    assert_eq!(
+        to_hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    // Key longer than the block size.
+    assert_eq!(
+        to_hex(&hmac_sha256(
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First"
+        )),
+        "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+    );
+}
+
+#[test]
+fn pbkdf2_sha256_vector() {
+    use crate::hash::{pbkdf2_sha256, to_hex};
+
+    let mut out = [0u8; 40];
+    pbkdf2_sha256(b"password", b"salt", 4096, &mut out);
+    assert_eq!(
+        to_hex(&out),
+        "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134af7ad98c1b458ce3f"
+    );
+}
+
+#[test]
+fn base64_roundtrip() {
+    use crate::base64;
+
+    for n in 0..20usize {
+        let bytes: Vec<u8> = (0..n).map(|b| (b * 37) as u8).collect();
+        assert_eq!(base64::decode(&base64::encode(&bytes)).unwrap(), bytes);
+        assert_eq!(
+            base64::decode_url(&base64::encode_url(&bytes)).unwrap(),
+            bytes
+        );
+    }
+    assert!(base64::decode("A").is_none());
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn auth_dummy_hash_is_well_formed() {
+    use crate::auth::{hash_password_with, verify_password};
+
+    // A password hash of the same shape as the one used to pad out the
+    // timing of logins for nonexistent users.
+    let hash = hash_password_with("", &[0u8; 16], 100_000);
+    assert!(hash.starts_with("$pbkdf2-sha256$100000$AAAAAAAAAAAAAAAAAAAAAA$"));
+    assert_eq!(hash.len(), 15 + 7 + 22 + 1 + 43);
+    assert!(!verify_password("anything", "not a hash"));
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn auth_rejects_malformed_pbkdf2_hashes() {
+    use crate::auth::{hash_password_with, verify_password};
+
+    // An empty digest mustn't match every password.
+    assert!(!verify_password("anything", "$pbkdf2-sha256$1000$AAAA$"));
+    assert!(!verify_password("", "$pbkdf2-sha256$1000$AAAA$"));
+    // Nor a truncated one.
+    let hash = hash_password_with("hunter2", b"saltsaltsaltsalt", 1000);
+    assert!(verify_password("hunter2", &hash));
+    assert!(!verify_password("hunter2", &hash[..hash.len() - 4]));
+    // Nor an empty salt, even with a digest of the right length.
+    let unsalted = hash_password_with("hunter2", b"", 1000);
+    assert!(unsalted.starts_with("$pbkdf2-sha256$1000$$"));
+    assert!(!verify_password("hunter2", &unsalted));
+}
+
+#[test]
+fn mock_multipart_request() {
+    use crate::testing::MockRequest;
+    use crate::Body;
+
+    let body = "--XyZ\r\n\
+                Content-Disposition: form-data; name=\"greeting\"\r\n\
+                \r\n\
+                hello\r\n\
+                --XyZ\r\n\
+                Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                Content-Type: text/plain\r\n\
+                \r\n\
+                file\r\ncontents\r\n\
+                --XyZ--\r\n";
+    let req = MockRequest::post("/upload")
+        .header("Content-Type", "multipart/form-data; boundary=XyZ")
+        .body(body)
+        .build()
+        .unwrap();
+
+    let expected_len = body.len().to_string();
+    assert_eq!(req.header("content-length"), Some(expected_len.as_str()));
+    match req.body() {
+        Body::Multipart(parts) => {
+            assert_eq!(parts.len(), 2);
+            assert_eq!(parts[0].body, b"hello");
+            assert_eq!(parts[1].headers.get("content-type").unwrap(), "text/plain");
+            assert_eq!(parts[1].body, b"file\r\ncontents");
+        }
+        b => panic!("expected multipart body, got {:?}", b),
+    }
+}
+
+#[test]
+fn mock_request_cookies() {
+    use crate::testing::MockRequest;
+
+    let req = MockRequest::get("/")
+        .header("Cookie", "a=1; session=\"abc\"; b=2")
+        .build()
+        .unwrap();
+    assert_eq!(req.cookie("session"), Some("abc"));
+    assert_eq!(req.cookie("b"), Some("2"));
+    assert_eq!(req.cookie("c"), None);
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn auth_login_cookie_roundtrip() {
+    use crate::auth::{hash_password_with, Authenticator, Users};
+    use crate::testing::MockRequest;
+
+    let mut users = Users::new();
+    users.insert(
+        "dan",
+        hash_password_with("hunter2", b"0123456789abcdef", 10),
+    );
+    let auth = Authenticator::new(users, "key");
+
+    let set_cookie = auth.login("dan", "hunter2").unwrap();
+    let cookie = set_cookie.split(';').next().unwrap();
+    let req = MockRequest::get("/")
+        .header("Cookie", cookie)
+        .build()
+        .unwrap();
+    assert_eq!(auth.user(&req).as_deref(), Some("dan"));
+
+    let other = Authenticator::new(Users::new(), "other key");
+    assert_eq!(other.user(&req), None);
+
+    let tampered = cookie.replacen("auth=", "auth=x", 1);
+    let req = MockRequest::get("/")
+        .header("Cookie", tampered)
+        .build()
+        .unwrap();
+    assert_eq!(auth.user(&req), None);
+
+    // Changing the password revokes the cookie.
+    let req = MockRequest::get("/")
+        .header("Cookie", cookie)
+        .build()
+        .unwrap();
+    let mut users = auth.users().clone();
+    users.insert(
+        "dan",
+        hash_password_with("hunter3", b"0123456789abcdef", 10),
+    );
+    assert_eq!(Authenticator::new(users, "key").user(&req), None);
+
+    assert!(set_cookie.contains("; HttpOnly; SameSite=Lax"));
+    assert!(auth.logout().starts_with("auth=; Path=/; Max-Age=0;"));
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn auth_logout_revokes_session() {
+    use crate::auth::{hash_password_with, Authenticator, Users};
+    use crate::testing::MockRequest;
+
+    let scratch = Scratch::new("sessions");
+    let dir = scratch.path();
+    let mut users = Users::new();
+    users.insert(
+        "dan",
+        hash_password_with("hunter2", b"0123456789abcdef", 10),
+    );
+    let auth = Authenticator::new(users, "key").with_sessions(dir);
+    let with_cookie = |set_cookie: &str| {
+        MockRequest::get("/")
+            .header("Cookie", set_cookie.split(';').next().unwrap())
+            .build()
+            .unwrap()
+    };
+
+    let first = with_cookie(&auth.login("dan", "hunter2").unwrap());
+    let second = with_cookie(&auth.login("dan", "hunter2").unwrap());
+    assert_eq!(auth.user(&first).as_deref(), Some("dan"));
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 2);
+
+    // Logging one session out leaves the other.
+    let removal = auth.logout_from(&first).unwrap();
+    assert!(removal.contains("Max-Age=0"));
+    assert_eq!(auth.user(&first), None);
+    assert_eq!(auth.user(&second).as_deref(), Some("dan"));
+    // Logging out again is harmless.
+    auth.logout_from(&first).unwrap();
+
+    // A cookie issued without a session doesn't work where they're kept.
+    let sessionless = Authenticator::new(auth.users().clone(), "key");
+    let plain = with_cookie(&sessionless.login("dan", "hunter2").unwrap());
+    assert_eq!(auth.user(&plain), None);
+}
+
+#[test]
+fn sha1_md5_vectors() {
+    use crate::hash::{md5, sha1, to_hex};
+
+    assert_eq!(
+        to_hex(&sha1(b"")),
+        "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+    );
+    assert_eq!(
+        to_hex(&sha1(&[b'a'; 1000])),
+        "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+    );
+    assert_eq!(to_hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(
+        to_hex(&md5(b"The quick brown fox jumps over the lazy dog")),
+        "9e107d9d372bb6826bd81d3542a419d6"
+    );
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn htpasswd_formats() {
+    use crate::auth::{verify_password, Users};
+
+    assert!(verify_password(
+        "password",
+        "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g="
+    ));
+    assert!(verify_password(
+        "password",
+        "$apr1$r31.....$ARC3pREO82RIm0aQ2zszC0"
+    ));
+    assert!(verify_password(
+        "password",
+        "$1$saltstri$qQY4WxjABChYG1ccLpfkz/"
+    ));
+    assert!(!verify_password(
+        "Password",
+        "$1$saltstri$qQY4WxjABChYG1ccLpfkz/"
+    ));
+    assert!(verify_password(
+        "U*U",
+        "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW"
+    ));
+    assert!(verify_password(
+        "",
+        "$2a$05$CCCCCCCCCCCCCCCCCCCCC.7uG0VCzI2bS7j6ymqJi9CdcdxiRTWNy"
+    ));
+    assert!(!verify_password(
+        "U*U*",
+        "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW"
+    ));
+
+    let users = Users::parse("dan:$2y$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW\n");
+    assert!(users.verify("dan", "U*U"));
+}
+
+#[test]
+fn parse_multipart_truncated_inputs() {
+    use crate::{parse_multipart, Body};
+
+    let body: &[u8] =
+        b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--xyz--\r\n";
+    // Every prefix of a valid body should parse (or fail) without
+    // panicking or looping forever.
+    for n in 0..body.len() {
+        match parse_multipart(&body[..n], "xyz") {
+            Body::Multipart(_) | Body::Err(_) => {}
+            b => panic!("unexpected {:?}", b),
+        }
+    }
+    assert!(matches!(
+        parse_multipart(b"--xyz\r\n\r\n", "xyz"),
+        Body::Multipart(_)
+    ));
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn totp_rfc6238_vectors() {
+    use crate::totp::{base32_decode, base32_encode, Totp};
+
+    let totp = Totp::new(&b"12345678901234567890"[..]).with_digits(8);
+    for (t, code) in [
+        (59, "94287082"),
+        (1111111109, "07081804"),
+        (1111111111, "14050471"),
+        (1234567890, "89005924"),
+        (2000000000, "69279037"),
+        (20000000000, "65353130"),
+    ] {
+        assert_eq!(totp.generate(t), code);
+        assert!(totp.verify(code, t));
+    }
+
+    let encoded = base32_encode(b"12345678901234567890");
+    assert_eq!(encoded, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    assert_eq!(
+        base32_decode(&encoded.to_lowercase()).unwrap(),
+        b"12345678901234567890"
+    );
+    assert!(base32_decode("not base32!").is_none());
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn login_from_with_lockout() {
+    use crate::auth::{hash_password_with, Authenticator, Users};
+    use crate::lockout::Lockout;
+    use crate::testing::MockRequest;
+
+    let scratch = Scratch::new("lockout");
+    let dir = scratch.path();
+    let mut users = Users::new();
+    users.insert(
+        "dan",
+        hash_password_with("hunter2", b"0123456789abcdef", 10),
+    );
+    let auth = Authenticator::new(users, "key")
+        .with_lockout(Lockout::new(dir).with_threshold(2).with_base_delay(60));
+    let req = MockRequest::post("/login")
+        .var("REMOTE_ADDR", "192.0.2.1")
+        .build()
+        .unwrap();
+
+    assert_eq!(auth.login_from(&req, "dan", "nope").unwrap_err().code, 403);
+    assert_eq!(auth.login_from(&req, "dan", "nope").unwrap_err().code, 403);
+    // Now even the right password is refused.
+    assert_eq!(
+        auth.login_from(&req, "dan", "hunter2").unwrap_err().code,
+        429
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+    assert!(auth.login_from(&req, "dan", "hunter2").is_ok());
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn lockout_concurrent_failures() {
+    use crate::lockout::Lockout;
+
+    let scratch = Scratch::new("lockout_concurrent");
+    let dir = scratch.path();
+    let lockout = Lockout::new(dir).with_base_delay(600);
+
+    // Failures recorded in parallel must all be counted.
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let lockout = lockout.clone();
+            std::thread::spawn(move || {
+                for _ in 0..5 {
+                    lockout.record_failure("dan", "192.0.2.1").unwrap();
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    let at = |threshold: u32| lockout.clone().with_threshold(threshold);
+    assert!(at(41).check("dan", "192.0.2.1").is_ok());
+    assert_eq!(at(40).check("dan", "192.0.2.1").unwrap_err().code, 429);
+
+    // Nothing is left locked.
+    let names: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names.len(), 1);
+    assert!(!names[0].contains('.'));
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn lockout_parallel_attempts() {
+    use crate::lockout::Lockout;
+    use std::sync::{Arc, Barrier};
+
+    let scratch = Scratch::new("lockout_parallel");
+    let dir = scratch.path();
+    let lockout = Lockout::new(dir).with_threshold(3).with_base_delay(600);
+
+    // However many guesses arrive at once, only `threshold` get through.
+    let barrier = Arc::new(Barrier::new(16));
+    let threads: Vec<_> = (0..16)
+        .map(|_| {
+            let lockout = lockout.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                lockout.attempt("dan", "192.0.2.1").is_ok()
+            })
+        })
+        .collect();
+    let allowed = threads
+        .into_iter()
+        .map(|t| t.join().unwrap())
+        .filter(|&ok| ok)
+        .count();
+    assert_eq!(allowed, 3);
+    assert_eq!(lockout.check("dan", "192.0.2.1").unwrap_err().code, 429);
+
+    // A successful attempt clears the failures before it.
+    let lockout = lockout.with_threshold(2);
+    _ = std::fs::remove_dir_all(dir);
+    let _ = lockout.attempt("dan", "192.0.2.2").unwrap();
+    lockout
+        .attempt("dan", "192.0.2.2")
+        .unwrap()
+        .succeeded()
+        .unwrap();
+    assert_eq!(lockout.remaining("dan", "192.0.2.2"), 0);
+    let _ = lockout.attempt("dan", "192.0.2.2").unwrap();
+    assert!(lockout.attempt("dan", "192.0.2.2").is_ok());
+    assert!(lockout.attempt("dan", "192.0.2.2").is_err());
+}
+
+#[test]
+fn request_warnings() {
+    use crate::testing::MockRequest;
+    use crate::{Body, Warning};
+
+    let body = "--b\r\n\
+                Content-Disposition: form-data; name=\"ok\"\r\n\
+                \r\n\
+                fine\r\n\
+                --b\r\n\
+                this is not a header\r\n\
+                \r\n\
+                lost\r\n\
+                --b--\r\n";
+    let req = MockRequest::post("/")
+        .query("a=1&b=2&a=3")
+        .header("Content-Type", "multipart/form-data; boundary=b")
+        .body(body)
+        .build()
+        .unwrap();
+
+    match req.body() {
+        Body::Multipart(form) => {
+            assert_eq!(form.len(), 2);
+            assert!(form.skipped_parts().is_empty());
+        }
+        b => panic!("expected multipart body, got {:?}", b),
+    }
+    let warnings = req.warnings();
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(warnings[0], Warning::DuplicateQueryKey("a".to_owned()));
+    assert!(matches!(warnings[1], Warning::MalformedPartHeaders(_)));
+}
+
+#[test]
+fn malformed_multipart_parts() {
+    use crate::{parse_multipart, parse_multipart_with_limits, Body, MultipartLimits};
+
+    let bad_line = "--b\r\n\
+                    Content-Disposition: form-data; name=\"a\"\r\n\
+                    not a header\r\n\
+                    \r\n\
+                    body\r\n\
+                    --b--\r\n";
+    let no_blank_line = "--b\r\n\
+                         Content-Disposition: form-data; name=\"a\"\r\n\
+                         Content-Type: text/plain\r\n\
+                         --b\r\n\
+                         Content-Disposition: form-data; name=\"c\"\r\n\
+                         \r\n\
+                         kept\r\n\
+                         --b--\r\n";
+
+    // By default, as in 0.8.0, the headers end at a bad line and the body
+    // starts after it (or after the last line break).
+    let parse = |body: &str| match parse_multipart(body.as_bytes(), "b") {
+        Body::Multipart(form) => form,
+        b => panic!("expected multipart body, got {:?}", b),
+    };
+    let form = parse(bad_line);
+    assert_eq!(form.len(), 1);
+    assert_eq!(form[0].name().as_deref(), Some("a"));
+    assert_eq!(form[0].body, b"\r\nbody");
+    assert!(form.skipped_parts().is_empty());
+    let form = parse(no_blank_line);
+    assert_eq!(form.len(), 2);
+    assert_eq!(form[0].name().as_deref(), Some("a"));
+    assert_eq!(form[0].body, b"Content-Type: text/plain");
+    assert_eq!(form[1].body, b"kept");
+
+    // Being strict, they're left out of the form.
+    let strict = MultipartLimits::new().with_strict(true);
+    let parse = |body: &str| match parse_multipart_with_limits(body.as_bytes(), "b", &strict) {
+        Body::Multipart(form) => form,
+        b => panic!("expected multipart body, got {:?}", b),
+    };
+    let form = parse(bad_line);
+    assert_eq!(form.len(), 0);
+    assert_eq!(form.skipped_parts().len(), 1);
+    assert!(form.skipped_parts()[0]
+        .details
+        .contains("malformed header line \"not a header\""));
+    let form = parse(no_blank_line);
+    assert_eq!(form.len(), 1);
+    assert_eq!(form[0].name().as_deref(), Some("c"));
+    assert_eq!(form.skipped_parts().len(), 1);
+    assert!(form.skipped_parts()[0].details.contains("no blank line"));
+
+    // Either way, a request says what happened.
+    let req = crate::testing::MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=b")
+        .body(bad_line)
+        .build()
+        .unwrap();
+    assert_eq!(req.warnings().len(), 1);
+    assert_eq!(req.warnings()[0].kind(), "malformed-part-headers");
+}
+
+#[test]
+fn audit_log_detects_tampering() {
+    use crate::audit::AuditLog;
+
+    let scratch = Scratch::new("audit");
+    let path = scratch.path().join("audit.log");
+    let log = AuditLog::new(&path);
+    log.append("dan", "first").unwrap();
+    log.append("dan", "second\twith a tab\nand a newline")
+        .unwrap();
+    log.append("eve", "third").unwrap();
+    assert_eq!(log.verify().unwrap(), 3);
+    assert_eq!(
+        log.records().unwrap()[1].action,
+        "second\twith a tab\nand a newline"
+    );
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::write(&path, text.replacen("first", "frist", 1)).unwrap();
+    assert!(log.verify().is_err());
+
+    // Removing a record from the middle breaks the chain, too.
+    let lines: Vec<&str> = text.lines().collect();
+    std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+    assert!(log.verify().is_err());
+
+    // A mangled last record is reported at its own line.
+    std::fs::write(&path, format!("{}\n{}\ngarbage\n\n", lines[0], lines[1])).unwrap();
+    let err = log.append("dan", "fourth").unwrap_err();
+    assert!(err
+        .details
+        .ends_with("at line 3: unable to parse last record"));
+}
+
+#[test]
+fn keyed_audit_log_resists_rehashing() {
+    use crate::audit::AuditLog;
+    use crate::hash::{sha256, to_hex};
+
+    let scratch = Scratch::new("audit_keyed");
+    let path = scratch.path().join("audit_keyed.log");
+
+    // Edit a record and recompute every hash from there on, as someone
+    // who can write the log but doesn't know the key would.
+    let rehash = |text: &str| {
+        let mut prev = "0".repeat(64);
+        let mut out = String::new();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let body = format!(
+                "{}\t{}\t{}\t{}\t{}",
+                fields[0],
+                fields[1],
+                fields[2],
+                fields[3].replace("first", "frist"),
+                &prev
+            );
+            prev = to_hex(&sha256(body.as_bytes()));
+            out.push_str(&format!("{}\t{}\n", &body, &prev));
+        }
+        out
+    };
+
+    let plain = AuditLog::new(&path);
+    plain.append("dan", "first").unwrap();
+    plain.append("dan", "second").unwrap();
+    let forged = rehash(&std::fs::read_to_string(&path).unwrap());
+    std::fs::write(&path, &forged).unwrap();
+    // Without a key, the forgery passes.
+    assert_eq!(plain.verify().unwrap(), 2);
+    std::fs::remove_file(&path).unwrap();
+
+    let keyed = AuditLog::new(&path).with_key("secret");
+    keyed.append("dan", "first").unwrap();
+    keyed.append("dan", "second").unwrap();
+    assert_eq!(keyed.verify().unwrap(), 2);
+    assert!(AuditLog::new(&path).with_key("guess").verify().is_err());
+    let forged = rehash(&std::fs::read_to_string(&path).unwrap());
+    std::fs::write(&path, &forged).unwrap();
+    assert!(keyed.verify().is_err());
+}
+
+#[test]
+fn abandoned_lock_file_taken_once() {
+    use crate::lockfile::LockGuard;
+    use std::sync::{Arc, Barrier};
+
+    let scratch = Scratch::new("lockfile");
+    let dir = scratch.path();
+    let path = dir.join("x.lock");
+
+    for _ in 0..20 {
+        // A lock file left behind by a process that died holding it.
+        std::fs::write(&path, "").unwrap();
+
+        let barrier = Arc::new(Barrier::new(8));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (barrier, path) = (barrier.clone(), path.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    let guard = LockGuard::try_acquire(&path).unwrap();
+                    // Hold on to it until everyone has tried.
+                    barrier.wait();
+                    guard.is_some()
+                })
+            })
+            .collect();
+        let holders = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .filter(|held| *held)
+            .count();
+        assert_eq!(holders, 1);
+        assert!(!path.exists());
+    }
+    // Nothing is left lying around.
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 0);
+}
+
+#[test]
+fn lock_held_while_file_replaced() {
+    use crate::lockfile::LockGuard;
+
+    let scratch = Scratch::new("lockfile_swap");
+    let dir = scratch.path();
+    let path = dir.join("x.lock");
+
+    let first = LockGuard::try_acquire(&path).unwrap().unwrap();
+    assert!(LockGuard::try_acquire(&path).unwrap().is_none());
+    // Somebody else's lock file, created after the first was taken, isn't
+    // removed when the first is released.
+    let second = {
+        std::fs::remove_file(&path).unwrap();
+        LockGuard::try_acquire(&path).unwrap().unwrap()
+    };
+    drop(first);
+    assert!(path.exists());
+    assert!(LockGuard::try_acquire(&path).unwrap().is_none());
+    drop(second);
+    assert!(!path.exists());
+}
+
+#[test]
+fn capture_replays_multipart_and_errors() {
+    use crate::testing::MockRequest;
+    use crate::{Body, Request};
+
+    let body = "--b\r\n\
+                Content-Disposition: form-data; name=\"f\"; filename=\"x.bin\"\r\n\
+                \r\n\
+                \x00\x01binary\r\n\
+                --b--\r\n";
+    let req = MockRequest::post("/")
+        .query("a=1")
+        .header("Content-Type", "multipart/form-data; boundary=b")
+        .body(body)
+        .build()
+        .unwrap();
+    let replayed = Request::from_capture(&req.to_capture()).unwrap();
+    match (req.body(), replayed.body()) {
+        (Body::Multipart(a), Body::Multipart(b)) => {
+            assert_eq!(a.len(), b.len());
+            assert_eq!(a[0].headers, b[0].headers);
+            assert_eq!(a[0].body, b[0].body);
+        }
+        x => panic!("expected multipart bodies, got {:?}", x),
+    }
+    assert_eq!(replayed.var("QUERY_STRING"), Some("a=1"));
+
+    // A request whose body couldn't be read.
+    let req = MockRequest::post("/")
+        .header("Content-Length", "10")
+        .build()
+        .unwrap();
+    assert!(matches!(req.body(), Body::Err(_)));
+    let replayed = Request::from_capture(&req.to_capture()).unwrap();
+    match replayed.body() {
+        Body::Err(e) => assert_eq!(e.code, 500),
+        b => panic!("expected error body, got {:?}", b),
+    }
+    assert_eq!(replayed.header("content-length"), Some("10"));
+
+    assert!(Request::from_capture("not a capture").is_err());
+
+    // Errors give the line number in the whole capture, header included.
+    let mut capture = req.to_capture();
+    let lines = capture.lines().count();
+    capture.push_str("bogus\n");
+    let e = Request::from_capture(&capture).unwrap_err();
+    assert_eq!(
+        e.details,
+        format!("line {}: unrecognized", lines + 1),
+        "{}",
+        capture
+    );
+    let e = Request::from_capture("dumb_cgi capture 1\nbody\t!!!\n").unwrap_err();
+    assert_eq!(e.details, "line 2: invalid body");
+}
+
+#[test]
+fn capture_escapes_names() {
+    use crate::testing::MockRequest;
+    use crate::Request;
+
+    let req = MockRequest::get("/")
+        .var("ODD\tNAME\nbody\tAAAA", "value")
+        .build()
+        .unwrap();
+    let capture = req.to_capture();
+    assert!(!capture.lines().any(|line| line.starts_with("body")));
+    let replayed = Request::from_capture(&capture).unwrap();
+    assert_eq!(replayed.var("ODD\tNAME\nbody\tAAAA"), Some("value"));
+    assert_eq!(replayed.to_capture(), capture);
+}
+
+/*
+Just enough of a serde data format to round-trip a `Request` without
+pulling in a real one.
+*/
+#[cfg(feature = "serde")]
+mod serde_value {
+    use serde_core::de::{self, value::MapDeserializer, IntoDeserializer, Visitor};
+    use serde_core::ser::{self, Impossible, Serialize};
+    use std::fmt;
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Value {
+        Null,
+        Num(u64),
+        Str(String),
+        Map(Vec<(String, Value)>),
+    }
+
+    impl Value {
+        pub fn get(&self, key: &str) -> Option<&Value> {
+            match self {
+                Value::Map(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct Error(String);
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", &self.0)
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+    impl ser::Error for Error {
+        fn custom<T: fmt::Display>(msg: T) -> Error {
+            Error(msg.to_string())
+        }
+    }
+
+    impl de::Error for Error {
+        fn custom<T: fmt::Display>(msg: T) -> Error {
+            Error(msg.to_string())
+        }
+    }
+
+    pub fn to_value<T: Serialize>(t: &T) -> Result<Value, Error> {
+        t.serialize(Serializer)
+    }
+
+    pub struct Serializer;
+
+    pub struct MapBuilder {
+        pairs: Vec<(String, Value)>,
+        key: Option<String>,
+    }
+
+    macro_rules! unsupported {
+        ($($f:ident($($t:ty),*) -> $r:ty;)*) => {
+            $(fn $f(self, $(_: $t),*) -> Result<$r, Error> {
+                Err(Error(stringify!($f).to_owned()))
+            })*
+        };
+    }
+
+    impl ser::Serializer for Serializer {
+        type Ok = Value;
+        type Error = Error;
+        type SerializeSeq = Impossible<Value, Error>;
+        type SerializeTuple = Impossible<Value, Error>;
+        type SerializeTupleStruct = Impossible<Value, Error>;
+        type SerializeTupleVariant = Impossible<Value, Error>;
+        type SerializeMap = MapBuilder;
+        type SerializeStruct = MapBuilder;
+        type SerializeStructVariant = Impossible<Value, Error>;
+
+        fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+            Ok(Value::Num(v as u64))
+        }
+        fn serialize_str(self, v: &str) -> Result<Value, Error> {
+            Ok(Value::Str(v.to_owned()))
+        }
+        fn serialize_none(self) -> Result<Value, Error> {
+            Ok(Value::Null)
+        }
+        fn serialize_some<T: Serialize + ?Sized>(self, v: &T) -> Result<Value, Error> {
+            v.serialize(self)
+        }
+        fn serialize_map(self, _: Option<usize>) -> Result<MapBuilder, Error> {
+            Ok(MapBuilder {
+                pairs: Vec::new(),
+                key: None,
+            })
+        }
+        fn serialize_struct(self, _: &'static str, n: usize) -> Result<MapBuilder, Error> {
+            self.serialize_map(Some(n))
+        }
+        fn serialize_newtype_struct<T: Serialize + ?Sized>(
+            self,
+            _: &'static str,
+            _: &T,
+        ) -> Result<Value, Error> {
+            Err(Error("newtype struct".to_owned()))
+        }
+        fn serialize_newtype_variant<T: Serialize + ?Sized>(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: &T,
+        ) -> Result<Value, Error> {
+            Err(Error("newtype variant".to_owned()))
+        }
+
+        unsupported! {
+            serialize_bool(bool) -> Value;
+            serialize_i8(i8) -> Value;
+            serialize_i16(i16) -> Value;
+            serialize_i32(i32) -> Value;
+            serialize_i64(i64) -> Value;
+            serialize_u8(u8) -> Value;
+            serialize_u32(u32) -> Value;
+            serialize_u64(u64) -> Value;
+            serialize_f32(f32) -> Value;
+            serialize_f64(f64) -> Value;
+            serialize_char(char) -> Value;
+            serialize_bytes(&[u8]) -> Value;
+            serialize_unit() -> Value;
+            serialize_unit_struct(&'static str) -> Value;
+            serialize_unit_variant(&'static str, u32, &'static str) -> Value;
+            serialize_seq(Option<usize>) -> Self::SerializeSeq;
+            serialize_tuple(usize) -> Self::SerializeTuple;
+            serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+            serialize_tuple_variant(&'static str, u32, &'static str, usize)
+                -> Self::SerializeTupleVariant;
+            serialize_struct_variant(&'static str, u32, &'static str, usize)
+                -> Self::SerializeStructVariant;
+        }
+    }
+
+    impl ser::SerializeMap for MapBuilder {
+        type Ok = Value;
+        type Error = Error;
+
+        fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+            match key.serialize(Serializer)? {
+                Value::Str(k) => {
+                    self.key = Some(k);
+                    Ok(())
+                }
+                v => Err(Error(format!("non-string key {:?}", &v))),
+            }
+        }
+        fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+            let key = self.key.take().ok_or_else(|| Error("no key".to_owned()))?;
+            self.pairs.push((key, value.serialize(Serializer)?));
+            Ok(())
+        }
+        fn end(self) -> Result<Value, Error> {
+            Ok(Value::Map(self.pairs))
+        }
+    }
+
+    impl ser::SerializeStruct for MapBuilder {
+        type Ok = Value;
+        type Error = Error;
+
+        fn serialize_field<T: Serialize + ?Sized>(
+            &mut self,
+            key: &'static str,
+            value: &T,
+        ) -> Result<(), Error> {
+            self.pairs
+                .push((key.to_owned(), value.serialize(Serializer)?));
+            Ok(())
+        }
+        fn end(self) -> Result<Value, Error> {
+            Ok(Value::Map(self.pairs))
+        }
+    }
+
+    impl<'de> de::Deserializer<'de> for Value {
+        type Error = Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self {
+                Value::Null => visitor.visit_none(),
+                Value::Num(n) => visitor.visit_u64(n),
+                Value::Str(s) => visitor.visit_string(s),
+                Value::Map(pairs) => visitor.visit_map(MapDeserializer::new(pairs.into_iter())),
+            }
+        }
+
+        fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self {
+                Value::Null => visitor.visit_none(),
+                v => visitor.visit_some(v),
+            }
+        }
+
+        serde_core::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf unit unit_struct newtype_struct seq tuple
+            tuple_struct map struct enum identifier ignored_any
+        }
+    }
+
+    impl IntoDeserializer<'_, Error> for Value {
+        type Deserializer = Value;
+
+        fn into_deserializer(self) -> Value {
+            self
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serde_round_trips_requests() {
+    use self::serde_value::{to_value, Value};
+    use crate::testing::MockRequest;
+    use crate::{Body, Request};
+    use serde_core::Deserialize;
+
+    let req = MockRequest::post("/submit")
+        .query("a=1&b=two")
+        .header("Content-Type", "application/octet-stream")
+        .body(&b"\x00\x01binary"[..])
+        .build()
+        .unwrap();
+    let value = to_value(&req).unwrap();
+    assert_eq!(
+        value.get("query"),
+        Some(&Value::Map(vec![
+            ("a".to_owned(), Value::Str("1".to_owned())),
+            ("b".to_owned(), Value::Str("two".to_owned())),
+        ]))
+    );
+    assert_eq!(
+        value.get("body"),
+        Some(&Value::Str(crate::base64::encode(b"\x00\x01binary")))
+    );
+    assert_eq!(value.get("error"), Some(&Value::Null));
+    assert_eq!(
+        value.get("headers").and_then(|h| h.get("content-type")),
+        Some(&Value::Str("application/octet-stream".to_owned()))
+    );
+
+    let replayed = Request::deserialize(value.clone()).unwrap();
+    assert_eq!(replayed.var("QUERY_STRING"), Some("a=1&b=two"));
+    match replayed.body() {
+        Body::Some(bytes) => assert_eq!(bytes, b"\x00\x01binary"),
+        b => panic!("expected a body, got {:?}", b),
+    }
+    assert_eq!(to_value(&replayed).unwrap(), value);
+
+    // A request whose body couldn't be read.
+    let req = MockRequest::post("/")
+        .header("Content-Length", "10")
+        .build()
+        .unwrap();
+    let value = to_value(&req).unwrap();
+    assert_eq!(value.get("body"), Some(&Value::Null));
+    assert_eq!(
+        value.get("error").and_then(|e| e.get("code")),
+        Some(&Value::Num(500))
+    );
+    match Request::deserialize(value).unwrap().body() {
+        Body::Err(e) => assert_eq!(e.code, 500),
+        b => panic!("expected error body, got {:?}", b),
+    }
+
+    assert!(Request::deserialize(Value::Map(Vec::new())).is_err());
+    assert!(Request::deserialize(Value::Str("nope".to_owned())).is_err());
+}
+
+#[test]
+fn request_is_clone_and_send() {
+    fn assert_send<T: Send + Sync>() {}
+    assert_send::<crate::Request>();
+    assert_send::<crate::Error>();
+
+    let req = crate::testing::MockRequest::post("/upload")
+        .header("Content-Type", "text/plain")
+        .body("hello")
+        .build()
+        .unwrap();
+    let copy = req.clone();
+
+    let handle = std::thread::spawn(move || match req.body() {
+        crate::Body::Some(bytes) => bytes.clone(),
+        _ => Vec::new(),
+    });
+    assert_eq!(handle.join().unwrap(), b"hello");
+    assert_eq!(copy.header("content-type"), Some("text/plain"));
+}
+
+#[test]
+fn i18n_catalogs_from_dir() {
+    use crate::{i18n::Catalogs, t};
+
+    let scratch = Scratch::new("i18n");
+    let dir = scratch.path();
+    std::fs::write(
+        dir.join("ru.po"),
+        "# Russian\nmsgid \"\"\nmsgstr \"Language: ru\\n\"\n\n\
+         msgid \"one file\"\nmsgid_plural \"{n} files\"\n\
+         msgstr[0] \"{n} файл\"\nmsgstr[1] \"{n} файла\"\nmsgstr[2] \"{n} \"\n\"файлов\"\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("pt_BR.po"), "msgid \"Save\"\nmsgstr \"Salvar\"\n").unwrap();
+    std::fs::write(dir.join("bad.po"), "msgid \"unterminated\n").unwrap();
+
+    assert!(Catalogs::load_dir("en", dir).is_err());
+    std::fs::remove_file(dir.join("bad.po")).unwrap();
+    let catalogs = Catalogs::load_dir("en", dir).unwrap();
+
+    let ru = catalogs.negotiate("fr;q=0.9, ru-RU");
+    assert_eq!(ru.language(), "ru");
+    assert_eq!(t!(ru, "one file", "{n} files", 1, n = 1), "1 файл");
+    assert_eq!(t!(ru, "one file", "{n} files", 3, n = 3), "3 файла");
+    assert_eq!(t!(ru, "one file", "{n} files", 11, n = 11), "11 файлов");
+
+    assert_eq!(t!(catalogs.negotiate("pt"), "Save"), "Salvar");
+    assert_eq!(catalogs.negotiate("pt;q=0, de").language(), "en");
+    assert_eq!(catalogs.negotiate("").language(), "en");
+}
+
+#[test]
+fn locale_formatting() {
+    use crate::i18n::{Catalogs, Locale};
+
+    let fr = Locale::for_language("fr_FR");
+    assert_eq!(fr.format_decimal(1234.5, 1), "1\u{202f}234,5");
+    assert_eq!(fr.format_decimal(-0.001, 2), "0,00");
+    assert_eq!(fr.format_date(2024, 3, 7), "07/03/2024");
+    assert_eq!(fr.format_time(0, 30), "00:30");
+
+    let us = Locale::for_language("xx");
+    assert_eq!(us.format_integer(-999), "-999");
+    assert_eq!(us.format_integer(i64::MIN), "-9,223,372,036,854,775,808");
+    assert_eq!(us.format_decimal(999.999, 2), "1,000.00");
+    assert_eq!(us.format_time(0, 0), "12:00 AM");
+
+    let catalogs = Catalogs::new("sv");
+    let sv = catalogs.negotiate("da").locale();
+    assert_eq!(sv.format_date(2024, 3, 7), "2024-03-07");
+}
+
+#[test]
+fn request_into_parts() {
+    let req = crate::testing::MockRequest::post("/upload")
+        .query("a=1")
+        .header("X-Thing", "yes")
+        .body(vec![7u8; 4096])
+        .build()
+        .unwrap();
+
+    let parts = req.clone().into_parts();
+    assert_eq!(parts.vars.get("REQUEST_METHOD").unwrap(), "POST");
+    assert_eq!(parts.headers.get("x-thing").unwrap(), "yes");
+    assert!(parts.warnings.is_empty());
+    match parts.body {
+        crate::Body::Some(bytes) => assert_eq!(bytes.len(), 4096),
+        _ => panic!("body should be present"),
+    }
+    match req.into_query() {
+        crate::Query::Some(map) => assert_eq!(map.get("a").unwrap(), "1"),
+        _ => panic!("query should be present"),
+    }
+}
+
+#[test]
+fn time_zones() {
+    use crate::time::{DateTime, TimeZone};
+
+    // 2024-03-10 06:59:59 and 07:00:00 UTC straddle the US spring-forward.
+    let eastern = TimeZone::posix("EST5EDT,M3.2.0,M11.1.0").unwrap();
+    assert_eq!(eastern.to_local(1_710_053_999).unwrap().hour, 1);
+    assert_eq!(eastern.to_local(1_710_054_000).unwrap().hour, 3);
+    assert_eq!(eastern.abbreviation(1_710_054_000), Some("EDT"));
+
+    let sydney = TimeZone::posix("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+    assert_eq!(sydney.offset_at(1_704_067_200), Some(11 * 3600));
+    assert_eq!(sydney.offset_at(1_719_835_200), Some(10 * 3600));
+
+    let dt = DateTime::from_unix(951_782_400, 0).unwrap();
+    assert_eq!((dt.year, dt.month, dt.day, dt.weekday()), (2000, 2, 29, 2));
+    assert_eq!(dt.to_unix(), 951_782_400);
+
+    assert_eq!(TimeZone::parse("Z").unwrap(), TimeZone::utc());
+    assert_eq!(TimeZone::parse("+0930").unwrap().offset_at(0), Some(34_200));
+    assert_eq!(TimeZone::parse("Nowhere/Special").unwrap_err().code, 400);
+    assert_eq!(TimeZone::named("/etc/passwd").unwrap_err().code, 400);
+    // Multi-byte characters where digits should be.
+    for bad in ["+1€", "-a€", "UTC-a€", "+€", "+1:€"] {
+        assert_eq!(TimeZone::parse(bad).unwrap_err().code, 400);
+    }
+
+    // Too far from the epoch to have a local time.
+    assert_eq!(DateTime::from_unix(i64::MAX, 1), None);
+    assert_eq!(DateTime::from_unix(i64::MIN, -1), None);
+    assert_eq!(eastern.to_local(i64::MIN), None);
+    assert_eq!(sydney.offset_at(i64::MAX - 3600), None);
+    assert_eq!(TimeZone::utc().to_local(i64::MAX).unwrap().offset, 0);
+
+    // A header claiming far more data than the file holds.
+    let mut tzif = b"TZif2".to_vec();
+    tzif.extend_from_slice(&[0u8; 15]);
+    tzif.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    tzif.extend_from_slice(&[0xff; 12]);
+    tzif.extend_from_slice(&[0, 0, 0, 0]);
+    assert!(TimeZone::from_tzif("bogus", &tzif).is_err());
+    tzif[4] = 0;
+    assert!(TimeZone::from_tzif("bogus", &tzif).is_err());
+
+    // Only check the zoneinfo database where there is one.
+    if let Ok(chicago) = TimeZone::named("America/Chicago") {
+        assert_eq!(chicago.offset_at(1_719_835_200), Some(-5 * 3600));
+        assert_eq!(chicago.offset_at(1_704_067_200), Some(-6 * 3600));
+        // Far enough in the future to need the footer rule.
+        assert_eq!(chicago.abbreviation(4_102_444_800), Some("CST"));
+        assert_eq!(chicago.abbreviation(4_118_083_200), Some("CDT"));
+    }
+}
+
+#[test]
+fn param_precedence() {
+    use crate::testing::MockRequest;
+
+    let body = "--xyz\r\n\
+                Content-Disposition: form-data; name=\"title\"\r\n\
+                \r\n\
+                Hello\r\n\
+                --xyz\r\n\
+                Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+                \r\n\
+                file contents\r\n\
+                --xyz--\r\n";
+    let req = MockRequest::post("/")
+        .query("title=from+query&x=1")
+        .header("Content-Type", "multipart/form-data; boundary=xyz")
+        .body(body)
+        .build()
+        .unwrap();
+    assert_eq!(req.param("title").as_deref(), Some("from query"));
+    assert_eq!(req.param("x").as_deref(), Some("1"));
+    assert_eq!(req.param("upload"), None);
+
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=xyz")
+        .body(body)
+        .build()
+        .unwrap();
+    assert_eq!(req.param("title").as_deref(), Some("Hello"));
+
+    // Repeated fields take their last value, whatever the body's encoding.
+    let repeated = "--xyz\r\n\
+                    Content-Disposition: form-data; name=\"tag\"\r\n\
+                    \r\n\
+                    first\r\n\
+                    --xyz\r\n\
+                    Content-Disposition: form-data; name=\"tag\"\r\n\
+                    \r\n\
+                    second\r\n\
+                    --xyz--\r\n";
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=xyz")
+        .body(repeated)
+        .build()
+        .unwrap();
+    assert_eq!(req.param("tag").as_deref(), Some("second"));
+    let req = MockRequest::post("/")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("tag=first&tag=second")
+        .build()
+        .unwrap();
+    assert_eq!(req.param("tag").as_deref(), Some("second"));
+
+    // An undecodable pair only loses itself.
+    let req = MockRequest::post("/")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("a=1&bad=%zz&b=2")
+        .build()
+        .unwrap();
+    assert_eq!(req.param("a").as_deref(), Some("1"));
+    assert_eq!(req.param("b").as_deref(), Some("2"));
+    assert_eq!(req.param("bad"), None);
+
+    // The same goes for the query string, even though `query()` is an error.
+    let req = MockRequest::post("/")
+        .query("a=1&bad=%zz&page=3")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("page=9&b=2")
+        .build()
+        .unwrap();
+    assert!(matches!(req.query(), crate::Query::Err(_)));
+    assert_eq!(req.param("a").as_deref(), Some("1"));
+    assert_eq!(req.param("page").as_deref(), Some("3"));
+    assert_eq!(req.param("b").as_deref(), Some("2"));
+    assert_eq!(req.param("bad"), None);
+
+    // A body that isn't a form isn't searched.
+    let req = MockRequest::post("/")
+        .header("Content-Type", "text/plain")
+        .body("title=nope")
+        .build()
+        .unwrap();
+    assert_eq!(req.param("title"), None);
+}
+
+#[test]
+fn request_extensions() {
+    #[derive(Clone, Debug, PartialEq)]
+    struct User(String);
+
+    let mut req = crate::testing::MockRequest::get("/").build().unwrap();
+    assert!(req.extensions().is_empty());
+    assert_eq!(req.extensions_mut().insert(User("dan".to_owned())), None);
+    assert_eq!(req.extensions_mut().insert(7u32), None);
+    assert_eq!(req.extensions_mut().insert(8u32), Some(7));
+
+    req.extensions_mut().get_mut::<User>().unwrap().0.push('!');
+    let copy = req.clone();
+    assert_eq!(
+        req.extensions_mut().remove::<User>(),
+        Some(User("dan!".to_owned()))
+    );
+    assert!(!req.extensions().contains::<User>());
+    assert_eq!(
+        copy.extensions().get::<User>(),
+        Some(&User("dan!".to_owned()))
+    );
+    assert_eq!(copy.into_parts().extensions.get::<u32>(), Some(&8));
+}
+
+#[test]
+fn flags_rollout() {
+    use crate::flags::Flags;
+    use crate::testing::MockRequest;
+
+    let mut flags = Flags::parse("# flags\n\nhalf = 50%\nnone = 0%\nall = on\n").unwrap();
+    assert!(Flags::parse("oops = 101%").is_err());
+    assert!(Flags::parse("just a name").is_err());
+
+    let ids: Vec<String> = (0..1000).map(|n| format!("user{}", n)).collect();
+    let count =
+        |flags: &Flags, name: &str| ids.iter().filter(|id| flags.enabled_for(name, id)).count();
+    let half = count(&flags, "half");
+    assert!((400..600).contains(&half), "{} of 1000 enabled", half);
+    assert_eq!(count(&flags, "none"), 0);
+    assert_eq!(count(&flags, "all"), 1000);
+
+    // Raising the percentage only adds users.
+    let before: Vec<bool> = ids.iter().map(|id| flags.enabled_for("half", id)).collect();
+    flags.set("half", 75.0);
+    for (id, was) in ids.iter().zip(before) {
+        assert!(!was || flags.enabled_for("half", id));
+    }
+
+    let flags = flags.with_cookie("session");
+    let req = MockRequest::get("/")
+        .header("Cookie", "session=user7")
+        .var("REMOTE_ADDR", "10.0.0.1")
+        .build()
+        .unwrap();
+    assert_eq!(
+        flags.enabled("half", &req),
+        flags.enabled_for("half", "user7")
+    );
+    let anon = MockRequest::get("/").build().unwrap();
+    assert!(!flags.enabled("half", &anon));
+    assert!(flags.enabled("all", &anon));
+}
+
+#[test]
+fn ab_bucketing() {
+    use crate::flags::{bucket, RolloutId};
+    use crate::testing::MockRequest;
+
+    let mut counts = [0usize; 4];
+    for n in 0..400 {
+        let mut req = MockRequest::get("/").build().unwrap();
+        req.extensions_mut().insert(RolloutId(format!("user{}", n)));
+        let a = bucket(&req, "exp", 4);
+        assert_eq!(a, bucket(&req, "exp", 4));
+        assert!(a.is_new());
+        counts[a.variant as usize] += 1;
+    }
+    assert!(counts.iter().all(|c| *c > 50), "{:?}", counts);
+
+    // A cookie for a variant that no longer exists is replaced.
+    let req = MockRequest::get("/")
+        .header("Cookie", "ab_my_exp=7")
+        .build()
+        .unwrap();
+    let a = bucket(&req, "my.exp", 2);
+    assert!(a.variant < 2);
+    let headers = a.headers();
+    assert_eq!(headers[0].0, "Set-Cookie");
+    assert_eq!(
+        headers[0].1,
+        format!(
+            "ab_my_exp={}; Path=/; Max-Age=31536000; SameSite=Lax",
+            a.variant
+        )
+    );
+}
+
+#[test]
+fn header_params() {
+    use crate::header_param;
+
+    let cd = "form-data; filename=\"semi;colon \\\\ \\\"q\\\".txt\"; NAME = bare ; flag; x=\"unterminated";
+    assert_eq!(
+        header_param(cd, "filename").as_deref(),
+        Some("semi;colon \\ \"q\".txt")
+    );
+    assert_eq!(header_param(cd, "name").as_deref(), Some("bare"));
+    assert_eq!(header_param(cd, "flag"), None);
+    assert_eq!(header_param(cd, "x").as_deref(), Some("unterminated"));
+    assert_eq!(header_param("form-data", "name"), None);
+    assert_eq!(
+        header_param("form-data; name=\"\"", "name").as_deref(),
+        Some("")
+    );
+}
+
+#[test]
+fn config_cache() {
+    use crate::config::Config;
+
+    let scratch = Scratch::new("config");
+    let dir = scratch.path();
+    let path = dir.join("site.ini");
+    let cache = dir.join("site.ini.cache");
+
+    std::fs::write(&path, "a = 1\n[s]\nb = \"two words\"\n").unwrap();
+    let config = Config::load_cached(&path).unwrap();
+    assert_eq!(config.get("s.b"), Some("two words"));
+    assert!(cache.exists());
+    assert_eq!(Config::load_cached(&path).unwrap(), config);
+
+    // A cache that matches the file's stamp is trusted, which shows it's used.
+    let mut doctored = config.clone();
+    doctored.set("a", "cached");
+    let stamp = crate::cachefile::source_stamp(&std::fs::metadata(&path).unwrap());
+    doctored.to_cache(stamp).write(&cache).unwrap();
+    assert_eq!(Config::load_cached(&path).unwrap().get("a"), Some("cached"));
+
+    // Changing the file invalidates the cache.
+    std::fs::write(&path, "a = 2\n").unwrap();
+    assert_eq!(Config::load_cached(&path).unwrap().get("a"), Some("2"));
+
+    // A corrupt cache is ignored.
+    std::fs::write(&cache, b"DCGICFG1 garbage").unwrap();
+    assert_eq!(Config::load_cached(&path).unwrap().get("a"), Some("2"));
+
+    assert!(Config::parse("[unclosed\n").is_err());
+    assert!(Config::parse("no equals sign\n").is_err());
+}
+
+#[test]
+fn multipart_extended_filenames() {
+    use std::collections::HashMap;
+
+    let part = |cd: &str| crate::MultipartPart {
+        headers: HashMap::from([("content-disposition".to_owned(), cd.to_owned())]),
+        body: Vec::new(),
+    };
+    let name = |cd: &str| part(cd).filename();
+
+    assert_eq!(
+        name("attachment; filename*=utf-8''%E2%82%AC%20rates+fees.txt").as_deref(),
+        Some("€ rates+fees.txt")
+    );
+    assert_eq!(
+        name("form-data; filename*=ISO-8859-1'de'%E4rger.txt").as_deref(),
+        Some("ärger.txt")
+    );
+    // Undecodable extended values fall back to the plain parameter.
+    assert_eq!(
+        name("form-data; filename*=KOI8-R''%C1; filename=\"fallback.txt\"").as_deref(),
+        Some("fallback.txt")
+    );
+    assert_eq!(
+        name("form-data; filename=\"fallback.txt\"; filename*=UTF-8''%FF").as_deref(),
+        Some("fallback.txt")
+    );
+    assert_eq!(name("form-data; filename*=UTF-8''%4"), None);
+}
+
+#[test]
+fn multipart_part_text() {
+    use crate::{MediaType, MultipartPart};
+    use std::collections::HashMap;
+
+    let part = |ct: Option<&str>, body: &[u8]| MultipartPart {
+        headers: ct
+            .map(|ct| HashMap::from([("content-type".to_owned(), ct.to_owned())]))
+            .unwrap_or_default(),
+        body: body.to_vec(),
+    };
+
+    assert_eq!(part(None, "héllo".as_bytes()).text().unwrap(), "héllo");
+    assert_eq!(part(None, b"\xff").text().unwrap_err().code, 400);
+    assert_eq!(
+        part(Some("text/plain; charset=US-ASCII"), b"ok")
+            .text()
+            .unwrap(),
+        "ok"
+    );
+    assert!(part(Some("text/plain; charset=us-ascii"), b"\xe9")
+        .text()
+        .is_err());
+    assert!(part(Some("text/plain; charset=klingon"), b"x")
+        .text()
+        .is_err());
+
+    let p = part(Some("image/PNG; name=\"a b.png\""), b"");
+    let mt = p.content_type().unwrap();
+    assert_eq!(mt.essence(), "image/png");
+    assert_eq!(mt.to_string(), "image/png; name=\"a b.png\"");
+    assert_eq!(MediaType::parse(&mt.to_string()), Some(mt));
+    assert!(part(Some("garbage"), b"").content_type().is_none());
+}
+
+#[test]
+fn request_timings() {
+    use crate::{RequestOptions, Response};
+    use std::time::Duration;
+
+    let opts = RequestOptions::new().with_timings(true);
+    let body = "--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--xyz--\r\n";
+    let mut req = crate::testing::MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=xyz")
+        .body(body)
+        .options(opts)
+        .build()
+        .unwrap();
+    let phases: Vec<&str> = req.timings().iter().map(|(name, _)| name).collect();
+    assert_eq!(phases, ["env", "body-read", "body-parse"]);
+
+    req.timings_mut()
+        .record("handler", Duration::from_millis(2));
+    req.timings_mut()
+        .record("handler", Duration::from_millis(3));
+    assert_eq!(req.timings().get("handler"), Some(Duration::from_millis(5)));
+    let header = req.timings().server_timing();
+    assert!(
+        header.contains("handler;dur=5.000, total;dur="),
+        "{}",
+        header
+    );
+
+    let mut req = crate::testing::MockRequest::get("/")
+        .options(opts)
+        .build()
+        .unwrap();
+    assert!(req.timings().get("body-read").is_none());
+    let response = req.time_handler(|req| {
+        std::thread::sleep(Duration::from_millis(1));
+        Response::new(200).with_body(req.method().to_owned())
+    });
+    assert!(req.timings().get("handler").unwrap() >= Duration::from_millis(1));
+    let response = response.with_server_timing(req.timings());
+    assert!(response
+        .get_header("Server-Timing")
+        .unwrap()
+        .starts_with("env;dur="));
+
+    // Without asking, nothing is recorded or sent.
+    let mut req = crate::testing::MockRequest::post("/")
+        .body("x")
+        .build()
+        .unwrap();
+    assert!(!req.timings().is_enabled());
+    let response = req.time_handler(|_| Response::new(204));
+    assert_eq!(req.timings().iter().count(), 0);
+    let response = response.with_server_timing(req.timings());
+    assert_eq!(response.get_header("Server-Timing"), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_environment() {
+    use crate::{Request, Warning};
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    let env = vec![
+        (
+            OsString::from("path_info"),
+            OsString::from_vec(b"/caf\xe9".to_vec()),
+        ),
+        (
+            OsString::from("HTTP_X_NAME"),
+            OsString::from_vec(b"\xff!".to_vec()),
+        ),
+        (OsString::from("QUERY_STRING"), OsString::from("a=1")),
+    ];
+    let req = Request::from_env(env, std::io::empty()).unwrap();
+
+    assert_eq!(req.var("PATH_INFO"), Some("/caf\u{fffd}"));
+    assert_eq!(req.header("x-name"), Some("\u{fffd}!"));
+    assert!(req
+        .warnings()
+        .contains(&Warning::NonUtf8Var("PATH_INFO".to_owned())));
+    assert!(req
+        .warnings()
+        .contains(&Warning::NonUtf8Header("x-name".to_owned())));
+
+    let mut vars: Vec<(&str, &str)> = req.vars().collect();
+    vars.sort();
+    assert_eq!(
+        vars,
+        [("PATH_INFO", "/caf\u{fffd}"), ("QUERY_STRING", "a=1")]
+    );
+    assert_eq!(
+        req.into_parts().vars.get("PATH_INFO").unwrap(),
+        "/caf\u{fffd}"
+    );
+}
+
+#[test]
+fn multipart_form_lookup() {
+    use crate::{MultipartForm, MultipartPart};
+    use std::collections::HashMap;
+
+    let part = |cd: &str, body: &str| MultipartPart {
+        headers: HashMap::from([("content-disposition".to_owned(), cd.to_owned())]),
+        body: body.as_bytes().to_vec(),
+    };
+    let form = MultipartForm::from(vec![
+        part("form-data; name=\"b\"", "1"),
+        part("form-data; name=\"a\"; filename=\"x.txt\"", "file"),
+        part("form-data; name=\"a\"", "2"),
+    ]);
+
+    // Lookups see files and fields alike, in order.
+    assert_eq!(form.get("a").unwrap().body, b"file");
+    assert_eq!(form.get_all("a").count(), 2);
+    assert!(form.get("c").is_none());
+    let names: Vec<String> = form.iter().filter_map(|p| p.name()).collect();
+    assert_eq!(names, ["b", "a", "a"]);
+    assert_eq!(form.into_parts().len(), 3);
+}
+
+#[test]
+fn content_store() {
+    use crate::store::ContentStore;
+
+    let scratch = Scratch::new("store");
+    let dir = scratch.path();
+    let store = ContentStore::new(dir);
+
+    let a = store.put(b"first").unwrap();
+    let b = store.put(b"second").unwrap();
+    assert_ne!(a, b);
+    assert_eq!(store.put(b"first").unwrap(), a);
+    assert_eq!(store.refs(&a).unwrap(), 2);
+    assert_eq!(store.refs(&b).unwrap(), 1);
+
+    let linked = dir.join("linked.txt");
+    store.link_to(&a, &linked).unwrap();
+    assert_eq!(std::fs::read(&linked).unwrap(), b"first");
+
+    assert_eq!(store.release(&b).unwrap(), 0);
+    assert_eq!(store.get(&b).unwrap_err().code, 404);
+    assert_eq!(store.release(&a).unwrap(), 1);
+    assert_eq!(store.release(&a).unwrap(), 0);
+    // The hard link outlives the stored file.
+    assert_eq!(std::fs::read(&linked).unwrap(), b"first");
+
+    // Ids that could name other files are refused.
+    assert_eq!(store.get("../../etc/passwd").unwrap_err().code, 404);
+    assert!(!store.contains(&"A".repeat(64)));
+}
+
+#[test]
+fn streaming_multipart() {
+    use std::io::Read;
+
+    use crate::testing::MockRequest;
+    use crate::{parse_multipart, Body, MultipartReader};
+
+    // Hands out its data a few bytes at a time, to exercise refilling.
+    struct Trickle<'a>(&'a [u8]);
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    let big: Vec<u8> = (0..5000u32).map(|n| (n % 251) as u8).collect();
+    let mut body: Vec<u8> = b"preamble\r\n--bnd\r\n\
+        Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+        short\r\n--bnd\r\n\
+        Content-Disposition: form-data; name=\"f\"; filename=\"x.bin\"\r\n\r\n"
+        .to_vec();
+    body.extend_from_slice(&big);
+    // Almost a boundary, inside a part.
+    body.extend_from_slice(
+        b"\r\n--bn\r\n--bnd\r\n\
+        Content-Disposition: form-data; name=\"skipped\"\r\n\r\n\
+        never read\r\n--bnd--\r\nepilogue",
+    );
+
+    let expected = match parse_multipart(&body, "bnd") {
+        Body::Multipart(form) => form.into_parts(),
+        b => panic!("expected multipart body, got {:?}", b),
+    };
+
+    let mut reader = MultipartReader::new(Trickle(&body), "bnd").with_buffer_size(64);
+    let mut parts = Vec::new();
+    while let Some(part) = reader.next_part().unwrap() {
+        if part.name().as_deref() == Some("skipped") {
+            continue;
+        }
+        parts.push(part.into_part().unwrap());
+    }
+    assert_eq!(parts.len(), 2);
+    for (got, want) in parts.iter().zip(expected.iter()) {
+        assert_eq!(got.headers, want.headers);
+        assert_eq!(got.body, want.body);
+    }
+    let mut want_f = big.clone();
+    want_f.extend_from_slice(b"\r\n--bn");
+    assert_eq!(parts[1].body, want_f);
+    assert!(reader.next_part().unwrap().is_none());
+
+    // A truncated body is an error, not a short part.
+    let truncated = &body[..body.len() / 2];
+    let mut reader = MultipartReader::new(truncated, "bnd");
+    reader.next_part().unwrap().unwrap().into_part().unwrap();
+    let part = reader.next_part().unwrap().unwrap();
+    assert_eq!(part.into_part().unwrap_err().code, 400);
+
+    // From a request whose body was left unread.
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=bnd")
+        .header("Content-Length", body.len().to_string())
+        .build()
+        .unwrap();
+    let mut reader = MultipartReader::for_request(&req, body.as_slice()).unwrap();
+    let mut part = reader.next_part().unwrap().unwrap();
+    let mut text = String::new();
+    part.read_to_string(&mut text).unwrap();
+    assert_eq!(text, "short");
+    let req = MockRequest::get("/").build().unwrap();
+    assert!(MultipartReader::for_request(&req, body.as_slice()).is_err());
+}
+
+#[test]
+fn gc_expires_old_files() {
+    use std::time::Duration;
+
+    use crate::gc::Gc;
+
+    let scratch = Scratch::new("gc");
+    let dir = scratch.path();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("a"), "12345").unwrap();
+    std::fs::write(dir.join("sub/b"), "123").unwrap();
+
+    // Nothing is an hour old yet.
+    let report = Gc::new().with_dir(dir, Duration::from_secs(3600)).run();
+    assert_eq!(report.removed, 0);
+
+    // A directory somebody else is collecting is skipped.
+    let lock = dir.join(".dumb_cgi_gc.lock");
+    let held = crate::lockfile::LockGuard::try_acquire(&lock)
+        .unwrap()
+        .unwrap();
+    let gc = Gc::new()
+        .with_dir(dir, Duration::ZERO)
+        .with_dir(dir.join("missing"), Duration::ZERO);
+    assert_eq!(gc.run().skipped, vec![dir]);
+    drop(held);
+
+    let report = gc.maybe_run(1).unwrap();
+    assert_eq!(report.removed, 2);
+    assert_eq!(report.bytes, 8);
+    assert!(report.errors.is_empty());
+    assert!(dir.join("sub").is_dir());
+    assert!(!lock.exists());
+}
+
+#[test]
+fn sanitize_windows_names() {
+    use crate::sanitize_filename;
+
+    for (name, expected) in [
+        ("CON", "_CON"),
+        ("nul.txt", "_nul.txt"),
+        ("Com1.tar.gz", "_Com1.tar.gz"),
+        ("lpt9", "_lpt9"),
+        ("COM\u{b9}.log", "_COM\u{b9}.log"),
+        ("aux .txt", "_aux .txt"),
+        ("CON.", "_CON"),
+        ("conout$", "_conout$"),
+        ("COM0", "COM0"),
+        ("COM10.txt", "COM10.txt"),
+        ("console.txt", "console.txt"),
+        ("report. . ", "report"),
+        ("report.txt...", "report.txt"),
+    ] {
+        assert_eq!(sanitize_filename(name), expected, "{:?}", name);
+    }
+
+    // The prefix doesn't push a long name past the limit.
+    let long = format!("nul.{}", "x".repeat(300));
+    let clean = sanitize_filename(&long);
+    assert!(clean.starts_with("_nul."));
+    assert_eq!(clean.len(), 200);
+}
+
+#[test]
+fn save_uploaded_files() {
+    use crate::testing::MockRequest;
+    use crate::Body;
+
+    let scratch = Scratch::new("uploads");
+    let dir = scratch.path();
+
+    let body = "--b\r\n\
+                Content-Disposition: form-data; name=\"title\"\r\n\
+                \r\n\
+                not a file\r\n\
+                --b\r\n\
+                Content-Disposition: form-data; name=\"f\"; filename=\"/tmp/../a.txt\"\r\n\
+                \r\n\
+                first\r\n\
+                --b\r\n\
+                Content-Disposition: form-data; name=\"f\"; filename=\"a.txt\"\r\n\
+                \r\n\
+                second!\r\n\
+                --b--\r\n";
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=b")
+        .body(body)
+        .build()
+        .unwrap();
+    let form = match req.body() {
+        Body::Multipart(form) => form,
+        b => panic!("expected multipart body, got {:?}", b),
+    };
+
+    let saved = form.save_files(dir).unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].path, dir.join("a.txt"));
+    assert_eq!(saved[0].filename.as_deref(), Some("/tmp/../a.txt"));
+    assert_eq!(saved[1].path, dir.join("a-1.txt"));
+    assert_eq!(saved[1].size, 7);
+    assert_eq!(std::fs::read(&saved[1].path).unwrap(), b"second!");
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 2);
+
+    // A missing directory fails, leaving nothing behind.
+    assert_eq!(form.save_files(dir.join("missing")).unwrap_err().code, 500);
+}
+
+#[test]
+fn maintenance_run_all() {
+    use crate::config::Config;
+    use crate::maintenance::run_all;
+
+    let scratch = Scratch::new("maint");
+    let dir = scratch.path();
+    let (scratch, cache) = (dir.join("scratch"), dir.join("cache"));
+    std::fs::create_dir_all(&scratch).unwrap();
+    std::fs::create_dir_all(&cache).unwrap();
+    std::fs::write(scratch.join("old"), "x").unwrap();
+    let log = dir.join("app.log");
+    std::fs::write(&log, "0123456789").unwrap();
+    std::fs::write(dir.join("app.log.1"), "older").unwrap();
+    for name in ["a", "b", "c"] {
+        std::fs::write(cache.join(name), "1234").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    let config = Config::parse(&format!(
+        "[gc]\nscratch = {} 0s\n[rotate]\nlog = {} 5 1\n[cache]\nc = {} 8\n",
+        scratch.display(),
+        log.display(),
+        cache.display()
+    ))
+    .unwrap();
+    let report = run_all(&config).unwrap();
+    assert!(report.is_ok(), "{}", report);
+    assert_eq!(report.gc.removed, 1);
+    assert_eq!(report.rotated, vec![log.clone()]);
+    assert!(!log.exists());
+    assert_eq!(std::fs::read(dir.join("app.log.1")).unwrap(), b"0123456789");
+    assert!(!dir.join("app.log.2").exists());
+    // The oldest cache file goes first.
+    assert_eq!(report.pruned.removed, 1);
+    assert!(!cache.join("a").exists() && cache.join("c").exists());
+    assert!(report.to_string().contains("cache.bytes: 4"));
+
+    let bad = Config::parse("[rotate]\nlog = /tmp/x.log huge").unwrap();
+    assert_eq!(run_all(&bad).unwrap_err().code, 500);
+}
+
+#[test]
+fn multipart_limits() {
+    use crate::testing::MockRequest;
+    use crate::{Body, MultipartLimits, MultipartReader};
+
+    let mut body = String::new();
+    for n in 0..5 {
+        body.push_str(&format!(
+            "--b\r\nContent-Disposition: form-data; name=\"f{}\"\r\n\r\n{}\r\n",
+            n,
+            "x".repeat(n * 10)
+        ));
+    }
+    body.push_str("--b--\r\n");
+    let build = |limits: MultipartLimits| {
+        MockRequest::post("/")
+            .header("Content-Type", "multipart/form-data; boundary=b")
+            .body(body.clone())
+            .multipart_limits(limits)
+            .build()
+            .unwrap()
+    };
+    let code = |limits: MultipartLimits| match build(limits).body() {
+        Body::Err(e) => Some(e.code),
+        Body::Multipart(_) => None,
+        b => panic!("unexpected body {:?}", b),
+    };
+
+    assert_eq!(code(MultipartLimits::new()), None);
+    assert_eq!(code(MultipartLimits::new().with_max_parts(5)), None);
+    assert_eq!(code(MultipartLimits::new().with_max_parts(4)), Some(413));
+    assert_eq!(code(MultipartLimits::new().with_max_part_size(40)), None);
+    assert_eq!(
+        code(MultipartLimits::new().with_max_part_size(39)),
+        Some(413)
+    );
+    assert_eq!(
+        code(MultipartLimits::new().with_max_header_bytes(20)),
+        Some(400)
+    );
+
+    // The streaming reader enforces the same limits.
+    let limits = MultipartLimits::new().with_max_part_size(25);
+    let mut reader = MultipartReader::new(body.as_bytes(), "b").with_limits(limits);
+    for _ in 0..3 {
+        reader.next_part().unwrap().unwrap().into_part().unwrap();
+    }
+    let part = reader.next_part().unwrap().unwrap();
+    assert_eq!(part.into_part().unwrap_err().code, 413);
+
+    let limits = MultipartLimits::new().with_max_parts(2);
+    let mut reader = MultipartReader::new(body.as_bytes(), "b").with_limits(limits);
+    reader.next_part().unwrap().unwrap();
+    reader.next_part().unwrap().unwrap();
+    assert_eq!(reader.next_part().err().unwrap().code, 413);
+}
+
+#[test]
+fn audit_log_rotation() {
+    use std::time::{Duration, SystemTime};
+
+    use crate::audit::AuditLog;
+    use crate::rotate::Rotation;
+
+    let scratch = Scratch::new("rotation");
+    let dir = scratch.path();
+    let path = dir.join("audit.log");
+    let old = |n: u32| dir.join(format!("audit.log.{}", n));
+
+    // Every append after the first finds the file too big.
+    let log = AuditLog::new(&path).with_rotation(Rotation::new().with_max_bytes(1).with_keep(2));
+    for n in 0..4 {
+        log.append("dan", &format!("action {}", n)).unwrap();
+    }
+    // The chain runs on through the files; the oldest kept one starts
+    // part way along (its predecessor has been discarded).
+    assert_eq!(log.records().unwrap()[0].sequence, 4);
+    assert_eq!(log.verify().unwrap(), 3);
+    assert_eq!(
+        AuditLog::new(old(1)).records().unwrap()[0].action,
+        "action 2"
+    );
+    assert!(old(2).exists());
+    assert!(!old(3).exists());
+
+    // A missing old file is caught...
+    let saved = std::fs::read(old(1)).unwrap();
+    std::fs::remove_file(old(1)).unwrap();
+    assert!(log.verify().unwrap_err().details.contains("missing"));
+    std::fs::write(old(1), &saved).unwrap();
+    // ...and so is a log file replaced with a fresh chain.
+    let saved = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    AuditLog::new(&path)
+        .append("eve", "nothing to see")
+        .unwrap();
+    assert!(log.verify().is_err());
+    std::fs::write(&path, &saved).unwrap();
+    assert_eq!(log.verify().unwrap(), 3);
+
+    // Rotated by somebody else, the chain still carries on.
+    assert!(Rotation::new()
+        .with_max_bytes(1)
+        .with_keep(2)
+        .rotate(&path)
+        .unwrap());
+    assert!(!path.exists());
+    assert_eq!(log.append("dan", "action 4").unwrap().sequence, 5);
+    assert_eq!(log.verify().unwrap(), 3);
+
+    // Rotating an audit log away without keeping it is refused.
+    let discard =
+        AuditLog::new(&path).with_rotation(Rotation::new().with_max_bytes(1).with_keep(0));
+    assert_eq!(discard.append("dan", "action 5").unwrap_err().code, 500);
+    assert_eq!(log.verify().unwrap(), 3);
+
+    // A file last written in an earlier period is rotated.
+    let f = std::fs::File::options().write(true).open(&path).unwrap();
+    f.set_modified(SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60))
+        .unwrap();
+    drop(f);
+    let daily = Rotation::new().with_period(Duration::from_secs(24 * 60 * 60));
+    assert!(daily.rotate(&path).unwrap());
+    assert!(!path.exists());
+    assert!(!daily.rotate(&path).unwrap());
+}
+
+#[test]
+fn tenants() {
+    use crate::tenant::Tenant;
+    use crate::testing::MockRequest;
+
+    let host = |h: &str| {
+        let req = MockRequest::get("/").header("Host", h).build().unwrap();
+        Tenant::from_host(&req).map(|t| t.id().to_owned())
+    };
+    assert_eq!(host("example.com").unwrap(), "example.com");
+    assert_eq!(host("EXAMPLE.com.:443").unwrap(), "example.com");
+    assert_eq!(host("[::1]:8080").unwrap_err().code, 400);
+    assert_eq!(host("../etc").unwrap_err().code, 400);
+    assert_eq!(host("..").unwrap_err().code, 400);
+
+    let req = MockRequest::get("/")
+        .var("SERVER_NAME", "fallback.test")
+        .build()
+        .unwrap();
+    assert_eq!(Tenant::from_host(&req).unwrap().id(), "fallback.test");
+
+    // Only known hosts, if they're given.
+    let known = |h: &str| {
+        let req = MockRequest::get("/").header("Host", h).build().unwrap();
+        Tenant::from_known_host(&req, &["Example.com", "shop.example.com"])
+    };
+    assert_eq!(known("EXAMPLE.com:443").unwrap().id(), "example.com");
+    assert_eq!(known("evil.example.com").unwrap_err().code, 400);
+    assert_eq!(known("../etc").unwrap_err().code, 400);
+
+    let req = MockRequest::get("/acme").build().unwrap();
+    let tenant = Tenant::from_path_prefix(&req).unwrap();
+    assert_eq!(tenant.path_info(&req), "/");
+    assert_eq!(tenant.cookie_name("auth"), "acme~auth");
+    // Ids that differ only in `.` and `_`, or in where the id ends and
+    // the cookie's own name starts, don't share cookies.
+    let a = Tenant::new("a.b").unwrap();
+    let b = Tenant::new("a_b").unwrap();
+    let c = Tenant::new("a").unwrap();
+    assert_ne!(a.cookie_name("s"), b.cookie_name("s"));
+    assert_ne!(b.cookie_name("s"), c.cookie_name("b_s"));
+    assert_ne!(a.cookie_name("s"), c.cookie_name("b.s"));
+    let req = MockRequest::get("/").build().unwrap();
+    assert_eq!(Tenant::from_path_prefix(&req).unwrap_err().code, 404);
+
+    let scratch = Scratch::new("tenant");
+    let dir = scratch.path();
+    let log = tenant.audit_log(dir, "audit.log").unwrap();
+    assert_eq!(log.path(), dir.join("acme/audit.log"));
+    assert!(dir.join("acme").is_dir());
+}
+
+#[test]
+fn zero_copy_multipart() {
+    use crate::testing::MockRequest;
+    use crate::{parse_multipart, Body, MultipartBuffer, MultipartLimits};
+
+    let body: &[u8] = b"preamble\r\n--bnd\r\n\
+        Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+        one\r\n--bnd\r\n\
+        no blank line\r\n--bnd\r\n\
+        Content-Disposition: form-data; name=\"f\"; filename=\"x.bin\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n\
+        \x00\x01\r\n\x02\r\n--bnd--\r\nepilogue";
+
+    let buf = MultipartBuffer::parse(body.to_vec(), "bnd").unwrap();
+    let form = match parse_multipart(body, "bnd") {
+        Body::Multipart(form) => form,
+        x => panic!("expected multipart, got {:?}", x),
+    };
+    // The same parts as the copying parser finds.
+    assert_eq!(buf.parts().len(), form.len());
+    for ((range, bytes), part) in buf.iter().zip(form.iter()) {
+        assert_eq!(range.headers, part.headers);
+        assert_eq!(bytes, part.body.as_slice());
+    }
+    assert_eq!(buf.get("a"), Some(&b"one"[..]));
+    assert_eq!(buf.get_all("a").count(), 1);
+    assert_eq!(buf.get_all("nope").count(), 0);
+    assert_eq!(buf.get("f"), Some(&b"\x00\x01\r\n\x02"[..]));
+    assert!(buf.skipped_parts().is_empty());
+    let strict = MultipartLimits::new().with_strict(true);
+    let strict_buf = MultipartBuffer::parse_with_limits(body.to_vec(), "bnd", &strict).unwrap();
+    assert_eq!(strict_buf.parts().len(), buf.parts().len() - 1);
+    assert_eq!(strict_buf.skipped_parts().len(), 1);
+    assert_eq!(buf.preamble(), b"preamble");
+    assert_eq!(buf.epilogue(), b"epilogue");
+    assert_eq!(buf.into_bytes(), body);
+
+    let limits = MultipartLimits::new().with_max_part_size(4);
+    let e = MultipartBuffer::parse_with_limits(body.to_vec(), "bnd", &limits).unwrap_err();
+    assert_eq!(e.code, 413);
+
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=bnd")
+        .header("Content-Length", body.len().to_string())
+        .build()
+        .unwrap();
+    let buf = MultipartBuffer::for_request(&req, body, &MultipartLimits::default()).unwrap();
+    assert_eq!(buf.parts().len(), 3);
+    let short = &body[..body.len() - 1];
+    let e = MultipartBuffer::for_request(&req, short, &MultipartLimits::default()).unwrap_err();
+    assert_eq!(e.code, 400);
+}
+
+#[test]
+fn boundary_search() {
+    use crate::request::{slicey_find, Finder};
+
+    fn naive(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        if needle.is_empty() {
+            return None;
+        }
+        haystack.windows(needle.len()).position(|w| w == needle)
+    }
+
+    // A small alphabet makes near-misses common.
+    let mut x: u32 = 12345;
+    let haystack: Vec<u8> = (0..20_000)
+        .map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            b"ab-\r\n"[(x >> 16) as usize % 5]
+        })
+        .collect();
+    let needles: [&[u8]; 7] = [
+        b"",
+        b"a",
+        b"\r\n",
+        b"\r\n-",
+        b"\r\n--ab",
+        b"ab-ab\r\na",
+        b"zzzz",
+    ];
+    for needle in needles {
+        let finder = Finder::new(needle);
+        for start in [0, 1, 7, 19_990, 20_000] {
+            let hay = &haystack[start..];
+            assert_eq!(
+                finder.find(hay),
+                naive(hay, needle),
+                "{:?} at {}",
+                needle,
+                start
+            );
+            assert_eq!(slicey_find(hay, needle), naive(hay, needle));
+        }
+    }
+}
+
+#[test]
+fn canonical_paths() {
+    use crate::canonical::{PathPolicy, TrailingSlash};
+    use crate::testing::MockRequest;
+
+    let strip = PathPolicy::new().with_trailing_slash(TrailingSlash::Strip);
+    assert_eq!(strip.normalize("/a/b//"), "/a/b");
+    assert_eq!(strip.normalize("/"), "/");
+    assert_eq!(strip.normalize("//"), "/");
+    assert_eq!(strip.normalize(""), "");
+    let add = PathPolicy::new().with_trailing_slash(TrailingSlash::Add);
+    assert_eq!(add.normalize("/a/b"), "/a/b/");
+    assert_eq!(add.normalize("/a/b.txt"), "/a/b.txt");
+    let lower = PathPolicy::new().with_lowercase(true);
+    assert_eq!(lower.normalize("/ÄB/c/"), "/äb/c/");
+
+    // Redirects encode the path, and keep the query string.
+    let req = MockRequest::get("/Two Words").build().unwrap();
+    let redirect = lower.check(&req).unwrap_err();
+    assert_eq!(redirect.get_status(), 301);
+    assert_eq!(redirect.get_header("location"), Some("/two%20words"));
+
+    // Matching instead of redirecting.
+    let req = MockRequest::get("/A/").build().unwrap();
+    let policy = strip.with_lowercase(true).with_redirect(false);
+    assert_eq!(policy.check(&req).unwrap(), "/a");
+    let req = MockRequest::get("/").build().unwrap();
+    assert_eq!(strip.check(&req).unwrap(), "/");
+}
+
+#[test]
+fn method_override() {
+    use crate::testing::MockRequest;
+
+    let mut req = MockRequest::post("/")
+        .header("X-HTTP-Method-Override", "put")
+        .build()
+        .unwrap();
+    assert_eq!(req.method(), "POST");
+    assert_eq!(req.apply_method_override(), Some("PUT"));
+    assert_eq!(req.method(), "PUT");
+
+    // Only POSTs, only from the body, and only to certain methods.
+    let mut req = MockRequest::get("/")
+        .header("X-HTTP-Method-Override", "DELETE")
+        .build()
+        .unwrap();
+    assert_eq!(req.apply_method_override(), None);
+    assert_eq!(req.method(), "GET");
+    let mut req = MockRequest::post("/")
+        .query("_method=DELETE")
+        .build()
+        .unwrap();
+    assert_eq!(req.apply_method_override(), None);
+    let mut req = MockRequest::post("/")
+        .header("X-Method-Override", "CONNECT")
+        .build()
+        .unwrap();
+    assert_eq!(req.apply_method_override(), None);
+
+    let body = "--b\r\nContent-Disposition: form-data; name=\"_method\"\r\n\r\npatch\r\n--b--\r\n";
+    let mut req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=b")
+        .body(body)
+        .build()
+        .unwrap();
+    assert_eq!(req.apply_method_override(), Some("PATCH"));
+    assert_eq!(req.original_method(), "POST");
+}
+
+#[test]
+fn multipart_sinks() {
+    use std::io::Write;
+
+    use crate::{MultipartLimits, MultipartReader, StreamedPart};
+
+    let body = "--b\r\n\
+        Content-Disposition: form-data; name=\"f\"; filename=\"a.txt\"\r\n\r\n\
+        0123456789\r\n--b\r\n\
+        Content-Disposition: form-data; name=\"g\"\r\n\r\n\
+        field\r\n--b--\r\n";
+
+    let mut reader = MultipartReader::new(body.as_bytes(), "b");
+    let parts = reader
+        .stream_to_sinks(|h| {
+            Ok(h.get("content-disposition")
+                .filter(|cd| cd.contains("filename"))
+                .map(|_| Vec::new()))
+        })
+        .unwrap();
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].filename().as_deref(), Some("a.txt"));
+    assert_eq!(parts[1].name().as_deref(), Some("g"));
+    assert!(matches!(&parts[1], StreamedPart::Buffered(p) if p.body == b"field"));
+
+    // Limits still apply to parts going to sinks.
+    let limits = MultipartLimits::new().with_max_part_size(5);
+    let mut reader = MultipartReader::new(body.as_bytes(), "b").with_limits(limits);
+    let e = reader
+        .stream_to_sinks(|_| Ok(Some(Vec::new())))
+        .unwrap_err();
+    assert_eq!(e.code, 413);
+
+    // As do errors from the sink itself.
+    #[derive(Debug)]
+    struct Full;
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk full"))
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut reader = MultipartReader::new(body.as_bytes(), "b");
+    let e = reader.stream_to_sinks(|_| Ok(Some(Full))).unwrap_err();
+    assert_eq!(e.code, 500);
+    assert!(e.details.contains("disk full"));
+
+    // The sink can refuse a part, too.
+    let mut reader = MultipartReader::new(body.as_bytes(), "b");
+    let e = reader
+        .stream_to_sinks(|_| -> Result<Option<Vec<u8>>, crate::Error> {
+            Err(crate::Error {
+                code: 415,
+                message: "No.".to_owned(),
+                details: String::new(),
+            })
+        })
+        .unwrap_err();
+    assert_eq!(e.code, 415);
+}
+
+#[test]
+fn multipart_from_reader() {
+    use crate::{multipart, MultipartLimits};
+
+    let limits = MultipartLimits::default();
+    let body = "ignored\r\n--b\r\n\
+        Content-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--b\r\n\
+        bad part\r\n--b--\r\n";
+    let form = multipart::parse(body.as_bytes(), "b", &limits).unwrap();
+    assert_eq!(form.len(), 2);
+    assert!(form.skipped_parts().is_empty());
+    assert_eq!(form.preamble(), b"ignored");
+    let strict = limits.with_strict(true);
+    let form = multipart::parse(body.as_bytes(), "b", &strict).unwrap();
+    assert_eq!(form.len(), 1);
+    assert_eq!(form.skipped_parts().len(), 1);
+
+    let e = multipart::parse("no boundary".as_bytes(), "b", &limits).unwrap_err();
+    assert_eq!(e.code, 400);
+    let e = multipart::parse(body.as_bytes(), "b", &limits.with_max_parts(1)).unwrap_err();
+    assert_eq!(e.code, 413);
+}
+
+#[test]
+fn temp_uploads() {
+    use crate::MultipartReader;
+
+    let scratch = Scratch::new("temp_uploads");
+    let dir = scratch.path();
+    let body = "--b\r\n\
+        Content-Disposition: form-data; name=\"f\"; filename=\"big.bin\"\r\n\r\n\
+        0123456789\r\n--b--\r\n";
+
+    let mut reader = MultipartReader::new(body.as_bytes(), "b");
+    let mut part = reader.next_part().unwrap().unwrap();
+    let upload = part.save_temp(dir).unwrap();
+    assert_eq!(upload.size(), 10);
+    assert_eq!(upload.filename(), Some("big.bin"));
+    let path = upload.path().to_owned();
+    assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+
+    // Two at once get different names.
+    let part = crate::MultipartPart {
+        headers: Default::default(),
+        body: b"x".to_vec(),
+    };
+    let other = part.save_temp(dir).unwrap();
+    assert_ne!(other.path(), path);
+    drop(other);
+
+    let saved = upload.persist();
+    assert_eq!(saved.path, path);
+    assert!(path.exists());
+    let entries = std::fs::read_dir(dir).unwrap().count();
+    assert_eq!(entries, 1);
+
+    // A failed move still cleans up.
+    let upload = part.save_temp(dir).unwrap();
+    let temp = upload.path().to_owned();
+    assert!(upload.persist_to(dir.join("no/such/dir")).is_err());
+    assert!(!temp.exists());
+}
+
+#[test]
+fn strict_closing_boundary() {
+    use crate::{parse_multipart_with_limits, Body, MultipartLimits};
+
+    let strict = MultipartLimits::new().with_strict(true);
+    let parse = |body: &[u8], limits: &MultipartLimits| match parse_multipart_with_limits(
+        body, "b", limits,
+    ) {
+        Body::Multipart(form) => Ok(form.len()),
+        Body::Err(e) => Err(e.code),
+        b => panic!("unexpected body {:?}", b),
+    };
+
+    let whole: &[u8] = b"--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+        --b\r\nContent-Disposition: form-data; name=\"c\"\r\n\r\n2\r\n--b--\r\n";
+    assert_eq!(parse(whole, &strict), Ok(2));
+    // No trailing newline is fine too.
+    assert_eq!(parse(&whole[..whole.len() - 2], &strict), Ok(2));
+    assert_eq!(parse(b"--b--", &strict), Ok(0));
+    assert_eq!(parse(b"--b--\r\n", &strict), Ok(0));
+
+    // Cut off at various points.
+    for cut in [3, 5, 20, 52, 58, 60, whole.len() - 5, whole.len() - 4] {
+        let body = &whole[..cut];
+        assert_eq!(parse(body, &strict), Err(400), "cut at {}", cut);
+        assert!(
+            parse(body, &MultipartLimits::default()).is_ok(),
+            "cut at {}",
+            cut
+        );
+    }
+}
+
+#[test]
+fn set_cookies() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::cookie::{Cookie, SameSite};
+    use crate::time::http_date;
+    use crate::{url_decode, EmptyResponse};
+
+    assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+    assert_eq!(http_date(951_782_400), "Tue, 29 Feb 2000 00:00:00 GMT");
+    assert_eq!(http_date(-1), "Wed, 31 Dec 1969 23:59:59 GMT");
+
+    let c = Cookie::new("n", "a b;c,\"d\"\\é%");
+    assert_eq!(c.value(), "a%20b%3Bc%2C%22d%22%5C%C3%A9%25");
+    assert_eq!(url_decode(c.value()).unwrap(), "a b;c,\"d\"\\é%");
+
+    let c = Cookie::new("id", "x")
+        .with_domain("example.com")
+        .with_expires(UNIX_EPOCH + Duration::from_secs(86_400))
+        .with_same_site(SameSite::None);
+    assert_eq!(
+        c.to_string(),
+        "id=x; Domain=example.com; Expires=Fri, 02 Jan 1970 00:00:00 GMT; Secure; SameSite=None"
+    );
+
+    // Cookies survive becoming a FullResponse, and aren't comma-joined.
+    let r = EmptyResponse::new(200)
+        .with_cookie(Cookie::new("a", "1").with_expires(UNIX_EPOCH))
+        .with_cookie(Cookie::removal("b"))
+        .with_content_type("text/plain");
+    assert_eq!(r.get_cookies().len(), 2);
+    assert_eq!(r.get_header("set-cookie"), None);
+}
+
+#[test]
+fn repeated_header_lines() {
+    use crate::EmptyResponse;
+
+    let mut r = EmptyResponse::new(200);
+    r.add_header("Set-Cookie", "a=1; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+    r.add_header("set-cookie", "b=2");
+    r.add_header("Vary", "Cookie");
+    r.add_header_line("Vary", "Accept");
+    assert_eq!(r.get_header("set-cookie"), None);
+    assert_eq!(
+        r.get_cookies(),
+        ["a=1; Expires=Thu, 01 Jan 1970 00:00:00 GMT", "b=2"]
+    );
+    assert_eq!(r.get_header_lines("VARY"), ["Cookie", "Accept"]);
+
+    let r = r
+        .with_content_type("text/plain")
+        .with_header_line("Link", "<a>");
+    assert_eq!(r.get_header_lines("vary").len(), 2);
+    assert_eq!(r.get_header_lines("link"), ["<a>"]);
+    assert!(r.get_header_lines("nope").is_empty());
+}
+
+#[test]
+fn expect_continue() {
+    use crate::testing::MockRequest;
+    use crate::{Body, ContinuePolicy, RequestOptions};
+
+    let post = |expect: Option<&str>, policy: ContinuePolicy| {
+        let mut m = MockRequest::post("/")
+            .body("hello")
+            .options(RequestOptions::new().with_expect_continue(policy));
+        if let Some(expect) = expect {
+            m = m.header("Expect", expect);
+        }
+        m.build().unwrap()
+    };
+
+    let req = post(Some("100-Continue"), ContinuePolicy::Read);
+    assert!(req.expects_continue());
+    assert!(matches!(req.body(), Body::Some(b) if b == b"hello"));
+
+    let req = post(Some("100-continue"), ContinuePolicy::Reject);
+    match req.body() {
+        Body::Err(e) => assert_eq!(e.code, 417),
+        b => panic!("expected 417, got {:?}", b),
+    }
+    let req = post(Some("100-continue"), ContinuePolicy::RejectOver(5));
+    assert!(matches!(req.body(), Body::Some(b) if b == b"hello"));
+    let req = post(Some("100-continue"), ContinuePolicy::RejectOver(4));
+    assert!(matches!(req.body(), Body::Err(_)));
+
+    let req = post(None, ContinuePolicy::Reject);
+    assert!(!req.expects_continue());
+    assert!(matches!(req.body(), Body::Some(b) if b == b"hello"));
+}
+
+#[test]
+fn header_order() {
+    use crate::EmptyResponse;
+
+    let r = EmptyResponse::new(200)
+        .with_header_line("Link", "<a>")
+        .with_header("LINK", "<b>")
+        .with_header_line("Link", "<c>")
+        .with_header("link", "<d>");
+    assert_eq!(r.get_header("Link"), Some("<b>, <d>"));
+    assert_eq!(r.get_header_lines("link"), ["<a>", "<b>, <d>", "<c>"]);
+
+    let r = r.with_content_type("text/plain").with_header("Link", "<e>");
+    assert_eq!(r.get_header_lines("link"), ["<a>", "<b>, <d>, <e>", "<c>"]);
+}
+
+#[test]
+fn multiple_errors() {
+    use crate::{parse_multipart_with_limits, Body, Error, Errors, MultipartLimits};
+
+    let body = b"--xyz\r\nbad\r\n\r\n1\r\n--xyz\r\nworse\r\n\r\n2\r\n--xyz--\r\n";
+    let strict = MultipartLimits::new().with_strict(true);
+    let form = match parse_multipart_with_limits(body, "xyz", &strict) {
+        Body::Multipart(form) => form,
+        b => panic!("should have parsed, got {:?}", b),
+    };
+    let mut errors = Errors::from(form.skipped_parts());
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors.to_error().code, 400);
+    assert_eq!(errors.to_error().message.lines().count(), 2);
+
+    errors.push(Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: "disk full".to_owned(),
+    });
+    let err = errors.to_error();
+    assert_eq!(err.code, 500);
+    assert!(err.details.ends_with("; disk full"));
+
+    assert_eq!(Errors::new().into_result(3), Ok(3));
+    let collected: Errors = errors.into_iter().filter(|e| e.code == 500).collect();
+    assert!(collected.into_result(()).is_err());
+}
+
+#[test]
+fn body_filters() {
+    use crate::filter::{Banner, Filter, Filters, Replace};
+    use crate::EmptyResponse;
+
+    let filters = Filters::new()
+        .with(Replace::new("{{x}}", "1"))
+        .with(|body: Vec<u8>| body.to_ascii_uppercase())
+        .with(Banner::new("<hr>"));
+
+    let r = EmptyResponse::new(200)
+        .with_content_type("application/json")
+        .with_body("[{{x}},{{x}}{{x}}]");
+    assert_eq!(filters.apply(r).get_body(), b"[1,11]");
+
+    let r = EmptyResponse::new(200)
+        .with_content_type("Text/HTML")
+        .with_body("<p>{{x}}</p></BODY>");
+    assert_eq!(filters.apply(r).get_body(), b"<P>1</P><hr></BODY>");
+
+    let r = EmptyResponse::new(200)
+        .with_content_type("application/octet-stream")
+        .with_body("{{x}}");
+    assert_eq!(filters.apply(r).get_body(), b"{{X}}");
+
+    assert_eq!(Replace::new("ab", "").filter(b"aab".to_vec()), b"a");
+}
+
+#[test]
+fn respond_to_writer() {
+    use crate::{cookie::Cookie, EmptyResponse};
+
+    let mut out: Vec<u8> = Vec::new();
+    EmptyResponse::new(302)
+        .with_header("Location", "/next")
+        .with_header("Status", "303")
+        .with_cookie(Cookie::new("a", "1"))
+        .with_header("Cache-Control", "no-store")
+        .respond_to(&mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "Status: 303 See Other\r\nLocation: /next\r\nSet-Cookie: a=1\r\nCache-Control: no-store\r\n\r\n"
+    );
+
+    let mut out: Vec<u8> = Vec::new();
+    EmptyResponse::new(200)
+        .with_header("Content-Type", "wrong/type")
+        .with_header("X-A", "1")
+        .with_content_type("text/plain")
+        .with_body("hi")
+        .respond_to(&mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "Status: 200 OK\r\nContent-Type: text/plain\r\nX-A: 1\r\nContent-length: 2\r\n\r\nhi"
+    );
+}
+
+#[test]
+fn minify_html() {
+    use crate::filter::{Filter, Filters, Minify};
+    use crate::EmptyResponse;
+
+    let page = "<html>\n  <head>\n    <script>\n  if (a  <  b) {}\n    </SCRIPT>\n  </head>\n  \
+                <body>  <p>Some   text</p><textarea name=t>  a\n  b</textarea >\t<pre>\n x</pre>\n</body>\n</html>\n";
+    let r = EmptyResponse::new(200)
+        .with_content_type("text/html")
+        .with_body(page);
+    let r = Filters::new().with(Minify).apply(r);
+    assert_eq!(
+        std::str::from_utf8(r.get_body()).unwrap(),
+        "<html>\n<head>\n<script>\n  if (a  <  b) {}\n    </SCRIPT>\n</head>\n\
+         <body> <p>Some text</p><textarea name=t>  a\n  b</textarea > <pre>\n x</pre>\n</body>\n</html>\n"
+    );
+
+    // An unclosed raw element keeps everything after it.
+    assert_eq!(Minify.filter(b"a  <pre>  b".to_vec()), b"a <pre>  b");
+    // Not a raw element, just one whose name starts like one.
+    assert_eq!(Minify.filter(b"<prefix>  b".to_vec()), b"<prefix> b");
+}
+
+#[test]
+fn response_bytes() {
+    use crate::EmptyResponse;
+
+    let r = EmptyResponse::new(200)
+        .with_header("X-A", "1")
+        .with_content_type("text/plain")
+        .with_body("body");
+    let bytes = r.to_bytes().unwrap();
+    assert_eq!(bytes, r.to_bytes().unwrap());
+    let mut out: Vec<u8> = Vec::new();
+    r.respond_to(&mut out).unwrap();
+    assert_eq!(bytes, out);
+
+    // No body, no Content-type or Content-length.
+    let r = EmptyResponse::new(200).with_content_type("text/plain");
+    assert_eq!(r.to_bytes().unwrap(), b"Status: 200 OK\r\n\r\n");
+}
+
+#[test]
+fn response_bytes_with_body_reader() {
+    use crate::{Respond, Response};
+    use std::io::Cursor;
+
+    // The reader is used up, so the response can't be sent afterward.
+    let r = Response::new(200).with_body_reader(Cursor::new(b"abc".to_vec()), Some(3));
+    let clone = r.clone();
+    assert!(r
+        .to_bytes()
+        .unwrap()
+        .ends_with(b"Content-length: 3\r\n\r\nabc"));
+    let e = r.to_bytes().unwrap_err();
+    assert!(e.to_string().contains("already used"));
+    let e = Respond::to_bytes(&clone).unwrap_err();
+    assert!(e.to_string().contains("already used"));
+    let mut out: Vec<u8> = Vec::new();
+    assert!(r.respond_to(&mut out).is_err());
+
+    // A reader that runs out early is an error, not a short body.
+    let r = Response::new(200).with_body_reader(Cursor::new(b"abc".to_vec()), Some(10));
+    let e = r.to_bytes().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    let r = Response::new(200).with_body_reader(Cursor::new(b"abc".to_vec()), Some(10));
+    let e = Respond::to_bytes(&r).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn respond_trait() {
+    use crate::{EmptyResponse, Error, Errors, Respond};
+
+    let responses: Vec<Box<dyn Respond>> = vec![
+        Box::new(EmptyResponse::new(204)),
+        Box::new(
+            EmptyResponse::new(200)
+                .with_content_type("text/plain")
+                .with_body("ok"),
+        ),
+        Box::new(Error {
+            code: 403,
+            message: "Forbidden.".to_owned(),
+            details: String::new(),
+        }),
+        Box::new(Errors::new()),
+    ];
+    let statuses: Vec<u16> = responses.iter().map(|r| r.status()).collect();
+    assert_eq!(statuses, [204, 200, 403, 400]);
+
+    assert_eq!(
+        Respond::to_bytes(&responses[1]).unwrap(),
+        EmptyResponse::new(200)
+            .with_content_type("text/plain")
+            .with_body("ok")
+            .to_bytes()
+            .unwrap()
+    );
+    assert!(responses[2]
+        .to_bytes()
+        .unwrap()
+        .ends_with(b"\r\n\r\nForbidden."));
+}
+
+#[test]
+fn charset_filter() {
+    use crate::filter::{Charset, Filter, Filters};
+    use crate::EmptyResponse;
+
+    let filters = Filters::new().with(Charset);
+    let r = EmptyResponse::new(200)
+        .with_content_type("text/html; charset=iso-8859-1")
+        .with_body("<!DOCTYPE html>\n<p>hi</p>");
+    let r = filters.apply(r);
+    assert_eq!(r.get_content_type(), "text/html; charset=iso-8859-1");
+    assert_eq!(
+        r.get_body(),
+        b"<!DOCTYPE html><meta charset=\"utf-8\">\n<p>hi</p>"
+    );
+
+    let page =
+        b"<HTML lang=en><Meta http-equiv=\"Content-Type\" content=\"text/html; charset=utf-8\">";
+    assert_eq!(Charset.filter(page.to_vec()), page);
+    assert_eq!(
+        Charset.filter(b"<p>".to_vec()),
+        b"<meta charset=\"utf-8\"><p>"
+    );
+    assert_eq!(
+        Charset.filter(b"<html><header>".to_vec()),
+        b"<html><meta charset=\"utf-8\"><header>"
+    );
+
+    let r = EmptyResponse::new(200)
+        .with_content_type("text/plain")
+        .with_body("<p>");
+    assert_eq!(filters.apply(r).get_content_type(), "text/plain");
+
+    // A page that says it's something other than UTF-8 is left alone,
+    // header and all.
+    for page in [
+        &b"<html><head><meta charset=\"iso-8859-1\"><title>Caf\xe9</title>"[..],
+        b"<meta http-equiv=content-type content='text/html; charset=windows-1252'>",
+    ] {
+        let r = EmptyResponse::new(200)
+            .with_content_type("text/html")
+            .with_body(page.to_vec());
+        let r = filters.apply(r);
+        assert_eq!(r.get_content_type(), "text/html");
+        assert_eq!(r.get_body(), page);
+    }
+    // One that says it's UTF-8 gets the header to match.
+    let r = EmptyResponse::new(200)
+        .with_content_type("text/html")
+        .with_body(&page[..]);
+    assert_eq!(
+        filters.apply(r).get_content_type(),
+        "text/html; charset=utf-8"
+    );
+}
+
+#[test]
+fn self_test() {
+    use crate::selftest::SelfTest;
+    use crate::store::ContentStore;
+
+    let scratch = Scratch::new("selftest");
+    let dir = scratch.path();
+    std::fs::write(dir.join("site.conf"), "a = 1\nb = 2\n").unwrap();
+
+    let report = SelfTest::new()
+        .with_config(dir.join("site.conf"))
+        .with_config(dir.join("missing.conf"))
+        .with_writable_dir(dir)
+        .with_writable_dir(dir.join("nope"))
+        .with_store(ContentStore::new(dir.join("store")))
+        .with_check("custom", || Ok("fine".to_owned()))
+        .run();
+    assert!(!report.is_ok());
+    assert_eq!(report.results.len(), 6);
+    assert_eq!(report.results[0].1, Ok("2 keys".to_owned()));
+    assert_eq!(report.failures().len(), 2);
+    assert!(report.failures()[0].ends_with("missing.conf"));
+    let text = report.to_string();
+    assert!(text.contains("ok   custom: fine\n"));
+    assert!(text.ends_with("2 of 6 checks failed\n"));
+
+    let req = crate::testing::MockRequest::get("/health").build().unwrap();
+    let r = report.to_response(&req);
+    assert_eq!(r.get_status(), 503);
+    assert_eq!(r.get_header("cache-control"), Some("no-store"));
+    let body = String::from_utf8(r.get_body().to_vec()).unwrap();
+    assert!(body.starts_with(r#"{"ok":false,"checks":[{"name":"config "#));
+    assert!(body.contains(r#"{"name":"custom","ok":true,"found":"fine"}"#));
+    assert!(!body.contains('\n'));
+
+    // Nothing left behind by the store check.
+    let leftovers = std::fs::read_dir(dir.join("store"))
+        .unwrap()
+        .flat_map(|d| std::fs::read_dir(d.unwrap().path()).unwrap())
+        .filter(|e| e.as_ref().unwrap().path().extension().is_none())
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+#[test]
+fn unified_response() {
+    use crate::{EmptyResponse, FullResponse, Response};
+
+    let r: FullResponse = Response::new(200)
+        .with_body("{}")
+        .with_content_type("application/json");
+    assert_eq!(r.get_content_type(), "application/json");
+    assert_eq!(
+        r.to_bytes().unwrap(),
+        b"Status: 200 OK\r\nContent-type: application/json\r\nContent-length: 2\r\n\r\n{}"
+    );
+
+    let r: EmptyResponse = Response::new(200).with_body(vec![0u8]);
+    assert_eq!(r.get_content_type(), "");
+    assert!(r
+        .to_bytes()
+        .unwrap()
+        .starts_with(b"Status: 200 OK\r\nContent-type: application/octet-stream\r\n"));
+
+    let r = Response::new(200)
+        .with_header("Content-Type", "image/png")
+        .with_body(vec![0u8]);
+    assert!(r
+        .to_bytes()
+        .unwrap()
+        .starts_with(b"Status: 200 OK\r\nContent-Type: image/png\r\nContent-length: 1\r\n"));
+}
+
+#[test]
+fn embedded_assets() {
+    use crate::assets::Assets;
+    use crate::include_assets;
+    use crate::testing::MockRequest;
+
+    static ASSETS: Assets = include_assets!("src", "lib.rs", "time.rs").with_max_age(60);
+    assert_eq!(ASSETS.names().collect::<Vec<_>>(), ["lib.rs", "time.rs"]);
+    assert_eq!(
+        ASSETS.get("time.rs").unwrap(),
+        std::fs::read("src/time.rs").unwrap()
+    );
+
+    let req = MockRequest::get("/lib.rs").build().unwrap();
+    let r = ASSETS.serve(&req).unwrap();
+    assert_eq!(r.get_content_type(), "application/octet-stream");
+    assert_eq!(r.get_header("cache-control"), Some("public, max-age=60"));
+    let etag = r.get_header("etag").unwrap().to_owned();
+
+    let req = MockRequest::get("/lib.rs")
+        .header("If-None-Match", format!("\"abc\", W/{}", etag))
+        .build()
+        .unwrap();
+    let r = ASSETS.serve(&req).unwrap();
+    assert_eq!(r.get_status(), 304);
+    assert!(r.get_body().is_empty());
+
+    let req = MockRequest::post("/lib.rs").build().unwrap();
+    assert_eq!(ASSETS.serve(&req).unwrap().get_status(), 405);
+    let req = MockRequest::get("/src/lib.rs").build().unwrap();
+    assert!(ASSETS.serve(&req).is_none());
+    let req = MockRequest::get("/lib.rsx").build().unwrap();
+    assert!(ASSETS.serve(&req).is_none());
+}
+
+#[test]
+fn response_builder() {
+    use crate::Response;
+
+    let r = Response::builder()
+        .header("X-A", "1")
+        .header("x-a", "2")
+        .body("")
+        .unwrap();
+    assert_eq!(r.get_status(), 200);
+    assert_eq!(r.get_header("X-A"), Some("1, 2"));
+
+    for status in [99, 1000] {
+        assert!(Response::builder().status(status).body("").is_err());
+    }
+    assert!(Response::builder()
+        .header("Bad Name", "x")
+        .body("")
+        .is_err());
+    assert!(Response::builder().header("", "x").body("").is_err());
+    let err = Response::builder()
+        .header("A", "\n")
+        .status(42)
+        .body("")
+        .unwrap_err();
+    assert!(err.details.starts_with("invalid value for header A"));
+    assert!(Response::builder().header("A", "tab\tok").body("").is_ok());
+
+    let r = Response::builder()
+        .header("Status", "201 Made It")
+        .body("")
+        .unwrap();
+    assert_eq!(r.get_status(), 201);
+    assert_eq!(r.get_status_text(), Some("Made It"));
+    for bad in ["", "abc", "20", "2010 OK", "099"] {
+        assert!(Response::builder().header("Status", bad).body("").is_err());
+    }
+}
+
+#[test]
+fn status_header_sets_status() {
+    use crate::Response;
+
+    let mut r = Response::new(200).with_content_type("text/plain");
+    r.add_header("status", "404");
+    assert_eq!(r.get_status(), 404);
+    assert_eq!(r.get_status_text(), None);
+    r.add_header_line("Status", "299 Odd");
+    assert_eq!((r.get_status(), r.get_status_text()), (299, Some("Odd")));
+    assert!(r.get_header_lines("status").is_empty());
+    let text = String::from_utf8(r.to_bytes().unwrap()).unwrap();
+    assert!(text.starts_with("Status: 299 Odd\r\n"));
+    assert_eq!(text.matches("Status").count(), 1);
+
+    // Not a status at all: the server would have answered 500.
+    let r = Response::new(200).with_header("Status", "fine");
+    assert_eq!(r.get_status(), 500);
+}
+
+#[test]
+fn templates() {
+    use crate::template::{Template, TemplateCache};
+
+    let t = Template::parse("{{a}}-{{{ a }}}-{{missing}}!").unwrap();
+    assert_eq!(t.names().collect::<Vec<_>>(), ["a", "a", "missing"]);
+    assert_eq!(t.render(&[("a", &"<&>")]), "&lt;&amp;&gt;-<&>-!");
+    assert_eq!(
+        Template::parse("{{ 3 }}").unwrap().render(&[("3", &3)]),
+        "3"
+    );
+    assert!(Template::parse("{{open").is_err());
+    assert!(Template::parse("{{two words}}").is_err());
+    assert_eq!(Template::parse("a } b").unwrap().render(&[]), "a } b");
+
+    let scratch = Scratch::new("templates");
+    let dir = scratch.path();
+    let path = dir.join("page.html");
+    std::fs::write(&path, "<p>{{x}}</p>").unwrap();
+
+    let loaded = Template::load_cached(&path).unwrap();
+    assert!(dir.join("page.html.cache").exists());
+    assert_eq!(Template::load_cached(&path).unwrap(), loaded);
+    assert_eq!(loaded.render(&[("x", &1)]), "<p>1</p>");
+
+    let mut cache = TemplateCache::new();
+    let first = cache.get(&path).unwrap();
+    assert!(std::sync::Arc::ptr_eq(&first, &cache.get(&path).unwrap()));
+
+    // A change to the file (here, its length) invalidates both caches.
+    std::fs::write(&path, "<b>{{{x}}}</b>").unwrap();
+    assert_eq!(cache.get(&path).unwrap().render(&[("x", &"&")]), "<b>&</b>");
+    assert_eq!(
+        Template::load_cached(&path).unwrap().render(&[("x", &"&")]),
+        "<b>&</b>"
+    );
+}
+
+#[test]
+fn escaping_contexts() {
+    use crate::escape::{attr, html, js_string, url_component};
+
+    assert_eq!(html("a'b").to_string(), "a&#39;b");
+    assert_eq!(attr("x=1 onclick").to_string(), "x&#x3D;1&#x20;onclick");
+    assert_eq!(attr("café").to_string(), "café");
+    assert_eq!(js_string("a\"b\\c\n").to_string(), "a\\x22b\\x5Cc\\x0A");
+    assert_eq!(js_string("\u{2028}é").to_string(), "\\u2028é");
+    assert_eq!(url_component("é/?").to_string(), "%C3%A9%2F%3F");
+    assert_eq!(url_component("a-b._~").to_string(), "a-b._~");
+}
+
+#[test]
+fn status_codes() {
+    use crate::{reason_phrase, Response, Status};
+
+    for status in Status::ALL {
+        assert_eq!(Status::from_code(status.code()), Some(*status));
+        assert_eq!(reason_phrase(status.code()), Some(status.reason()));
+    }
+    assert_eq!(Status::ALL.len(), 61);
+    assert_eq!(Status::ImUsed.to_string(), "226 IM Used");
+    assert!(Status::SeeOther.is_redirection() && !Status::SeeOther.is_success());
+    assert!(Status::LoopDetected.is_server_error());
+
+    let mut r = Response::builder()
+        .status(Status::Created)
+        .body("")
+        .unwrap();
+    assert_eq!(r.get_status(), 201);
+    r.set_status(Status::Accepted);
+    assert_eq!(r.to_bytes().unwrap(), b"Status: 202 Accepted\r\n\r\n");
+    r.set_status(599);
+    assert_eq!(r.to_bytes().unwrap(), b"Status: 599\r\n\r\n");
+}
+
+#[test]
+fn status_text() {
+    use crate::Response;
+
+    let r = Response::new(299).with_status_text("Custom Thing");
+    assert_eq!(r.to_bytes().unwrap(), b"Status: 299 Custom Thing\r\n\r\n");
+
+    let mut r = Response::new(404).with_status_text("No Such Fish\r\nX-Evil: yes");
+    assert_eq!(r.get_status_text(), Some("No Such FishX-Evil: yes"));
+    r.set_status_text("");
+    assert_eq!(r.to_bytes().unwrap(), b"Status: 404 Not Found\r\n\r\n");
+}
+
+#[test]
+fn nested_query() {
+    use crate::{FormValue, Query};
+
+    let v = |s: &str| FormValue::Value(s.to_owned());
+    let form =
+        Query::parse_nested("items[][id]=1&items[][qty]=2&items[][id]=3&a[b][c][]=x&weird[=1")
+            .unwrap();
+    let items = form["items"].as_list().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0]["id"], v("1"));
+    assert_eq!(items[0]["qty"], v("2"));
+    assert_eq!(items[1]["id"], v("3"));
+    assert_eq!(form["a"]["b"]["c"], FormValue::List(vec![v("x")]));
+    assert_eq!(form["weird["], v("1"));
+
+    assert_eq!(Query::parse_nested("a=1&a[b]=2").unwrap_err().code, 400);
+    assert!(Query::parse_nested("a[]=1&a[x]=2").is_err());
+    // Mixing a plain value with a list or map is an error in either order.
+    for mixed in ["a=1&a[]=2", "a[]=1&a=2", "a=1&a[x]=2", "a[x]=1&a=2"] {
+        assert!(Query::parse_nested(mixed).is_err(), "{}", mixed);
+    }
+    // A repeated plain value is still replaced.
+    assert_eq!(Query::parse_nested("a=1&a=2").unwrap()["a"], v("2"));
+    let deep = format!("a{}=1", "[b]".repeat(17));
+    assert!(Query::parse_nested(&deep).is_err());
+    assert!(Query::parse_nested(&format!("a{}=1", "[b]".repeat(16))).is_ok());
+}
+
+#[test]
+fn legacy_charsets() {
+    use crate::charset::Charset;
+    use crate::testing::MockRequest;
+
+    let body: &[u8] =
+        b"--xyz\r\nContent-Disposition: form-data; name=\"_charset_\"\r\n\r\nISO-8859-15\r\n\
+                        --xyz\r\nContent-Disposition: form-data; name=\"price\"\r\n\r\n5 \xa4\r\n\
+                        --xyz--\r\n";
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=xyz")
+        .body(body)
+        .build()
+        .unwrap();
+    assert_eq!(req.form_charset(Charset::Utf8), Charset::Latin9);
+    assert_eq!(
+        req.decoded_form(Charset::Utf8).unwrap()["price"],
+        "5 \u{20ac}"
+    );
+
+    // No `_charset_`; the charset comes from the hint.
+    let req = MockRequest::get("/").query("q=na%EFve").build().unwrap();
+    assert_eq!(req.form_charset(Charset::Windows1252), Charset::Windows1252);
+    assert_eq!(
+        req.decoded_form(Charset::Windows1252).unwrap()["q"],
+        "naïve"
+    );
+    assert_eq!(req.decoded_form(Charset::Utf8).unwrap_err().code, 400);
+}
+
+#[test]
+fn nph_responses() {
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    let bytes = Response::new(302)
+        .with_nph(true)
+        .with_header("Location", "/elsewhere")
+        .with_header("Date", "Sun, 06 Nov 1994 08:49:37 GMT")
+        .to_bytes()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(bytes).unwrap(),
+        "HTTP/1.1 302 Found\r\n\
+         Location: /elsewhere\r\n\
+         Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+         Connection: close\r\n\
+         Content-length: 0\r\n\r\n"
+    );
+
+    let bytes = Response::new(304)
+        .with_nph(true)
+        .with_status_text("Still Good")
+        .with_header("Connection", "keep-alive")
+        .to_bytes()
+        .unwrap();
+    let text = String::from_utf8(bytes).unwrap();
+    assert!(text.starts_with("HTTP/1.1 304 Still Good\r\n"));
+    assert!(!text.contains("Content-length"));
+    assert!(text.contains("Connection: keep-alive\r\n"));
+
+    // The protocol comes from the request, not the process environment.
+    let req = MockRequest::get("/")
+        .var("SERVER_PROTOCOL", "HTTP/1.0")
+        .build()
+        .unwrap();
+    let response = Response::new(200).with_nph_for(&req);
+    assert!(response.is_nph());
+    let text = String::from_utf8(response.to_bytes().unwrap()).unwrap();
+    assert!(text.starts_with("HTTP/1.0 200 OK\r\n"));
+    let req = MockRequest::get("/").build().unwrap();
+    let bytes = Response::new(200).with_nph_for(&req).to_bytes().unwrap();
+    let text = String::from_utf8(bytes).unwrap();
+    assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[test]
+fn collided_headers() {
+    use crate::testing::MockRequest;
+    use crate::{Request, Warning};
+
+    let env = vec![
+        ("HTTP_X_TAG", "one"),
+        ("HTTP_x_tag", "two"),
+        ("QUERY_STRING", "a=1&a=2"),
+    ];
+    let req = Request::from_env(env, std::io::empty()).unwrap();
+    assert!(req
+        .warnings()
+        .contains(&Warning::CollidedHeader("x-tag".to_owned())));
+    let mut kinds: Vec<&str> = req.warnings().iter().map(Warning::kind).collect();
+    kinds.sort();
+    assert_eq!(kinds, ["collided-header", "duplicate-query-key"]);
+
+    let body = "--xyz\r\n\
+                Content-Disposition: form-data; name=\"a\"\r\n\
+                Content-Disposition: form-data; name=\"b\"\r\n\
+                \r\n1\r\n--xyz--\r\n";
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=xyz")
+        .body(body)
+        .build()
+        .unwrap();
+    assert_eq!(
+        req.warnings(),
+        [Warning::CollidedHeader("content-disposition".to_owned())]
+    );
+}
+
+#[test]
+fn bare_lf_multipart_body() {
+    use crate::testing::MockRequest;
+    use crate::{Body, MultipartLimits, RequestOptions, Warning};
+
+    let body = "--xyz\nContent-Disposition: form-data; name=\"a\"\n\nhello\n--xyz--\n";
+    let build = |opts: RequestOptions| {
+        MockRequest::post("/")
+            .header("Content-Type", "multipart/form-data; boundary=xyz")
+            .body(body)
+            .options(opts)
+            .build()
+            .unwrap()
+    };
+
+    let req = build(RequestOptions::new());
+    match req.body() {
+        Body::Multipart(form) => {
+            assert!(form.is_empty());
+            assert!(form.skipped_parts().is_empty());
+        }
+        b => panic!("expected an empty form, got {:?}", b),
+    }
+    assert_eq!(req.warnings(), [Warning::BareLfLineEndings]);
+    assert_eq!(req.warnings()[0].kind(), "bare-lf-line-endings");
+
+    let strict =
+        RequestOptions::new().with_multipart_limits(MultipartLimits::new().with_strict(true));
+    let req = build(strict);
+    match req.body() {
+        Body::Err(e) => {
+            assert_eq!(e.code, 400);
+            assert!(e.details.contains("bare LF"), "{}", e.details);
+        }
+        b => panic!("expected an error, got {:?}", b),
+    }
+}
+
+#[test]
+fn unterminated_multipart_body() {
+    use crate::testing::MockRequest;
+    use crate::{Body, Warning};
+
+    // Cut off in the middle of the second part.
+    let body = "--xyz\r\n\
+                Content-Disposition: form-data; name=\"a\"\r\n\
+                \r\n\
+                1\r\n\
+                --xyz\r\n\
+                Content-Disposition: form-data; name=\"b\"\r\n\
+                \r\n\
+                2";
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=xyz")
+        .body(body)
+        .build()
+        .unwrap();
+    match req.body() {
+        Body::Multipart(form) => {
+            assert_eq!(form.len(), 1);
+            assert_eq!(form[0].body, b"1");
+        }
+        b => panic!("expected a form, got {:?}", b),
+    }
+    assert_eq!(req.warnings(), [Warning::UnterminatedMultipart]);
+    assert_eq!(req.warnings()[0].kind(), "unterminated-multipart");
+
+    // A properly closed body has no such warning.
+    let req = MockRequest::post("/")
+        .header("Content-Type", "multipart/form-data; boundary=xyz")
+        .body("--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--xyz--\r\n")
+        .build()
+        .unwrap();
+    assert!(req.warnings().is_empty());
+}
+
+#[test]
+fn content_length_longer_than_body() {
+    use crate::{Body, Request};
+
+    let env = vec![
+        ("REQUEST_METHOD", "POST"),
+        ("HTTP_CONTENT_TYPE", "text/plain"),
+        ("HTTP_CONTENT_LENGTH", "100"),
+    ];
+    let req = Request::from_env(env, &b"only twenty-six bytes here"[..]).unwrap();
+    match req.body() {
+        Body::Err(e) => assert!(e.details.contains("Error reading request body")),
+        b => panic!("a truncated body should be an error, got {:?}", b),
+    }
+    assert!(req.warnings().is_empty());
+}
+
+#[test]
+fn streamed_response() {
+    use crate::Response;
+    use std::io::Write;
+
+    let mut body = Response::new(200)
+        .with_nph(true)
+        .with_header("Date", "Sun, 06 Nov 1994 08:49:37 GMT")
+        .start_to(Vec::new())
+        .unwrap();
+    body.write_all(b"part one, ").unwrap();
+    body.write_all(b"part two").unwrap();
+    assert_eq!(
+        String::from_utf8(body.finish().unwrap()).unwrap(),
+        "HTTP/1.1 200 OK\r\n\
+         Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+         Content-type: application/octet-stream\r\n\
+         Connection: close\r\n\r\n\
+         part one, part two"
+    );
+}
+
+#[test]
+fn body_tee() {
+    use crate::audit::AuditLog;
+    use crate::filter::{Filters, Tee};
+    use crate::Response;
+
+    let scratch = Scratch::new("tee_test");
+    let path = scratch.path().join("tee_test.log");
+    let filters = Filters::new().with(
+        Tee::to_audit_log(64, AuditLog::new(&path), "tee")
+            .with_redacted_field("password")
+            .with_redaction(|text| text.replace("dan", "d**")),
+    );
+    let r = Response::new(200)
+        .with_content_type("application/x-www-form-urlencoded")
+        .with_body("user=dan&password=hunter2&xpassword=kept");
+    let r = filters.apply(r);
+    assert_eq!(r.get_body(), b"user=dan&password=hunter2&xpassword=kept");
+
+    let records = AuditLog::new(&path).records().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(
+        records[0].action,
+        "response body: user=d**&password=[redacted]&xpassword=kept"
+    );
+}
+
+#[test]
+fn body_reader() {
+    use crate::Response;
+
+    // Unknown length: no Content-length, copied to the end.
+    let r = Response::new(200).with_body_reader(std::io::Cursor::new(vec![b'x'; 20_000]), None);
+    let bytes = r.to_bytes().unwrap();
+    let head = b"Status: 200 OK\r\nContent-type: application/octet-stream\r\n\r\n";
+    assert!(bytes.starts_with(head));
+    assert_eq!(bytes.len(), head.len() + 20_000);
+
+    // The reader comes up short of the promised length.
+    let r = Response::new(200).with_body_reader(std::io::Cursor::new(b"short".to_vec()), Some(10));
+    let mut out = Vec::new();
+    let err = r.clone().respond_to(&mut out).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(String::from_utf8_lossy(&out).contains("Content-length: 10\r\n"));
+    // The clone shared the reader, which has been used up.
+    assert!(r.respond_to(&mut Vec::new()).is_err());
+
+    // Replaced by an ordinary body.
+    let r = Response::new(200)
+        .with_body_reader(std::io::empty(), None)
+        .with_body("plain");
+    assert!(r
+        .to_bytes()
+        .unwrap()
+        .ends_with(b"Content-length: 5\r\n\r\nplain"));
+}
+
+#[test]
+fn memory_budget() {
+    use crate::testing::MockRequest;
+    use crate::{Body, Query, RequestOptions};
+
+    let body =
+        "--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n0123456789\r\n--xyz--\r\n";
+    let form = |budget: usize| {
+        MockRequest::post("/")
+            .query("q=abc")
+            .header("Content-Type", "multipart/form-data; boundary=xyz")
+            .body(body)
+            .options(RequestOptions::new().with_memory_budget(budget))
+            .build()
+            .unwrap()
+    };
+
+    // Query (5) + body (72) + parsed part (10 + the disposition header).
+    assert_eq!(body.len(), 72);
+    let req = form(200);
+    assert!(matches!(req.query(), Query::Some(_)));
+    assert!(matches!(req.body(), Body::Multipart(_)));
+
+    // Room for the body, but not the parts copied out of it.
+    match form(80).body() {
+        Body::Err(e) => {
+            assert_eq!(e.code, 413);
+            assert!(e.details.starts_with("multipart form"));
+        }
+        _ => panic!("parts should have been over budget"),
+    }
+
+    // Not even room for the query string.
+    let req = form(4);
+    assert!(matches!(req.query(), Query::Err(e) if e.code == 413));
+}
+
+#[test]
+fn send_file() {
+    use crate::Response;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let scratch = Scratch::new("send_file");
+    let dir = scratch.path();
+    let path = dir.join("report");
+    std::fs::write(&path, vec![7u8; 100_000]).unwrap();
+    let file = std::fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(784_111_777))
+        .unwrap();
+
+    let r = Response::send_file(&path).unwrap();
+    assert_eq!(r.get_content_type(), "application/octet-stream");
+    assert_eq!(
+        r.get_header("last-modified"),
+        Some("Sun, 06 Nov 1994 08:49:37 GMT")
+    );
+    let mut out = Vec::new();
+    r.respond_to(&mut out).unwrap();
+    assert!(out.ends_with(&[7u8; 100_000]));
+
+    assert_eq!(Response::send_file(dir).unwrap_err().code, 404);
+}
+
+#[test]
+fn terminal_policy() {
+    use crate::{TerminalPolicy, Warning};
+
+    assert_eq!(TerminalPolicy::default(), TerminalPolicy::SkipBody);
+    assert!(!TerminalPolicy::SkipBody.read_body().unwrap());
+    assert!(TerminalPolicy::Read.read_body().unwrap());
+    assert_eq!(TerminalPolicy::Reject.read_body().unwrap_err().code, 500);
+    assert_eq!(Warning::TerminalStdin.kind(), "terminal-stdin");
+}
+
+#[test]
+fn terminal_stdin_skips_body() {
+    use crate::{Body, Request, RequestOptions, TerminalPolicy, Warning};
+
+    let env = || {
+        vec![
+            ("REQUEST_METHOD", "POST"),
+            ("HTTP_CONTENT_TYPE", "text/plain"),
+            ("HTTP_CONTENT_LENGTH", "5"),
+        ]
+    };
+    let opts = |policy| RequestOptions::new().with_on_terminal(policy);
+
+    let req = Request::from_stdin_env(env(), &b"hello"[..], true, &RequestOptions::new()).unwrap();
+    assert!(matches!(req.body(), Body::None));
+    assert_eq!(req.warnings(), &[Warning::TerminalStdin]);
+
+    let req =
+        Request::from_stdin_env(env(), &b"hello"[..], true, &opts(TerminalPolicy::Read)).unwrap();
+    assert!(matches!(req.body(), Body::Some(b) if b == b"hello"));
+    assert!(req.warnings().is_empty());
+
+    let e = Request::from_stdin_env(env(), &b"hello"[..], true, &opts(TerminalPolicy::Reject))
+        .unwrap_err();
+    assert_eq!(e.code, 500);
+
+    // Not a terminal: the policy doesn't matter.
+    let req = Request::from_stdin_env(env(), &b"hello"[..], false, &opts(TerminalPolicy::Reject))
+        .unwrap();
+    assert!(matches!(req.body(), Body::Some(b) if b == b"hello"));
+}
+
+#[test]
+fn mime_types() {
+    use crate::mime;
+
+    assert_eq!(mime::from_extension(".WOFF2"), Some("font/woff2"));
+    assert_eq!(mime::from_extension("tar.gz"), None);
+    assert_eq!(mime::from_path("backup.tar.gz"), Some("application/gzip"));
+    assert_eq!(mime::from_path(".bashrc"), None);
+    assert_eq!(mime::from_path("dir.d/"), None);
+}
+
+#[test]
+fn stable_json() {
+    use crate::json::{Json, JsonFormat};
+    use crate::Response;
+    use std::collections::HashMap;
+
+    let nested = Json::object()
+        .with("z", Json::object().with("y", 1).with("x", Json::Null))
+        .with("a", vec![1.5, f64::NAN])
+        .with("m", "tab\tquote\"\u{1}");
+    assert_eq!(
+        nested.to_string(),
+        r#"{"z":{"y":1,"x":null},"a":[1.5,null],"m":"tab\tquote\"\u0001"}"#
+    );
+    let stable = JsonFormat::new().with_sorted_keys(true);
+    assert_eq!(
+        nested.to_string_with(&stable),
+        r#"{"a":[1.5,null],"m":"tab\tquote\"\u0001","z":{"x":null,"y":1}}"#
+    );
+
+    // The same data from two differently-ordered maps makes the same body.
+    let keys: Vec<String> = (0..50).map(|n| format!("k{}", n)).collect();
+    let forward: HashMap<String, i64> = keys.iter().cloned().zip(0..).collect();
+    let backward: HashMap<String, i64> = keys.iter().rev().cloned().zip((0..50).rev()).collect();
+    let a = Response::new(200).with_json_formatted(&Json::from(forward), &stable);
+    let b = Response::new(200).with_json_formatted(&Json::from(backward), &stable);
+    assert_eq!(a.get_body(), b.get_body());
+}
+
+#[test]
+fn pretty_json() {
+    use crate::json::{Json, JsonFormat};
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    let value = Json::object()
+        .with("b", vec![1, 2])
+        .with("a", Json::object())
+        .with("c", Json::Array(Vec::new()))
+        .with("d", Json::object().with("e", "f"));
+    let pretty = JsonFormat::new().with_pretty(true).with_sorted_keys(true);
+    assert_eq!(
+        value.to_string_with(&pretty),
+        "{\n  \"a\": {},\n  \"b\": [\n    1,\n    2\n  ],\n  \"c\": [],\n  \"d\": {\n    \"e\": \"f\"\n  }\n}\n"
+    );
+
+    let req = MockRequest::get("/").query("pretty=no").build().unwrap();
+    let r = Response::new(200).with_json_for(&req, &value);
+    assert!(!r.get_body().contains(&b'\n'));
+    let req = MockRequest::get("/")
+        .query("x=1&pretty=TRUE")
+        .build()
+        .unwrap();
+    let r = Response::new(200).with_json_for(&req, &value);
+    assert!(r.get_body().ends_with(b"\n}\n"));
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn gzip_compression() {
+    use crate::gzip;
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    // Same bits zlib writes for a lone literal in a fixed-code block.
+    let packed = gzip::compress(b"a");
+    assert_eq!(packed[..3], [0x1f, 0x8b, 8]);
+    assert_eq!(packed[10..13], [0x4b, 0x04, 0x00]);
+    assert_eq!(packed[13..17], gzip::crc32(b"a").to_le_bytes());
+    assert_eq!(packed[17..], 1u32.to_le_bytes());
+
+    let page = "<li>item</li>\n".repeat(500);
+    let page_for = |accept: Option<&str>| {
+        let mut req = MockRequest::get("/list");
+        if let Some(accept) = accept {
+            req = req.header("Accept-Encoding", accept);
+        }
+        Response::new(200)
+            .with_content_type("text/html")
+            .with_header("Vary", "Cookie")
+            .with_body(page.clone())
+            .compressed_for(&req.build().unwrap())
+    };
+
+    let r = page_for(Some("br, GZIP;q=0.8"));
+    assert_eq!(r.get_header("content-encoding"), Some("gzip"));
+    assert_eq!(r.get_header("vary"), Some("Cookie, Accept-Encoding"));
+    assert!(r.get_body().len() < 200);
+    assert!(r.get_body().ends_with(&(page.len() as u32).to_le_bytes()));
+
+    for accept in [None, Some("gzip;q=0"), Some("br, identity")] {
+        let r = page_for(accept);
+        assert_eq!(r.get_header("content-encoding"), None);
+        assert_eq!(r.get_header("vary"), Some("Cookie, Accept-Encoding"));
+        assert_eq!(r.get_body(), page.as_bytes());
+    }
+
+    // Bodies that wouldn't shrink are sent as they are.
+    let req = MockRequest::get("/")
+        .header("Accept-Encoding", "*")
+        .build()
+        .unwrap();
+    let r = Response::new(200).with_body("tiny").compressed_for(&req);
+    assert_eq!(r.get_header("content-encoding"), None);
+    assert_eq!(r.get_body(), b"tiny");
+}
+
+/*
+Just enough of an inflater (RFC 1951: stored and fixed-Huffman blocks) to
+check the compressor's output without pulling in a real one.
+*/
+#[cfg(feature = "compression")]
+fn inflate(data: &[u8]) -> Vec<u8> {
+    struct Bits<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+    impl Bits<'_> {
+        fn bit(&mut self) -> u32 {
+            let b = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+            self.pos += 1;
+            b as u32
+        }
+        fn bits(&mut self, n: u32) -> u32 {
+            (0..n).fold(0, |v, i| v | (self.bit() << i))
+        }
+        // Huffman codes are packed most-significant bit first.
+        fn code(&mut self, n: u32) -> u32 {
+            (0..n).fold(0, |v, _| (v << 1) | self.bit())
+        }
+        fn fixed_literal(&mut self) -> u32 {
+            let c = self.code(7);
+            if c <= 0x17 {
+                return c + 256;
+            }
+            let c = (c << 1) | self.bit();
+            match c {
+                0x30..=0xBF => c - 0x30,
+                0xC0..=0xC7 => c - 0xC0 + 280,
+                _ => ((c << 1) | self.bit()) - 0x190 + 144,
+            }
+        }
+    }
+    const LEN_BASE: [usize; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+        131, 163, 195, 227, 258,
+    ];
+    const DIST_BASE: [usize; 30] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+        2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+    ];
+    let len_extra = |i: usize| {
+        if (8..28).contains(&i) {
+            (i as u32 - 4) / 4
+        } else {
+            0
+        }
+    };
+    let dist_extra = |i: usize| if i >= 4 { (i as u32 - 2) / 2 } else { 0 };
+
+    let mut r = Bits { data, pos: 0 };
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let last = r.bit() == 1;
+        match r.bits(2) {
+            0 => {
+                r.pos = r.pos.div_ceil(8) * 8;
+                let len = r.bits(16) as usize;
+                r.bits(16);
+                out.extend_from_slice(&data[r.pos / 8..r.pos / 8 + len]);
+                r.pos += 8 * len;
+            }
+            1 => loop {
+                let sym = r.fixed_literal() as usize;
+                match sym {
+                    0..=255 => out.push(sym as u8),
+                    256 => break,
+                    _ => {
+                        let i = sym - 257;
+                        let len = LEN_BASE[i] + r.bits(len_extra(i)) as usize;
+                        let i = r.code(5) as usize;
+                        let dist = DIST_BASE[i] + r.bits(dist_extra(i)) as usize;
+                        assert!(dist <= out.len() && dist <= 32 * 1024);
+                        for _ in 0..len {
+                            out.push(out[out.len() - dist]);
+                        }
+                    }
+                }
+            },
+            t => panic!("unsupported block type {}", t),
+        }
+        if last {
+            return out;
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn gzip_round_trip() {
+    use crate::gzip;
+
+    // Long runs (the longest matches), repeats near and far (up to the
+    // edge of the window), and bytes of every value (both literal code
+    // lengths), over several KB.
+    let mut data: Vec<u8> = Vec::new();
+    let mut x: u32 = 1;
+    for i in 0..3000u32 {
+        data.extend_from_slice(
+            format!("<tr><td>{}</td><td>item {}</td></tr>\n", i, i % 17).as_bytes(),
+        );
+        if i % 500 == 0 {
+            data.extend(std::iter::repeat_n(b'=', 1000));
+            for _ in 0..300 {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                data.push(x as u8);
+            }
+        }
+    }
+    let tail = data[data.len() - 40_000..data.len() - 39_000].to_vec();
+    data.extend_from_slice(&tail);
+    assert!(data.len() > 100_000);
+
+    let packed = gzip::compress(&data);
+    assert!(packed.len() < data.len() / 4);
+    assert_eq!(packed[..10], [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+    let n = packed.len();
+    assert_eq!(inflate(&packed[10..n - 8]), data);
+    assert_eq!(packed[n - 8..n - 4], gzip::crc32(&data).to_le_bytes());
+    assert_eq!(packed[n - 4..], (data.len() as u32).to_le_bytes());
+
+    let packed = gzip::zlib(&data);
+    assert_eq!(inflate(&packed[2..packed.len() - 4]), data);
+    assert_eq!(inflate(&gzip::compress(b"")[10..]), b"");
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_etag_differs() {
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    let page = "<li>item</li>\n".repeat(500);
+    let respond = |accept: &str, etag: &str| {
+        let req = MockRequest::get("/list")
+            .header("Accept-Encoding", accept)
+            .build()
+            .unwrap();
+        let r = Response::new(200)
+            .with_content_type("text/html")
+            .with_body(page.clone());
+        let r = match etag {
+            "" => r.with_etag_from_body(),
+            etag => r.with_header("ETag", etag),
+        };
+        r.compressed_for(&req)
+    };
+
+    let identity = respond("identity", "");
+    let gzipped = respond("gzip", "");
+    assert_eq!(gzipped.get_header("content-encoding"), Some("gzip"));
+    let tag = identity.get_header("etag").unwrap();
+    assert_ne!(gzipped.get_header("etag"), Some(tag));
+    assert_eq!(
+        gzipped.get_header("etag").unwrap(),
+        format!("{}-gzip\"", tag.trim_end_matches('"'))
+    );
+
+    // A revalidation of the compressed copy matches the compressed tag.
+    let gz_tag = gzipped.get_header("etag").unwrap().to_owned();
+    let req = MockRequest::get("/list")
+        .header("Accept-Encoding", "gzip")
+        .header("If-None-Match", &gz_tag)
+        .build()
+        .unwrap();
+    assert_eq!(respond("gzip", "").not_modified_if(&req).get_status(), 304);
+
+    // Weak tags may be shared by both, and are left alone.
+    let weak = respond("gzip", "W/\"v1\"");
+    assert_eq!(weak.get_header("etag"), Some("W/\"v1\""));
+}
+
+#[test]
+fn wizard_steps() {
+    use crate::testing::MockRequest;
+    use crate::wizard::{Step, Wizard};
+
+    let scratch = Scratch::new("wizard_test");
+    let dir = scratch.path();
+    let wizard = Wizard::new("order", dir)
+        .with_step(Step::new("size").with_required_field("size"))
+        .with_step(
+            Step::new("extras")
+                .with_field("cheese")
+                .with_field("count")
+                .with_validator(|a| match a.get("count").map(|c| c.parse::<u32>()) {
+                    Some(Ok(1..=9)) => Vec::new(),
+                    _ => vec![("count".to_owned(), "1 to 9, please.".to_owned())],
+                }),
+        );
+
+    let first = wizard
+        .process(&MockRequest::get("/order").build().unwrap())
+        .unwrap();
+    let cookie = wizard.cookie(&first).to_string();
+    assert!(cookie.starts_with("order_wizard="));
+    assert!(cookie.ends_with("; HttpOnly; SameSite=Lax"));
+    let cookie = cookie.split(';').next().unwrap().to_owned();
+    let post = |body: &str| {
+        let req = MockRequest::post("/order")
+            .header("Cookie", &cookie)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_owned())
+            .build()
+            .unwrap();
+        wizard.process(&req).unwrap()
+    };
+
+    let state = post("size=large&wizard_action=next");
+    assert_eq!(state.step(), 1);
+    // Going back saves this page's answers without checking them.
+    let state = post("cheese=on&count=many&wizard_action=back");
+    assert_eq!((state.step(), state.value("count")), (0, Some("many")));
+    assert!(state.errors().is_empty());
+    let state = post("size=+&wizard_action=next");
+    assert_eq!(state.error("size"), Some("This field is required."));
+    let state = post("size=small&wizard_action=next");
+    assert_eq!(state.step(), 1);
+
+    // Plain reloads just show the current page with the saved answers.
+    let reload = MockRequest::get("/order")
+        .header("Cookie", &cookie)
+        .build()
+        .unwrap();
+    let state = wizard.process(&reload).unwrap();
+    assert_eq!((state.step(), state.value("cheese")), (1, Some("on")));
+
+    // An unchecked checkbox isn't sent, and is forgotten.
+    let state = post("count=12&wizard_action=next");
+    assert_eq!(state.error("count"), Some("1 to 9, please."));
+    assert!(!state.is_complete());
+    let state = post("count=2&wizard_action=next");
+    assert!(state.is_complete());
+    assert_eq!(state.value("cheese"), None);
+    assert_eq!(state.value("size"), Some("small"));
+
+    let removal = wizard.finish(&state).unwrap();
+    assert_eq!(removal.name(), "order_wizard");
+    let state = wizard.process(&reload).unwrap();
+    assert_eq!(state.step(), 0);
+    assert!(state.answers().is_empty());
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compression_policy() {
+    use crate::gzip::{self, Coding, CompressionPolicy};
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    // zlib's header, and the Adler-32 of "Wikipedia" from its article.
+    let packed = gzip::zlib(b"Wikipedia");
+    assert_eq!(packed[..2], [0x78, 0x01]);
+    assert_eq!(packed[packed.len() - 4..], 0x11E6_0398u32.to_be_bytes());
+
+    let policy = CompressionPolicy::new();
+    assert!(policy.allows("TEXT/CSS", 2000));
+    assert!(policy.allows("application/atom+xml; charset=utf-8", 2000));
+    assert!(!policy.allows("application/octet-stream", 2000));
+    assert!(!policy.allows("text/css", 1023));
+    let only_csv = policy.clone().with_types(["text/csv"]);
+    assert!(only_csv.allows("text/csv", 2000));
+    assert!(!only_csv.allows("text/html", 2000));
+    assert!(!policy.clone().with_codings([]).allows("text/css", 2000));
+
+    assert_eq!(policy.choose("deflate, gzip"), Some(Coding::Gzip));
+    assert_eq!(policy.choose("x-gzip;q=0, *"), Some(Coding::Deflate));
+    assert_eq!(policy.choose("identity, *;q=0"), None);
+
+    let csv = "id,name\n".to_owned() + &"1,widget\n".repeat(300);
+    let respond = |accept: &str, policy: &CompressionPolicy| {
+        let req = MockRequest::get("/export")
+            .header("Accept-Encoding", accept)
+            .build()
+            .unwrap();
+        Response::new(200)
+            .with_header("Content-Type", "text/csv")
+            .with_body(csv.clone())
+            .compressed_with(&req, policy)
+    };
+
+    let deflate_first = CompressionPolicy::new().with_codings([Coding::Deflate, Coding::Gzip]);
+    let r = respond("gzip, deflate", &deflate_first);
+    assert_eq!(r.get_header("content-encoding"), Some("deflate"));
+    assert_eq!(r.get_body()[..2], [0x78, 0x01]);
+    let r = respond("br", &deflate_first);
+    assert_eq!(r.get_header("content-encoding"), None);
+    assert_eq!(r.get_header("vary"), Some("Accept-Encoding"));
+    // Not worth compressing, so not varying either.
+    let r = respond(
+        "gzip",
+        &CompressionPolicy::new().with_min_size(csv.len() + 1),
+    );
+    assert_eq!(r.get_header("content-encoding"), None);
+    assert_eq!(r.get_header("vary"), None);
+}
+
+#[test]
+fn submit_tokens() {
+    use crate::submit::SubmitGuard;
+    use crate::testing::MockRequest;
+
+    let scratch = Scratch::new("submit_test");
+    let dir = scratch.path();
+    let guard = SubmitGuard::new("key", dir).with_field_name("once");
+
+    let a = guard.token().unwrap();
+    let b = guard.token().unwrap();
+    assert_ne!(a, b);
+    assert!(guard.check_token(&a).is_ok());
+    assert_eq!(guard.check_token(&a).unwrap_err().code, 409);
+    assert!(guard.check_token(&b).is_ok());
+
+    // Signed with another key, tampered with, or mangled.
+    let other = SubmitGuard::new("other key", dir).token().unwrap();
+    let (issued, rest) = other.split_once('.').unwrap();
+    let tampered = format!("{}.{}", issued.parse::<u64>().unwrap() + 1, rest);
+    for token in [other.as_str(), tampered.as_str(), "", "1.2", "x.y.z"] {
+        let err = guard.check_token(token).unwrap_err();
+        assert_eq!(err.code, 400);
+        assert!(err.message.contains("reload"));
+    }
+
+    // Out of date.
+    let expired = SubmitGuard::new("key", dir).with_max_age(0);
+    let token = expired.token().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert_eq!(expired.check_token(&token).unwrap_err().code, 400);
+
+    let post = |body: String| {
+        MockRequest::post("/comment")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .build()
+            .unwrap()
+    };
+    let token = guard.token().unwrap();
+    assert!(guard.check(&post(format!("once={}", &token))).is_ok());
+    assert_eq!(
+        guard
+            .check(&post(format!("once={}", &token)))
+            .unwrap_err()
+            .code,
+        409
+    );
+    assert_eq!(
+        guard.check(&post("text=hi".to_owned())).unwrap_err().code,
+        400
+    );
+    assert!(guard.hidden_input().unwrap().contains("name=\"once\""));
+}
+
+#[test]
+fn conditional_get() {
+    use crate::testing::MockRequest;
+    use crate::time::{http_date, parse_http_date};
+    use crate::Response;
+
+    assert_eq!(
+        parse_http_date("Thu, 29 Feb 2024 23:59:60 GMT"),
+        Some(1_709_251_200)
+    );
+    for bad in [
+        "Fri, 30 Feb 2024 00:00:00 GMT",
+        "Sun, 06 Nov 1994 24:00:00 GMT",
+        "Sun, 06 Nov 1994 08:49 GMT",
+        "Sun, 06 Foo 1994 08:49:37 GMT",
+    ] {
+        assert_eq!(parse_http_date(bad), None, "{}", bad);
+    }
+    assert_eq!(
+        parse_http_date(&http_date(1_700_000_000)),
+        Some(1_700_000_000)
+    );
+
+    let modified = http_date(1_700_000_000);
+    let page = || {
+        Response::new(200)
+            .with_content_type("text/plain")
+            .with_header("Last-Modified", modified.as_str())
+            .with_header("Content-Language", "en")
+            .with_header("Vary", "Accept-Language")
+            .with_body("hello")
+            .with_etag_from_body()
+    };
+    let etag = page().get_header("etag").unwrap().to_owned();
+    let get = |name: &str, value: &str| {
+        let req = MockRequest::get("/").header(name, value).build().unwrap();
+        page().not_modified_if(&req)
+    };
+
+    let r = get("If-None-Match", &format!("W/{}", &etag));
+    assert_eq!(r.get_status(), 304);
+    assert_eq!(r.get_content_type(), "");
+    assert_eq!(r.get_header("content-language"), None);
+    assert_eq!(r.get_header("vary"), Some("Accept-Language"));
+    assert_eq!(r.get_header("last-modified"), Some(modified.as_str()));
+    let bytes = r.to_bytes().unwrap();
+    assert!(bytes.starts_with(b"Status: 304 Not Modified\r\n"));
+    assert!(bytes.ends_with(b"\r\n\r\n"));
+    assert_eq!(get("If-None-Match", "*").get_status(), 304);
+    assert_eq!(get("If-None-Match", "\"other\"").get_status(), 200);
+
+    assert_eq!(get("If-Modified-Since", &modified).get_status(), 304);
+    assert_eq!(
+        get("If-Modified-Since", &http_date(1_700_000_001)).get_status(),
+        304
+    );
+    assert_eq!(
+        get("If-Modified-Since", &http_date(1_699_999_999)).get_status(),
+        200
+    );
+    assert_eq!(get("If-Modified-Since", "yesterday").get_status(), 200);
+
+    // If-None-Match takes precedence over If-Modified-Since.
+    let req = MockRequest::get("/")
+        .header("If-None-Match", "\"other\"")
+        .header("If-Modified-Since", modified.as_str())
+        .build()
+        .unwrap();
+    assert_eq!(page().not_modified_if(&req).get_status(), 200);
+
+    // Only for successful GETs and HEADs.
+    let req = MockRequest::post("/")
+        .header("If-None-Match", "*")
+        .build()
+        .unwrap();
+    assert_eq!(page().not_modified_if(&req).get_status(), 200);
+    let req = MockRequest::get("/")
+        .header("If-None-Match", "*")
+        .build()
+        .unwrap();
+    let r = Response::new(404).with_body("gone").with_etag_from_body();
+    assert_eq!(r.not_modified_if(&req).get_status(), 404);
+}
+
+#[test]
+fn post_redirect_get() {
+    use crate::flash::{Flash, Flasher};
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    let flasher = Flasher::new("key");
+    let sent = [Flash::success("2 + 2 = 4; 100% sure")];
+    let post = MockRequest::post("/cart")
+        .var("HTTPS", "on")
+        .build()
+        .unwrap();
+    let r = Response::prg_redirect(&post, "/cart\r\nSet-Cookie: x=1", &flasher, &sent);
+    assert_eq!(r.get_header("location"), Some("/cartSet-Cookie: x=1"));
+    let cookies = r.get_cookies();
+    assert_eq!(cookies.len(), 1);
+    assert!(cookies[0].starts_with("flashes="));
+    assert!(cookies[0].contains("; Path=/; Max-Age=300;"));
+    assert!(cookies[0].ends_with("; Secure; HttpOnly; SameSite=Lax"));
+    assert!(r
+        .to_bytes()
+        .unwrap()
+        .starts_with(b"Status: 303 See Other\r\n"));
+
+    let flash = cookies[0].split(';').next().unwrap().to_owned();
+    let get = MockRequest::get("/cart")
+        .header("Cookie", format!("theme=dark; {}", &flash))
+        .build()
+        .unwrap();
+    let mut page = Response::new(200);
+    assert_eq!(get.take_flash(&flasher, &mut page), sent);
+    assert_eq!(page.get_cookies().len(), 1);
+    assert!(page.get_cookies()[0].starts_with("flashes=; Path=/; Max-Age=0"));
+
+    // The message is signed: another key doesn't accept it.
+    let mut page = Response::new(200);
+    assert!(get.take_flash(&Flasher::new("other"), &mut page).is_empty());
+
+    // Over plain HTTP, the cookie is only `Secure` if the flasher says so.
+    let post = MockRequest::post("/cart").build().unwrap();
+    let r = Response::prg_redirect(&post, "/cart", &flasher, &sent);
+    assert!(!r.get_cookies()[0].contains("Secure"));
+    let r = Response::prg_redirect(&post, "/cart", &flasher.clone().with_secure(true), &sent);
+    assert!(r.get_cookies()[0].contains("; Secure;"));
+
+    // No message: no flash cookie, and nothing to take.
+    let r = Response::prg_redirect(&post, "/cart", &flasher, &[]);
+    assert!(r.get_cookies().is_empty());
+    let mut page = Response::new(200);
+    assert!(MockRequest::get("/cart")
+        .build()
+        .unwrap()
+        .take_flash(&flasher, &mut page)
+        .is_empty());
+    assert!(page.get_cookies().is_empty());
+}
+
+#[test]
+fn flash_messages() {
+    use crate::flash::{render, Flash, Flasher, Level};
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    let flasher = Flasher::new("key")
+        .with_cookie_name("notes")
+        .with_secure(true);
+    let sent = vec![
+        Flash::error("Card declined: <insufficient funds>"),
+        Flash::new(Level::Info, "Try another card | or call us."),
+    ];
+    let mut r = Response::new(303);
+    flasher.set(&mut r, &sent);
+    let set_cookie = r.get_cookies()[0];
+    assert!(set_cookie.starts_with("notes="));
+    assert!(set_cookie.ends_with("; Path=/; Max-Age=300; Secure; HttpOnly; SameSite=Lax"));
+    let cookie = set_cookie.split(';').next().unwrap().to_owned();
+
+    let take = |flasher: &Flasher, cookie: &str| {
+        let req = MockRequest::get("/")
+            .header("Cookie", cookie)
+            .build()
+            .unwrap();
+        let mut page = Response::new(200);
+        let messages = flasher.take(&req, &mut page);
+        (messages, page.get_cookies().len())
+    };
+    assert_eq!(take(&flasher, &cookie), (sent.clone(), 1));
+    assert_eq!(
+        render(&sent),
+        "<div class=\"flash flash-error\" role=\"alert\">Card declined: &lt;insufficient funds&gt;</div>\n\
+         <div class=\"flash flash-info\" role=\"status\">Try another card | or call us.</div>\n"
+    );
+
+    // Tampered with, or signed with another key: dropped, but removed.
+    let tampered = cookie.replacen("error", "success", 1);
+    assert_eq!(take(&flasher, &tampered), (Vec::new(), 1));
+    let other = Flasher::new("other key").with_cookie_name("notes");
+    assert_eq!(take(&other, &cookie), (Vec::new(), 1));
+    // No cookie: nothing to do.
+    assert_eq!(take(&flasher, "theme=dark"), (Vec::new(), 0));
+
+    let mut r = Response::new(303);
+    flasher.set(&mut r, &[]);
+    assert!(r.get_cookies().is_empty());
+    assert_eq!(render(&[]), "");
+}
+
+#[test]
+fn last_modified() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    let when = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let r = Response::new(200)
+        .with_header("Last-Modified", "yesterday")
+        .with_last_modified(when);
+    assert_eq!(
+        r.get_header_lines("last-modified"),
+        ["Tue, 14 Nov 2023 22:13:20 GMT"]
+    );
+    let r = Response::new(200).with_last_modified(UNIX_EPOCH - Duration::from_secs(86_400));
+    assert_eq!(
+        r.get_header("last-modified"),
+        Some("Wed, 31 Dec 1969 00:00:00 GMT")
+    );
+
+    // send_file() dates the response by the file's mtime, so it can be
+    // checked against If-Modified-Since.
+    let scratch = Scratch::new("mtime");
+    let path = scratch.path().join("mtime.txt");
+    std::fs::write(&path, "dated").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(when)
+        .unwrap();
+    let r = Response::send_file(&path).unwrap();
+    assert_eq!(
+        r.get_header("last-modified"),
+        Some("Tue, 14 Nov 2023 22:13:20 GMT")
+    );
+    let req = MockRequest::get("/dated.txt")
+        .header("If-Modified-Since", "Tue, 14 Nov 2023 22:13:20 GMT")
+        .build()
+        .unwrap();
+    assert_eq!(r.not_modified_if(&req).get_status(), 304);
+}
+
+#[test]
+fn cache_control() {
+    use crate::cache::CacheControl;
+    use crate::Response;
+
+    assert_eq!(CacheControl::new().to_string(), "");
+    // Written in a fixed order, whatever order the directives were set in.
+    let everything = CacheControl::new()
+        .immutable()
+        .no_transform()
+        .proxy_revalidate()
+        .must_revalidate()
+        .stale_if_error(86_400)
+        .stale_while_revalidate(30)
+        .s_maxage(600)
+        .max_age(60)
+        .no_store()
+        .no_cache();
+    assert_eq!(
+        CacheControl::public()
+            .max_age(60)
+            .s_maxage(600)
+            .stale_while_revalidate(30)
+            .stale_if_error(86_400)
+            .no_cache()
+            .no_store()
+            .must_revalidate()
+            .proxy_revalidate()
+            .no_transform()
+            .immutable()
+            .to_string(),
+        "public, ".to_owned() + &everything.to_string()
+    );
+    assert_eq!(
+        everything.to_string(),
+        "no-cache, no-store, max-age=60, s-maxage=600, stale-while-revalidate=30, \
+         stale-if-error=86400, must-revalidate, proxy-revalidate, no-transform, immutable"
+    );
+
+    let r = Response::new(200)
+        .with_header("Cache-Control", "pubilc, max-age=60")
+        .with_cache_control(CacheControl::public().max_age(31_536_000).immutable());
+    assert_eq!(
+        r.get_header_lines("cache-control"),
+        ["public, max-age=31536000, immutable"]
+    );
+}
+
+#[test]
+fn cors() {
+    use crate::cors::Cors;
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    let from = |origin: &str| {
+        MockRequest::get("/api")
+            .header("Origin", origin)
+            .build()
+            .unwrap()
+    };
+    let preflight = |origin: &str| {
+        MockRequest::new("OPTIONS", "/api")
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", "PUT")
+            .header("Access-Control-Request-Headers", "x-token, content-type")
+            .build()
+            .unwrap()
+    };
+
+    // Any origin, without credentials: a plain `*`.
+    let cors = Cors::new().with_any_origin().with_timing_allow(true);
+    let r = cors.apply(&from("https://a.example"), Response::new(200));
+    assert_eq!(r.get_header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(r.get_header("Timing-Allow-Origin"), Some("*"));
+    assert_eq!(r.get_header("Access-Control-Allow-Credentials"), None);
+    assert_eq!(r.get_header("Vary"), Some("Origin"));
+
+    // Any origin, "with" credentials: still `*`, and no credentials, even
+    // for a `null` origin.
+    let cors = cors.with_credentials(true);
+    for origin in ["https://a.example", "null"] {
+        let r = cors.apply(&from(origin), Response::new(200));
+        assert_eq!(r.get_header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(r.get_header("Access-Control-Allow-Credentials"), None);
+        let r = cors.preflight(&preflight(origin)).unwrap();
+        assert_eq!(r.get_header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(r.get_header("Access-Control-Allow-Credentials"), None);
+    }
+
+    // A validator, with credentials: the origin is reflected.
+    let cors = Cors::new()
+        .with_origin_validator(|o| o == "https://a.example")
+        .with_credentials(true)
+        .with_timing_allow(true);
+    let r = cors.apply(&from("https://a.example"), Response::new(200));
+    assert_eq!(
+        r.get_header("Access-Control-Allow-Origin"),
+        Some("https://a.example")
+    );
+    assert_eq!(
+        r.get_header("Timing-Allow-Origin"),
+        Some("https://a.example")
+    );
+    assert_eq!(
+        r.get_header("Access-Control-Allow-Credentials"),
+        Some("true")
+    );
+    assert_eq!(r.get_header("Vary"), Some("Origin"));
+    let r = cors.apply(&from("null"), Response::new(200));
+    assert_eq!(r.get_header("Access-Control-Allow-Origin"), None);
+    assert_eq!(r.get_header("Access-Control-Allow-Credentials"), None);
+
+    // A validator; refused origins get nothing.
+    let cors = Cors::new()
+        .with_origin_validator(|o| o.ends_with(".example"))
+        .with_expose_headers(["X-Page", "X-Total"])
+        .with_any_header();
+    let r = cors.apply(&from("https://b.example"), Response::new(200));
+    assert_eq!(
+        r.get_header("Access-Control-Expose-Headers"),
+        Some("X-Page, X-Total")
+    );
+    let r = cors.apply(&from("https://evil.test"), Response::new(200));
+    assert_eq!(r.get_header("Access-Control-Allow-Origin"), None);
+    assert_eq!(r.get_header("Access-Control-Expose-Headers"), None);
+    let r = cors.apply(
+        &MockRequest::get("/api").build().unwrap(),
+        Response::new(200),
+    );
+    assert_eq!(r.get_header("Access-Control-Allow-Origin"), None);
+    assert_eq!(r.get_header("Vary"), Some("Origin"));
+    let r = cors.apply(
+        &from("https://b.example"),
+        Response::new(200).with_header("Vary", "Accept-Encoding, origin"),
+    );
+    assert_eq!(r.get_header("Vary"), Some("Accept-Encoding, origin"));
+
+    // Preflights.
+    let r = cors.preflight(&preflight("https://b.example")).unwrap();
+    assert_eq!(r.get_status(), 204);
+    assert_eq!(
+        r.get_header("Access-Control-Allow-Headers"),
+        Some("x-token, content-type")
+    );
+    assert_eq!(
+        r.get_header("Access-Control-Allow-Methods"),
+        Some("GET, HEAD, POST")
+    );
+    assert_eq!(r.get_header("Access-Control-Max-Age"), None);
+    let r = cors.preflight(&preflight("https://evil.test")).unwrap();
+    assert_eq!(r.get_status(), 204);
+    assert_eq!(r.get_header("Access-Control-Allow-Origin"), None);
+    assert_eq!(r.get_header("Access-Control-Allow-Methods"), None);
+    assert_eq!(
+        r.get_header("Vary"),
+        Some("Origin, Access-Control-Request-Method, Access-Control-Request-Headers")
+    );
+    let plain_options = MockRequest::new("OPTIONS", "/api")
+        .header("Origin", "https://b.example")
+        .build()
+        .unwrap();
+    assert!(cors.preflight(&plain_options).is_none());
+
+    // Listed origins, with a fixed header list.
+    let cors = Cors::new()
+        .with_origin("https://one.example")
+        .with_origin("https://two.example")
+        .with_allowed_headers(["X-Token"])
+        .with_max_age(60);
+    assert!(cors.allows_origin("https://two.example"));
+    assert!(!cors.allows_origin("https://three.example"));
+    let r = cors.preflight(&preflight("https://one.example")).unwrap();
+    assert_eq!(
+        r.get_header("Access-Control-Allow-Headers"),
+        Some("X-Token")
+    );
+    assert_eq!(r.get_header("Access-Control-Max-Age"), Some("60"));
+}
+
+#[test]
+fn http10_compat() {
+    use crate::testing::MockRequest;
+    use crate::Response;
+    use std::io::Write;
+
+    let old = MockRequest::get("/")
+        .var("SERVER_PROTOCOL", "HTTP/1.0")
+        .build()
+        .unwrap();
+    let new = MockRequest::get("/").build().unwrap();
+
+    // Nothing changes for HTTP/1.1.
+    let r = Response::new(307)
+        .with_header("Location", "/elsewhere")
+        .with_header("Transfer-Encoding", "chunked")
+        .http10_compat_for(&new);
+    assert_eq!(
+        r.to_bytes().unwrap(),
+        b"Status: 307 Temporary Redirect\r\nLocation: /elsewhere\r\n\
+          Transfer-Encoding: chunked\r\n\r\n"
+    );
+    let r = r.http10_compat_for(&old);
+    assert_eq!(
+        r.to_bytes().unwrap(),
+        b"Status: 302 Found\r\nLocation: /elsewhere\r\nContent-length: 0\r\n\r\n"
+    );
+    let r = Response::new(308)
+        .with_status_text("Moved For Good")
+        .http10_compat_for(&old);
+    assert!(r
+        .to_bytes()
+        .unwrap()
+        .starts_with(b"Status: 301 Moved Permanently\r\n"));
+
+    // Readers of unknown length are measured.
+    let r = Response::new(200)
+        .with_content_type("text/plain")
+        .with_body_reader(std::io::Cursor::new(b"streamed".to_vec()), None)
+        .http10_compat_for(&old);
+    assert_eq!(
+        r.to_bytes().unwrap(),
+        b"Status: 200 OK\r\nContent-type: text/plain\r\nContent-length: 8\r\n\r\nstreamed"
+    );
+
+    // Streamed bodies are held until they're finished.
+    let mut body = Response::new(200)
+        .with_content_type("text/csv")
+        .with_body("n\n")
+        .http10_compat_for(&old)
+        .start_to(Vec::new())
+        .unwrap();
+    writeln!(body, "1").unwrap();
+    body.flush().unwrap();
+    writeln!(body, "2").unwrap();
+    assert_eq!(
+        body.finish().unwrap(),
+        b"Status: 200 OK\r\nContent-type: text/csv\r\nContent-length: 6\r\n\r\nn\n1\n2\n"
+    );
+
+    // Cache-Control gets its HTTP/1.0 equivalents, unless they're set.
+    let r = Response::new(200)
+        .with_header("Cache-Control", "public, max-age=60")
+        .http10_compat_for(&old);
+    let text = String::from_utf8(r.to_bytes().unwrap()).unwrap();
+    let expires = text.split("\r\nExpires: ").nth(1).unwrap();
+    let expires = crate::time::parse_http_date(&expires[..29]).unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    assert!((now + 58..=now + 61).contains(&expires));
+    let r = Response::new(200)
+        .with_header("Cache-Control", "no-cache")
+        .with_header("Expires", "0")
+        .with_header("Pragma", "no-cache")
+        .http10_compat_for(&old);
+    assert_eq!(
+        r.to_bytes().unwrap(),
+        b"Status: 200 OK\r\nCache-Control: no-cache\r\nExpires: 0\r\nPragma: no-cache\r\n\
+          Content-length: 0\r\n\r\n"
+    );
+}
+
+#[test]
+fn security_headers() {
+    use crate::security::{FrameOptions, SecurityHeaders, DEFAULT_HSTS_MAX_AGE};
+    use crate::Response;
+
+    let r = SecurityHeaders::new()
+        .with_secure(true)
+        .apply(Response::new(200));
+    assert_eq!(r.get_header("X-Content-Type-Options"), Some("nosniff"));
+    assert_eq!(r.get_header("X-Frame-Options"), Some("SAMEORIGIN"));
+    assert_eq!(
+        r.get_header("Referrer-Policy"),
+        Some("strict-origin-when-cross-origin")
+    );
+    assert_eq!(
+        r.get_header("Strict-Transport-Security"),
+        Some(format!("max-age={}", DEFAULT_HSTS_MAX_AGE).as_str())
+    );
+
+    // No HSTS over plain HTTP.
+    let r = SecurityHeaders::new()
+        .with_secure(false)
+        .apply(Response::new(200));
+    assert_eq!(r.get_header("Strict-Transport-Security"), None);
+
+    // Everything off.
+    let none = SecurityHeaders::new()
+        .with_nosniff(false)
+        .with_frame_options(None)
+        .with_referrer_policy(None)
+        .with_hsts(None)
+        .with_secure(true);
+    assert_eq!(
+        none.apply(Response::new(200)).to_bytes().unwrap(),
+        Response::new(200).to_bytes().unwrap()
+    );
+
+    // Existing headers are kept.
+    let r = SecurityHeaders::new()
+        .with_frame_options(Some(FrameOptions::Deny))
+        .with_referrer_policy(Some("no-referrer"))
+        .with_hsts(Some(600))
+        .with_hsts_subdomains(true)
+        .with_secure(true)
+        .apply(Response::new(200).with_header("Referrer-Policy", "same-origin"));
+    assert_eq!(r.get_header("X-Frame-Options"), Some("DENY"));
+    assert_eq!(r.get_header("Referrer-Policy"), Some("same-origin"));
+    assert_eq!(
+        r.get_header("Strict-Transport-Security"),
+        Some("max-age=600; includeSubDomains")
+    );
+}
+
+#[test]
+fn byte_order_marks() {
+    use crate::charset::{decode_text, strip_bom, with_bom, Charset};
+    use crate::testing::MockRequest;
+    use crate::Response;
+
+    assert_eq!(strip_bom(b"\xEF\xBB"), b"\xEF\xBB");
+    assert_eq!(strip_bom(b"\xEF\xBB\xBF"), b"");
+    assert_eq!(strip_bom(&with_bom("a,b")), b"a,b");
+
+    // The mark beats the charset it's given.
+    assert_eq!(
+        decode_text(b"\xEF\xBB\xBFcaf\xC3\xA9", Charset::Latin1).unwrap(),
+        "café"
+    );
+    assert_eq!(
+        decode_text(
+            b"\xFE\xFF\x00c\x00a\x00f\x00\xE9\xD8\x3D\xDE\x00",
+            Charset::Utf8
+        )
+        .unwrap(),
+        "café\u{1F600}"
+    );
+    assert!(decode_text(b"\xFF\xFE{\x00\"", Charset::Utf8).is_err());
+    assert!(decode_text(b"\xFF\xFE\x00\xD8", Charset::Utf8).is_err());
+    assert!(decode_text(b"\xC3", Charset::Utf8).is_err());
+
+    let req = |content_type: &str, body: &[u8]| {
+        MockRequest::post("/")
+            .header("Content-Type", content_type)
+            .body(body.to_vec())
+            .build()
+            .unwrap()
+    };
+    assert_eq!(
+        req("text/csv; charset=windows-1252", b"\x93hi\x94")
+            .body_text()
+            .unwrap(),
+        "\u{201c}hi\u{201d}"
+    );
+    assert_eq!(
+        req("application/json; charset=utf-8", b"\xFF\xFE1\x00")
+            .body_text()
+            .unwrap(),
+        "1"
+    );
+    let err = req("application/json; charset=koi8-r", b"{}")
+        .body_text()
+        .unwrap_err();
+    assert_eq!(err.code, 400);
+    assert_eq!(
+        MockRequest::get("/").build().unwrap().body_text().unwrap(),
+        ""
+    );
+
+    // A BOM is only added once.
+    let r = Response::new(200).with_body(with_bom("x")).with_utf8_bom();
+    assert_eq!(r.get_body(), b"\xEF\xBB\xBFx");
+    let r = Response::new(200)
+        .with_content_type("text/csv")
+        .with_body_reader(std::io::Cursor::new(b"a,b\n".to_vec()), Some(4))
+        .with_utf8_bom();
+    assert!(r
+        .to_bytes()
+        .unwrap()
+        .ends_with(b"Content-length: 7\r\n\r\n\xEF\xBB\xBFa,b\n"));
+}
+
+#[test]
+fn content_security_policy() {
+    use crate::security::{nonce, Csp, SELF, UNSAFE_INLINE};
+    use crate::Response;
+
+    // Keywords are quoted, strings are split, and sources that would
+    // break the policy are dropped.
+    let csp = Csp::new()
+        .default_src(["self"])
+        .script_src(["'self' https://cdn.example.com", "evil; script-src *"])
+        .style_src([SELF, UNSAFE_INLINE, "Unsafe-Eval"])
+        .connect_src(["a.example,b.example"])
+        .report_uri("/csp report;x");
+    assert_eq!(
+        csp.to_string(),
+        "default-src 'self'; script-src 'self' https://cdn.example.com; \
+         style-src 'self' 'unsafe-inline' 'unsafe-eval'; connect-src 'none'; \
+         report-uri /cspreportx"
+    );
+
+    // Setting a directive again replaces it in place.
+    let csp = csp
+        .script_src([SELF, nonce("abc").as_str()])
+        .frame_ancestors(Vec::<&str>::new());
+    assert_eq!(
+        csp.to_string(),
+        "default-src 'self'; script-src 'self' 'nonce-abc'; \
+         style-src 'self' 'unsafe-inline' 'unsafe-eval'; connect-src 'none'; \
+         report-uri /cspreportx; frame-ancestors 'none'"
+    );
+
+    assert_eq!(Csp::new().to_string(), "");
+    assert_eq!(
+        Csp::new().upgrade_insecure_requests().to_string(),
+        "upgrade-insecure-requests"
+    );
+
+    let r = Response::new(200)
+        .with_header("Content-Security-Policy", "default-src *")
+        .with_csp(&Csp::new().default_src([SELF]));
+    assert_eq!(
+        r.get_header_lines("content-security-policy"),
+        ["default-src 'self'"]
+    );
+    assert_eq!(r.get_header("Content-Security-Policy-Report-Only"), None);
+}
@@ -0,0 +1,161 @@
+/*!
+Tools for testing code that consumes `Request`s without having to fake a
+whole CGI environment in a subprocess.
+
+```rust
+use dumb_cgi::{testing::MockRequest, Query};
+
+let req = MockRequest::get("/search")
+    .query("q=dumb+cgi&page=2")
+    .header("Accept", "text/html")
+    .build()
+    .unwrap();
+
+assert_eq!(req.var("REQUEST_METHOD"), Some("GET"));
+assert_eq!(req.var("PATH_INFO"), Some("/search"));
+assert_eq!(req.header("accept"), Some("text/html"));
+match req.query() {
+    Query::Some(map) => assert_eq!(map.get("q").unwrap(), "dumb cgi"),
+    _ => panic!("query string should have parsed"),
+}
+```
+*/
+
+use crate::{Error, MultipartLimits, Request, RequestOptions};
+
+/**
+A builder for `Request`s that come from a fake environment instead of the
+real one.
+
+The resulting `Request` is produced by exactly the same parsing code as
+`Request::new()`; the only difference is where the environment variables
+and body bytes come from.
+*/
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    vars: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+    options: RequestOptions,
+}
+
+impl MockRequest {
+    /**
+    Start building a request with the given method and path.
+
+    This sets the `REQUEST_METHOD`, `PATH_INFO`, `SERVER_PROTOCOL`, and
+    `GATEWAY_INTERFACE` variables.
+    */
+    pub fn new<M, P>(method: M, path: P) -> MockRequest
+    where
+        M: Into<String>,
+        P: Into<String>,
+    {
+        MockRequest {
+            vars: vec![
+                ("GATEWAY_INTERFACE".to_owned(), "CGI/1.1".to_owned()),
+                ("SERVER_PROTOCOL".to_owned(), "HTTP/1.1".to_owned()),
+                ("REQUEST_METHOD".to_owned(), method.into()),
+                ("PATH_INFO".to_owned(), path.into()),
+            ],
+            headers: Vec::new(),
+            body: None,
+            options: RequestOptions::default(),
+        }
+    }
+
+    /// Start building a `GET` request for `path`.
+    pub fn get<P: Into<String>>(path: P) -> MockRequest {
+        MockRequest::new("GET", path)
+    }
+
+    /// Start building a `POST` request for `path`.
+    pub fn post<P: Into<String>>(path: P) -> MockRequest {
+        MockRequest::new("POST", path)
+    }
+
+    /// Set the (still percent-encoded) query string.
+    pub fn query<T: Into<String>>(self, query_string: T) -> MockRequest {
+        self.var("QUERY_STRING", query_string)
+    }
+
+    /**
+    Set an environment variable. Setting the same variable twice replaces
+    the first value.
+    */
+    pub fn var<N, V>(self, name: N, value: V) -> MockRequest
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let mut new = self;
+        let name = name.into().to_uppercase();
+        new.vars.retain(|(k, _)| k != &name);
+        new.vars.push((name, value.into()));
+        new
+    }
+
+    /**
+    Add a request header. The name is mangled the same way a web server
+    would mangle it when exposing it to a CGI program (so both
+    `"Content-Type"` and `"content_type"` are fine).
+    */
+    pub fn header<N, V>(self, name: N, value: V) -> MockRequest
+    where
+        N: AsRef<str>,
+        V: Into<String>,
+    {
+        let mut new = self;
+        let name = name.as_ref().replace('-', "_").to_uppercase();
+        new.headers.retain(|(k, _)| k != &name);
+        new.headers.push((name, value.into()));
+        new
+    }
+
+    /**
+    Set the request body. A `Content-Length` header is added automatically
+    (replacing any set explicitly).
+    */
+    pub fn body<B: Into<Vec<u8>>>(self, body: B) -> MockRequest {
+        let mut new = self;
+        new.body = Some(body.into());
+        new
+    }
+
+    /// Parse a multipart body with `limits` (as `Request::new_with_limits()` would).
+    pub fn multipart_limits(self, limits: MultipartLimits) -> MockRequest {
+        let mut new = self;
+        new.options.multipart = limits;
+        new
+    }
+
+    /// Read the request according to `options` (as `Request::new_with_options()` would).
+    pub fn options(self, options: RequestOptions) -> MockRequest {
+        let mut new = self;
+        new.options = options;
+        new
+    }
+
+    /**
+    Produce a `Request` from the fake environment.
+
+    This can fail in exactly the same ways `Request::new()` can.
+    */
+    pub fn build(self) -> Result<Request, Error> {
+        let mut env = self.vars;
+        let has_body = self.body.is_some();
+        for (name, value) in self.headers.into_iter() {
+            if has_body && name == "CONTENT_LENGTH" {
+                continue;
+            }
+            env.push((format!("HTTP_{}", &name), value));
+        }
+
+        let body = self.body.unwrap_or_default();
+        if has_body {
+            env.push(("HTTP_CONTENT_LENGTH".to_owned(), body.len().to_string()));
+        }
+
+        Request::from_env_with(env, Some(body.as_slice()), &self.options)
+    }
+}
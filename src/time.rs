@@ -0,0 +1,826 @@
+/*!
+Converting timestamps to local times for display.
+
+A `TimeZone` is either a fixed offset from UTC or a set of rules for a
+named zone. Named zones are read from the system's compiled zoneinfo
+database (`/usr/share/zoneinfo`, or wherever the `TZDIR` environment
+variable points) or given as a POSIX `TZ` rule string, so this works
+without bundling the tz database.
+
+```rust
+use dumb_cgi::time::TimeZone;
+
+// 2024-07-01 12:00:00 UTC
+let t = 1_719_835_200;
+
+let ist = TimeZone::parse("+05:30").unwrap();
+assert_eq!(ist.to_local(t).unwrap().to_string(), "2024-07-01T17:30:00+05:30");
+
+let berlin = TimeZone::posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+let local = berlin.to_local(t).unwrap();
+assert_eq!((local.hour, local.minute), (14, 0));
+assert_eq!(berlin.abbreviation(t), Some("CEST"));
+```
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+const SECS_PER_DAY: i64 = 86_400;
+
+fn tz_error(details: String) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details,
+    }
+}
+
+/*
+Days since 1970-01-01 of the given (proleptic Gregorian) date.
+
+This and `civil_from_days()` are Howard Hinnant's algorithms.
+*/
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = if month <= 2 {
+        yoe + era * 400 + 1
+    } else {
+        yoe + era * 400
+    };
+    (year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/**
+A calendar date and time of day at a particular offset from UTC.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    /// 1 through 12.
+    pub month: u32,
+    /// 1 through 31.
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    /// Seconds east of UTC.
+    pub offset: i32,
+}
+
+impl DateTime {
+    /**
+    Return the date and time `unix` seconds after the Unix epoch, as seen
+    at `offset` seconds east of UTC, or `None` if that's too far from the
+    epoch to represent.
+
+    ```rust
+    # use dumb_cgi::time::DateTime;
+    let dt = DateTime::from_unix(0, -5 * 3600).unwrap();
+    assert_eq!(dt.to_string(), "1969-12-31T19:00:00-05:00");
+    assert_eq!(DateTime::from_unix(i64::MAX, 3600), None);
+    ```
+    */
+    pub fn from_unix(unix: i64, offset: i32) -> Option<DateTime> {
+        let local = unix.checked_add(offset as i64)?;
+        Some(DateTime::from_local(local, offset))
+    }
+
+    /* The date and time `local` seconds after the epoch, labeled `offset`. */
+    fn from_local(local: i64, offset: i32) -> DateTime {
+        let (year, month, day) = civil_from_days(local.div_euclid(SECS_PER_DAY));
+        let secs = local.rem_euclid(SECS_PER_DAY) as u32;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+            offset,
+        }
+    }
+
+    /// Return the number of seconds since the Unix epoch.
+    pub fn to_unix(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECS_PER_DAY
+            + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+            - self.offset as i64
+    }
+
+    /// Return the day of the week, from 0 (Sunday) through 6 (Saturday).
+    pub fn weekday(&self) -> u32 {
+        (days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7) as u32
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/**
+Format `unix` (seconds since the Unix epoch) as an HTTP date, as used in
+`Expires`, `Last-Modified`, and `Date` headers.
+
+```rust
+# use dumb_cgi::time::http_date;
+assert_eq!(http_date(784_111_777), "Sun, 06 Nov 1994 08:49:37 GMT");
+```
+*/
+pub fn http_date(unix: i64) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    let dt = DateTime::from_local(unix, 0);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        DAYS[dt.weekday() as usize],
+        dt.day,
+        MONTHS[(dt.month - 1) as usize],
+        dt.year,
+        dt.hour,
+        dt.minute,
+        dt.second
+    )
+}
+
+/**
+Parse an HTTP date (as in `If-Modified-Since` headers) into seconds since
+the Unix epoch. Besides the usual format (the one `http_date()` writes),
+the two obsolete ones HTTP still requires accepting are understood.
+
+```rust
+# use dumb_cgi::time::parse_http_date;
+assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784_111_777));
+assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784_111_777));
+assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(784_111_777));
+assert_eq!(parse_http_date("06 Nov 1994"), None);
+```
+*/
+pub fn parse_http_date(s: &str) -> Option<i64> {
+    let fields: Vec<&str> = s.split([' ', ',', '-']).filter(|f| !f.is_empty()).collect();
+    let (day, month, year, time) = match fields[..] {
+        [_, day, month, year, time, "GMT"] => (day, month, year, time),
+        [_, month, day, time, year] => (day, month, year, time),
+        _ => return None,
+    };
+
+    let month = MONTHS.iter().position(|&m| m == month)? as u32 + 1;
+    let year: i64 = match year.parse().ok()? {
+        // Two-digit years are from RFC 850 dates, which went out of use
+        // in the 1990s.
+        y @ 0..=69 => y + 2000,
+        y @ 70..=99 => y + 1900,
+        y => y,
+    };
+    let day: u32 = day.parse().ok()?;
+    if !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    let mut hms = time.split(':').map(|n| n.parse::<i64>().ok());
+    let (hour, minute, second) = match (hms.next(), hms.next(), hms.next(), hms.next()) {
+        (Some(Some(h)), Some(Some(m)), Some(Some(s)), None) => (h, m, s),
+        _ => return None,
+    };
+    if !(0..24).contains(&hour) || !(0..60).contains(&minute) || !(0..=60).contains(&second) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Writes an RFC 3339 timestamp, like `2024-07-01T14:00:00+02:00`.
+impl std::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.unsigned_abs();
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            sign,
+            offset / 3600,
+            offset / 60 % 60
+        )
+    }
+}
+
+/*
+When in the year a POSIX TZ rule switches between standard and daylight
+time.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RuleDate {
+    /// `Jn`: day 1 through 365, never counting February 29th.
+    Julian(u32),
+    /// `n`: day 0 through 365, counting February 29th.
+    Zero(u32),
+    /// `Mm.w.d`: day `d` (0 = Sunday) of week `w` (5 = last) of month `m`.
+    MonthWeekDay(u32, u32, u32),
+}
+
+impl RuleDate {
+    /* The day (since the epoch) this date falls on in `year`. */
+    fn day_in(self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        match self {
+            RuleDate::Julian(n) => {
+                let n = n as i64;
+                jan1 + n - 1 + (is_leap_year(year) && n >= 60) as i64
+            }
+            RuleDate::Zero(n) => jan1 + n as i64,
+            RuleDate::MonthWeekDay(m, w, d) => {
+                let first = days_from_civil(year, m, 1);
+                let first_wday = (first + 4).rem_euclid(7);
+                let mut day = first + (d as i64 - first_wday).rem_euclid(7) + 7 * (w as i64 - 1);
+                let last = first + days_in_month(year, m) as i64 - 1;
+                while day > last {
+                    day -= 7;
+                }
+                day
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PosixRule {
+    std_abbr: String,
+    std_offset: i32,
+    dst: Option<DstRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DstRule {
+    abbr: String,
+    offset: i32,
+    start: RuleDate,
+    start_time: i32,
+    end: RuleDate,
+    end_time: i32,
+}
+
+/*
+A tiny cursor for parsing POSIX TZ strings.
+*/
+struct Cursor<'a>(&'a [u8]);
+
+impl Cursor<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) {
+            self.0 = &self.0[1..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let len = self.0.iter().take_while(|b| b.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        let n = std::str::from_utf8(&self.0[..len]).ok()?.parse().ok()?;
+        self.0 = &self.0[len..];
+        Some(n)
+    }
+
+    fn abbreviation(&mut self) -> Option<String> {
+        // Abbreviations are either alphabetic, or anything inside <...>.
+        let (len, skip) = if self.eat(b'<') {
+            let len = self.0.iter().position(|&b| b == b'>')?;
+            (len, len + 1)
+        } else {
+            let len = self
+                .0
+                .iter()
+                .take_while(|b| b.is_ascii_alphabetic())
+                .count();
+            (len, len)
+        };
+        if len < 3 {
+            return None;
+        }
+        let abbr = String::from_utf8(self.0[..len].to_vec()).ok()?;
+        self.0 = &self.0[skip..];
+        Some(abbr)
+    }
+
+    /* `[+-]hh[:mm[:ss]]`, as seconds. */
+    fn hms(&mut self) -> Option<i32> {
+        let negative = if self.eat(b'-') {
+            true
+        } else {
+            self.eat(b'+');
+            false
+        };
+        let mut secs = self.number()? as i32 * 3600;
+        if self.eat(b':') {
+            secs += self.number()? as i32 * 60;
+            if self.eat(b':') {
+                secs += self.number()? as i32;
+            }
+        }
+        Some(if negative { -secs } else { secs })
+    }
+
+    fn rule_date(&mut self) -> Option<RuleDate> {
+        let date = if self.eat(b'J') {
+            RuleDate::Julian(self.number().filter(|n| (1..=365).contains(n))?)
+        } else if self.eat(b'M') {
+            let m = self.number().filter(|n| (1..=12).contains(n))?;
+            self.eat(b'.').then_some(())?;
+            let w = self.number().filter(|n| (1..=5).contains(n))?;
+            self.eat(b'.').then_some(())?;
+            let d = self.number().filter(|n| *n <= 6)?;
+            RuleDate::MonthWeekDay(m, w, d)
+        } else {
+            RuleDate::Zero(self.number().filter(|n| *n <= 365)?)
+        };
+        Some(date)
+    }
+
+    /* A rule date, optionally followed by `/time` (default 02:00). */
+    fn transition(&mut self) -> Option<(RuleDate, i32)> {
+        let date = self.rule_date()?;
+        let time = if self.eat(b'/') { self.hms()? } else { 7200 };
+        Some((date, time))
+    }
+}
+
+impl PosixRule {
+    fn parse(s: &str) -> Option<PosixRule> {
+        let mut c = Cursor(s.as_bytes());
+        let std_abbr = c.abbreviation()?;
+        // POSIX offsets are hours *west* of UTC.
+        let std_offset = -c.hms()?;
+        if c.peek().is_none() {
+            return Some(PosixRule {
+                std_abbr,
+                std_offset,
+                dst: None,
+            });
+        }
+
+        let abbr = c.abbreviation()?;
+        let offset = match c.peek() {
+            Some(b',') | None => std_offset + 3600,
+            _ => -c.hms()?,
+        };
+        let ((start, start_time), (end, end_time)) = if c.eat(b',') {
+            let start = c.transition()?;
+            c.eat(b',').then_some(())?;
+            (start, c.transition()?)
+        } else {
+            // The US rules are the traditional default.
+            (
+                (RuleDate::MonthWeekDay(3, 2, 0), 7200),
+                (RuleDate::MonthWeekDay(11, 1, 0), 7200),
+            )
+        };
+        if c.peek().is_some() {
+            return None;
+        }
+
+        Some(PosixRule {
+            std_abbr,
+            std_offset,
+            dst: Some(DstRule {
+                abbr,
+                offset,
+                start,
+                start_time,
+                end,
+                end_time,
+            }),
+        })
+    }
+
+    /*
+    The offset and abbreviation in effect at `unix`, or `None` if that's
+    too far from the epoch to work out.
+    */
+    fn info_at(&self, unix: i64) -> Option<(i32, &str)> {
+        let dst = match &self.dst {
+            None => return Some((self.std_offset, &self.std_abbr)),
+            Some(dst) => dst,
+        };
+        let local = unix.checked_add(self.std_offset as i64)?;
+        let (year, _, _) = civil_from_days(local.div_euclid(SECS_PER_DAY));
+        // Transition times are in the local time in effect before them.
+        let transition = |date: RuleDate, time: i32, offset: i32| {
+            date.day_in(year)
+                .checked_mul(SECS_PER_DAY)?
+                .checked_add(time as i64 - offset as i64)
+        };
+        let start = transition(dst.start, dst.start_time, self.std_offset)?;
+        let end = transition(dst.end, dst.end_time, dst.offset)?;
+        let in_dst = if start < end {
+            start <= unix && unix < end
+        } else {
+            // Southern hemisphere: daylight time spans the new year.
+            !(end <= unix && unix < start)
+        };
+        if in_dst {
+            Some((dst.offset, &dst.abbr))
+        } else {
+            Some((self.std_offset, &self.std_abbr))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LocalType {
+    offset: i32,
+    abbr: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rules {
+    Fixed(i32),
+    Posix(PosixRule),
+    Zoneinfo {
+        transitions: Vec<(i64, usize)>,
+        types: Vec<LocalType>,
+        footer: Option<PosixRule>,
+    },
+}
+
+/*
+Read a big-endian integer of `N` bytes from the front of `data`.
+*/
+fn take<const N: usize>(data: &mut &[u8]) -> Option<[u8; N]> {
+    let bytes = data.get(..N)?.try_into().ok()?;
+    *data = &data[N..];
+    Some(bytes)
+}
+
+/*
+Parse a compiled zoneinfo (TZif) file.
+*/
+fn parse_tzif(mut data: &[u8]) -> Option<Rules> {
+    fn header(data: &mut &[u8]) -> Option<(u8, [usize; 6])> {
+        if &take::<4>(data)? != b"TZif" {
+            return None;
+        }
+        let version = take::<1>(data)?[0];
+        take::<15>(data)?;
+        let mut counts = [0usize; 6];
+        for count in counts.iter_mut() {
+            *count = u32::from_be_bytes(take::<4>(data)?) as usize;
+        }
+        Some((version, counts))
+    }
+    /*
+    The length of the data block the header's `counts` describe, with
+    times `time_size` bytes long; `None` on overflow.
+    */
+    fn block_len(counts: [usize; 6], time_size: usize) -> Option<usize> {
+        let [isut, isstd, leap, time, typ, chars] = counts;
+        time.checked_mul(time_size + 1)?
+            .checked_add(typ.checked_mul(6)?)?
+            .checked_add(chars)?
+            .checked_add(leap.checked_mul(time_size + 4)?)?
+            .checked_add(isstd)?
+            .checked_add(isut)
+    }
+
+    let (version, counts) = header(&mut data)?;
+    let time_size = if version >= b'2' {
+        // Skip the 32-bit data; the 64-bit version follows it.
+        data = data.get(block_len(counts, 4)?..)?;
+        8
+    } else {
+        4
+    };
+    let counts = if version >= b'2' {
+        header(&mut data)?.1
+    } else {
+        counts
+    };
+    // The counts come from the file; make sure the data is really there
+    // before allocating anything based on them.
+    if block_len(counts, time_size)? > data.len() {
+        return None;
+    }
+    let [isut, isstd, leap, time_count, type_count, char_count] = counts;
+
+    let mut times = Vec::with_capacity(time_count);
+    for _ in 0..time_count {
+        let t = if time_size == 8 {
+            i64::from_be_bytes(take::<8>(&mut data)?)
+        } else {
+            i32::from_be_bytes(take::<4>(&mut data)?) as i64
+        };
+        times.push(t);
+    }
+    let indices = data.get(..time_count)?.to_vec();
+    data = &data[time_count..];
+
+    let mut raw_types = Vec::with_capacity(type_count);
+    for _ in 0..type_count {
+        let offset = i32::from_be_bytes(take::<4>(&mut data)?);
+        let [_is_dst, abbr_idx] = take::<2>(&mut data)?;
+        raw_types.push((offset, abbr_idx as usize));
+    }
+    let chars = data.get(..char_count)?;
+    data = &data[char_count..];
+    let types = raw_types
+        .into_iter()
+        .map(|(offset, idx)| {
+            let abbr = chars.get(idx..).unwrap_or_default();
+            let len = abbr.iter().position(|&b| b == 0).unwrap_or(abbr.len());
+            LocalType {
+                offset,
+                abbr: String::from_utf8_lossy(&abbr[..len]).into_owned(),
+            }
+        })
+        .collect::<Vec<_>>();
+    if types.is_empty() {
+        return None;
+    }
+
+    let mut transitions = Vec::with_capacity(time_count);
+    for (t, idx) in times.into_iter().zip(indices) {
+        if idx as usize >= types.len() {
+            return None;
+        }
+        transitions.push((t, idx as usize));
+    }
+
+    let footer = if version >= b'2' {
+        data = data.get(leap * (time_size + 4) + isstd + isut..)?;
+        let text = std::str::from_utf8(data).ok()?;
+        text.trim_matches('\n')
+            .lines()
+            .next()
+            .filter(|s| !s.is_empty())
+            .and_then(PosixRule::parse)
+    } else {
+        None
+    };
+
+    Some(Rules::Zoneinfo {
+        transitions,
+        types,
+        footer,
+    })
+}
+
+/*
+Parse `Z`, `UTC`, `GMT`, or an offset like `+05:30`, `-0800`, or `+9`
+into seconds east of UTC.
+*/
+fn parse_offset(s: &str) -> Option<i32> {
+    let s = s.trim();
+    if ["Z", "UTC", "GMT"]
+        .iter()
+        .any(|z| s.eq_ignore_ascii_case(z))
+    {
+        return Some(0);
+    }
+    let s = s
+        .strip_prefix("UTC")
+        .or_else(|| s.strip_prefix("GMT"))
+        .unwrap_or(s);
+    let (negative, digits) = match s.as_bytes().first()? {
+        b'+' => (false, &s[1..]),
+        b'-' => (true, &s[1..]),
+        _ => return None,
+    };
+    // Checking this first also keeps `split_at()` on a char boundary.
+    if !digits.bytes().all(|b| b.is_ascii_digit() || b == b':') {
+        return None;
+    }
+    let (hours, minutes) = match digits.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if digits.len() == 4 => digits.split_at(2),
+        None => (digits, "0"),
+    };
+    if hours.is_empty() || hours.len() > 2 || minutes.len() > 2 {
+        return None;
+    }
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    let secs = hours * 3600 + minutes * 60;
+    Some(if negative { -secs } else { secs })
+}
+
+/**
+A time zone: a fixed offset from UTC, or the rules for a named zone.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeZone {
+    name: String,
+    rules: Rules,
+}
+
+impl TimeZone {
+    /// Coordinated Universal Time.
+    pub fn utc() -> TimeZone {
+        TimeZone {
+            name: "UTC".to_owned(),
+            rules: Rules::Fixed(0),
+        }
+    }
+
+    /// A zone always `offset` seconds east of UTC.
+    pub fn fixed(offset: i32) -> TimeZone {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let abs = offset.unsigned_abs();
+        TimeZone {
+            name: format!("{}{:02}:{:02}", sign, abs / 3600, abs / 60 % 60),
+            rules: Rules::Fixed(offset),
+        }
+    }
+
+    /**
+    A zone following a POSIX `TZ` rule like `"EST5EDT,M3.2.0,M11.1.0"`.
+    Returns `None` if the rule can't be parsed.
+    */
+    pub fn posix(rule: &str) -> Option<TimeZone> {
+        Some(TimeZone {
+            name: rule.to_owned(),
+            rules: Rules::Posix(PosixRule::parse(rule)?),
+        })
+    }
+
+    /**
+    A zone from the contents of a compiled zoneinfo (TZif) file.
+    */
+    pub fn from_tzif(name: &str, data: &[u8]) -> Result<TimeZone, Error> {
+        match parse_tzif(data) {
+            Some(rules) => Ok(TimeZone {
+                name: name.to_owned(),
+                rules,
+            }),
+            None => Err(tz_error(format!("Invalid zoneinfo data for {}", name))),
+        }
+    }
+
+    /**
+    Load the IANA zone `name` (like `"America/Chicago"`) from the system
+    zoneinfo database.
+
+    Names are checked before being used as paths, so it's safe to pass
+    a name that came from a cookie or query string; a bad or unknown
+    name is an error with code 400.
+    */
+    pub fn named(name: &str) -> Result<TimeZone, Error> {
+        let valid = !name.is_empty()
+            && name.split('/').all(|part| {
+                !part.is_empty()
+                    && !part.starts_with('.')
+                    && part
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b"_-+.".contains(&b))
+            });
+        let unknown = || Error {
+            code: 400,
+            message: "Unknown time zone.".to_owned(),
+            details: format!("Unknown time zone name {:?}", name),
+        };
+        if !valid {
+            return Err(unknown());
+        }
+        let dir = std::env::var_os("TZDIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/usr/share/zoneinfo"));
+        match std::fs::read(dir.join(name)) {
+            Ok(data) => TimeZone::from_tzif(name, &data),
+            Err(_) => Err(unknown()),
+        }
+    }
+
+    /**
+    Interpret a user-supplied zone: either an offset (`"Z"`, `"UTC"`,
+    `"+05:30"`, `"-0800"`) or an IANA zone name.
+
+    ```rust
+    # use dumb_cgi::time::TimeZone;
+    assert_eq!(TimeZone::parse("-08:00").unwrap(), TimeZone::fixed(-8 * 3600));
+    assert!(TimeZone::parse("../../etc/passwd").is_err());
+    ```
+    */
+    pub fn parse(s: &str) -> Result<TimeZone, Error> {
+        match parse_offset(s) {
+            Some(0) => Ok(TimeZone::utc()),
+            Some(offset) => Ok(TimeZone::fixed(offset)),
+            None => TimeZone::named(s.trim()),
+        }
+    }
+
+    /**
+    The server's local zone: from the `TZ` environment variable (a zone
+    name, `:`-prefixed path or name, or POSIX rule), or `/etc/localtime`
+    if that isn't set, or UTC if neither can be used.
+    */
+    pub fn local() -> TimeZone {
+        if let Ok(tz) = std::env::var("TZ") {
+            let tz = tz.trim_start_matches(':');
+            if tz.starts_with('/') {
+                if let Some(zone) = TimeZone::from_file(Path::new(tz)) {
+                    return zone;
+                }
+            } else if let Ok(zone) = TimeZone::named(tz) {
+                return zone;
+            } else if let Some(zone) = TimeZone::posix(tz) {
+                return zone;
+            }
+            return TimeZone::utc();
+        }
+        TimeZone::from_file(Path::new("/etc/localtime")).unwrap_or_else(TimeZone::utc)
+    }
+
+    fn from_file(path: &Path) -> Option<TimeZone> {
+        let data = std::fs::read(path).ok()?;
+        TimeZone::from_tzif(&path.to_string_lossy(), &data).ok()
+    }
+
+    /// The name this zone was created with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn info_at(&self, unix: i64) -> Option<(i32, &str)> {
+        match &self.rules {
+            Rules::Fixed(offset) => Some((*offset, &self.name)),
+            Rules::Posix(rule) => rule.info_at(unix),
+            Rules::Zoneinfo {
+                transitions,
+                types,
+                footer,
+            } => {
+                let n = transitions.partition_point(|(t, _)| *t <= unix);
+                if n == transitions.len() {
+                    if let Some(rule) = footer {
+                        return rule.info_at(unix);
+                    }
+                }
+                let ty = match n {
+                    0 => &types[0],
+                    n => &types[transitions[n - 1].1],
+                };
+                Some((ty.offset, &ty.abbr))
+            }
+        }
+    }
+
+    /// The offset from UTC (in seconds east) in effect at `unix`, or `None`
+    /// if `unix` is too far from the epoch to work it out.
+    pub fn offset_at(&self, unix: i64) -> Option<i32> {
+        Some(self.info_at(unix)?.0)
+    }
+
+    /// The abbreviation (like `"PDT"`) in effect at `unix`, or `None` if
+    /// `unix` is too far from the epoch to work it out.
+    pub fn abbreviation(&self, unix: i64) -> Option<&str> {
+        Some(self.info_at(unix)?.1)
+    }
+
+    /// The local date and time in this zone at `unix`, or `None` if it
+    /// can't be represented.
+    pub fn to_local(&self, unix: i64) -> Option<DateTime> {
+        DateTime::from_unix(unix, self.offset_at(unix)?)
+    }
+}
@@ -0,0 +1,154 @@
+/*!
+Measuring where the time goes in handling a request.
+
+This is off unless asked for with `RequestOptions::with_timings()`.
+Then a `Request` records how long it took to parse the environment
+(`env`), read the body (`body-read`), and parse it (`body-parse`), which
+costs a few reads of the clock; `Request::time_handler()` adds the time
+your own code takes (`handler`), and you can add phases of your own.
+Report them in a `Server-Timing` header (which browsers' developer tools
+display) with `Response::with_server_timing()`, or on stderr (which
+usually ends up in the web server's error log) with `Timings::report()`.
+
+This is a quick way to see whether per-request startup costs are
+significant next to the work your program actually does.
+
+```rust
+use dumb_cgi::{testing::MockRequest, RequestOptions, Response};
+
+let mut req = MockRequest::post("/")
+    .body("some data")
+    .options(RequestOptions::new().with_timings(true))
+    .build()
+    .unwrap();
+
+let response = req.time_handler(|req| {
+    // ...handle the request...
+    Response::new(204)
+});
+
+let response = response.with_server_timing(req.timings());
+assert!(response.get_header("server-timing").unwrap().contains("handler;dur="));
+```
+
+When timings are off, recording a phase does nothing, and neither does
+reporting them.
+*/
+
+use std::time::{Duration, Instant};
+
+/**
+Named durations recorded while handling a request.
+*/
+#[derive(Debug, Clone)]
+pub struct Timings {
+    started: Instant,
+    phases: Vec<(String, Duration)>,
+    enabled: bool,
+}
+
+impl Default for Timings {
+    fn default() -> Timings {
+        Timings::new()
+    }
+}
+
+impl Timings {
+    /// Start timing now, with no phases recorded.
+    pub fn new() -> Timings {
+        Timings {
+            started: Instant::now(),
+            phases: Vec::new(),
+            enabled: true,
+        }
+    }
+
+    /*
+    Timings that record nothing, for a request that didn't ask for them.
+    */
+    pub(crate) fn disabled() -> Timings {
+        Timings {
+            enabled: false,
+            ..Timings::new()
+        }
+    }
+
+    /// Return whether phases are being recorded.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /*
+    Move the start time back to `started` (for when work started before
+    this was created).
+    */
+    pub(crate) fn set_started(&mut self, started: Instant) {
+        self.started = started;
+    }
+
+    /**
+    Record that the phase `name` took `duration`. Recording the same phase
+    again adds to its time.
+    */
+    pub fn record(&mut self, name: &str, duration: Duration) {
+        if !self.enabled {
+            return;
+        }
+        match self.phases.iter_mut().find(|(n, _)| n == name) {
+            Some((_, d)) => *d += duration,
+            None => self.phases.push((name.to_owned(), duration)),
+        }
+    }
+
+    /// Record that the phase `name` has taken from `start` until now.
+    pub fn record_since(&mut self, name: &str, start: Instant) {
+        self.record(name, start.elapsed());
+    }
+
+    /// Run `f`, recording how long it took as the phase `name`.
+    pub fn time<T, F: FnOnce() -> T>(&mut self, name: &str, f: F) -> T {
+        let start = Instant::now();
+        let value = f();
+        self.record_since(name, start);
+        value
+    }
+
+    /// Return the time recorded for the phase `name`.
+    pub fn get(&self, name: &str) -> Option<Duration> {
+        self.phases.iter().find(|(n, _)| n == name).map(|(_, d)| *d)
+    }
+
+    /// Iterate over the recorded phases, in the order they were first recorded.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.phases.iter().map(|(n, d)| (n.as_str(), *d))
+    }
+
+    /// Return the time since timing started.
+    pub fn total(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /**
+    Return the phases (and a `total`) formatted as the value of a
+    `Server-Timing` header, like `env;dur=0.052, total;dur=1.317`
+    (durations in milliseconds).
+    */
+    pub fn server_timing(&self) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let mut parts: Vec<String> = self
+            .iter()
+            .map(|(name, d)| format!("{};dur={:.3}", name, ms(d)))
+            .collect();
+        parts.push(format!("total;dur={:.3}", ms(self.total())));
+        parts.join(", ")
+    }
+
+    /// Write the phases and total to stderr on a single line (if
+    /// they're being recorded).
+    pub fn report(&self) {
+        if !self.enabled {
+            return;
+        }
+        eprintln!("dumb_cgi timings: {}", self.server_timing());
+    }
+}
@@ -0,0 +1,188 @@
+/*!
+Time-based one-time passwords (as per
+[RFC 6238](https://www.rfc-editor.org/rfc/rfc6238)), for adding a second
+factor to a login form.
+
+This module is only available with the `auth` feature enabled.
+
+```rust
+use dumb_cgi::totp::Totp;
+
+let totp = Totp::from_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+assert_eq!(totp.generate(59), "287082");
+assert!(totp.verify("287082", 59 + 30));  // one step of clock drift is OK
+assert!(!totp.verify("287082", 59 + 90));
+
+// Hand this to an authenticator app (usually as a QR code).
+let uri = totp.provisioning_uri("My Site", "dan@example.com");
+assert!(uri.starts_with("otpauth://totp/My%20Site:dan%40example.com?secret="));
+```
+*/
+
+use crate::auth::now_secs;
+use crate::hash::{constant_time_eq, hmac_sha1};
+use crate::url_encode;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/**
+Decode an RFC 4648 base32 string (as TOTP secrets are usually presented).
+
+Case, spaces, hyphens, and trailing `=` padding are all ignored.
+*/
+pub fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut v = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer: u64 = 0;
+    let mut bits: u32 = 0;
+    for c in text.trim_end_matches('=').chars() {
+        if c == ' ' || c == '-' {
+            continue;
+        }
+        let c = c.to_ascii_uppercase() as u8;
+        let n = BASE32_ALPHABET.iter().position(|a| *a == c)? as u64;
+        buffer = (buffer << 5) | n;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            v.push((buffer >> bits) as u8);
+        }
+    }
+    Some(v)
+}
+
+/// Encode `bytes` as unpadded RFC 4648 base32.
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u64 = 0;
+    let mut bits: u32 = 0;
+    for &b in bytes.iter() {
+        buffer = (buffer << 8) | (b as u64);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            s.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        s.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    s
+}
+
+/**
+A TOTP generator/verifier for a single shared secret.
+
+Defaults are those every authenticator app supports: HMAC-SHA-1, six
+digits, a thirty-second time step, and acceptance of codes up to one step
+early or late (to allow for clock drift and slow typists).
+*/
+#[derive(Debug, Clone)]
+pub struct Totp {
+    secret: Vec<u8>,
+    digits: u32,
+    step: u64,
+    skew: u64,
+}
+
+impl Totp {
+    /// Create a new `Totp` from the raw bytes of a shared secret.
+    pub fn new<S: Into<Vec<u8>>>(secret: S) -> Totp {
+        Totp {
+            secret: secret.into(),
+            digits: 6,
+            step: 30,
+            skew: 1,
+        }
+    }
+
+    /// Create a new `Totp` from a base32-encoded shared secret.
+    pub fn from_base32(secret: &str) -> Option<Totp> {
+        base32_decode(secret).map(Totp::new)
+    }
+
+    /// Builder-pattern method for setting the number of digits in a code
+    /// (between 6 and 9).
+    pub fn with_digits(self, digits: u32) -> Totp {
+        let mut new = self;
+        new.digits = digits.clamp(6, 9);
+        new
+    }
+
+    /// Builder-pattern method for setting the time step (in seconds).
+    pub fn with_step(self, seconds: u64) -> Totp {
+        let mut new = self;
+        new.step = seconds.max(1);
+        new
+    }
+
+    /// Builder-pattern method for setting how many time steps of drift
+    /// (in either direction) `verify()` tolerates.
+    pub fn with_skew(self, steps: u64) -> Totp {
+        let mut new = self;
+        new.skew = steps;
+        new
+    }
+
+    /// Return the shared secret, base32-encoded.
+    pub fn secret_base32(&self) -> String {
+        base32_encode(&self.secret)
+    }
+
+    fn code_for_counter(&self, counter: u64) -> String {
+        let mac = hmac_sha1(&self.secret, &counter.to_be_bytes());
+        let offset = (mac[mac.len() - 1] & 0xf) as usize;
+        let binary = u32::from_be_bytes([
+            mac[offset] & 0x7f,
+            mac[offset + 1],
+            mac[offset + 2],
+            mac[offset + 3],
+        ]);
+        let code = binary as u64 % 10u64.pow(self.digits);
+        format!("{:0width$}", code, width = self.digits as usize)
+    }
+
+    /// Return the code valid at `unix_time` (seconds since the Unix epoch).
+    pub fn generate(&self, unix_time: u64) -> String {
+        self.code_for_counter(unix_time / self.step)
+    }
+
+    /// Return the code valid right now.
+    pub fn now(&self) -> String {
+        self.generate(now_secs())
+    }
+
+    /// Return whether `code` is valid at `unix_time`, allowing for the
+    /// configured amount of clock drift.
+    pub fn verify(&self, code: &str, unix_time: u64) -> bool {
+        let code = code.trim();
+        let counter = unix_time / self.step;
+        let first = counter.saturating_sub(self.skew);
+        let last = counter.saturating_add(self.skew);
+        let mut matched = false;
+        for c in first..=last {
+            matched |= constant_time_eq(self.code_for_counter(c).as_bytes(), code.as_bytes());
+        }
+        matched
+    }
+
+    /// Return whether `code` is valid right now.
+    pub fn verify_now(&self, code: &str) -> bool {
+        self.verify(code, now_secs())
+    }
+
+    /**
+    Return an `otpauth://` URI describing this generator, for enrolling
+    it in an authenticator app (usually by rendering it as a QR code).
+    */
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            url_encode(issuer),
+            url_encode(account),
+            self.secret_base32(),
+            url_encode(issuer),
+            self.digits,
+            self.step
+        )
+    }
+}
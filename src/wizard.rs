@@ -0,0 +1,436 @@
+/*!
+Multi-page forms ("wizards"), with the answers from earlier pages kept on
+the server until the last page is done.
+
+A `Wizard` is a list of `Step`s, each naming the form fields on its page
+and (optionally) how to check them. Every page's form posts back to the
+same program with a submit button named `wizard_action` (`ACTION_FIELD`)
+whose value is `next` or `back`; `Wizard::process()` saves the page's
+fields, checks them if moving forward, and says which page to show next
+(with any errors to show on it). The answers are kept in a small file in
+the wizard's directory, found again through a cookie.
+
+```rust
+use dumb_cgi::testing::MockRequest;
+use dumb_cgi::wizard::{Step, Wizard};
+
+let dir = std::env::temp_dir().join("dumb_cgi_wizard_doctest");
+let wizard = Wizard::new("signup", &dir)
+    .with_step(Step::new("account").with_required_field("email"))
+    .with_step(Step::new("profile").with_field("name").with_field("bio"));
+
+// The first visit starts on the first page.
+let req = MockRequest::get("/signup").build().unwrap();
+let state = wizard.process(&req).unwrap();
+assert_eq!(state.step(), 0);
+let cookie = wizard.cookie(&state).to_string();
+let cookie = cookie.split(';').next().unwrap();
+
+// Leaving out a required field keeps the user on the same page.
+let post = |body: &str| {
+    MockRequest::post("/signup")
+        .header("Cookie", cookie)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body.to_owned())
+        .build()
+        .unwrap()
+};
+let state = wizard.process(&post("email=&wizard_action=next")).unwrap();
+assert_eq!(state.step(), 0);
+assert_eq!(state.error("email"), Some("This field is required."));
+
+let state = wizard.process(&post("email=dan%40example.com&wizard_action=next")).unwrap();
+assert_eq!(state.step(), 1);
+let state = wizard.process(&post("name=Dan&bio=&wizard_action=next")).unwrap();
+assert!(state.is_complete());
+assert_eq!(state.value("email"), Some("dan@example.com"));
+assert_eq!(state.value("name"), Some("Dan"));
+
+// Done with it: remove the saved answers (and the cookie).
+let removal = wizard.finish(&state).unwrap();
+# std::fs::remove_dir_all(&dir).unwrap();
+```
+
+Nothing removes the files of wizards people give up on partway through;
+point a `gc::Gc` at the directory to expire them.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::cookie::{Cookie, SameSite};
+use crate::hash::{fill_random, to_hex};
+use crate::{url_decode, url_encode, Error, Request};
+
+/// The name of the submit button whose value (`next` or `back`) says
+/// which way to go.
+pub const ACTION_FIELD: &str = "wizard_action";
+
+/// The message `Step::with_required_field()` gives for an empty field.
+const REQUIRED: &str = "This field is required.";
+
+/**
+The type of a step's check on the answers: given all the answers so far
+(including the step's own), it returns a list of `(field, message)`
+pairs for any problems, which is empty if there aren't any.
+*/
+pub type Validator = dyn Fn(&BTreeMap<String, String>) -> Vec<(String, String)> + Send + Sync;
+
+/**
+One page of a `Wizard`.
+*/
+#[derive(Clone, Default)]
+pub struct Step {
+    name: String,
+    fields: Vec<String>,
+    required: Vec<String>,
+    validator: Option<Arc<Validator>>,
+}
+
+impl std::fmt::Debug for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Step")
+            .field("name", &self.name)
+            .field("fields", &self.fields)
+            .field("required", &self.required)
+            .field("validator", &self.validator.is_some())
+            .finish()
+    }
+}
+
+impl Step {
+    /// Create a step (with no fields) called `name`.
+    pub fn new<N: Into<String>>(name: N) -> Step {
+        Step {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Builder-pattern method for adding a form field to the step.
+    pub fn with_field<N: Into<String>>(self, name: N) -> Step {
+        let mut new = self;
+        new.fields.push(name.into());
+        new
+    }
+
+    /// Builder-pattern method for adding a form field that can't be left
+    /// empty (or only whitespace) to the step.
+    pub fn with_required_field<N: Into<String>>(self, name: N) -> Step {
+        let mut new = self;
+        let name = name.into();
+        new.required.push(name.clone());
+        new.fields.push(name);
+        new
+    }
+
+    /**
+    Builder-pattern method for setting a check the step's answers have to
+    pass before the user can go on to the next step (see `Validator`).
+
+    ```rust
+    # use dumb_cgi::wizard::Step;
+    let step = Step::new("dates")
+        .with_field("arrive")
+        .with_field("leave")
+        .with_validator(|answers| {
+            // ISO 8601 dates sort in date order.
+            match (answers.get("arrive"), answers.get("leave")) {
+                (Some(a), Some(l)) if l <= a => {
+                    vec![("leave".to_owned(), "You have to leave after you arrive.".to_owned())]
+                }
+                _ => Vec::new(),
+            }
+        });
+    ```
+    */
+    pub fn with_validator<F>(self, validator: F) -> Step
+    where
+        F: Fn(&BTreeMap<String, String>) -> Vec<(String, String)> + Send + Sync + 'static,
+    {
+        let mut new = self;
+        new.validator = Some(Arc::new(validator));
+        new
+    }
+
+    /// Return the step's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the names of the step's form fields.
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /*
+    Return the problems with the step's answers in `answers`.
+    */
+    fn check(&self, answers: &BTreeMap<String, String>) -> Vec<(String, String)> {
+        let mut errors: Vec<(String, String)> = self
+            .required
+            .iter()
+            .filter(|f| answers.get(*f).is_none_or(|v| v.trim().is_empty()))
+            .map(|f| (f.clone(), REQUIRED.to_owned()))
+            .collect();
+        if let Some(validator) = &self.validator {
+            errors.extend(validator(answers));
+        }
+        errors
+    }
+}
+
+/**
+Where one user has got to in a `Wizard`, returned by `Wizard::process()`.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WizardState {
+    id: String,
+    step: usize,
+    answers: BTreeMap<String, String>,
+    errors: Vec<(String, String)>,
+    complete: bool,
+}
+
+impl WizardState {
+    /// Return the index of the step whose page should be shown.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Return the answer given for the field `name` (on any step), if any.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.answers.get(name).map(|v| v.as_str())
+    }
+
+    /// Return all the answers given so far.
+    pub fn answers(&self) -> &BTreeMap<String, String> {
+        &self.answers
+    }
+
+    /// Return the problems that kept the user from moving on, as
+    /// `(field, message)` pairs.
+    pub fn errors(&self) -> &[(String, String)] {
+        &self.errors
+    }
+
+    /// Return the first problem with the field `name`, if any.
+    pub fn error(&self, name: &str) -> Option<&str> {
+        self.errors
+            .iter()
+            .find(|(f, _)| f == name)
+            .map(|(_, m)| m.as_str())
+    }
+
+    /// Return whether the last step has been passed, so all the answers
+    /// are in (and have been checked).
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: 500,
+        message: "Internal server error.".to_owned(),
+        details: format!("Error accessing wizard file {}: {}", path.display(), &e),
+    }
+}
+
+/*
+Return a new random state file id.
+*/
+fn new_id() -> Result<String, Error> {
+    let mut bytes = [0u8; 16];
+    fill_random(&mut bytes)?;
+    Ok(to_hex(&bytes))
+}
+
+fn is_id(s: &str) -> bool {
+    s.len() == 32 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/**
+A multi-page form (see the module documentation).
+*/
+#[derive(Debug, Clone)]
+pub struct Wizard {
+    name: String,
+    dir: PathBuf,
+    steps: Vec<Step>,
+    secure: bool,
+}
+
+impl Wizard {
+    /**
+    Create a wizard (with no steps) called `name`, keeping users' answers
+    in files in `dir` (which will be created if necessary).
+
+    The name is used in the cookie and file names, so it should be short
+    and plain, like `signup`; several wizards can share a directory.
+    */
+    pub fn new<N: Into<String>, P: Into<PathBuf>>(name: N, dir: P) -> Wizard {
+        Wizard {
+            name: name.into(),
+            dir: dir.into(),
+            steps: Vec::new(),
+            secure: false,
+        }
+    }
+
+    /// Builder-pattern method for adding a step after the current ones.
+    pub fn with_step(self, step: Step) -> Wizard {
+        let mut new = self;
+        new.steps.push(step);
+        new
+    }
+
+    /// Builder-pattern method for setting whether the cookie is marked
+    /// `Secure` (only sent over HTTPS).
+    pub fn with_secure(self, secure: bool) -> Wizard {
+        let mut new = self;
+        new.secure = secure;
+        new
+    }
+
+    /// Return the wizard's steps.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Return the name of the cookie that finds a user's answers.
+    pub fn cookie_name(&self) -> String {
+        format!("{}_wizard", &self.name)
+    }
+
+    fn path_for(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("wizard-{}-{}", &self.name, id))
+    }
+
+    /*
+    Read the state for the cookie in `req`, or start a new one if there
+    isn't one (or its file has gone).
+    */
+    fn load(&self, req: &Request) -> Result<WizardState, Error> {
+        let mut state = WizardState {
+            id: String::new(),
+            step: 0,
+            answers: BTreeMap::new(),
+            errors: Vec::new(),
+            complete: false,
+        };
+        let id = match req.cookie(&self.cookie_name()) {
+            Some(id) if is_id(id) => id,
+            _ => {
+                state.id = new_id()?;
+                return Ok(state);
+            }
+        };
+        state.id = id.to_owned();
+        let path = self.path_for(id);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(state),
+            Err(e) => return Err(io_error(&path, e)),
+        };
+        for line in text.lines() {
+            // A damaged line loses an answer, not the whole wizard.
+            if let Some((name, value)) = line.split_once('=') {
+                if let (Ok(name), Ok(value)) = (url_decode(name), url_decode(value)) {
+                    state.answers.insert(name, value);
+                }
+            } else if let Ok(step) = line.parse() {
+                state.step = step;
+            }
+        }
+        state.step = state.step.min(self.steps.len().saturating_sub(1));
+        Ok(state)
+    }
+
+    fn save(&self, state: &WizardState) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| io_error(&self.dir, e))?;
+        let mut text = format!("{}\n", state.step);
+        for (name, value) in state.answers.iter() {
+            text.push_str(&url_encode(name));
+            text.push('=');
+            text.push_str(&url_encode(value));
+            text.push('\n');
+        }
+        let path = self.path_for(&state.id);
+        // As with `Lockout`, write to a temporary file and rename it into
+        // place, so a concurrent request never sees half the answers.
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp, text).map_err(|e| io_error(&tmp, e))?;
+        std::fs::rename(&tmp, &path).map_err(|e| io_error(&path, e))
+    }
+
+    /**
+    Handle a request to the wizard, returning the state to show the user.
+
+    If `req` is a `POST` with a `wizard_action`, the current step's fields
+    are saved from it (a field that wasn't sent, like an unchecked
+    checkbox, is forgotten). Then, for `back`, the previous step becomes
+    current; for `next`, the step's answers are checked, and if they pass,
+    the next step becomes current (or, after the last step, the wizard is
+    complete). Any other request just shows the current step again.
+    */
+    pub fn process(&self, req: &Request) -> Result<WizardState, Error> {
+        let mut state = self.load(req)?;
+        let action = match req.method() {
+            "POST" => req.param(ACTION_FIELD),
+            _ => None,
+        };
+        let (action, step) = match (action, self.steps.get(state.step)) {
+            (Some(action), Some(step)) => (action, step),
+            _ => return Ok(state),
+        };
+
+        for field in step.fields.iter() {
+            match req.param(field) {
+                Some(value) => state.answers.insert(field.clone(), value),
+                None => state.answers.remove(field),
+            };
+        }
+        match action.as_str() {
+            "back" => state.step = state.step.saturating_sub(1),
+            "next" => {
+                state.errors = step.check(&state.answers);
+                if state.errors.is_empty() {
+                    if state.step + 1 < self.steps.len() {
+                        state.step += 1;
+                    } else {
+                        state.complete = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.save(&state)?;
+        Ok(state)
+    }
+
+    /// Return the cookie that has to be set on the response to keep the
+    /// user's place in the wizard.
+    pub fn cookie(&self, state: &WizardState) -> Cookie {
+        Cookie::new(self.cookie_name(), &state.id)
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax)
+            .with_secure(self.secure)
+    }
+
+    /**
+    Forget the answers saved for `state` (once they've been used, when
+    the wizard is complete, or if the user cancels), returning a cookie
+    that removes the wizard's cookie from the user agent.
+    */
+    pub fn finish(&self, state: &WizardState) -> Result<Cookie, Error> {
+        let path = self.path_for(&state.id);
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_error(&path, e)),
+        }
+        Ok(Cookie::removal(self.cookie_name()))
+    }
+}